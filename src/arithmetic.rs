@@ -0,0 +1,687 @@
+#![allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss,
+    clippy::cast_possible_wrap
+)]
+
+//! This file exposes a single struct that can decode an arithmetic coded
+//! bitstream in a JPEG file
+//!
+//! The decoder follows Annex D of the spec (ITU-T T.81) closely, with the
+//! register layout and the probability estimation table packing borrowed from
+//! libjpeg's `jdarith.c` written by Guido Vollbeding.
+//!
+//! Unlike Huffman coding, there are no tables to transmit, the decoder adapts
+//! its probability estimates as it goes, each estimate is kept in a single byte
+//! called a statistics bin, the top bit holds the more probable symbol(MPS) and
+//! the lower 7 bits index into the probability estimation state machine table.
+//!
+//! The only thing an encoder may tweak is the conditioning of DC and AC statistics
+//! via the DAC marker, see `parse_dac` in headers.rs
+
+use crate::decoder::{Decoder, MAX_COMPONENTS};
+use crate::errors::DecodeErrors;
+use crate::marker::Marker;
+use crate::misc::UN_ZIGZAG;
+use crate::reader::ByteReader;
+
+/// Number of statistics bins needed for DC coefficient coding
+///
+/// Section F.1.4.4.1.3 says we need at least 49.
+const DC_STAT_BINS: usize = 64;
+
+/// Number of statistics bins needed for AC coefficient coding
+///
+/// Section F.1.4.4.2 says we need at least 245.
+const AC_STAT_BINS: usize = 256;
+
+/// Index of the entry in [`ARITHMETIC_TABLE`] used for coding with a fixed
+/// probability of 0.5.
+///
+/// The entry never changes state, so we can work on copies of it.
+const FIXED_PROBABILITY: u8 = 113;
+
+/// Table D.2 of the spec, packed into a single integer
+///
+/// Each entry contains `Qe_Value << 16 | Next_Index_MPS << 8 | Switch_MPS << 7 | Next_Index_LPS`
+#[rustfmt::skip]
+const ARITHMETIC_TABLE: [u32; 114] = {
+    const fn v(qe: u32, next_lps: u32, next_mps: u32, switch: u32) -> u32
+    {
+        (qe << 16) | (next_mps << 8) | (switch << 7) | next_lps
+    }
+    [
+        v(0x5a1d,   1,   1, 1), v(0x2586,  14,   2, 0), v(0x1114,  16,   3, 0), v(0x080b,  18,   4, 0),
+        v(0x03d8,  20,   5, 0), v(0x01da,  23,   6, 0), v(0x00e5,  25,   7, 0), v(0x006f,  28,   8, 0),
+        v(0x0036,  30,   9, 0), v(0x001a,  33,  10, 0), v(0x000d,  35,  11, 0), v(0x0006,   9,  12, 0),
+        v(0x0003,  10,  13, 0), v(0x0001,  12,  13, 0), v(0x5a7f,  15,  15, 1), v(0x3f25,  36,  16, 0),
+        v(0x2cf2,  38,  17, 0), v(0x207c,  39,  18, 0), v(0x17b9,  40,  19, 0), v(0x1182,  42,  20, 0),
+        v(0x0cef,  43,  21, 0), v(0x09a1,  45,  22, 0), v(0x072f,  46,  23, 0), v(0x055c,  48,  24, 0),
+        v(0x0406,  49,  25, 0), v(0x0303,  51,  26, 0), v(0x0240,  52,  27, 0), v(0x01b1,  54,  28, 0),
+        v(0x0144,  56,  29, 0), v(0x00f5,  57,  30, 0), v(0x00b7,  59,  31, 0), v(0x008a,  60,  32, 0),
+        v(0x0068,  62,  33, 0), v(0x004e,  63,  34, 0), v(0x003b,  32,  35, 0), v(0x002c,  33,   9, 0),
+        v(0x5ae1,  37,  37, 1), v(0x484c,  64,  38, 0), v(0x3a0d,  65,  39, 0), v(0x2ef1,  67,  40, 0),
+        v(0x261f,  68,  41, 0), v(0x1f33,  69,  42, 0), v(0x19a8,  70,  43, 0), v(0x1518,  72,  44, 0),
+        v(0x1177,  73,  45, 0), v(0x0e74,  74,  46, 0), v(0x0bfb,  75,  47, 0), v(0x09f8,  77,  48, 0),
+        v(0x0861,  78,  49, 0), v(0x0706,  79,  50, 0), v(0x05cd,  48,  51, 0), v(0x04de,  50,  52, 0),
+        v(0x040f,  50,  53, 0), v(0x0363,  51,  54, 0), v(0x02d4,  52,  55, 0), v(0x025c,  53,  56, 0),
+        v(0x01f8,  54,  57, 0), v(0x01a4,  55,  58, 0), v(0x0160,  56,  59, 0), v(0x0125,  57,  60, 0),
+        v(0x00f6,  58,  61, 0), v(0x00cb,  59,  62, 0), v(0x00ab,  61,  63, 0), v(0x008f,  61,  32, 0),
+        v(0x5b12,  65,  65, 1), v(0x4d04,  80,  66, 0), v(0x412c,  81,  67, 0), v(0x37d8,  82,  68, 0),
+        v(0x2fe8,  83,  69, 0), v(0x293c,  84,  70, 0), v(0x2379,  86,  71, 0), v(0x1edf,  87,  72, 0),
+        v(0x1aa9,  87,  73, 0), v(0x174e,  72,  74, 0), v(0x1424,  72,  75, 0), v(0x119c,  74,  76, 0),
+        v(0x0f6b,  74,  77, 0), v(0x0d51,  75,  78, 0), v(0x0bb6,  77,  79, 0), v(0x0a40,  77,  48, 0),
+        v(0x5832,  80,  81, 1), v(0x4d1c,  88,  82, 0), v(0x438e,  89,  83, 0), v(0x3bdd,  90,  84, 0),
+        v(0x34ee,  91,  85, 0), v(0x2eae,  92,  86, 0), v(0x299a,  93,  87, 0), v(0x2516,  86,  71, 0),
+        v(0x5570,  88,  89, 1), v(0x4ca9,  95,  90, 0), v(0x44d9,  96,  91, 0), v(0x3e22,  97,  92, 0),
+        v(0x3824,  99,  93, 0), v(0x32b4,  99,  94, 0), v(0x2e17,  93,  86, 0), v(0x56a8,  95,  96, 1),
+        v(0x4f46, 101,  97, 0), v(0x47e5, 102,  98, 0), v(0x41cf, 103,  99, 0), v(0x3c3d, 104, 100, 0),
+        v(0x375e,  99,  93, 0), v(0x5231, 105, 102, 0), v(0x4c0f, 106, 103, 0), v(0x4639, 107, 104, 0),
+        v(0x415e, 103,  99, 0), v(0x5627, 105, 106, 1), v(0x50e7, 108, 107, 0), v(0x4b85, 109, 103, 0),
+        v(0x5597, 110, 109, 0), v(0x504f, 111, 107, 0), v(0x5a10, 110, 111, 1), v(0x5522, 112, 109, 0),
+        v(0x59eb, 112, 111, 1),
+        // Fixed probability estimate of 0.5, see Section 10.3 Table 5 of ITU-T Rec. T.851.
+        v(0x5a1d, 113, 113, 0),
+    ]
+};
+
+/// Conditioning values for arithmetic decoding as defined by a DAC marker
+///
+/// See section B.2.4.3 of the spec.
+#[derive(Copy, Clone)]
+pub(crate) struct ArithmeticConditioning
+{
+    /// Lower bound for DC conditioning, (L)
+    pub dc_lower: [u8; MAX_COMPONENTS],
+    /// Upper bound for DC conditioning, (U)
+    pub dc_upper: [u8; MAX_COMPONENTS],
+    /// AC conditioning (Kx)
+    pub ac_k:     [u8; MAX_COMPONENTS],
+}
+
+impl ArithmeticConditioning
+{
+    /// Default conditioning values used if no DAC marker is present,
+    /// See sections F.1.4.4.1.4 and F.1.4.4.2.1
+    pub(crate) const fn new() -> ArithmeticConditioning
+    {
+        ArithmeticConditioning {
+            dc_lower: [0; MAX_COMPONENTS],
+            dc_upper: [1; MAX_COMPONENTS],
+            ac_k:     [5; MAX_COMPONENTS],
+        }
+    }
+}
+
+/// An `ArithmeticDecoder` struct, the arithmetic coding counterpart of
+/// `BitStream`
+pub(crate) struct ArithmeticDecoder
+{
+    /// C register, base of the coding interval and input bit buffer
+    c:            u32,
+    /// A register, normalized size of coding interval
+    a:            u32,
+    /// Number of bits left in the bit buffer part of `c`
+    ct:           i32,
+    /// Statistics bins for DC coefficients
+    dc_stats:     [[u8; DC_STAT_BINS]; MAX_COMPONENTS],
+    /// Statistics bins for AC coefficients
+    ac_stats:     [[u8; AC_STAT_BINS]; MAX_COMPONENTS],
+    /// Context index for DC conditioning of each component
+    dc_context:   [usize; MAX_COMPONENTS],
+    /// Conditioning tables, from DAC
+    conditioning: ArithmeticConditioning,
+    /// Is the current scan part of a progressive image?
+    progressive:  bool,
+    /// Start of spectral selection
+    spec_start:   u8,
+    /// End of spectral selection
+    spec_end:     u8,
+    /// Successive approximation bit position high
+    succ_high:    u8,
+    /// Successive approximation bit position low
+    succ_low:     u8,
+    /// Did we find a marker(RST/EOF) during decoding?
+    pub marker:   Option<Marker>,
+}
+
+impl ArithmeticDecoder
+{
+    /// Create a new arithmetic decoder
+    pub(crate) const fn new() -> ArithmeticDecoder
+    {
+        ArithmeticDecoder {
+            c:            0,
+            a:            0,
+            ct:           -16,
+            dc_stats:     [[0; DC_STAT_BINS]; MAX_COMPONENTS],
+            ac_stats:     [[0; AC_STAT_BINS]; MAX_COMPONENTS],
+            dc_context:   [0; MAX_COMPONENTS],
+            conditioning: ArithmeticConditioning::new(),
+            progressive:  false,
+            spec_start:   0,
+            spec_end:     0,
+            succ_high:    0,
+            succ_low:     0,
+            marker:       None,
+        }
+    }
+
+    /// Set up the decoder for a new scan using conditioning tables and
+    /// scan parameters currently defined by the image
+    pub(crate) fn start_scan(&mut self, decoder: &Decoder)
+    {
+        self.conditioning = decoder.conditioning;
+        self.progressive = decoder.is_progressive;
+        self.spec_start = decoder.spec_start;
+        self.spec_end = decoder.spec_end;
+        self.succ_high = decoder.succ_high;
+        self.succ_low = decoder.succ_low;
+
+        self.reset();
+    }
+
+    /// Read a byte from the stream, handling byte stuffing and markers
+    ///
+    /// Once a marker is seen, the decoder is fed zeroes until it is done,
+    /// this is legal in arithmetic coding, see section D.2.6
+    #[inline]
+    fn read_byte(&mut self, reader: &mut ByteReader) -> u32
+    {
+        if self.marker.is_some()
+        {
+            return 0;
+        }
+        let mut byte = read_u8(reader);
+
+        if byte == 0xFF
+        {
+            // swallow any fill bytes
+            while byte == 0xFF
+            {
+                byte = read_u8(reader);
+            }
+
+            if byte == 0
+            {
+                // stuffed zero, the data byte is 0xFF
+                return 0xFF;
+            }
+            // a marker, store it and feed zeroes
+            self.marker = Marker::from_u8(byte);
+
+            if self.marker.is_none()
+            {
+                warn!("Unknown marker 0xFF{byte:X} in arithmetic coded data");
+                // make sure we do not read past the unknown marker
+                self.marker = Some(Marker::EOI);
+            }
+            return 0;
+        }
+        u32::from(byte)
+    }
+
+    /// Decode a single binary decision using the statistics bin `stat`
+    ///
+    /// This is the combined Decode, Renorm_D and Estimate procedures from
+    /// Annex D.2
+    #[inline]
+    fn decode(&mut self, reader: &mut ByteReader, stat: &mut u8) -> u8
+    {
+        // Renormalization and data input, see section D.2.6
+        while self.a < 0x8000
+        {
+            self.ct -= 1;
+
+            if self.ct < 0
+            {
+                let data = self.read_byte(reader);
+
+                self.c = (self.c << 8) | data;
+                self.ct += 8;
+
+                if self.ct < 0
+                {
+                    // need more initial bytes
+                    self.ct += 1;
+
+                    if self.ct == 0
+                    {
+                        // got two initial bytes, re-init A and exit the loop
+                        self.a = 0x8000;
+                    }
+                }
+            }
+            self.a <<= 1;
+        }
+
+        let mut sv = *stat;
+        let entry = ARITHMETIC_TABLE[usize::from(sv & 0x7F)];
+        // Next_Index_LPS + Switch_MPS
+        let nl = (entry & 0xFF) as u8;
+        // Next_Index_MPS
+        let nm = ((entry >> 8) & 0xFF) as u8;
+        // Qe value
+        let qe = entry >> 16;
+
+        // Decode and estimation procedures per section D.2.4 and D.2.5
+        self.a -= qe;
+        let temp = self.a << self.ct;
+
+        if self.c >= temp
+        {
+            self.c -= temp;
+            // Conditional LPS (less probable symbol) exchange
+            if self.a < qe
+            {
+                self.a = qe;
+                *stat = (sv & 0x80) ^ nm;
+            }
+            else
+            {
+                self.a = qe;
+                *stat = (sv & 0x80) ^ nl;
+                sv ^= 0x80;
+            }
+        }
+        else if self.a < 0x8000
+        {
+            // Conditional MPS (more probable symbol) exchange
+            if self.a < qe
+            {
+                *stat = (sv & 0x80) ^ nl;
+                sv ^= 0x80;
+            }
+            else
+            {
+                *stat = (sv & 0x80) ^ nm;
+            }
+        }
+        sv >> 7
+    }
+
+    /// Decode a difference value for a DC coefficient
+    ///
+    /// See section F.1.4.4.1 of the spec
+    fn decode_dc_diff(
+        &mut self, reader: &mut ByteReader, table: usize, component: usize,
+    ) -> Result<i32, DecodeErrors>
+    {
+        let mut stats = self.dc_stats[table];
+        let context = self.dc_context[component];
+        // Figure F.19: Decode_DC_DIFF
+        if self.decode(reader, &mut stats[context]) == 0
+        {
+            self.dc_stats[table] = stats;
+            self.dc_context[component] = 0;
+            return Ok(0);
+        }
+        // Figure F.22: Decoding the sign of v
+        let sign = usize::from(self.decode(reader, &mut stats[context + 1]));
+        let mut st = context + 2 + sign;
+        // Figure F.23: Decoding the magnitude category of v
+        let mut m = i32::from(self.decode(reader, &mut stats[st]));
+
+        if m != 0
+        {
+            // Table F.4: X1 = 20
+            st = 20;
+
+            while self.decode(reader, &mut stats[st]) == 1
+            {
+                m <<= 1;
+
+                if m == 0x8000
+                {
+                    return Err(DecodeErrors::MCUError(
+                        "Arithmetic decoding, magnitude overflow in DC coefficient".to_string(),
+                    ));
+                }
+                st += 1;
+            }
+        }
+        // Section F.1.4.4.1.2: Establish dc_context conditioning category
+        let lower = (1_i32 << self.conditioning.dc_lower[table]) >> 1;
+        let upper = (1_i32 << self.conditioning.dc_upper[table]) >> 1;
+
+        if m < lower
+        {
+            // zero diff category
+            self.dc_context[component] = 0;
+        }
+        else if m > upper
+        {
+            // large diff category
+            self.dc_context[component] = 12 + (sign * 4);
+        }
+        else
+        {
+            // small diff category
+            self.dc_context[component] = 4 + (sign * 4);
+        }
+        let mut v = m;
+        // Figure F.24: Decoding the magnitude bit pattern of v
+        st += 14;
+
+        m >>= 1;
+
+        while m != 0
+        {
+            if self.decode(reader, &mut stats[st]) == 1
+            {
+                v |= m;
+            }
+            m >>= 1;
+        }
+        self.dc_stats[table] = stats;
+
+        v += 1;
+
+        if sign == 1
+        {
+            v = -v;
+        }
+        Ok(v)
+    }
+
+    /// Decode AC coefficients between `start` and `end` (inclusive) into `block`
+    ///
+    /// Values are multiplied by `1 << shift` before being written
+    ///
+    /// See section F.1.4.4.2 of the spec.
+    fn decode_ac(
+        &mut self, reader: &mut ByteReader, table: usize, start: usize, end: usize, shift: u8,
+        block: &mut [i16; 64],
+    ) -> Result<(), DecodeErrors>
+    {
+        let mut stats = self.ac_stats[table];
+        let k_limit = usize::from(self.conditioning.ac_k[table]);
+
+        let mut k = start;
+        // Figure F.20: Decode_AC_coefficients
+        while k <= end
+        {
+            let mut st = 3 * (k - 1);
+
+            if self.decode(reader, &mut stats[st]) == 1
+            {
+                // EOB
+                break;
+            }
+
+            while self.decode(reader, &mut stats[st + 1]) == 0
+            {
+                st += 3;
+                k += 1;
+
+                if k > end
+                {
+                    return Err(DecodeErrors::MCUError(
+                        "Arithmetic decoding, spectral overflow in AC coefficients".to_string(),
+                    ));
+                }
+            }
+            // Figure F.22: Decoding the sign of v
+            let mut fixed = FIXED_PROBABILITY;
+            let sign = self.decode(reader, &mut fixed);
+
+            st += 2;
+            // Figure F.23: Decoding the magnitude category of v
+            let mut m = i32::from(self.decode(reader, &mut stats[st]));
+
+            if m != 0 && self.decode(reader, &mut stats[st]) == 1
+            {
+                m <<= 1;
+                st = if k <= k_limit { 189 } else { 217 };
+
+                while self.decode(reader, &mut stats[st]) == 1
+                {
+                    m <<= 1;
+
+                    if m == 0x8000
+                    {
+                        return Err(DecodeErrors::MCUError(
+                            "Arithmetic decoding, magnitude overflow in AC coefficient".to_string(),
+                        ));
+                    }
+                    st += 1;
+                }
+            }
+            let mut v = m;
+            // Figure F.24: Decoding the magnitude bit pattern of v
+            st += 14;
+
+            m >>= 1;
+
+            while m != 0
+            {
+                if self.decode(reader, &mut stats[st]) == 1
+                {
+                    v |= m;
+                }
+                m >>= 1;
+            }
+            v += 1;
+
+            if sign == 1
+            {
+                v = -v;
+            }
+            block[UN_ZIGZAG[k] & 63] = (v as i16).wrapping_mul(1 << shift);
+
+            k += 1;
+        }
+        self.ac_stats[table] = stats;
+
+        Ok(())
+    }
+
+    /// Decode the coefficients of a block present in the current scan
+    ///
+    /// For sequential images this is the whole block, for progressive images
+    /// this is either a DC or an AC band, first scan or a refinement scan.
+    ///
+    /// # Arguments
+    /// - reader: The bitstream from where we read more bits.
+    /// - dc_table: Index of the conditioning table for DC coefficients
+    /// - ac_table: Index of the conditioning table for AC coefficients
+    /// - component: Position of the component in the frame, used for DC conditioning.
+    /// - block: A memory region where we will write out the decoded values
+    /// - dc_prediction: Last DC value for this component
+    pub fn decode_block(
+        &mut self, reader: &mut ByteReader, dc_table: usize, ac_table: usize, component: usize,
+        block: &mut [i16; 64], dc_prediction: &mut i32,
+    ) -> Result<(), DecodeErrors>
+    {
+        if !self.progressive
+        {
+            let diff = self.decode_dc_diff(reader, dc_table, component)?;
+
+            *dc_prediction = dc_prediction.wrapping_add(diff);
+
+            block[0] = *dc_prediction as i16;
+
+            return self.decode_ac(reader, ac_table, 1, 63, 0, block);
+        }
+
+        let start = usize::from(self.spec_start);
+        let end = usize::from(self.spec_end);
+
+        if start == 0
+        {
+            if self.succ_high == 0
+            {
+                self.decode_dc_first(reader, dc_table, component, &mut block[0], dc_prediction)
+            }
+            else
+            {
+                self.decode_dc_refine(reader, &mut block[0]);
+                Ok(())
+            }
+        }
+        else if self.succ_high == 0
+        {
+            self.decode_ac(reader, ac_table, start, end, self.succ_low, block)
+        }
+        else
+        {
+            self.decode_ac_refine(reader, ac_table, start, end, block)
+        }
+    }
+
+    /// Decode the first scan of DC coefficients in a progressive image
+    ///
+    /// See section G.1.3.1 of the spec
+    fn decode_dc_first(
+        &mut self, reader: &mut ByteReader, table: usize, component: usize, coeff: &mut i16,
+        dc_prediction: &mut i32,
+    ) -> Result<(), DecodeErrors>
+    {
+        let diff = self.decode_dc_diff(reader, table, component)?;
+
+        *dc_prediction = dc_prediction.wrapping_add(diff);
+
+        *coeff = (*dc_prediction as i16).wrapping_mul(1 << self.succ_low);
+
+        Ok(())
+    }
+
+    /// Decode a refinement scan of DC coefficients in a progressive image
+    ///
+    /// Refinement bits are coded with a fixed probability, see section G.1.3.2
+    fn decode_dc_refine(&mut self, reader: &mut ByteReader, coeff: &mut i16)
+    {
+        let mut fixed = FIXED_PROBABILITY;
+
+        if self.decode(reader, &mut fixed) == 1
+        {
+            *coeff |= 1 << self.succ_low;
+        }
+    }
+
+    /// Decode a refinement scan of AC coefficients in a progressive image
+    ///
+    /// See section G.1.3.3 of the spec
+    fn decode_ac_refine(
+        &mut self, reader: &mut ByteReader, table: usize, start: usize, end: usize,
+        block: &mut [i16; 64],
+    ) -> Result<(), DecodeErrors>
+    {
+        let mut stats = self.ac_stats[table];
+
+        let p1 = 1_i16 << self.succ_low;
+        let m1 = -1_i16 << self.succ_low;
+
+        // Establish EOBx, the end of block position of the previous stage
+        let mut eob_x = end;
+
+        while eob_x > 0 && block[UN_ZIGZAG[eob_x] & 63] == 0
+        {
+            eob_x -= 1;
+        }
+
+        let mut k = start;
+
+        while k <= end
+        {
+            let mut st = 3 * (k - 1);
+
+            if k > eob_x && self.decode(reader, &mut stats[st]) == 1
+            {
+                // EOB
+                break;
+            }
+
+            loop
+            {
+                let coeff = &mut block[UN_ZIGZAG[k] & 63];
+
+                if *coeff != 0
+                {
+                    // previously non-zero coefficient, decode a correction bit
+                    if self.decode(reader, &mut stats[st + 2]) == 1
+                    {
+                        if *coeff < 0
+                        {
+                            *coeff = coeff.wrapping_add(m1);
+                        }
+                        else
+                        {
+                            *coeff = coeff.wrapping_add(p1);
+                        }
+                    }
+                    break;
+                }
+
+                if self.decode(reader, &mut stats[st + 1]) == 1
+                {
+                    // newly non-zero coefficient
+                    let mut fixed = FIXED_PROBABILITY;
+
+                    *coeff = if self.decode(reader, &mut fixed) == 1
+                    {
+                        m1
+                    }
+                    else
+                    {
+                        p1
+                    };
+
+                    break;
+                }
+                st += 3;
+                k += 1;
+
+                if k > end
+                {
+                    return Err(DecodeErrors::MCUError(
+                        "Arithmetic decoding, spectral overflow in AC refinement".to_string(),
+                    ));
+                }
+            }
+            k += 1;
+        }
+        self.ac_stats[table] = stats;
+
+        Ok(())
+    }
+
+    /// Find the next marker in the stream if we have not seen one yet.
+    ///
+    /// Unlike the Huffman decoder which reads ahead, the arithmetic decoder
+    /// may stop before the bytes flushed by the encoder at the end of a restart interval,
+    /// so we have to look for the marker ourselves.
+    pub(crate) fn find_marker(&mut self, reader: &mut ByteReader)
+    {
+        while self.marker.is_none() && !reader.is_empty()
+        {
+            self.read_byte(reader);
+        }
+    }
+
+    /// Reset the decoder
+    ///
+    /// This zeroes out all statistics bins and forces the decoder to read
+    /// two new bytes to fill the C register.
+    #[cold]
+    pub fn reset(&mut self)
+    {
+        self.c = 0;
+        self.a = 0;
+        self.ct = -16;
+        self.dc_stats = [[0; DC_STAT_BINS]; MAX_COMPONENTS];
+        self.ac_stats = [[0; AC_STAT_BINS]; MAX_COMPONENTS];
+        self.dc_context = [0; MAX_COMPONENTS];
+        self.marker = None;
+    }
+}
+
+/// Read a byte from underlying file
+///
+/// Returns zero if we are at the end of the stream
+#[inline(always)]
+fn read_u8(reader: &mut ByteReader) -> u8
+{
+    // if we have nothing left fill buffer with zeroes
+    reader.read_u8()
+}
@@ -0,0 +1,358 @@
+//! Depth maps and other auxiliary images of camera JPEGs
+//!
+//! Cameras store images next to the photo for editing it afterwards, they come
+//! from
+//! - GDepth and GImage XMP properties of Google Camera portrait photos, a depth
+//!   map, its confidence and the photo before blurring its background, encoded
+//!   in base64, usually in the extended XMP packet.
+//! - Dynamic Depth files, whose items are appended after the image and listed
+//!   in its container directory (see the `container` module), with the
+//!   parameters of the depth map in `Depth:` XMP properties.
+//! - Images of type 0 in the MP index, e.g. gain maps and Apple's portrait
+//!   effects matte, the other types are views of the scene or previews, see
+//!   `Decoder::decode_mp_image`.
+//!
+//! Depth maps store a value between 0 and 1 for each pixel, see
+//! `DepthMetadata::distance` to turn it into a distance.
+
+use crate::container::{container_items, ItemSemantic};
+use crate::errors::DecodeErrors;
+use crate::gainmap::GainMapMetadata;
+use crate::mpf::MpImageType;
+use crate::xmp::xmp_property;
+use crate::Decoder;
+
+/// What an auxiliary image is
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AuxiliaryRole
+{
+    /// A depth map, with `AuxiliaryImage::depth` giving its parameters
+    Depth,
+    /// Confidence of the values of a depth map
+    Confidence,
+    /// The image before effects, e.g. background blur, were applied
+    Original,
+    /// A gain map, see `Decoder::gain_map`
+    GainMap,
+    /// Any other role, as written, e.g. a container item semantic or the
+    /// `apdi:AuxiliaryImageType` of Apple's auxiliary images
+    Other(String),
+    /// An image of the MP index without anything saying what it is
+    Unknown,
+}
+
+/// How the values of a depth map are encoded
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DepthFormat
+{
+    /// Values are inverse distances, scaled so 0 is `near` and 1 is `far`
+    RangeInverse,
+    /// Values are distances, between `near` and `far`
+    RangeLinear,
+    /// Any other format, as written
+    Other(String),
+}
+
+/// Parameters of a depth map
+#[derive(Clone, Debug, PartialEq)]
+pub struct DepthMetadata
+{
+    pub format:       DepthFormat,
+    /// Distance of values of 0
+    pub near:         f32,
+    /// Distance of values of 1
+    pub far:          f32,
+    /// Units of `near` and `far`, e.g. `Meters`
+    pub units:        Option<String>,
+    /// What distances are measured along, `OpticalAxis` or `OpticalRay`
+    pub measure_type: Option<String>,
+}
+
+impl DepthMetadata
+{
+    /// Parse the properties whose names start with `prefix`, e.g. `GDepth:`
+    ///
+    /// Returns `None` if the format, near or far is missing.
+    fn from_xmp<'a>(
+        property: impl Fn(&str) -> Option<&'a str>, prefix: &str,
+    ) -> Option<DepthMetadata>
+    {
+        let property = |name: &str| property(&format!("{prefix}{name}"));
+        let distance = |name: &str| property(name).and_then(|x| x.trim().parse::<f32>().ok());
+
+        let format = match property("Format")?
+        {
+            "RangeInverse" => DepthFormat::RangeInverse,
+            "RangeLinear" => DepthFormat::RangeLinear,
+            format => DepthFormat::Other(format.to_string()),
+        };
+
+        Some(DepthMetadata {
+            format,
+            near: distance("Near")?,
+            far: distance("Far")?,
+            units: property("Units").map(str::to_string),
+            measure_type: property("MeasureType").map(str::to_string),
+        })
+    }
+
+    /// The distance a depth map `value` between 0 and 1 stands for, e.g. a
+    /// sample of an 8 bit depth map divided by 255
+    ///
+    /// Returns `None` for formats other than `RangeInverse` and `RangeLinear`.
+    #[must_use]
+    pub fn distance(&self, value: f32) -> Option<f32>
+    {
+        match self.format
+        {
+            DepthFormat::RangeLinear => Some(value * (self.far - self.near) + self.near),
+            DepthFormat::RangeInverse =>
+            {
+                Some(self.far * self.near / (self.far - value * (self.far - self.near)))
+            }
+            DepthFormat::Other(_) => None,
+        }
+    }
+}
+
+/// An image stored next to the main one
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuxiliaryImage
+{
+    pub role:   AuxiliaryRole,
+    /// MIME type of the image, e.g. `image/jpeg`
+    pub mime:   String,
+    /// Where the image starts in the buffer, `None` for images stored in XMP
+    pub offset: Option<usize>,
+    /// The encoded image
+    pub data:   Vec<u8>,
+    /// Parameters of depth maps
+    pub depth:  Option<DepthMetadata>,
+}
+
+/// Value of each base64 character, 0xFF for other characters
+#[allow(clippy::cast_possible_truncation)]
+const BASE64_VALUES: [u8; 256] = {
+    let mut values = [0xFF; 256];
+    let alphabet = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut i = 0;
+
+    while i < alphabet.len()
+    {
+        values[alphabet[i] as usize] = i as u8;
+        i += 1;
+    }
+    values
+};
+
+/// Decode base64 `text`, ignoring whitespace
+///
+/// Returns `None` if it has other characters or is truncated.
+#[allow(clippy::cast_possible_truncation)]
+fn decode_base64(text: &str) -> Option<Vec<u8>>
+{
+    let mut data = Vec::with_capacity(text.len() / 4 * 3);
+    let mut bits = 0_u32;
+    let mut count = 0;
+
+    for c in text
+        .trim_end_matches(|c: char| c == '=' || c.is_whitespace())
+        .bytes()
+    {
+        if c.is_ascii_whitespace()
+        {
+            continue;
+        }
+        let value = BASE64_VALUES[usize::from(c)];
+
+        if value == 0xFF
+        {
+            return None;
+        }
+        bits = (bits << 6) | u32::from(value);
+        count += 6;
+
+        if count >= 8
+        {
+            count -= 8;
+            data.push((bits >> count) as u8);
+        }
+    }
+    // a single character left can't make a byte
+    (count < 6).then_some(data)
+}
+
+/// What an image of type 0 of the MP index is, from its gain map metadata or
+/// the auxiliary image type of Apple's images
+///
+/// Returns `None` if its headers can't be read.
+fn mp_image_role(data: &[u8]) -> Option<AuxiliaryRole>
+{
+    let mut decoder = Decoder::new();
+
+    decoder.read_headers(data).ok()?;
+
+    if GainMapMetadata::from_headers(&decoder).is_some()
+    {
+        return Some(AuxiliaryRole::GainMap);
+    }
+    let xmp = decoder.xmp().unwrap_or_default();
+
+    let role = match xmp_property(&xmp, "apdi:AuxiliaryImageType")
+    {
+        Some(kind) if kind.ends_with(":hdrgainmap") => AuxiliaryRole::GainMap,
+        Some(kind) => AuxiliaryRole::Other(kind.to_string()),
+        None => AuxiliaryRole::Unknown,
+    };
+
+    Some(role)
+}
+
+impl Decoder
+{
+    /// List the depth maps and other auxiliary images of a camera JPEG
+    ///
+    /// Images are listed in this order, GDepth and GImage images from XMP
+    /// properties, images of the container directory and images of type 0 in
+    /// the MP index. Use `decode_auxiliary_image` to decode them.
+    ///
+    /// This reads the headers of `buf`, afterwards the decoder describes it.
+    ///
+    /// # Errors
+    /// If the headers of the image can't be read.
+    pub fn auxiliary_images(&mut self, buf: &[u8]) -> Result<Vec<AuxiliaryImage>, DecodeErrors>
+    {
+        self.read_headers(buf)?;
+
+        let mut images = vec![];
+
+        let xmp = self.xmp().unwrap_or_default();
+        let extended = self.extended_xmp().unwrap_or_default();
+        let property =
+            |name: &str| xmp_property(&xmp, name).or_else(|| xmp_property(&extended, name));
+
+        // GDepth and GImage
+        let embedded = [
+            (AuxiliaryRole::Depth, "GDepth:Data", "GDepth:Mime"),
+            (
+                AuxiliaryRole::Confidence,
+                "GDepth:ConfidenceData",
+                "GDepth:ConfidenceMime",
+            ),
+            (AuxiliaryRole::Original, "GImage:Data", "GImage:Mime"),
+        ];
+
+        for (role, data, mime) in embedded
+        {
+            let Some(encoded) = property(data)
+            else
+            {
+                continue;
+            };
+            let Some(data) = decode_base64(encoded)
+            else
+            {
+                warn!("{data} isn't valid base64, ignoring it");
+                continue;
+            };
+            let depth = (role == AuxiliaryRole::Depth)
+                .then(|| DepthMetadata::from_xmp(property, "GDepth:"))
+                .flatten();
+
+            images.push(AuxiliaryImage {
+                role,
+                mime: property(mime).unwrap_or("").to_string(),
+                offset: None,
+                data,
+                depth,
+            });
+        }
+
+        // Dynamic Depth, the primary image ends somewhere after the headers
+        let items = container_items(&xmp, 0, buf.len()).unwrap_or_default();
+
+        for item in items.iter().filter(|x| x.mime.starts_with("image/"))
+        {
+            let role = match &item.semantic
+            {
+                ItemSemantic::Depth => AuxiliaryRole::Depth,
+                ItemSemantic::Confidence => AuxiliaryRole::Confidence,
+                ItemSemantic::Original => AuxiliaryRole::Original,
+                ItemSemantic::GainMap => AuxiliaryRole::GainMap,
+                ItemSemantic::Other(semantic) => AuxiliaryRole::Other(semantic.clone()),
+                ItemSemantic::Primary | ItemSemantic::MotionPhoto => continue,
+            };
+            let depth = (role == AuxiliaryRole::Depth)
+                .then(|| DepthMetadata::from_xmp(property, "Depth:"))
+                .flatten();
+
+            images.push(AuxiliaryImage {
+                role,
+                mime: item.mime.clone(),
+                offset: Some(item.offset),
+                data: buf[item.offset..item.offset + item.length].to_vec(),
+                depth,
+            });
+        }
+
+        // MP index, skipping images already listed by the container directory
+        let mp_images = self.mpf.as_ref().map_or(&[][..], |x| x.images());
+
+        for (index, image) in mp_images.iter().enumerate().skip(1)
+        {
+            if image.image_type != MpImageType::Undefined
+                || items.iter().any(|x| x.offset == image.offset)
+            {
+                continue;
+            }
+            let Some(data) = self.mpf.as_ref().and_then(|x| x.image_data(buf, index))
+            else
+            {
+                warn!("Image {index} of the MP index is out of bounds");
+                continue;
+            };
+            let role = mp_image_role(data).unwrap_or_else(|| {
+                warn!("Can't read headers of image {index} of the MP index");
+                AuxiliaryRole::Unknown
+            });
+
+            images.push(AuxiliaryImage {
+                role,
+                mime: "image/jpeg".to_string(),
+                offset: Some(image.offset),
+                data: data.to_vec(),
+                depth: None,
+            });
+        }
+
+        Ok(images)
+    }
+
+    /// Decode an auxiliary image with the options of this decoder
+    ///
+    /// Afterwards the decoder describes the auxiliary image, e.g. `width` and
+    /// `info` are its own, but keeps its options, grayscale images such as
+    /// depth maps don't change the output colorspace of the next image.
+    ///
+    /// # Errors
+    /// If the image isn't a JPEG image, e.g. PNG depth maps, or it can't be
+    /// decoded.
+    pub fn decode_auxiliary_image(
+        &mut self, image: &AuxiliaryImage,
+    ) -> Result<Vec<u8>, DecodeErrors>
+    {
+        if !image.data.starts_with(&[0xFF, 0xD8])
+        {
+            return Err(DecodeErrors::Format(format!(
+                "Auxiliary image isn't a JPEG image, its type is `{}`",
+                image.mime
+            )));
+        }
+        let options = self.options;
+        let pixels = self.decode_buffer(&image.data);
+
+        self.options = options;
+
+        pixels
+    }
+}
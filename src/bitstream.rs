@@ -39,12 +39,12 @@
 //!
 //! Knock yourself out.
 use std::cmp::min;
-use std::io::Cursor;
 
 use crate::errors::DecodeErrors;
 use crate::huffman::{HuffmanTable, HUFF_LOOKAHEAD};
 use crate::marker::Marker;
 use crate::misc::UN_ZIGZAG;
+use crate::reader::ByteReader;
 
 macro_rules! decode_huff {
     ($stream:tt,$symbol:tt,$table:tt) => {
@@ -156,7 +156,7 @@ impl BitStream
     ///
     /// This function will only refill if `self.count` is less than 32
     #[inline(never)] // to many call sites?
-    fn refill(&mut self, reader: &mut Cursor<Vec<u8>>) -> Result<bool, DecodeErrors>
+    fn refill(&mut self, reader: &mut ByteReader) -> Result<bool, DecodeErrors>
     {
         /// Macro version of a single byte refill.
         /// Arguments
@@ -217,19 +217,14 @@ impl BitStream
         if self.bits_left <= 32 && self.marker.is_none()
         {
             // So before we do anything, check if we have a 0xFF byte
-            let position = reader.position() as usize;
-
-            if ((position + 4) as usize) < (reader.get_ref().len())
+            if let Some(buf) = reader.peek::<4>()
             {
-                // we have 4 bytes to spare, read the 4 bytes into a temporary buffer
-                let mut buf = [0; 4];
-                buf.copy_from_slice(reader.get_ref().get(position..position + 4).unwrap());
-                // create buffer
+                // we have 4 bytes to spare, create buffer
                 let msb_buf = u32::from_be_bytes(buf);
                 // check if we have 0xff
                 if !has_byte(msb_buf, 255)
                 {
-                    reader.set_position((position + 4) as u64);
+                    reader.skip(4);
                     self.bits_left += 32;
                     self.buffer <<= 32;
                     self.buffer |= u64::from(msb_buf);
@@ -270,12 +265,14 @@ impl BitStream
     )]
     #[inline(always)]
     fn decode_dc(
-        &mut self, reader: &mut Cursor<Vec<u8>>, dc_table: &HuffmanTable, dc_prediction: &mut i32,
+        &mut self, reader: &mut ByteReader, dc_table: &HuffmanTable, dc_prediction: &mut i32,
     ) -> Result<bool, DecodeErrors>
     {
         let (mut symbol, r);
 
-        if self.bits_left < 16
+        // a DC code can take up to 16 bits and its difference up to 15 more
+        // (12 bit images), so make sure we have enough bits for both
+        if self.bits_left < 32
         {
             self.refill(reader)?;
         };
@@ -296,6 +293,43 @@ impl BitStream
         return Ok(true);
     }
 
+    /// Decode the difference of a sample in a lossless image
+    ///
+    /// Differences are coded like DC coefficients (Table H.2), with the exception
+    /// of category 16 which has no extra bits and always means 32768.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub(crate) fn decode_lossless_diff(
+        &mut self, reader: &mut ByteReader, table: &HuffmanTable,
+    ) -> Result<i32, DecodeErrors>
+    {
+        let mut symbol;
+
+        if self.bits_left < 32
+        {
+            self.refill(reader)?;
+        }
+
+        symbol = self.peek_bits::<HUFF_LOOKAHEAD>();
+        symbol = table.lookup[symbol as usize];
+
+        decode_huff!(self, symbol, table);
+
+        match symbol
+        {
+            0 => Ok(0),
+            1..=15 =>
+            {
+                let r = self.get_bits(symbol as u8);
+
+                Ok(huff_extend(r, symbol))
+            }
+            16 => Ok(32768),
+            _ => Err(DecodeErrors::Format(format!(
+                "Invalid lossless difference category {symbol}, corrupt JPEG"
+            ))),
+        }
+    }
+
     /// Decode a Minimum Code Unit(MCU) as quickly as possible
     ///
     /// # Arguments
@@ -312,7 +346,7 @@ impl BitStream
     )]
     #[inline(never)]
     pub fn decode_mcu_block(
-        &mut self, reader: &mut Cursor<Vec<u8>>, dc_table: &HuffmanTable, ac_table: &HuffmanTable,
+        &mut self, reader: &mut ByteReader, dc_table: &HuffmanTable, ac_table: &HuffmanTable,
         block: &mut [i16; 64], dc_prediction: &mut i32,
     ) -> Result<(), DecodeErrors>
     {
@@ -405,7 +439,7 @@ impl BitStream
     #[allow(clippy::cast_possible_truncation)]
     #[inline]
     pub(crate) fn decode_prog_dc_first(
-        &mut self, reader: &mut Cursor<Vec<u8>>, dc_table: &HuffmanTable, block: &mut i16,
+        &mut self, reader: &mut ByteReader, dc_table: &HuffmanTable, block: &mut i16,
         dc_prediction: &mut i32,
     ) -> Result<(), DecodeErrors>
     {
@@ -415,7 +449,7 @@ impl BitStream
     }
     #[inline]
     pub(crate) fn decode_prog_dc_refine(
-        &mut self, reader: &mut Cursor<Vec<u8>>, block: &mut i16,
+        &mut self, reader: &mut ByteReader, block: &mut i16,
     ) -> Result<(), DecodeErrors>
     {
         // refinement scan
@@ -441,7 +475,7 @@ impl BitStream
         return k;
     }
     pub(crate) fn decode_mcu_ac_first(
-        &mut self, reader: &mut Cursor<Vec<u8>>, ac_table: &HuffmanTable, block: &mut [i16; 64],
+        &mut self, reader: &mut ByteReader, ac_table: &HuffmanTable, block: &mut [i16; 64],
     ) -> Result<bool, DecodeErrors>
     {
         let shift = self.successive_low;
@@ -505,7 +539,7 @@ impl BitStream
         return Ok(true);
     }
     pub(crate) fn decode_mcu_ac_refine(
-        &mut self, reader: &mut Cursor<Vec<u8>>, table: &HuffmanTable, block: &mut [i16; 64],
+        &mut self, reader: &mut ByteReader, table: &HuffmanTable, block: &mut [i16; 64],
     ) -> Result<bool, DecodeErrors>
     {
         let bit = (1 << self.successive_low) as i16;
@@ -678,6 +712,17 @@ impl BitStream
         self.aligned_buffer = 0;
         self.eob_run = 0;
     }
+
+    /// Read more of the stream if the bits left could be those padding its last
+    /// byte, so a marker right after them is found, see `marker`
+    pub(crate) fn read_to_marker(&mut self, reader: &mut ByteReader) -> Result<(), DecodeErrors>
+    {
+        if self.bits_left < 8 && self.marker.is_none()
+        {
+            self.refill(reader)?;
+        }
+        Ok(())
+    }
 }
 
 /// Do the equivalent of JPEG HUFF_EXTEND
@@ -692,14 +737,10 @@ fn huff_extend(x: i32, s: i32) -> i32
 ///
 /// Function is inlined (as always)
 #[inline(always)]
-#[allow(clippy::cast_possible_truncation)]
-fn read_u8(reader: &mut Cursor<Vec<u8>>) -> u64
+fn read_u8(reader: &mut ByteReader) -> u64
 {
-    let pos = reader.position();
-
-    reader.set_position(pos + 1);
     // if we have nothing left fill buffer with zeroes
-    u64::from(*reader.get_ref().get(pos as usize).unwrap_or(&0))
+    u64::from(reader.read_u8())
 }
 
 fn has_zero(v: u32) -> bool
@@ -16,6 +16,8 @@
 //! Currently supported conversions are
 //!
 //! - `YCbCr` to `RGB,RGBA,GRAYSCALE,RGBX`.
+//! - `YCCK` to `CMYK` and `CMYK` to `RGB,RGBA,RGBX`, for images decoded into coefficients.
+//! - `RGB` to `RGB,RGBA,RGBX`, for images which weren't converted to `YCbCr` when encoding.
 //!
 //!
 //! Hey there, if your reading this it means you probably need something, so let me help you.
@@ -51,7 +53,10 @@ mod avx;
 mod scalar;
 mod sse;
 
-pub use scalar::{ycbcr_to_grayscale, ycbcr_to_ycbcr};
+pub use scalar::{
+    cmyk_to_rgb_u16, rgb_to_rgb, ycbcr_to_grayscale, ycbcr_to_rgb_u16, ycbcr_to_ycbcr,
+    ycck_to_cmyk_u16,
+};
 
 use crate::misc::ColorSpace;
 
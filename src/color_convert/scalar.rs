@@ -88,82 +88,144 @@ pub fn ycbcr_to_rgb_16_scalar(
     *pos += 48;
 }
 
-pub fn ycbcr_to_grayscale(y: &[i16], width: usize, output: &mut [u8])
+/// Convert a row of luminance samples to grayscale
+pub fn ycbcr_to_grayscale(y: &[i16], output: &mut [u8])
 {
-    // Convert i16's to u8's
-    let temp_output = y.iter().map(|x| *x as u8).collect::<Vec<u8>>();
-    // chunk according to width.
-
-    let width_mcu = y.len() / width;
-
-    let width_chunk = y.len() / width_mcu;
-
-    let mut start = 0;
+    for (y, out) in y.iter().zip(output.iter_mut())
+    {
+        *out = *y as u8;
+    }
+}
 
-    let mut end = width;
+/// Convert YcbCr to YCbCr
+///
+/// Basically all we do is interleave a row of each component
+pub fn ycbcr_to_ycbcr(y: &[i16], cb: &[i16], cr: &[i16], output: &mut [u8])
+{
+    // OPTIMIZE-TIP: Don't do loops in Rust, use iterators in such manners to ensure super
+    // powers on optimization.
+    // Using indexing will cause Rust to do bounds checking and prevent some cool optimization
+    // options. See this  compiler-explorer link https://godbolt.org/z/Kh3M43hYr for what I mean.
+    for (((y, cb), cr), out) in y
+        .iter()
+        .zip(cb.iter())
+        .zip(cr.iter())
+        .zip(output.chunks_exact_mut(3))
+    {
+        out[0] = *y as u8;
+        out[1] = *cb as u8;
+        out[2] = *cr as u8;
+    }
+}
 
-    for chunk in temp_output.chunks_exact(width_chunk)
+/// Convert RGB to RGB(A/X)
+///
+/// A row of samples of each component is interleaved as it is, with an opaque
+/// alpha channel if `num_components` is 4
+pub fn rgb_to_rgb(r: &[i16], g: &[i16], b: &[i16], output: &mut [u8], num_components: usize)
+{
+    for (((r, g), b), pixel) in r
+        .iter()
+        .zip(g.iter())
+        .zip(b.iter())
+        .zip(output.chunks_exact_mut(num_components))
     {
-        // copy data, row wise, we do it row wise to discard fill bits if the
-        // image has an uneven width not divisible by 8.
+        pixel[0] = *r as u8;
+        pixel[1] = *g as u8;
+        pixel[2] = *b as u8;
 
-        output[start..end].copy_from_slice(&chunk[0..width]);
-        start += width;
-        end += width;
+        if num_components == 4
+        {
+            pixel[3] = 255;
+        }
     }
 }
 
-/// Convert YcbCr to YCbCr
+/// Convert YCbCr samples of a high precision image to RGB(A/X)
 ///
-/// Basically all we do is remove fill bytes (if there) in the edges
-pub fn ycbcr_to_ycbcr(
-    channels: &[Vec<i16>; 3], width: usize, h_samp: usize, v_samp: usize, output: &mut [u8],
+/// This follows libjpeg's table based conversion, carried out for one
+/// pixel at a time since it isn't a hot path.
+///
+/// `max_value` is the largest sample value for the image precision, it's used to
+/// center chroma samples, clamp the output, and as the alpha value.
+pub fn ycbcr_to_rgb_u16(
+    y: &[u16], cb: &[u16], cr: &[u16], output: &mut [u16], num_components: usize, max_value: u16,
 )
 {
-    // copy to a temporary vector.
+    const SCALE_BITS: i32 = 16;
+    const ONE_HALF: i32 = 1 << (SCALE_BITS - 1);
 
-    let mcu_chunks = channels[0].len() / (h_samp * v_samp);
+    let center = (i32::from(max_value) + 1) / 2;
 
-    // pixels we write per width. since this is YcbCr we write
-    // width times color components.
-    let stride = width * 3;
+    let clamp = |x: i32| x.clamp(0, i32::from(max_value)) as u16;
 
-    let mut start = 0;
+    for (((y, cb), cr), out) in y
+        .iter()
+        .zip(cb.iter())
+        .zip(cr.iter())
+        .zip(output.chunks_exact_mut(num_components))
+    {
+        let y = i32::from(*y);
+        let cb = i32::from(*cb) - center;
+        let cr = i32::from(*cr) - center;
 
-    let mut end = width * 3;
+        out[0] = clamp(y + ((91_881 * cr + ONE_HALF) >> SCALE_BITS));
+        out[1] = clamp(y + ((-22_554 * cb + ONE_HALF - 46_802 * cr) >> SCALE_BITS));
+        out[2] = clamp(y + ((116_130 * cb + ONE_HALF) >> SCALE_BITS));
 
-    let addition = width * 3;
+        if num_components == 4
+        {
+            out[3] = max_value;
+        }
+    }
+}
 
-    // width which accounts number of fill bytes
-    let width_chunk = mcu_chunks >> 3;
-    // vector for temporary storage.
-    let mut temp_output = vec![0; width_chunk * 3];
+/// Convert YCCK samples to CMYK
+///
+/// Y, Cb and Cr are converted to RGB like `ycbcr_to_rgb_u16`, and inverted to get
+/// C, M and Y, K is passed through as it is. This is what libjpeg does.
+///
+/// `output` receives interleaved CMYK samples.
+pub fn ycck_to_cmyk_u16(
+    y: &[u16], cb: &[u16], cr: &[u16], k: &[u16], output: &mut [u16], max_value: u16,
+)
+{
+    ycbcr_to_rgb_u16(y, cb, cr, output, 4, max_value);
 
-    for ((y_chunk, cb_chunk), cr_chunk) in channels[0]
-        .chunks_exact(width_chunk)
-        .zip(channels[1].chunks_exact(width_chunk))
-        .zip(channels[2].chunks_exact(width_chunk))
+    for (out, k) in output.chunks_exact_mut(4).zip(k)
     {
-        // OPTIMIZE-TIP: Don't do loops in Rust, use iterators in such manners to ensure super
-        // powers on optimization.
-        // Using indexing will cause Rust to do bounds checking and prevent some cool optimization
-        // options. See this  compiler-explorer link https://godbolt.org/z/Kh3M43hYr for what I mean.
-
-        for (((y, cb), cr), out) in y_chunk
-            .iter()
-            .zip(cb_chunk.iter())
-            .zip(cr_chunk.iter())
-            .zip(temp_output.chunks_exact_mut(3))
+        for sample in &mut out[..3]
         {
-            out[0] = *y as u8;
-            out[1] = *cb as u8;
-            out[2] = *cr as u8;
+            *sample = max_value - *sample;
         }
 
-        output[start..end].copy_from_slice(&temp_output[0..stride]);
+        out[3] = *k;
+    }
+}
 
-        start += addition;
+/// Convert interleaved CMYK samples to RGB(A/X)
+///
+/// Adobe applications store CMYK inverted (0 is full ink), so each of R,G and B is the
+/// product of its (inverted) C, M or Y sample and K, the same approximation libjpeg's
+/// tools use.
+pub fn cmyk_to_rgb_u16(cmyk: &[u16], output: &mut [u16], num_components: usize, max_value: u16)
+{
+    let scale = u32::from(max_value);
 
-        end += addition;
+    for (pixel, out) in cmyk
+        .chunks_exact(4)
+        .zip(output.chunks_exact_mut(num_components))
+    {
+        let k = u32::from(pixel[3]);
+
+        for (out, c) in out.iter_mut().zip(&pixel[..3])
+        {
+            *out = ((u32::from(*c) * k + scale / 2) / scale) as u16;
+        }
+
+        if num_components == 4
+        {
+            out[3] = max_value;
+        }
     }
 }
@@ -6,18 +6,12 @@
 use crate::decoder::MAX_COMPONENTS;
 use crate::errors::DecodeErrors;
 use crate::misc::Aligned32;
-use crate::upsampler::upsample_no_op;
-
-/// Represents an up-sampler function, this function will be called to upsample
-/// a down-sampled image
-
-pub type UpSampler = fn(&[i16], usize) -> Vec<i16>;
 
 /// Component Data from start of frame
 #[derive(Clone)]
 pub(crate) struct Components
 {
-    /// The type of component that has the metadata below, can be Y,Cb or Cr
+    /// The type of component that has the metadata below, can be Y,Cb,Cr or K
     pub component_id:              ComponentID,
     /// Sub-sampling ratio of this component in the x-plane
     pub vertical_sample:           usize,
@@ -33,32 +27,34 @@ pub(crate) struct Components
     pub quantization_table:        Aligned32<[i32; 64]>,
     /// dc prediction for the component
     pub dc_pred:                   i32,
-    /// An up-sampling function, can be basic or SSE, depending
-    /// on the platform
-    pub up_sampler:                UpSampler,
     /// How pixels do we need to go to get to the next line?
     pub width_stride:              usize,
-    /// Component ID for progressive
+    /// Identifier of the component, scans refer to components by it
     pub(crate) id:                 u8,
 }
 
 impl Components
 {
     /// Create a new instance from three bytes from the start of frame
+    ///
+    /// `position` is the index of the component in the start of frame, which decides
+    /// what the component is used for, the identifier in `a[0]` can be anything.
     #[inline]
-    pub fn from(a: [u8; 3]) -> Result<Components, DecodeErrors>
+    pub fn from(a: [u8; 3], position: usize) -> Result<Components, DecodeErrors>
     {
-        let id = match a[0]
+        let id = match position
         {
-            1 => ComponentID::Y,
-            2 => ComponentID::Cb,
-            3 => ComponentID::Cr,
+            0 => ComponentID::Y,
+            1 => ComponentID::Cb,
+            2 => ComponentID::Cr,
+            3 => ComponentID::K,
             r =>
             {
                 return Err(DecodeErrors::Format(format!(
-                        "Unknown component id found,{}, expected value between 1 and 3\nNote I and Q components are not supported yet",
-                        r
-                    )));
+                    "Too many components, found component {}, expected at most {}",
+                    r + 1,
+                    MAX_COMPONENTS
+                )));
             }
         };
 
@@ -73,20 +69,20 @@ impl Components
                 quantization_table_number, MAX_COMPONENTS
             )));
         }
-        // check that upsampling ratios are powers of two
+        // sampling factors lie between 1 and 4, see B.2.2 of the spec
         // if these fail, it's probably a corrupt image.
-        if !horizontal_sample.is_power_of_two()
+        if !(1..=4).contains(&horizontal_sample)
         {
             return Err(DecodeErrors::Format(format!(
-                "Horizontal sample is not a power of two({}) cannot decode",
+                "Horizontal sample should be between 1 and 4, found {} cannot decode",
                 horizontal_sample
             )));
         }
 
-        if !vertical_sample.is_power_of_two()
+        if !(1..=4).contains(&vertical_sample)
         {
             return Err(DecodeErrors::Format(format!(
-                "Vertical sub-sample is not power of two({}) cannot decode",
+                "Vertical sample should be between 1 and 4, found {} cannot decode",
                 vertical_sample
             )));
         }
@@ -106,7 +102,6 @@ impl Components
             ac_huff_table: 0,
             quantization_table: Aligned32([0; 64]),
             dc_pred: 0,
-            up_sampler: upsample_no_op,
             // set later
             width_stride: horizontal_sample,
             id: a[0],
@@ -115,6 +110,9 @@ impl Components
 }
 
 /// Component ID's
+///
+/// These are assigned by the position of a component in the start of frame,
+/// RGB and CMYK images use the same ones for their components.
 #[derive(Copy, Debug, Clone, PartialEq, Eq)]
 pub enum ComponentID
 {
@@ -124,13 +122,6 @@ pub enum ComponentID
     Cb,
     /// Red chrominance
     Cr,
-}
-
-#[derive(Copy, Debug, Clone, PartialEq, Eq)]
-pub enum SubSampRatios
-{
-    HV,
-    V,
-    H,
-    None,
+    /// Black channel of CMYK and YCCK images
+    K,
 }
@@ -0,0 +1,261 @@
+//! Data appended after the end of an image
+//!
+//! Some cameras put more files after the EOI marker of a JPEG image and
+//! describe them in its XMP packet. Google's container format, used by motion
+//! photos (with `GCamera:MotionPhoto`), UltraHDR gain maps and Dynamic Depth,
+//! lists every file in a `Container:Directory` sequence, the first item is the
+//! primary image and the others follow it in the same order, e.g.
+//!
+//! ```text
+//! <Container:Directory>
+//!   <rdf:Seq>
+//!     <rdf:li rdf:parseType="Resource">
+//!       <Container:Item Item:Semantic="Primary" Item:Mime="image/jpeg"/>
+//!     </rdf:li>
+//!     <rdf:li rdf:parseType="Resource">
+//!       <Container:Item Item:Semantic="MotionPhoto" Item:Mime="video/mp4" Item:Length="123456"/>
+//!     </rdf:li>
+//!   </rdf:Seq>
+//! </Container:Directory>
+//! ```
+//!
+//! Items after the primary one must have an `Item:Length`, with `Item:Padding`
+//! bytes between an item and the next, so they are found from the end of the
+//! file. Older motion photos only have `GCamera:MicroVideo="1"` and
+//! `GCamera:MicroVideoOffset`, the size of the video ending the file.
+
+use crate::xmp::xmp_property;
+use crate::Decoder;
+
+/// What an item of a container is
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ItemSemantic
+{
+    /// The image the container is, before its EOI marker
+    Primary,
+    /// The video of a motion photo
+    MotionPhoto,
+    /// The gain map of an UltraHDR image
+    GainMap,
+    /// A depth map
+    Depth,
+    /// Confidence of the values of a depth map
+    Confidence,
+    /// The image before effects, e.g. background blur, were applied
+    Original,
+    /// Any other semantic, as written
+    Other(String),
+}
+
+impl ItemSemantic
+{
+    fn from_name(semantic: &str) -> ItemSemantic
+    {
+        match semantic
+        {
+            "Primary" => ItemSemantic::Primary,
+            "MotionPhoto" => ItemSemantic::MotionPhoto,
+            "GainMap" => ItemSemantic::GainMap,
+            "Depth" => ItemSemantic::Depth,
+            "Confidence" => ItemSemantic::Confidence,
+            "Original" => ItemSemantic::Original,
+            _ => ItemSemantic::Other(semantic.to_string()),
+        }
+    }
+}
+
+/// A file appended after the image
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ContainerItem
+{
+    pub semantic: ItemSemantic,
+    /// MIME type of the item, e.g. `video/mp4`
+    pub mime:     String,
+    /// Where the item starts, from the start of the buffer
+    pub offset:   usize,
+    /// Size of the item in bytes
+    pub length:   usize,
+}
+
+/// Data after the EOI marker of an image
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Trailer<'a>
+{
+    /// Where the trailer starts, right after the EOI marker
+    pub offset: usize,
+    /// Bytes of the trailer, up to the end of the buffer
+    pub data:   &'a [u8],
+    /// Items the XMP packet of the image says are in the trailer, in the order
+    /// they are stored
+    ///
+    /// This is empty if the image declares none, or they don't fit in the
+    /// trailer.
+    pub items:  Vec<ContainerItem>,
+}
+
+impl<'a> Trailer<'a>
+{
+    /// The bytes of `item`
+    ///
+    /// Returns `None` if the item isn't part of the trailer.
+    #[must_use]
+    pub fn item_data(&self, item: &ContainerItem) -> Option<&'a [u8]>
+    {
+        let start = item.offset.checked_sub(self.offset)?;
+
+        self.data.get(start..start.checked_add(item.length)?)
+    }
+
+    /// The video of a motion photo
+    ///
+    /// Returns `None` if the trailer has no item with the `MotionPhoto` semantic.
+    #[must_use]
+    pub fn motion_photo(&self) -> Option<&'a [u8]>
+    {
+        let item = self
+            .items
+            .iter()
+            .find(|x| x.semantic == ItemSemantic::MotionPhoto)?;
+
+        self.item_data(item)
+    }
+}
+
+/// An item of a `Container:Directory`, as written
+struct DirectoryEntry
+{
+    semantic: ItemSemantic,
+    mime:     String,
+    length:   Option<usize>,
+    padding:  usize,
+}
+
+/// Parse the items of the `Container:Directory` of `xmp`
+///
+/// Returns `None` if there is no directory.
+fn parse_directory(xmp: &str) -> Option<Vec<DirectoryEntry>>
+{
+    let start = xmp.find("<Container:Directory")?;
+    let directory = &xmp[start..];
+    let directory = &directory[..directory
+        .find("</Container:Directory>")
+        .unwrap_or(directory.len())];
+
+    let entries = directory
+        .split("<Container:Item")
+        .skip(1)
+        .map(|item| {
+            let number = |name| xmp_property(item, name).and_then(|x| x.trim().parse().ok());
+
+            DirectoryEntry {
+                semantic: ItemSemantic::from_name(
+                    xmp_property(item, "Item:Semantic").unwrap_or(""),
+                ),
+                mime:     xmp_property(item, "Item:Mime").unwrap_or("").to_string(),
+                length:   number("Item:Length"),
+                padding:  number("Item:Padding").unwrap_or(0),
+            }
+        })
+        .collect();
+
+    Some(entries)
+}
+
+/// Items after the primary one of the `Container:Directory` of `xmp`, in a
+/// buffer of `end` bytes whose primary image ends before `start`
+///
+/// Returns `None` if there is no directory, and no items if they don't fit.
+pub(crate) fn container_items(xmp: &str, start: usize, end: usize) -> Option<Vec<ContainerItem>>
+{
+    let entries = parse_directory(xmp)?;
+
+    Some(locate_items(&entries, start, end).unwrap_or_else(|| {
+        warn!("Items of the container directory don't fit after the image");
+        vec![]
+    }))
+}
+
+/// Work out where the items after the primary one are, from the end of a
+/// buffer of `end` bytes whose trailer starts at `start`
+///
+/// Returns `None` if an item has no length or they don't fit in the trailer.
+fn locate_items(entries: &[DirectoryEntry], start: usize, end: usize)
+    -> Option<Vec<ContainerItem>>
+{
+    let mut items = Vec::with_capacity(entries.len().saturating_sub(1));
+    let mut position = end;
+
+    // the padding of an item is between it and the next
+    for (entry, next) in entries.iter().zip(entries.get(1..).unwrap_or(&[])).rev()
+    {
+        let length = next.length?;
+        let item_start = position.checked_sub(length)?;
+
+        items.push(ContainerItem {
+            semantic: next.semantic.clone(),
+            mime: next.mime.clone(),
+            offset: item_start,
+            length,
+        });
+
+        position = item_start.checked_sub(entry.padding)?;
+    }
+    if position < start
+    {
+        return None;
+    }
+    items.reverse();
+
+    Some(items)
+}
+
+impl Decoder
+{
+    /// Returns the data after the EOI marker that ended decoding, and the
+    /// items the XMP packet of the image says are in it
+    ///
+    /// `buf` is the buffer the image was decoded from, for `decode_file` the
+    /// contents of the file. Use `Trailer::motion_photo` to get the video of a
+    /// motion photo.
+    ///
+    /// Returns `None` if no image was decoded, or there is nothing after it.
+    #[must_use]
+    pub fn trailer<'a>(&self, buf: &'a [u8]) -> Option<Trailer<'a>>
+    {
+        let offset = self.end_of_image? + 2;
+        let data = buf.get(offset..).filter(|x| !x.is_empty())?;
+
+        let mut items = vec![];
+
+        if let Some(xmp) = self.xmp()
+        {
+            if let Some(directory) = container_items(&xmp, offset, buf.len())
+            {
+                items = directory;
+            }
+            else if xmp_property(&xmp, "GCamera:MicroVideo") == Some("1")
+            {
+                let length = xmp_property(&xmp, "GCamera:MicroVideoOffset")
+                    .and_then(|x| x.trim().parse::<usize>().ok())
+                    .filter(|x| *x <= data.len());
+
+                match length
+                {
+                    Some(length) => items.push(ContainerItem {
+                        semantic: ItemSemantic::MotionPhoto,
+                        mime: "video/mp4".to_string(),
+                        offset: buf.len() - length,
+                        length,
+                    }),
+                    None => warn!("Micro video offset is missing or past the image"),
+                }
+            }
+        }
+
+        Some(Trailer {
+            offset,
+            data,
+            items,
+        })
+    }
+}
@@ -1,22 +1,31 @@
 //! Main image logic.
 #![allow(clippy::doc_markdown)]
 
-use std::fs::read;
-use std::io::{BufRead, Cursor, Read};
+use std::fs::File;
+use std::io::Read;
 use std::num::NonZeroU32;
 use std::path::Path;
 
+use crate::arithmetic::ArithmeticConditioning;
 use crate::color_convert::choose_ycbcr_to_rgb_convert_func;
-use crate::components::{ComponentID, Components, SubSampRatios};
+use crate::components::Components;
 use crate::errors::{DecodeErrors, UnsupportedSchemes};
-use crate::headers::{parse_dqt, parse_huffman, parse_sos, parse_start_of_frame};
+use crate::exif::Exif;
+use crate::headers::{
+    find_eoi, find_scan_end, parse_app, parse_com, parse_dac, parse_dhp, parse_dnl, parse_dqt,
+    parse_exp, parse_huffman, parse_sos, parse_start_of_frame,
+};
+use crate::hierarchical::Hierarchy;
 use crate::huffman::HuffmanTable;
+use crate::icc::IccTransform;
 use crate::idct::choose_idct_func;
 use crate::marker::Marker;
 use crate::misc::{read_byte, read_u16_be, Aligned32, ColorSpace, SOFMarkers};
-use crate::upsampler::{
-    choose_horizontal_samp_function, choose_hv_samp_function, upsample_vertical,
-};
+use crate::mpf::Mpf;
+use crate::photoshop::Photoshop;
+use crate::reader::ByteReader;
+use crate::worker::{apply_orientation, post_process_u16};
+use crate::xmp::{assemble_extended_xmp, xmp_property, ExtendedXmpChunk};
 use crate::ZuneJpegOptions;
 
 /// Maximum components
@@ -53,45 +62,75 @@ pub type ColorConvert16Ptr = fn(&[i16; 16], &[i16; 16], &[i16; 16], &mut [u8], &
 ///
 /// Multiply each 64 element block of `&mut [i16]` with `&Aligned32<[i32;64]>`
 /// Carry out IDCT (type 3 dct) on ach block of 64 i16's
-pub type IDCTPtr = fn(&[i16], &Aligned32<[i32; 64]>, usize, usize, usize) -> Vec<i16>;
+///
+/// Blocks are in raster order, the `usize` is the distance between rows of
+/// the output, i.e. 8 times the number of blocks in a row.
+pub type IDCTPtr = fn(&[i16], &Aligned32<[i32; 64]>, usize) -> Vec<i16>;
 
 /// A Decoder Instance
-#[allow(clippy::upper_case_acronyms)]
+#[allow(clippy::upper_case_acronyms, clippy::struct_excessive_bools)]
 pub struct Decoder
 {
     /// Struct to hold image information from SOI
-    pub(crate) info:              ImageInfo,
+    pub(crate) info:                ImageInfo,
     ///  Quantization tables, will be set to none and the tables will
     /// be moved to `components` field
-    pub(crate) qt_tables:         [Option<[i32; 64]>; MAX_COMPONENTS],
+    pub(crate) qt_tables:           [Option<[i32; 64]>; MAX_COMPONENTS],
     /// DC Huffman Tables with a maximum of 4 tables for each  component
-    pub(crate) dc_huffman_tables: [Option<HuffmanTable>; MAX_COMPONENTS],
+    pub(crate) dc_huffman_tables:   [Option<HuffmanTable>; MAX_COMPONENTS],
     /// AC Huffman Tables with a maximum of 4 tables for each component
-    pub(crate) ac_huffman_tables: [Option<HuffmanTable>; MAX_COMPONENTS],
+    pub(crate) ac_huffman_tables:   [Option<HuffmanTable>; MAX_COMPONENTS],
     /// Image components, holds information like DC prediction and quantization
     /// tables of a component
-    pub(crate) components:        Vec<Components>,
+    pub(crate) components:          Vec<Components>,
     /// maximum horizontal component of all channels in the image
-    pub(crate) h_max:             usize,
+    pub(crate) h_max:               usize,
     // maximum vertical component of all channels in the image
-    pub(crate) v_max:             usize,
+    pub(crate) v_max:               usize,
     /// mcu's  width (interleaved scans)
-    pub(crate) mcu_width:         usize,
+    pub(crate) mcu_width:           usize,
     /// MCU height(interleaved scans
-    pub(crate) mcu_height:        usize,
+    pub(crate) mcu_height:          usize,
     /// Number of MCU's in the x plane
-    pub(crate) mcu_x:             usize,
+    pub(crate) mcu_x:               usize,
     /// Number of MCU's in the y plane
-    pub(crate) mcu_y:             usize,
+    pub(crate) mcu_y:               usize,
     /// Is the image interleaved?
-    pub(crate) interleaved:       bool,
-    pub(crate) sub_sample_ratio:  SubSampRatios,
+    pub(crate) interleaved:         bool,
     /// Image input colorspace, should be YCbCr for a sane image, might be
     /// grayscale too
-    pub(crate) input_colorspace:  ColorSpace,
+    pub(crate) input_colorspace:    ColorSpace,
+    /// Color transform from Adobe's APP14 marker, 0 means the image is RGB (or CMYK)
+    /// and 2 means it's YCCK
+    pub(crate) adobe_transform:     Option<u8>,
+    /// Whether the image has a JFIF marker, which implies YCbCr
+    pub(crate) jfif:                bool,
+    /// Chunks of the embedded ICC profile from APP2 markers, as their
+    /// sequence number, number of chunks and data
+    pub(crate) icc_chunks:          Vec<(u8, u8, Vec<u8>)>,
+    /// EXIF metadata from the APP1 marker
+    pub(crate) exif:                Option<Exif>,
+    /// The standard XMP packet, from an APP1 marker
+    pub(crate) xmp:                 Option<Vec<u8>>,
+    /// Chunks of the extended XMP packet, from APP1 markers
+    pub(crate) extended_xmp_chunks: Vec<ExtendedXmpChunk>,
+    /// Photoshop image resources, from APP13 markers
+    pub(crate) photoshop:           Vec<u8>,
+    /// The MP index, from an APP2 marker
+    pub(crate) mpf:                 Option<Mpf>,
+    /// Offset of the EOI marker that ended decoding
+    pub(crate) end_of_image:        Option<usize>,
     // Progressive image details
     /// Is the image progressive?
-    pub(crate) is_progressive:    bool,
+    pub(crate) is_progressive:      bool,
+    /// Is the image arithmetic coded?
+    pub(crate) is_arithmetic:       bool,
+    /// Is the image lossless?
+    pub(crate) is_lossless:         bool,
+    /// The image a hierarchical image builds up to, from its DHP marker
+    pub(crate) hierarchy:           Option<Hierarchy>,
+    /// Arithmetic coding conditioning tables, set by DAC
+    pub(crate) conditioning:        ArithmeticConditioning,
 
     /// Start of spectral scan
     pub(crate) spec_start:       u8,
@@ -141,10 +180,13 @@ impl Decoder
             mcu_x: 0,
             mcu_y: 0,
             interleaved: false,
-            sub_sample_ratio: SubSampRatios::None,
 
             // Progressive information
             is_progressive: false,
+            is_arithmetic: false,
+            is_lossless: false,
+            hierarchy: None,
+            conditioning: ArithmeticConditioning::new(),
             spec_start: 0,
             spec_end: 0,
             succ_high: 0,
@@ -157,6 +199,15 @@ impl Decoder
 
             // Colorspace
             input_colorspace: ColorSpace::YCbCr,
+            adobe_transform: None,
+            jfif: false,
+            icc_chunks: vec![],
+            exif: None,
+            xmp: None,
+            extended_xmp_chunks: vec![],
+            photoshop: vec![],
+            mpf: None,
+            end_of_image: None,
             // This should be kept at par with MAX_COMPONENTS, or until the RFC at
             // https://github.com/rust-lang/rfcs/pull/2920 is accepted
             // Store MCU blocks
@@ -177,7 +228,71 @@ impl Decoder
     /// See DecodeErrors for an explanation
     pub fn decode_buffer(&mut self, buf: &[u8]) -> Result<Vec<u8>, DecodeErrors>
     {
-        self.decode_internal(Cursor::new(buf.to_vec()))
+        self.decode_internal(ByteReader::new(buf))
+    }
+
+    /// Decode an image from any reader, e.g. a file or a socket
+    ///
+    /// Bytes are read as the decoder needs them, a few kilobytes at a time, the
+    /// reader isn't read to the end first. It doesn't need to be buffered, and
+    /// is read up to the EOI marker, or a little past it.
+    ///
+    /// Images whose height comes in a DNL marker are the exception unless
+    /// they're baseline images, their first scan is kept in memory until the
+    /// marker is found.
+    ///
+    /// # Errors
+    /// If the reader returns an error, or see DecodeErrors for an explanation
+    pub fn decode_reader<R: Read>(&mut self, reader: R) -> Result<Vec<u8>, DecodeErrors>
+    {
+        let mut reader = reader;
+
+        self.decode_internal(ByteReader::from_reader(&mut reader))
+    }
+
+    /// Decode image `index` of a Multi-Picture (MPO) file already in memory
+    ///
+    /// The images are listed in the MP index of the first image, see `mpf`,
+    /// index 0 is the primary image. Afterwards the decoder describes the
+    /// decoded image, e.g. `width` and `info` are those of image `index`, but
+    /// keeps its options.
+    ///
+    /// # Errors
+    /// If the buffer has no MP index, if the index has no image `index` or
+    /// it's out of the buffer, or if the image can't be decoded.
+    pub fn decode_mp_image(&mut self, buf: &[u8], index: usize) -> Result<Vec<u8>, DecodeErrors>
+    {
+        // grayscale images switch the output colorspace, keep it for the next image
+        let options = self.options;
+        let pixels = self.decode_mp_image_inner(buf, index);
+
+        self.options = options;
+
+        pixels
+    }
+
+    fn decode_mp_image_inner(&mut self, buf: &[u8], index: usize) -> Result<Vec<u8>, DecodeErrors>
+    {
+        self.read_headers(buf)?;
+
+        let mpf = self
+            .mpf
+            .take()
+            .ok_or(DecodeErrors::FormatStatic("Image has no MP index"))?;
+
+        let image = mpf.image_data(buf, index).ok_or_else(|| {
+            DecodeErrors::Format(format!(
+                "Image {index} isn't in the MP index, or is out of bounds, the index has {} images",
+                mpf.images().len()
+            ))
+        })?;
+
+        let pixels = self.decode_buffer(image)?;
+
+        // the offset is from the start of the whole file
+        self.end_of_image = self.end_of_image.map(|x| x + mpf.images()[index].offset);
+
+        Ok(pixels)
     }
 
     /// Create a new Decoder instance
@@ -194,11 +309,7 @@ impl Decoder
     where
         P: AsRef<Path> + Clone,
     {
-        //Read to an in memory buffer
-        let buffer = Cursor::new(read(file)?);
-
-        info!("File size: {} bytes", buffer.get_ref().len());
-        self.decode_internal(buffer)
+        self.decode_reader(File::open(file)?)
     }
 
     /// Returns the image information
@@ -217,8 +328,222 @@ impl Decoder
         {
             return None;
         }
+        let mut info = self.info.clone();
+
+        if self.orientation() >= 5
+        {
+            std::mem::swap(&mut info.width, &mut info.height);
+        }
 
-        return Some(self.info.clone());
+        return Some(info);
+    }
+
+    /// Returns the ICC profile embedded in the image
+    ///
+    /// The profile is split into chunks stored in APP2 markers, this puts them
+    /// back together in order of their sequence numbers.
+    ///
+    /// This is available after `read_headers`, no pixels need to be decoded.
+    ///
+    /// Returns `None` if the image has no profile, or if the chunks are
+    /// inconsistent (missing, repeated or disagreeing on their number) or
+    /// don't make up a profile.
+    #[must_use]
+    pub fn icc_profile(&self) -> Option<Vec<u8>>
+    {
+        let count = self.icc_chunks.first()?.1;
+
+        if usize::from(count) != self.icc_chunks.len()
+            || self.icc_chunks.iter().any(|(_, n, _)| *n != count)
+        {
+            warn!("ICC profile chunks are missing or inconsistent, ignoring the profile");
+            return None;
+        }
+
+        let mut profile = Vec::new();
+
+        // sequence numbers start at one
+        for sequence in 1..=count
+        {
+            let Some((_, _, data)) = self.icc_chunks.iter().find(|(s, _, _)| *s == sequence)
+            else
+            {
+                warn!("ICC profile chunk {sequence} of {count} is missing, ignoring the profile");
+                return None;
+            };
+            profile.extend_from_slice(data);
+        }
+
+        // the profile header is 128 bytes, starting with the profile size and
+        // with a signature at byte 36
+        if profile.len() < 128
+            || u32::from_be_bytes(profile[..4].try_into().unwrap()) as usize != profile.len()
+            || &profile[36..40] != b"acsp"
+        {
+            warn!("Embedded ICC profile has an invalid header, ignoring it");
+            return None;
+        }
+
+        Some(profile)
+    }
+
+    /// Returns the EXIF metadata of the image
+    ///
+    /// This is available after `read_headers`, no pixels need to be decoded.
+    ///
+    /// Returns `None` if the image has no EXIF segment or its header is invalid.
+    #[must_use]
+    pub fn exif(&self) -> Option<&Exif>
+    {
+        self.exif.as_ref()
+    }
+
+    /// Returns the standard XMP packet of the image
+    ///
+    /// This is available after `read_headers`, no pixels need to be decoded.
+    ///
+    /// Properties which didn't fit in the packet are in the extended packet,
+    /// see `extended_xmp`.
+    ///
+    /// Returns `None` if the image has no XMP packet or it isn't valid UTF-8.
+    #[must_use]
+    pub fn xmp(&self) -> Option<String>
+    {
+        let packet = String::from_utf8(self.xmp.clone()?).ok();
+
+        if packet.is_none()
+        {
+            warn!("XMP packet isn't valid UTF-8, ignoring it");
+        }
+        packet
+    }
+
+    /// Returns the extended XMP packet of the image
+    ///
+    /// Packets larger than a marker are split into the standard packet, returned
+    /// by `xmp`, and an extended packet written in chunks. This puts the chunks
+    /// whose GUID is named by the `xmpNote:HasExtendedXMP` property of the standard
+    /// packet back together, and checks that the result matches the GUID (which is
+    /// its MD5 digest).
+    ///
+    /// Both packets hold properties of the same resource, e.g. for
+    /// `rdf:Description` elements with the same `rdf:about`, they can be merged by
+    /// an XMP parser.
+    ///
+    /// Returns `None` if there is no extended packet, if its chunks are missing or
+    /// inconsistent, if it doesn't match its GUID or it isn't valid UTF-8.
+    #[must_use]
+    pub fn extended_xmp(&self) -> Option<String>
+    {
+        if self.extended_xmp_chunks.is_empty()
+        {
+            return None;
+        }
+        let xmp = self.xmp()?;
+
+        let Some(guid) = xmp_property(&xmp, "xmpNote:HasExtendedXMP")
+        else
+        {
+            warn!(
+                "Image has extended XMP chunks but its XMP packet doesn't name them, ignoring them"
+            );
+            return None;
+        };
+        let packet =
+            String::from_utf8(assemble_extended_xmp(&self.extended_xmp_chunks, guid)?).ok();
+
+        if packet.is_none()
+        {
+            warn!("Extended XMP packet isn't valid UTF-8, ignoring it");
+        }
+        packet
+    }
+
+    /// Returns the Photoshop image resources of the image
+    ///
+    /// These hold IPTC-IIM metadata, see `Photoshop::iptc`, the resolution of the
+    /// image and a thumbnail among others.
+    ///
+    /// This is available after `read_headers`, no pixels need to be decoded.
+    ///
+    /// Returns `None` if the image has no APP13 segment from Photoshop or it
+    /// has no valid resource.
+    #[must_use]
+    pub fn photoshop(&self) -> Option<Photoshop>
+    {
+        if self.photoshop.is_empty()
+        {
+            return None;
+        }
+        Photoshop::parse(&self.photoshop)
+    }
+
+    /// Returns the Multi-Picture Format index of the image
+    ///
+    /// MPO files, e.g. from stereo cameras, have additional images after the
+    /// first one, the index says what and where they are. Use `decode_mp_image`
+    /// to decode them.
+    ///
+    /// This is available after `read_headers`, no pixels need to be decoded.
+    ///
+    /// Returns `None` if the image has no index.
+    #[must_use]
+    pub fn mpf(&self) -> Option<&Mpf>
+    {
+        self.mpf.as_ref()
+    }
+
+    /// Returns the offset of the EOI marker that ended decoding
+    ///
+    /// Files may have data after the image, e.g. motion photos end with a
+    /// video, see `trailer`. The offset is from the start of the buffer given
+    /// to `decode_buffer`, or of the file for `decode_file` and `decode_mp_image`.
+    ///
+    /// Returns `None` if no image was decoded, or the image has no EOI marker.
+    #[must_use]
+    pub fn end_of_image(&self) -> Option<usize>
+    {
+        self.end_of_image
+    }
+
+    /// The EXIF orientation to apply to decoded pixels, 1 (upright) unless
+    /// the options ask for it to be applied
+    pub(crate) fn orientation(&self) -> u16
+    {
+        if !self.options.get_apply_orientation()
+        {
+            return 1;
+        }
+
+        self.exif.as_ref().and_then(Exif::orientation).unwrap_or(1)
+    }
+
+    /// Rotate and flip pixels decoded upright as `orientation` says
+    pub(crate) fn orient<T: Copy>(&self, pixels: Vec<T>) -> Vec<T>
+    {
+        apply_orientation(
+            pixels,
+            usize::from(self.info.width),
+            usize::from(self.info.height),
+            self.orientation(),
+        )
+    }
+
+    /// Transform from the embedded ICC profile to sRGB for samples between 0 and
+    /// `max_value`, if the options ask for one and the profile can be used
+    pub(crate) fn icc_transform(&self, max_value: u16) -> Option<IccTransform>
+    {
+        if !self.options.get_icc_to_srgb()
+        {
+            return None;
+        }
+
+        IccTransform::new(
+            &self.icc_profile()?,
+            self.input_colorspace,
+            self.options.get_out_colorspace(),
+            max_value,
+        )
     }
 
     /// Decode Decoder headers
@@ -227,18 +552,26 @@ impl Decoder
     /// image
     ///
     /// # Supported Headers
-    ///  - APP(0)
+    ///  - APP(0) -> JFIF and JFXX segments
+    ///  - APP(1) -> EXIF metadata and XMP packets
+    ///  - APP(2) -> ICC profile chunks
+    ///  - APP(14) -> Adobe segment
+    ///  - APP(n) -> Other application segments, kept as they are
+    ///  - COM -> Comments
     ///  - SOF(O)
+    ///  - SOF(1) -> Extended sequential, 8 and 12 bit
+    ///  - SOF(2) -> Progressive, 8 and 12 bit
+    ///  - SOF(3) -> Lossless, 2 to 16 bit
+    ///  - SOF(5), SOF(6), SOF(7) -> Differential frames of hierarchical images
+    ///  - DHP, EXP -> Hierarchical progression and expansion of references
     ///  - DQT -> Quantization tables
     ///  - DHT -> Huffman tables
     ///  - SOS -> Start of Scan
+    ///  - DAC -> Arithmetic conditioning tables
     /// # Unsupported Headers
-    ///  - SOF(n) -> Decoder images which are not baseline/progressive
-    ///  - DAC -> Images using Arithmetic tables
+    ///  - SOF(n) -> Decoder images which are not baseline/progressive/arithmetic
     ///  - JPG(n)
-    fn decode_headers_internal<R>(&mut self, buf: &mut R) -> Result<(), DecodeErrors>
-    where
-        R: Read + BufRead,
+    fn decode_headers_internal(&mut self, buf: &mut ByteReader) -> Result<(), DecodeErrors>
     {
         // First two bytes should be jpeg soi marker
         let magic_bytes = read_u16_be(buf)?;
@@ -250,6 +583,23 @@ impl Decoder
         {
             return Err(DecodeErrors::IllegalMagicBytes(magic_bytes));
         }
+        // headers may be read more than once, e.g. by `read_headers` and then `decode_buffer`
+        self.info = ImageInfo::default();
+        self.icc_chunks.clear();
+        self.exif = None;
+        self.xmp = None;
+        self.extended_xmp_chunks.clear();
+        self.photoshop.clear();
+        self.mpf = None;
+        self.end_of_image = None;
+        self.hierarchy = None;
+        self.components.clear();
+        self.conditioning = ArithmeticConditioning::new();
+        self.adobe_transform = None;
+        self.jfif = false;
+        self.restart_interval = 0;
+        self.todo = 0x7fff_ffff;
+
         loop
         {
             // read a byte
@@ -268,6 +618,12 @@ impl Decoder
 
                     if n == Marker::SOS
                     {
+                        if self.info.height == 0 && !self.decodes_mcu_rows()
+                        {
+                            // the height comes in a DNL segment after the scan, the
+                            // baseline decoder reads it once it gets there
+                            self.read_dnl_ahead(buf)?;
+                        }
                         return Ok(());
                     }
                 }
@@ -286,7 +642,7 @@ impl Decoder
                     }
 
                     warn!("Skipping {} bytes", length - 2);
-                    buf.consume((length - 2) as usize);
+                    buf.skip(usize::from(length - 2));
                 }
             }
             last_byte = m;
@@ -301,28 +657,115 @@ impl Decoder
             }
         }
     }
-    pub(crate) fn parse_marker_inner<R: Read + BufRead>(
-        &mut self, m: Marker, buf: &mut R,
+    /// Read the height of the image from the DNL segment after its first scan
+    ///
+    /// The scan is read ahead and kept in `buf` until the segment is found,
+    /// the scan is decoded afterwards. This is for images decoded into
+    /// coefficients or samples of the whole image before any output, which
+    /// take more memory than the scan does.
+    fn read_dnl_ahead(&mut self, buf: &mut ByteReader) -> Result<(), DecodeErrors>
+    {
+        // only bytes read since the last search are searched, and the last
+        // byte before them in case it starts a marker
+        let mut searched = 0;
+
+        let end = loop
+        {
+            let data = buf.buffered();
+
+            if let Some(end) = find_scan_end(&data[searched..])
+            {
+                break Some(searched + end);
+            }
+            searched = data.len().saturating_sub(1);
+
+            if !buf.read_more()
+            {
+                break None;
+            }
+        };
+
+        // the height follows the marker and the length of the segment
+        while end.is_some_and(|x| buf.buffered().len() < x + 6) && buf.read_more()
+        {}
+
+        let Some(position) = end.filter(|x| buf.buffered()[x + 1] == 0xDC)
+        else
+        {
+            return Err(DecodeErrors::FormatStatic(
+                "Image height is zero and no DNL marker follows the first scan",
+            ));
+        };
+
+        parse_dnl(&mut &buf.buffered()[position + 2..], self)
+    }
+
+    #[allow(clippy::too_many_lines)]
+    pub(crate) fn parse_marker_inner(
+        &mut self, m: Marker, buf: &mut ByteReader,
     ) -> Result<(), DecodeErrors>
     {
         match m
         {
-            Marker::SOF(0 | 2) =>
+            Marker::SOF(0 | 1 | 2 | 3 | 5 | 6 | 7 | 9 | 10) =>
             {
-                let marker = {
-                    // choose marker
-                    if m == Marker::SOF(0)
+                // frames of hierarchical images may use different coding processes
+                self.is_progressive = false;
+                self.is_arithmetic = false;
+                self.is_lossless = false;
+
+                // choose marker
+                let marker = match m
+                {
+                    Marker::SOF(0) => SOFMarkers::BaselineDct,
+                    Marker::SOF(1) => SOFMarkers::ExtendedSequentialHuffman,
+                    Marker::SOF(2) =>
                     {
-                        SOFMarkers::BaselineDct
+                        self.is_progressive = true;
+
+                        SOFMarkers::ProgressiveDctHuffman
                     }
-                    else
+                    Marker::SOF(3) =>
+                    {
+                        self.is_lossless = true;
+
+                        SOFMarkers::LosslessHuffman
+                    }
+                    Marker::SOF(5) => SOFMarkers::DifferentialSequentialHuffman,
+                    Marker::SOF(6) =>
                     {
                         self.is_progressive = true;
 
-                        SOFMarkers::ProgressiveDctHuffman
+                        SOFMarkers::DifferentialProgressiveHuffman
+                    }
+                    Marker::SOF(7) =>
+                    {
+                        self.is_lossless = true;
+
+                        SOFMarkers::DifferentialLosslessHuffman
+                    }
+                    Marker::SOF(9) =>
+                    {
+                        self.is_arithmetic = true;
+
+                        SOFMarkers::ExtendedSequentialDctArithmetic
+                    }
+                    _ =>
+                    {
+                        self.is_progressive = true;
+                        self.is_arithmetic = true;
+
+                        SOFMarkers::ProgressiveDctArithmetic
                     }
                 };
 
+                if marker.is_differential() && self.hierarchy.is_none()
+                {
+                    return Err(DecodeErrors::Format(format!(
+                        "Found a differential frame ({marker:?}) outside a hierarchical image"
+                    )));
+                }
+
                 info!("Image encoding scheme =`{:?}`", marker);
                 // get components
                 parse_start_of_frame(buf, marker, self)?;
@@ -339,11 +782,18 @@ impl Decoder
 
                 return Err(DecodeErrors::Format("Unsupported image format".to_string()));
             }
-            // APP(0) segment
-            // Marker::APP(0 | 1) =>
-            // {
-            //     parse_app(buf, m, &mut self.info)?;
-            // }
+            // JFIF, EXIF, XMP, ICC profile and Adobe segments, others are kept as they are
+            Marker::APP(n) =>
+            {
+                // offset of the marker, which we've just read
+                let offset = buf.position().saturating_sub(2);
+
+                parse_app(buf, n, offset, self)?;
+            }
+            Marker::COM =>
+            {
+                parse_com(buf, self)?;
+            }
             // Quantization tables
             Marker::DQT =>
             {
@@ -365,13 +815,25 @@ impl Decoder
             }
             Marker::EOI => return Err(DecodeErrors::Format("Premature End of image".to_string())),
 
-            Marker::DAC | Marker::DNL =>
+            // Arithmetic coding conditioning tables
+            Marker::DAC =>
             {
-                return Err(DecodeErrors::Format(format!(
-                    "Parsing of the following header `{:?}` is not supported,\
-                                cannot continue",
-                    m
-                )));
+                parse_dac(self, buf)?;
+            }
+            // Number of lines, checked against the height read before
+            Marker::DNL =>
+            {
+                parse_dnl(buf, self)?;
+            }
+            // Hierarchical progression
+            Marker::DHP =>
+            {
+                parse_dhp(buf, self)?;
+            }
+            // Expansion of references of the next frame of a hierarchical image
+            Marker::EXP =>
+            {
+                parse_exp(buf, self)?;
             }
             Marker::DRI =>
             {
@@ -404,7 +866,7 @@ impl Decoder
                     )));
                 }
                 warn!("Skipping {} bytes", length - 2);
-                buf.consume((length - 2) as usize);
+                buf.skip(usize::from(length - 2));
             }
         }
         Ok(())
@@ -417,26 +879,312 @@ impl Decoder
         return self.options.get_out_colorspace();
     }
 
-    fn decode_internal(&mut self, buf: Cursor<Vec<u8>>) -> Result<Vec<u8>, DecodeErrors>
+    fn decode_internal(&mut self, buf: ByteReader) -> Result<Vec<u8>, DecodeErrors>
+    {
+        let mut buf = buf;
+
+        let pixels = self.decode_pixels(&mut buf);
+
+        // the decoder saw errors of the reader as the end of the data, they
+        // explain whatever went wrong
+        buf.check_error()?;
+
+        let pixels = pixels?;
+
+        self.end_of_image = find_eoi(&mut buf);
+
+        Ok(pixels)
+    }
+
+    fn decode_internal_u16(&mut self, buf: ByteReader) -> Result<Vec<u16>, DecodeErrors>
     {
         let mut buf = buf;
 
-        self.decode_headers_internal(&mut buf)?;
+        let pixels = self.decode_pixels_u16(&mut buf);
+
+        buf.check_error()?;
+
+        let pixels = pixels?;
+
+        self.end_of_image = find_eoi(&mut buf);
+
+        Ok(pixels)
+    }
+
+    /// Decode the headers and pixels of an image, turned by the EXIF orientation
+    #[allow(clippy::cast_possible_truncation)]
+    fn decode_pixels(&mut self, buf: &mut ByteReader) -> Result<Vec<u8>, DecodeErrors>
+    {
+        self.decode_headers_internal(buf)?;
+
+        if self.needs_wide_decoding()
+        {
+            // decode at full precision and keep the most significant bits,
+            // lossless images can have less than 8 bits, scale those up
+            let precision = self.info.precision;
+
+            let pixels = self
+                .decode_wide(buf)?
+                .iter()
+                .map(|x| {
+                    if precision >= 8
+                    {
+                        (x >> (precision - 8)) as u8
+                    }
+                    else
+                    {
+                        (x << (8 - precision)) as u8
+                    }
+                })
+                .collect();
+
+            return Ok(self.orient(pixels));
+        }
+        self.decode_8_bit(buf)
+    }
+
+    /// Decode the headers and pixels of an image into 16 bit samples
+    fn decode_pixels_u16(&mut self, buf: &mut ByteReader) -> Result<Vec<u16>, DecodeErrors>
+    {
+        self.decode_headers_internal(buf)?;
+
+        if self.needs_wide_decoding()
+        {
+            let pixels = self.decode_wide(buf)?;
+
+            Ok(self.orient(pixels))
+        }
+        else
+        {
+            // 8 bit images go through the usual path and are widened
+            Ok(self.decode_8_bit(buf)?.into_iter().map(u16::from).collect())
+        }
+    }
+
+    /// Whether the image can't be decoded by the 8 bit path
+    ///
+    /// That's hierarchical, lossless and high precision images,
+    /// and those with four components.
+    fn needs_wide_decoding(&self) -> bool
+    {
+        self.hierarchy.is_some()
+            || self.is_lossless
+            || self.info.precision > 8
+            || self.info.components == 4
+    }
+
+    /// Work out the colorspace of an image with `components`
+    ///
+    /// This follows libjpeg, a JFIF marker means three component images are YCbCr,
+    /// otherwise Adobe's APP14 marker tells whether the encoder transformed RGB to YCbCr
+    /// (and CMYK to YCCK). Images with neither are RGB if their components are
+    /// called R, G and B, and YCbCr or CMYK otherwise.
+    pub(crate) fn detect_colorspace(&self, components: &[Components]) -> ColorSpace
+    {
+        match components.len()
+        {
+            1 => ColorSpace::GRAYSCALE,
+            3 =>
+            {
+                let rgb = if self.jfif
+                {
+                    false
+                }
+                else if let Some(transform) = self.adobe_transform
+                {
+                    transform == 0
+                }
+                else
+                {
+                    components.iter().map(|c| c.id).eq(*b"RGB")
+                };
+
+                if rgb
+                {
+                    ColorSpace::RGB
+                }
+                else
+                {
+                    ColorSpace::YCbCr
+                }
+            }
+            4 =>
+            {
+                if self.adobe_transform == Some(2)
+                {
+                    ColorSpace::YCCK
+                }
+                else
+                {
+                    ColorSpace::CMYK
+                }
+            }
+            _ => ColorSpace::YCbCr,
+        }
+    }
+
+    /// Decode an image which doesn't go through the 8 bit path into 16 bit samples
+    fn decode_wide(&mut self, buf: &mut ByteReader) -> Result<Vec<u16>, DecodeErrors>
+    {
+        if self.hierarchy.is_some()
+        {
+            self.decode_hierarchical(buf)
+        }
+        else if self.is_lossless
+        {
+            self.decode_lossless(buf)
+        }
+        else
+        {
+            self.decode_high_precision(buf)
+        }
+    }
 
-        if self.is_progressive
+    /// Decode the image data of an image with 8 bits of precision
+    ///
+    /// Post processing writes pixels where the EXIF orientation turns them.
+    fn decode_8_bit(&mut self, buf: &mut ByteReader) -> Result<Vec<u8>, DecodeErrors>
+    {
+        let (input, output) = (self.input_colorspace, self.options.get_out_colorspace());
+
+        if !matches!(
+            (input, output),
+            (
+                ColorSpace::YCbCr | ColorSpace::GRAYSCALE,
+                ColorSpace::GRAYSCALE
+            ) | (
+                ColorSpace::YCbCr,
+                ColorSpace::YCbCr | ColorSpace::RGB | ColorSpace::RGBA | ColorSpace::RGBX
+            ) | (
+                ColorSpace::RGB,
+                ColorSpace::RGB | ColorSpace::RGBA | ColorSpace::RGBX
+            )
+        )
         {
-            self.decode_mcu_ycbcr_progressive(&mut buf)
+            return Err(DecodeErrors::Format(format!(
+                "Conversion from {input:?} to {output:?} colorspace is not supported"
+            )));
+        }
+        if self.decodes_mcu_rows()
+        {
+            self.decode_mcu_ycbcr_baseline(buf)
         }
         else
         {
-            self.decode_mcu_ycbcr_baseline(&mut buf)
+            self.decode_mcu_ycbcr_progressive(buf)
+        }
+    }
+
+    /// Whether the baseline decoder decodes the image, a few rows of MCU's at a time
+    ///
+    /// Arithmetic coded images share the progressive path since
+    /// they are decoded to coefficients first, so do sequential images whose
+    /// components are split across multiple scans, the baseline decoder expects
+    /// one scan with all of them.
+    fn decodes_mcu_rows(&self) -> bool
+    {
+        !(self.needs_wide_decoding()
+            || self.is_progressive
+            || self.is_arithmetic
+            || self.has_multiple_scans())
+    }
+
+    /// Whether the first scan of the image doesn't contain all of its components,
+    /// e.g. a sequential image with a scan for each component
+    fn has_multiple_scans(&self) -> bool
+    {
+        usize::from(self.num_scans) < self.components.len()
+    }
+
+    /// Decode an image with more than 8 bits of precision or four components
+    ///
+    /// Such images are always decoded into coefficients first, since the
+    /// 8 bit IDCT, up-sampling and color conversion routines can't hold their samples
+    /// (or only handle three components).
+    fn decode_high_precision(&mut self, buf: &mut ByteReader) -> Result<Vec<u16>, DecodeErrors>
+    {
+        let (block, ..) = self.decode_coefficients(buf)?;
+
+        if self.input_colorspace == ColorSpace::GRAYSCALE
+        {
+            // see finish_progressive_decoding, sampling factors of a lone
+            // component are meaningless.
+            self.h_max = self.components[0].horizontal_sample;
+            self.v_max = self.components[0].vertical_sample;
         }
+
+        post_process_u16(
+            &block,
+            &self.components,
+            self.input_colorspace,
+            self.options.get_out_colorspace(),
+            usize::from(self.info.width),
+            usize::from(self.info.height),
+            self.h_max,
+            self.v_max,
+            self.info.precision,
+            self.icc_transform((1 << self.info.precision) - 1).as_ref(),
+        )
     }
+
+    /// Decode a buffer already in memory into 16 bit samples
+    ///
+    /// Images with more than 8 bits of precision (e.g 12 bit sequential and progressive images)
+    /// are decoded at their full precision, so samples of a 12 bit image lie between 0 and 4095.
+    /// 8 bit images are decoded as usual and widened.
+    ///
+    /// Lossless images are decoded at their precision (between 2 and 16 bits), with their
+    /// components interleaved in the order they are stored, without any color conversion.
+    /// The same goes for hierarchical images whose last frame is lossless, those are decoded
+    /// at the resolution given by their DHP marker.
+    ///
+    /// `decode_buffer` can also decode high precision images, but keeps only the
+    /// 8 most significant bits of each sample.
+    ///
+    /// The precision of the image can be found in `ImageInfo::precision`
+    ///
+    /// # Errors
+    /// See DecodeErrors for an explanation
+    pub fn decode_buffer_u16(&mut self, buf: &[u8]) -> Result<Vec<u16>, DecodeErrors>
+    {
+        self.decode_internal_u16(ByteReader::new(buf))
+    }
+
+    /// Decode an image from any reader into 16 bit samples
+    ///
+    /// See `decode_reader` for how the reader is read and `decode_buffer_u16`
+    /// for how samples are represented
+    ///
+    /// # Errors
+    /// If the reader returns an error, or see DecodeErrors for an explanation
+    pub fn decode_reader_u16<R: Read>(&mut self, reader: R) -> Result<Vec<u16>, DecodeErrors>
+    {
+        let mut reader = reader;
+
+        self.decode_internal_u16(ByteReader::from_reader(&mut reader))
+    }
+
+    /// Decode a valid jpeg file into 16 bit samples
+    ///
+    /// See `decode_buffer_u16` for how samples are represented
+    ///
+    /// # Errors
+    /// See DecodeErrors for an explanation
+    pub fn decode_file_u16<P>(&mut self, file: P) -> Result<Vec<u16>, DecodeErrors>
+    where
+        P: AsRef<Path> + Clone,
+    {
+        self.decode_reader_u16(File::open(file)?)
+    }
+
     /// Read only headers from a jpeg image buffer
     ///
     /// This allows you to extract important information like
     /// image width and height without decoding the full image
     ///
+    /// Baseline images whose height comes in a DNL marker after their scan have
+    /// a height of zero until they are decoded.
+    ///
     /// # Examples
     /// ```no_run
     /// use zune_jpeg::Decoder;
@@ -451,9 +1199,7 @@ impl Decoder
     /// See DecodeErrors enum for list of possible errors during decoding
     pub fn read_headers(&mut self, buf: &[u8]) -> Result<(), DecodeErrors>
     {
-        let mut cursor = Cursor::new(buf);
-
-        self.decode_headers_internal(&mut cursor)?;
+        self.decode_headers_internal(&mut ByteReader::new(buf))?;
         Ok(())
     }
     /// Create a new decoder with the specified options to be used for decoding
@@ -464,62 +1210,16 @@ impl Decoder
         Decoder::default(options)
     }
 
-    /// Set up-sampling routines in case an image is down sampled
-    pub(crate) fn set_upsampling(&mut self) -> Result<(), DecodeErrors>
+    /// Set the color conversion routine for the output colorspace
+    pub(crate) fn set_color_convert(&mut self)
     {
-        // no sampling, return early
-        // check if horizontal max ==1
-        if self.h_max == self.v_max && self.h_max == 1
+        if let Some(color_convert) = choose_ycbcr_to_rgb_convert_func(
+            self.options.get_out_colorspace(),
+            self.options.get_use_unsafe(),
+        )
         {
-            return Ok(());
-        }
-
-        // match for other ratios
-        match (self.h_max, self.v_max)
-        {
-            (2, 1) =>
-            {
-                self.sub_sample_ratio = SubSampRatios::H;
-                // horizontal sub-sampling
-                info!("Horizontal sub-sampling (2,1)");
-
-                let up_sampler = choose_horizontal_samp_function(self.options.get_use_unsafe());
-
-                self.components[1..]
-                    .iter_mut()
-                    .for_each(|x| x.up_sampler = up_sampler);
-            }
-            (1, 2) =>
-            {
-                self.sub_sample_ratio = SubSampRatios::V;
-                // Vertical sub-sampling
-                info!("Vertical sub-sampling (1,2)");
-
-                self.components[1..]
-                    .iter_mut()
-                    .for_each(|x| x.up_sampler = upsample_vertical);
-            }
-            (2, 2) =>
-            {
-                self.sub_sample_ratio = SubSampRatios::HV;
-                // vertical and horizontal sub sampling
-                info!("Vertical and horizontal sub-sampling(2,2)");
-
-                self.components[1..].iter_mut().for_each(|x| {
-                    x.up_sampler = choose_hv_samp_function(self.options.get_use_unsafe());
-                });
-            }
-            (_, _) =>
-            {
-                // no op. Do nothing
-                // Jokes , panic...
-                return Err(DecodeErrors::Format(
-                    "Unknown down-sampling method, cannot continue".to_string(),
-                ));
-            }
+            self.color_convert_16 = color_convert;
         }
-
-        return Ok(());
     }
 
     /// Set output colorspace to be RGBA
@@ -557,19 +1257,37 @@ impl Decoder
     #[must_use]
     /// Get the width of the image as a u16
     ///
-    /// The width lies between 0 and 65535
+    /// The width lies between 0 and 65535, this is the height stored in the
+    /// image if the output is rotated by 90 degrees, see
+    /// `ZuneJpegOptions::set_apply_orientation`
     pub fn width(&self) -> u16
     {
-        self.info.width
+        if self.orientation() >= 5
+        {
+            self.info.height
+        }
+        else
+        {
+            self.info.width
+        }
     }
 
     /// Get the height of the image as a u16
     ///
-    /// The height lies between 0 and 65535
+    /// The height lies between 0 and 65535, this is the width stored in the
+    /// image if the output is rotated by 90 degrees, see
+    /// `ZuneJpegOptions::set_apply_orientation`
     #[must_use]
     pub fn height(&self) -> u16
     {
-        self.info.height
+        if self.orientation() >= 5
+        {
+            self.info.width
+        }
+        else
+        {
+            self.info.height
+        }
     }
 
     /// Set the number of threads the decoder should use during decoding
@@ -608,37 +1326,21 @@ impl Decoder
     /// before trying to decode.
     pub(crate) fn check_component_dimensions(&self) -> Result<(), DecodeErrors>
     {
-        // find  y component
-        let y_comp = self
-            .components
-            .iter()
-            .find(|c| c.component_id == ComponentID::Y)
-            .ok_or(DecodeErrors::FormatStatic(
+        if self.components.is_empty()
+        {
+            return Err(DecodeErrors::FormatStatic(
                 "Could not find Y component for the image",
-            ))?;
-
-        let y_width = y_comp.width_stride;
-        let cb_cr_width = y_width / self.h_max;
+            ));
+        }
 
         for comp in &self.components
         {
-            if comp.component_id == ComponentID::Y
-            {
-                continue;
-            }
+            // a row of MCU's holds `horizontal_sample` blocks of the component per MCU
+            let expected = self.mcu_x * comp.horizontal_sample * 8;
 
-            if comp.width_stride != cb_cr_width
+            if comp.width_stride != expected
             {
-                return Err(DecodeErrors::Format(format!("Invalid image width and height stride for component {:?}, expected {}, but found {}", comp.component_id, cb_cr_width, comp.width_stride)));
-            }
-
-            if (comp.horizontal_sample != 1 || comp.vertical_sample != 1)
-                && comp.component_id != ComponentID::Y
-            {
-                return Err(DecodeErrors::Format(format!(
-                    "Invalid component sample for component {:?}, expected (1,1), found ({},{})",
-                    comp.component_id, comp.vertical_sample, comp.horizontal_sample
-                )));
+                return Err(DecodeErrors::Format(format!("Invalid image width and height stride for component {:?}, expected {}, but found {}", comp.component_id, expected, comp.width_stride)));
             }
         }
 
@@ -652,19 +1354,88 @@ impl Decoder
 pub struct ImageInfo
 {
     /// Width of the image
-    pub width:         u16,
+    pub width:           u16,
     /// Height of image
-    pub height:        u16,
-    /// PixelDensity
-    pub pixel_density: u8,
+    pub height:          u16,
+    /// Sample precision of the image in bits, from the start of frame
+    pub precision:       u8,
+    /// The sample precision, like `precision`, despite its name
+    ///
+    /// Pixel densities are `x_density` and `y_density`.
+    #[deprecated(
+        since = "0.2.0",
+        note = "It holds the sample precision, use `precision`"
+    )]
+    pub pixel_density:   u8,
     /// Start of frame markers
-    pub sof:           SOFMarkers,
-    /// Horizontal sample
-    pub x_density:     u16,
-    /// Vertical sample
-    pub y_density:     u16,
+    pub sof:             SOFMarkers,
+    /// Horizontal pixel density from the JFIF segment, in `density_units`
+    pub x_density:       u16,
+    /// Vertical pixel density from the JFIF segment, in `density_units`
+    pub y_density:       u16,
     /// Number of components
-    pub components:    u8,
+    pub components:      u8,
+    /// Units of the pixel densities, 0 means they only give the aspect ratio
+    /// of pixels, 1 that they're dots per inch and 2 dots per centimeter
+    pub density_units:   u8,
+    /// Major and minor version of the JFIF segment, `None` if the image has none
+    pub jfif_version:    Option<(u8, u8)>,
+    /// Thumbnails from the JFIF segment and JFXX extension segments
+    pub jfif_thumbnails: Vec<JfifThumbnail>,
+    /// Fields of Adobe's APP14 segment
+    pub adobe:           Option<AdobeInfo>,
+    /// Contents of COM segments, usually text
+    pub comments:        Vec<Vec<u8>>,
+    /// APPn segments the decoder doesn't interpret, in the order they appear
+    pub app_segments:    Vec<AppSegment>,
+}
+
+/// Fields of Adobe's APP14 segment
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct AdobeInfo
+{
+    /// Version of the segment, usually 100
+    pub version:   u16,
+    pub flags0:    u16,
+    pub flags1:    u16,
+    /// Color transform of the encoder, 0 for none (RGB or CMYK), 1 for YCbCr
+    /// and 2 for YCCK
+    pub transform: u8,
+}
+
+/// An application segment
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AppSegment
+{
+    /// Number of the marker, e.g. 13 for APP13
+    pub marker: u8,
+    /// Offset of the marker from the start of the image
+    pub offset: usize,
+    /// Contents of the segment, after its length
+    pub data:   Vec<u8>,
+}
+
+/// A thumbnail from the JFIF segment or a JFXX extension segment
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum JfifThumbnail
+{
+    /// A JPEG image
+    Jpeg(Vec<u8>),
+    /// Indices into a palette of 256 RGB colors, one per pixel
+    Palette
+    {
+        width:   u8,
+        height:  u8,
+        palette: Vec<u8>,
+        indices: Vec<u8>,
+    },
+    /// RGB pixels
+    Rgb
+    {
+        width:  u8,
+        height: u8,
+        pixels: Vec<u8>,
+    },
 }
 
 impl ImageInfo
@@ -687,13 +1458,15 @@ impl ImageInfo
         self.height = height;
     }
 
-    /// Set the image density
+    /// Set the sample precision of the image
     ///
     /// Found in the start of frame
 
-    pub(crate) fn set_density(&mut self, density: u8)
+    #[allow(deprecated)]
+    pub(crate) fn set_precision(&mut self, precision: u8)
     {
-        self.pixel_density = density;
+        self.precision = precision;
+        self.pixel_density = precision;
     }
 
     /// Set image Start of frame marker
@@ -705,10 +1478,9 @@ impl ImageInfo
         self.sof = marker;
     }
 
-    /// Set image x-density(dots per pixel)
+    /// Set image x-density
     ///
     /// Found in the APP(0) marker
-    #[allow(dead_code)]
     pub(crate) fn set_x(&mut self, sample: u16)
     {
         self.x_density = sample;
@@ -717,7 +1489,6 @@ impl ImageInfo
     /// Set image y-density
     ///
     /// Found in the APP(0) marker
-    #[allow(dead_code)]
     pub(crate) fn set_y(&mut self, sample: u16)
     {
         self.y_density = sample;
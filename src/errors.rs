@@ -5,10 +5,7 @@ use std::error::Error;
 use std::fmt::{Debug, Display, Formatter};
 
 use crate::decoder::MAX_DIMENSIONS;
-use crate::misc::{
-    START_OF_FRAME_EXT_AR, START_OF_FRAME_EXT_SEQ, START_OF_FRAME_LOS_SEQ,
-    START_OF_FRAME_LOS_SEQ_AR, START_OF_FRAME_PROG_DCT_AR,
-};
+use crate::misc::START_OF_FRAME_LOS_SEQ_AR;
 
 /// Common Decode errors
 #[allow(clippy::module_name_repetitions)]
@@ -194,11 +191,7 @@ impl UnsupportedSchemes
 
         match int
         {
-            START_OF_FRAME_PROG_DCT_AR => Some(Self::ProgressiveDctArithmetic),
-            START_OF_FRAME_LOS_SEQ => Some(Self::LosslessHuffman),
             START_OF_FRAME_LOS_SEQ_AR => Some(Self::LosslessArithmetic),
-            START_OF_FRAME_EXT_SEQ => Some(Self::ExtendedSequentialHuffman),
-            START_OF_FRAME_EXT_AR => Some(Self::ExtendedSequentialDctArithmetic),
             _ => None,
         }
     }
@@ -0,0 +1,596 @@
+//! EXIF metadata from APP1 segments
+//!
+//! The segment holds a TIFF structure (CIPA DC-008, 4.6), a header giving the
+//! byte order followed by image file directories (IFDs) of tagged values.
+//! We read IFD0, the Exif and GPS IFDs it points to, the Interoperability IFD
+//! and IFD1, which describes the thumbnail.
+//!
+//! Values of every entry are kept, the most commonly needed ones have typed
+//! accessors on [`Exif`], everything else can be looked up with [`Exif::get`]
+//! or by going through [`Exif::entries`].
+//!
+//! Broken entries and directories are skipped rather than failing the
+//! decode, EXIF data is written by many tools, not all of them careful.
+
+/// Entries in a directory past which we assume the data is corrupt
+const MAX_ENTRIES: usize = 4096;
+
+/// The directory an entry was found in
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Ifd
+{
+    /// IFD0, describing the primary image
+    Primary,
+    /// Exif IFD, with capture settings
+    Exif,
+    /// GPS IFD, with location information
+    Gps,
+    /// Interoperability IFD
+    Interoperability,
+    /// IFD1, describing the thumbnail
+    Thumbnail,
+}
+
+/// An unsigned fraction
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Rational
+{
+    pub numerator:   u32,
+    pub denominator: u32,
+}
+
+impl Rational
+{
+    /// The value of the fraction, `None` if the denominator is zero
+    #[must_use]
+    pub fn to_f64(self) -> Option<f64>
+    {
+        (self.denominator != 0).then(|| f64::from(self.numerator) / f64::from(self.denominator))
+    }
+}
+
+/// A signed fraction
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SRational
+{
+    pub numerator:   i32,
+    pub denominator: i32,
+}
+
+impl SRational
+{
+    /// The value of the fraction, `None` if the denominator is zero
+    #[must_use]
+    pub fn to_f64(self) -> Option<f64>
+    {
+        (self.denominator != 0).then(|| f64::from(self.numerator) / f64::from(self.denominator))
+    }
+}
+
+/// Value of an entry, one variant per TIFF field type
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExifValue
+{
+    Byte(Vec<u8>),
+    /// Text, without its terminating NUL
+    Ascii(String),
+    Short(Vec<u16>),
+    Long(Vec<u32>),
+    Rational(Vec<Rational>),
+    SByte(Vec<i8>),
+    Undefined(Vec<u8>),
+    SShort(Vec<i16>),
+    SLong(Vec<i32>),
+    SRational(Vec<SRational>),
+    Float(Vec<f32>),
+    Double(Vec<f64>),
+}
+
+impl ExifValue
+{
+    /// The first value as an unsigned integer, for byte, short and long values
+    #[must_use]
+    pub fn to_u32(&self) -> Option<u32>
+    {
+        match self
+        {
+            ExifValue::Byte(v) => v.first().copied().map(u32::from),
+            ExifValue::Short(v) => v.first().copied().map(u32::from),
+            ExifValue::Long(v) => v.first().copied(),
+            _ => None,
+        }
+    }
+
+    /// The text of an ASCII value
+    #[must_use]
+    pub fn as_str(&self) -> Option<&str>
+    {
+        match self
+        {
+            ExifValue::Ascii(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// The fractions of a rational value
+    #[must_use]
+    pub fn as_rationals(&self) -> Option<&[Rational]>
+    {
+        match self
+        {
+            ExifValue::Rational(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+/// A tagged value
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExifEntry
+{
+    /// Directory the entry was found in
+    pub ifd:   Ifd,
+    /// Tag number, e.g. `0x0112` for the orientation
+    pub tag:   u16,
+    pub value: ExifValue,
+}
+
+/// EXIF metadata of an image
+#[derive(Clone, Debug, PartialEq)]
+pub struct Exif
+{
+    /// The TIFF structure, offsets in it are relative to its start
+    pub(crate) data:    Vec<u8>,
+    pub(crate) entries: Vec<ExifEntry>,
+}
+
+/// Reads numbers in the byte order of the TIFF header
+#[derive(Copy, Clone)]
+pub(crate) struct Reader<'a>
+{
+    pub(crate) data:       &'a [u8],
+    pub(crate) big_endian: bool,
+}
+
+impl<'a> Reader<'a>
+{
+    /// A reader for the TIFF structure in `data`, `None` if its header is invalid
+    pub(crate) fn new(data: &'a [u8]) -> Option<Reader<'a>>
+    {
+        let big_endian = match data.get(..4)?
+        {
+            b"MM\0\x2A" => true,
+            b"II\x2A\0" => false,
+            _ => return None,
+        };
+
+        Some(Reader { data, big_endian })
+    }
+
+    pub(crate) fn bytes<const N: usize>(self, offset: usize) -> Option<[u8; N]>
+    {
+        self.data
+            .get(offset..offset.checked_add(N)?)?
+            .try_into()
+            .ok()
+    }
+
+    pub(crate) fn u16(self, offset: usize) -> Option<u16>
+    {
+        let bytes = self.bytes(offset)?;
+
+        Some(
+            if self.big_endian
+            {
+                u16::from_be_bytes(bytes)
+            }
+            else
+            {
+                u16::from_le_bytes(bytes)
+            },
+        )
+    }
+
+    pub(crate) fn u32(self, offset: usize) -> Option<u32>
+    {
+        let bytes = self.bytes(offset)?;
+
+        Some(
+            if self.big_endian
+            {
+                u32::from_be_bytes(bytes)
+            }
+            else
+            {
+                u32::from_le_bytes(bytes)
+            },
+        )
+    }
+
+    fn u64(self, offset: usize) -> Option<u64>
+    {
+        let bytes = self.bytes(offset)?;
+
+        Some(
+            if self.big_endian
+            {
+                u64::from_be_bytes(bytes)
+            }
+            else
+            {
+                u64::from_le_bytes(bytes)
+            },
+        )
+    }
+
+    /// Read `count` items of `size` bytes each starting at `offset`
+    fn items<T>(
+        self, offset: usize, count: usize, size: usize, read: impl Fn(usize) -> Option<T>,
+    ) -> Option<Vec<T>>
+    {
+        // make sure all of them are there before allocating
+        self.data
+            .get(offset..offset.checked_add(count.checked_mul(size)?)?)?;
+
+        (0..count).map(|i| read(offset + i * size)).collect()
+    }
+
+    /// Read the value of the entry at `entry`, `None` if it's of an unknown
+    /// type or goes past the end of the data
+    #[allow(clippy::cast_possible_wrap)]
+    pub(crate) fn value(self, entry: usize) -> Option<ExifValue>
+    {
+        let kind = self.u16(entry + 2)?;
+        let count = self.u32(entry + 4)? as usize;
+
+        let size = match kind
+        {
+            1 | 2 | 6 | 7 => 1,
+            3 | 8 => 2,
+            4 | 9 | 11 => 4,
+            5 | 10 | 12 => 8,
+            _ => return None,
+        };
+        // values which fit in four bytes are stored in the entry itself
+        let offset = if count.checked_mul(size)? <= 4
+        {
+            entry + 8
+        }
+        else
+        {
+            self.u32(entry + 8)? as usize
+        };
+
+        let value = match kind
+        {
+            1 => ExifValue::Byte(self.data.get(offset..offset.checked_add(count)?)?.to_vec()),
+            2 =>
+            {
+                let text = self.data.get(offset..offset.checked_add(count)?)?;
+                let end = text.iter().position(|x| *x == 0).unwrap_or(text.len());
+
+                ExifValue::Ascii(String::from_utf8_lossy(&text[..end]).into_owned())
+            }
+            3 => ExifValue::Short(self.items(offset, count, 2, |o| self.u16(o))?),
+            4 => ExifValue::Long(self.items(offset, count, 4, |o| self.u32(o))?),
+            5 => ExifValue::Rational(self.items(offset, count, 8, |o| {
+                Some(Rational {
+                    numerator:   self.u32(o)?,
+                    denominator: self.u32(o + 4)?,
+                })
+            })?),
+            6 => ExifValue::SByte(
+                self.data
+                    .get(offset..offset.checked_add(count)?)?
+                    .iter()
+                    .map(|x| *x as i8)
+                    .collect(),
+            ),
+            7 => ExifValue::Undefined(self.data.get(offset..offset.checked_add(count)?)?.to_vec()),
+            8 => ExifValue::SShort(self.items(offset, count, 2, |o| Some(self.u16(o)? as i16))?),
+            9 => ExifValue::SLong(self.items(offset, count, 4, |o| Some(self.u32(o)? as i32))?),
+            10 => ExifValue::SRational(self.items(offset, count, 8, |o| {
+                Some(SRational {
+                    numerator:   self.u32(o)? as i32,
+                    denominator: self.u32(o + 4)? as i32,
+                })
+            })?),
+            11 => ExifValue::Float(
+                self.items(offset, count, 4, |o| Some(f32::from_bits(self.u32(o)?)))?,
+            ),
+            _ => ExifValue::Double(
+                self.items(offset, count, 8, |o| Some(f64::from_bits(self.u64(o)?)))?,
+            ),
+        };
+
+        Some(value)
+    }
+}
+
+impl Exif
+{
+    /// Parse the TIFF structure following `Exif\0\0` in an APP1 segment
+    ///
+    /// Returns `None` if the header is invalid, broken directories
+    /// and entries are skipped.
+    pub(crate) fn parse(data: Vec<u8>) -> Option<Exif>
+    {
+        let reader = Reader::new(&data)?;
+
+        let mut entries = Vec::new();
+        // offsets of directories we've read, so loops in corrupt data end
+        let mut visited = Vec::new();
+
+        let mut directories = vec![(Ifd::Primary, reader.u32(4)? as usize)];
+
+        while let Some((ifd, offset)) = directories.pop()
+        {
+            if visited.contains(&offset)
+            {
+                warn!("EXIF directory at {offset} is referenced twice, ignoring it");
+                continue;
+            }
+            visited.push(offset);
+
+            let Some(count) = reader.u16(offset).map(usize::from)
+            else
+            {
+                warn!("EXIF {ifd:?} directory offset {offset} is out of bounds, ignoring it");
+                continue;
+            };
+
+            if count > MAX_ENTRIES
+            {
+                warn!("EXIF {ifd:?} directory has {count} entries, ignoring it");
+                continue;
+            }
+
+            for i in 0..count
+            {
+                let entry = offset + 2 + i * 12;
+
+                let Some(tag) = reader.u16(entry)
+                else
+                {
+                    warn!("EXIF {ifd:?} directory is truncated");
+                    break;
+                };
+                let Some(value) = reader.value(entry)
+                else
+                {
+                    warn!("Skipping invalid EXIF entry 0x{tag:04X} in {ifd:?} directory");
+                    continue;
+                };
+
+                // pointers to the other directories
+                let child = match (ifd, tag)
+                {
+                    (Ifd::Primary, 0x8769) => Some(Ifd::Exif),
+                    (Ifd::Primary, 0x8825) => Some(Ifd::Gps),
+                    (Ifd::Exif, 0xA005) => Some(Ifd::Interoperability),
+                    _ => None,
+                };
+
+                if let (Some(child), Some(position)) = (child, value.to_u32())
+                {
+                    directories.push((child, position as usize));
+                }
+
+                entries.push(ExifEntry { ifd, tag, value });
+            }
+
+            // IFD0 links to IFD1, which is where the thumbnail is described
+            if ifd == Ifd::Primary
+            {
+                match reader.u32(offset + 2 + count * 12)
+                {
+                    Some(0) | None => (),
+                    Some(next) => directories.push((Ifd::Thumbnail, next as usize)),
+                }
+            }
+        }
+
+        Some(Exif { data, entries })
+    }
+
+    /// All entries, in the order they were read
+    #[must_use]
+    pub fn entries(&self) -> &[ExifEntry]
+    {
+        &self.entries
+    }
+
+    /// The value of `tag` in the directory `ifd`
+    #[must_use]
+    pub fn get(&self, ifd: Ifd, tag: u16) -> Option<&ExifValue>
+    {
+        self.entries
+            .iter()
+            .find(|entry| entry.ifd == ifd && entry.tag == tag)
+            .map(|entry| &entry.value)
+    }
+
+    fn get_str(&self, ifd: Ifd, tag: u16) -> Option<&str>
+    {
+        self.get(ifd, tag)?.as_str()
+    }
+
+    fn get_rational(&self, ifd: Ifd, tag: u16) -> Option<Rational>
+    {
+        self.get(ifd, tag)?.as_rationals()?.first().copied()
+    }
+
+    /// How the image should be rotated and flipped for display, between 1 and 8
+    ///
+    /// 1 means the image is upright, 6 that it should be rotated 90 degrees
+    /// clockwise, see the `Orientation` tag of the TIFF specification for all
+    /// of them.
+    #[must_use]
+    pub fn orientation(&self) -> Option<u16>
+    {
+        let orientation = self.get(Ifd::Primary, 0x0112)?.to_u32()?;
+
+        u16::try_from(orientation)
+            .ok()
+            .filter(|x| (1..=8).contains(x))
+    }
+
+    /// Manufacturer of the camera
+    #[must_use]
+    pub fn make(&self) -> Option<&str>
+    {
+        self.get_str(Ifd::Primary, 0x010F)
+    }
+
+    /// Model of the camera
+    #[must_use]
+    pub fn model(&self) -> Option<&str>
+    {
+        self.get_str(Ifd::Primary, 0x0110)
+    }
+
+    /// When the picture was taken, as `YYYY:MM:DD HH:MM:SS`
+    ///
+    /// This is the `DateTimeOriginal` tag, falling back to `DateTime` (when
+    /// the file was last changed) if the image doesn't have it.
+    #[must_use]
+    pub fn date_time_original(&self) -> Option<&str>
+    {
+        self.get_str(Ifd::Exif, 0x9003)
+            .or_else(|| self.get_str(Ifd::Primary, 0x0132))
+    }
+
+    /// Offset from UTC of `date_time_original`, as `+HH:MM` or `-HH:MM`
+    #[must_use]
+    pub fn offset_time_original(&self) -> Option<&str>
+    {
+        self.get_str(Ifd::Exif, 0x9011)
+    }
+
+    /// Exposure time in seconds
+    #[must_use]
+    pub fn exposure_time(&self) -> Option<Rational>
+    {
+        self.get_rational(Ifd::Exif, 0x829A)
+    }
+
+    /// The F number, i.e. the aperture
+    #[must_use]
+    pub fn f_number(&self) -> Option<Rational>
+    {
+        self.get_rational(Ifd::Exif, 0x829D)
+    }
+
+    /// ISO speed of the camera
+    #[must_use]
+    pub fn iso(&self) -> Option<u32>
+    {
+        self.get(Ifd::Exif, 0x8827)?.to_u32()
+    }
+
+    /// Focal length of the lens in millimeters
+    #[must_use]
+    pub fn focal_length(&self) -> Option<Rational>
+    {
+        self.get_rational(Ifd::Exif, 0x920A)
+    }
+
+    /// Latitude in degrees, positive north of the equator
+    #[must_use]
+    pub fn gps_latitude(&self) -> Option<f64>
+    {
+        self.gps_coordinate(0x0002, 0x0001, "S")
+    }
+
+    /// Longitude in degrees, positive east of Greenwich
+    #[must_use]
+    pub fn gps_longitude(&self) -> Option<f64>
+    {
+        self.gps_coordinate(0x0004, 0x0003, "W")
+    }
+
+    /// Altitude in meters, negative below sea level
+    #[must_use]
+    pub fn gps_altitude(&self) -> Option<f64>
+    {
+        let altitude = self.get_rational(Ifd::Gps, 0x0006)?.to_f64()?;
+        // reference 1 means below sea level
+        let below = self.get(Ifd::Gps, 0x0005).and_then(|x| match x
+        {
+            ExifValue::Byte(v) | ExifValue::Undefined(v) => v.first().copied(),
+            _ => None,
+        }) == Some(1);
+
+        Some(if below { -altitude } else { altitude })
+    }
+
+    /// Degrees, minutes and seconds of `tag`, negated if the reference
+    /// in `reference_tag` is `negative`
+    fn gps_coordinate(&self, tag: u16, reference_tag: u16, negative: &str) -> Option<f64>
+    {
+        let parts = self.get(Ifd::Gps, tag)?.as_rationals()?;
+
+        if parts.len() != 3
+        {
+            return None;
+        }
+        let degrees = parts[0].to_f64()? + parts[1].to_f64()? / 60.0 + parts[2].to_f64()? / 3600.0;
+
+        if self.get_str(Ifd::Gps, reference_tag)? == negative
+        {
+            Some(-degrees)
+        }
+        else
+        {
+            Some(degrees)
+        }
+    }
+
+    /// Number of pixels per resolution unit horizontally
+    #[must_use]
+    pub fn x_resolution(&self) -> Option<Rational>
+    {
+        self.get_rational(Ifd::Primary, 0x011A)
+    }
+
+    /// Number of pixels per resolution unit vertically
+    #[must_use]
+    pub fn y_resolution(&self) -> Option<Rational>
+    {
+        self.get_rational(Ifd::Primary, 0x011B)
+    }
+
+    /// Unit of the resolution, 2 for inches (the default) and 3 for centimeters
+    #[must_use]
+    pub fn resolution_unit(&self) -> Option<u16>
+    {
+        u16::try_from(self.get(Ifd::Primary, 0x0128)?.to_u32()?).ok()
+    }
+
+    /// The JPEG thumbnail of IFD1, as stored
+    ///
+    /// Returns `None` if there is no thumbnail, if it's uncompressed (which is
+    /// rare) or if its offset and length don't point at a JPEG image.
+    #[must_use]
+    pub fn thumbnail(&self) -> Option<&[u8]>
+    {
+        // compression 6 is JPEG, older writers leave it out
+        if let Some(compression) = self.get(Ifd::Thumbnail, 0x0103)
+        {
+            if compression.to_u32()? != 6
+            {
+                return None;
+            }
+        }
+        let offset = usize::try_from(self.get(Ifd::Thumbnail, 0x0201)?.to_u32()?).ok()?;
+        let length = usize::try_from(self.get(Ifd::Thumbnail, 0x0202)?.to_u32()?).ok()?;
+
+        let thumbnail = self.data.get(offset..offset.checked_add(length)?);
+
+        if thumbnail.is_none()
+        {
+            warn!("EXIF thumbnail is out of bounds, ignoring it");
+        }
+        thumbnail.filter(|x| x.starts_with(&[0xFF, 0xD8]))
+    }
+}
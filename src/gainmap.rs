@@ -0,0 +1,566 @@
+//! Gain maps of UltraHDR and ISO 21496-1 images
+//!
+//! These images are an SDR JPEG image, decoded as usual, followed by a second
+//! JPEG image, the gain map, listed in the MP index of the first one. The gain
+//! map says how much brighter each pixel should be on an HDR display, its
+//! parameters are in `hdrgm:` XMP properties of the gain map (UltraHDR) or in
+//! an APP2 segment of ISO 21496-1 metadata (Apple's adaptive HDR, newer
+//! UltraHDR images), which we prefer when both are there.
+//!
+//! Gains and capacities are log2 of ratios, as the UltraHDR specification
+//! defines them. For a gain map sample `g` between 0 and 1 and a display boost
+//! `b`, a linear SDR value `sdr` becomes
+//!
+//! ```text
+//! g' = g ^ (1 / gamma)
+//! gain = gain_map_min * (1 - g') + gain_map_max * g'
+//! weight = clamp((log2(b) - hdr_capacity_min) / (hdr_capacity_max - hdr_capacity_min), 0, 1)
+//! hdr = (sdr + offset_sdr) * 2 ^ (gain * weight) - offset_hdr
+//! ```
+
+use crate::errors::DecodeErrors;
+use crate::misc::ColorSpace;
+use crate::xmp::xmp_property;
+use crate::Decoder;
+
+/// Signature of APP2 segments holding ISO 21496-1 metadata
+const ISO_SIGNATURE: &[u8] = b"urn:iso:std:iso:ts:21496:-1\0";
+
+/// Number of entries in the tables of gains, gain map samples are
+/// interpolated so they aren't limited to 256 values
+const GAIN_STEPS: usize = 1024;
+
+/// Parameters of a gain map, one value per red, green and blue channel
+///
+/// Metadata with a single channel has the same value in all three.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GainMapMetadata
+{
+    /// log2 of the smallest gain, for gain map samples of 0
+    pub gain_map_min:          [f32; 3],
+    /// log2 of the largest gain, for gain map samples of 1
+    pub gain_map_max:          [f32; 3],
+    /// Gamma gain map samples are encoded with
+    pub gamma:                 [f32; 3],
+    /// Added to SDR values before applying the gain
+    pub offset_sdr:            [f32; 3],
+    /// Taken from HDR values after applying the gain
+    pub offset_hdr:            [f32; 3],
+    /// log2 of the display boost below which none of the gain is applied
+    pub hdr_capacity_min:      f32,
+    /// log2 of the display boost from which all of the gain is applied
+    pub hdr_capacity_max:      f32,
+    /// Whether the base image is the HDR one, and the gain map makes SDR
+    /// from it
+    pub base_rendition_is_hdr: bool,
+}
+
+/// A gain map and its parameters
+#[derive(Clone, Debug, PartialEq)]
+pub struct GainMap
+{
+    pub metadata: GainMapMetadata,
+    /// The gain map, a JPEG image
+    pub data:     Vec<u8>,
+}
+
+/// Reads the big endian numbers of ISO 21496-1 metadata
+struct IsoReader<'a>
+{
+    data:               &'a [u8],
+    position:           usize,
+    /// Denominator of all fractions, if the metadata uses one
+    common_denominator: Option<u32>,
+}
+
+impl IsoReader<'_>
+{
+    fn bytes<const N: usize>(&mut self) -> Option<[u8; N]>
+    {
+        let bytes = self.data.get(self.position..self.position + N)?;
+
+        self.position += N;
+        bytes.try_into().ok()
+    }
+
+    fn u32(&mut self) -> Option<u32>
+    {
+        self.bytes().map(u32::from_be_bytes)
+    }
+
+    /// A fraction, `signed` if its numerator is
+    #[allow(clippy::cast_possible_wrap, clippy::cast_possible_truncation)]
+    fn fraction(&mut self, signed: bool) -> Option<f32>
+    {
+        let numerator = self.u32()?;
+        let numerator = if signed
+        {
+            f64::from(numerator as i32)
+        }
+        else
+        {
+            f64::from(numerator)
+        };
+        let denominator = match self.common_denominator
+        {
+            Some(denominator) => denominator,
+            None => self.u32()?,
+        };
+
+        (denominator != 0).then(|| (numerator / f64::from(denominator)) as f32)
+    }
+}
+
+impl GainMapMetadata
+{
+    /// Parse ISO 21496-1 metadata, after its signature
+    ///
+    /// Returns `None` if the data is truncated, of an unknown version, or only
+    /// has a version as the segment of the base image does.
+    pub(crate) fn from_iso(data: &[u8]) -> Option<GainMapMetadata>
+    {
+        let mut reader = IsoReader {
+            data,
+            position: 0,
+            common_denominator: None,
+        };
+        // minimum version a reader has to support and version of the writer
+        let minimum_version = u16::from_be_bytes(reader.bytes()?);
+        let _ = reader.bytes::<2>()?;
+
+        if minimum_version != 0
+        {
+            warn!("Unsupported ISO 21496-1 metadata version {minimum_version}");
+            return None;
+        }
+        let [flags] = reader.bytes()?;
+        let channels = if flags & 0x80 == 0 { 1 } else { 3 };
+
+        if flags & 0x08 != 0
+        {
+            reader.common_denominator = Some(reader.u32()?);
+        }
+        let base_headroom = reader.fraction(false)?;
+        let alternate_headroom = reader.fraction(false)?;
+        let base_rendition_is_hdr = flags & 0x04 != 0;
+
+        let mut metadata = GainMapMetadata {
+            gain_map_min: [0.0; 3],
+            gain_map_max: [0.0; 3],
+            gamma: [1.0; 3],
+            offset_sdr: [0.0; 3],
+            offset_hdr: [0.0; 3],
+            hdr_capacity_min: base_headroom.min(alternate_headroom),
+            hdr_capacity_max: base_headroom.max(alternate_headroom),
+            base_rendition_is_hdr,
+        };
+
+        for channel in 0..channels
+        {
+            metadata.gain_map_min[channel] = reader.fraction(true)?;
+            metadata.gain_map_max[channel] = reader.fraction(true)?;
+            metadata.gamma[channel] = reader.fraction(false)?;
+            // offsets of the base and alternate images
+            let base_offset = reader.fraction(true)?;
+            let alternate_offset = reader.fraction(true)?;
+
+            (metadata.offset_sdr[channel], metadata.offset_hdr[channel]) = if base_rendition_is_hdr
+            {
+                (alternate_offset, base_offset)
+            }
+            else
+            {
+                (base_offset, alternate_offset)
+            };
+        }
+
+        if channels == 1
+        {
+            for values in [
+                &mut metadata.gain_map_min,
+                &mut metadata.gain_map_max,
+                &mut metadata.gamma,
+                &mut metadata.offset_sdr,
+                &mut metadata.offset_hdr,
+            ]
+            {
+                *values = [values[0]; 3];
+            }
+        }
+
+        Some(metadata)
+    }
+
+    /// Parse `hdrgm:` properties of the XMP packet of a gain map
+    ///
+    /// Returns `None` if the packet doesn't describe a gain map or misses a
+    /// required property, those without a default.
+    pub(crate) fn from_xmp(xmp: &str) -> Option<GainMapMetadata>
+    {
+        xmp_property(xmp, "hdrgm:Version")?;
+
+        let scalar =
+            |name: &str| xmp_property(xmp, name).and_then(|x| x.trim().parse::<f32>().ok());
+
+        Some(GainMapMetadata {
+            gain_map_min:          xmp_channels(xmp, "hdrgm:GainMapMin").unwrap_or([0.0; 3]),
+            gain_map_max:          xmp_channels(xmp, "hdrgm:GainMapMax")?,
+            gamma:                 xmp_channels(xmp, "hdrgm:Gamma").unwrap_or([1.0; 3]),
+            offset_sdr:            xmp_channels(xmp, "hdrgm:OffsetSDR").unwrap_or([1.0 / 64.0; 3]),
+            offset_hdr:            xmp_channels(xmp, "hdrgm:OffsetHDR").unwrap_or([1.0 / 64.0; 3]),
+            hdr_capacity_min:      scalar("hdrgm:HDRCapacityMin").unwrap_or(0.0),
+            hdr_capacity_max:      scalar("hdrgm:HDRCapacityMax")?,
+            base_rendition_is_hdr: xmp_property(xmp, "hdrgm:BaseRenditionIsHDR")
+                .is_some_and(|x| x.eq_ignore_ascii_case("true")),
+        })
+    }
+
+    /// Gain map metadata of an image whose headers `decoder` read, ISO 21496-1
+    /// metadata if it has some, otherwise `hdrgm:` XMP properties
+    pub(crate) fn from_headers(decoder: &Decoder) -> Option<GainMapMetadata>
+    {
+        let iso = decoder.info.app_segments.iter().find_map(|x| {
+            (x.marker == 2)
+                .then(|| x.data.strip_prefix(ISO_SIGNATURE))
+                .flatten()
+                .and_then(GainMapMetadata::from_iso)
+        });
+
+        iso.or_else(|| GainMapMetadata::from_xmp(&decoder.xmp()?))
+    }
+
+    /// How much of the gain to apply on a display with `display_boost`,
+    /// between 0 and 1
+    fn weight(&self, display_boost: f32) -> f32
+    {
+        let range = self.hdr_capacity_max - self.hdr_capacity_min;
+
+        if range <= 0.0
+        {
+            return if display_boost.log2() >= self.hdr_capacity_max
+            {
+                1.0
+            }
+            else
+            {
+                0.0
+            };
+        }
+        ((display_boost.log2() - self.hdr_capacity_min) / range).clamp(0.0, 1.0)
+    }
+}
+
+/// Values of a property with a value per channel, written as a single value
+/// or as a sequence of one or three values
+fn xmp_channels(xmp: &str, name: &str) -> Option<[f32; 3]>
+{
+    if let Some(value) = xmp_property(xmp, name).and_then(|x| x.trim().parse().ok())
+    {
+        return Some([value; 3]);
+    }
+    let start = xmp.find(&format!("<{name}>"))?;
+    let end = start + xmp[start..].find(&format!("</{name}>"))?;
+
+    let values: Vec<f32> = xmp[start..end]
+        .split("<rdf:li>")
+        .skip(1)
+        .filter_map(|x| x.split('<').next()?.trim().parse().ok())
+        .collect();
+
+    match values[..]
+    {
+        [value] => Some([value; 3]),
+        [red, green, blue] => Some([red, green, blue]),
+        _ => None,
+    }
+}
+
+impl Decoder
+{
+    /// Find the gain map of an UltraHDR or ISO 21496-1 image
+    ///
+    /// The gain map is the first image of the MP index after the primary one
+    /// with gain map metadata, as ISO 21496-1 metadata or `hdrgm:` XMP
+    /// properties.
+    ///
+    /// This reads the headers of `buf`, afterwards the decoder describes it.
+    ///
+    /// Returns `Ok(None)` if the image has no gain map.
+    ///
+    /// # Errors
+    /// If the headers of the image can't be read.
+    pub fn gain_map(&mut self, buf: &[u8]) -> Result<Option<GainMap>, DecodeErrors>
+    {
+        self.read_headers(buf)?;
+
+        let Some(mpf) = &self.mpf
+        else
+        {
+            return Ok(None);
+        };
+
+        for index in 1..mpf.images().len()
+        {
+            let Some(data) = mpf.image_data(buf, index)
+            else
+            {
+                warn!("Image {index} of the MP index is out of bounds");
+                continue;
+            };
+            let mut decoder = Decoder::new();
+
+            if decoder.read_headers(data).is_err()
+            {
+                warn!("Can't read headers of image {index} of the MP index");
+                continue;
+            }
+
+            if let Some(metadata) = GainMapMetadata::from_headers(&decoder)
+            {
+                return Ok(Some(GainMap {
+                    metadata,
+                    data: data.to_vec(),
+                }));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Decode an image with a gain map to linear HDR RGB, for a display
+    /// `display_boost` times brighter than SDR white
+    ///
+    /// The SDR image is decoded with the options of the decoder, as RGB, and
+    /// turned linear with the sRGB transfer function, the gain map is applied to
+    /// it as much as the display boost allows. 1.0 is SDR white, a display boost
+    /// of 1 gives the SDR image back.
+    ///
+    /// Afterwards the decoder describes the SDR image.
+    ///
+    /// # Errors
+    /// If the image has no gain map, if its gain map makes SDR from an HDR
+    /// image, or if either image can't be decoded.
+    pub fn decode_hdr(&mut self, buf: &[u8], display_boost: f32) -> Result<Vec<f32>, DecodeErrors>
+    {
+        let gain_map = self
+            .gain_map(buf)?
+            .ok_or(DecodeErrors::FormatStatic("Image has no gain map"))?;
+        let metadata = gain_map.metadata;
+
+        if metadata.base_rendition_is_hdr
+        {
+            return Err(DecodeErrors::FormatStatic(
+                "Gain maps making SDR from an HDR base image are not supported",
+            ));
+        }
+
+        // the gain map, one or three channels
+        let options = self
+            .options
+            .set_apply_orientation(false)
+            .set_icc_to_srgb(false);
+        let mut decoder = Decoder::new_with_options(options);
+
+        decoder.read_headers(&gain_map.data)?;
+
+        let colorspace = if decoder.info.components == 1
+        {
+            ColorSpace::GRAYSCALE
+        }
+        else
+        {
+            ColorSpace::RGB
+        };
+        let mut decoder = Decoder::new_with_options(options.set_out_colorspace(colorspace));
+
+        let gains = decoder.decode_buffer(&gain_map.data)?;
+        let gain_width = usize::from(decoder.info.width);
+        let gain_height = usize::from(decoder.info.height);
+
+        // the SDR image, oriented with the HDR one at the end
+        let user_options = self.options;
+
+        self.options = user_options
+            .set_out_colorspace(ColorSpace::RGB)
+            .set_apply_orientation(false);
+        let sdr = self.decode_buffer(buf);
+        // grayscale images are decoded as such whatever the options
+        let sdr_channels = self.get_output_colorspace().num_components();
+        self.options = user_options;
+
+        let sdr = sdr?;
+        let width = usize::from(self.info.width);
+        let height = usize::from(self.info.height);
+
+        let linear = srgb_to_linear_table();
+        let tables = gain_tables(&metadata, metadata.weight(display_boost));
+        let channels = colorspace.num_components();
+
+        let mut hdr = vec![0.0; width * height * 3];
+
+        for (y, row) in hdr.chunks_exact_mut(width * 3).enumerate()
+        {
+            let (y0, y1, fy) = sample_position(y, height, gain_height);
+
+            for (x, pixel) in row.chunks_exact_mut(3).enumerate()
+            {
+                let (x0, x1, fx) = sample_position(x, width, gain_width);
+
+                for (channel, value) in pixel.iter_mut().enumerate()
+                {
+                    let gain_channel = channel.min(channels - 1);
+                    let sample = |gx: usize, gy: usize| {
+                        f32::from(gains[(gy * gain_width + gx) * channels + gain_channel])
+                    };
+                    // bilinear interpolation between the four nearest samples
+                    let top = sample(x0, y0) * (1.0 - fx) + sample(x1, y0) * fx;
+                    let bottom = sample(x0, y1) * (1.0 - fx) + sample(x1, y1) * fx;
+                    let gain = (top * (1.0 - fy) + bottom * fy) / 255.0;
+
+                    #[allow(
+                        clippy::cast_possible_truncation,
+                        clippy::cast_sign_loss,
+                        clippy::cast_precision_loss
+                    )]
+                    let step = (gain * (GAIN_STEPS - 1) as f32).round() as usize;
+                    let sdr = sdr[(y * width + x) * sdr_channels + channel.min(sdr_channels - 1)];
+                    let sdr = linear[usize::from(sdr)];
+
+                    *value = ((sdr + metadata.offset_sdr[channel]) * tables[channel][step]
+                        - metadata.offset_hdr[channel])
+                        .max(0.0);
+                }
+            }
+        }
+
+        Ok(self.orient(hdr))
+    }
+
+    /// Decode an image with a gain map to linear HDR RGB as half floats
+    ///
+    /// This is `decode_hdr` with each value converted to an IEEE 754 half
+    /// precision float, returned as its bits.
+    ///
+    /// # Errors
+    /// See `decode_hdr`
+    pub fn decode_hdr_f16(
+        &mut self, buf: &[u8], display_boost: f32,
+    ) -> Result<Vec<u16>, DecodeErrors>
+    {
+        let hdr = self.decode_hdr(buf, display_boost)?;
+
+        Ok(hdr.into_iter().map(f32_to_f16).collect())
+    }
+}
+
+/// Gain map samples around output position `position` of `size`, for a
+/// gain map `gain_size` samples long, and the weight of the second one
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss,
+    clippy::cast_precision_loss
+)]
+fn sample_position(position: usize, size: usize, gain_size: usize) -> (usize, usize, f32)
+{
+    // centers of pixels line up
+    let position = ((position as f32 + 0.5) * gain_size as f32 / size as f32 - 0.5)
+        .clamp(0.0, (gain_size - 1) as f32);
+    let first = position.floor() as usize;
+
+    (
+        first,
+        (first + 1).min(gain_size - 1),
+        position - first as f32,
+    )
+}
+
+/// Linear values of 8 bit sRGB values
+fn srgb_to_linear_table() -> [f32; 256]
+{
+    let mut table = [0.0; 256];
+
+    for (i, value) in (0..=255_u8).zip(table.iter_mut())
+    {
+        let v = f32::from(i) / 255.0;
+
+        *value = if v <= 0.04045
+        {
+            v / 12.92
+        }
+        else
+        {
+            ((v + 0.055) / 1.055).powf(2.4)
+        };
+    }
+
+    table
+}
+
+/// Gain factors of each channel for `GAIN_STEPS` gain map samples between 0
+/// and 1, with `weight` of the gain applied
+#[allow(clippy::cast_precision_loss)]
+fn gain_tables(metadata: &GainMapMetadata, weight: f32) -> [Vec<f32>; 3]
+{
+    [0, 1, 2].map(|channel| {
+        (0..GAIN_STEPS)
+            .map(|step| {
+                let mut gain = step as f32 / (GAIN_STEPS - 1) as f32;
+
+                if metadata.gamma[channel] > 0.0
+                {
+                    gain = gain.powf(1.0 / metadata.gamma[channel]);
+                }
+                let log_gain = metadata.gain_map_min[channel] * (1.0 - gain)
+                    + metadata.gain_map_max[channel] * gain;
+
+                (log_gain * weight).exp2()
+            })
+            .collect()
+    })
+}
+
+/// Bits of the IEEE 754 half precision float nearest to `value`
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_possible_wrap,
+    clippy::cast_sign_loss
+)]
+fn f32_to_f16(value: f32) -> u16
+{
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xFF) as i32;
+    let mantissa = bits & 0x7F_FFFF;
+
+    // infinities and NaNs
+    if exponent == 0xFF
+    {
+        return sign | 0x7C00 | if mantissa == 0 { 0 } else { 0x200 };
+    }
+    let exponent = exponent - 127 + 15;
+
+    if exponent >= 0x1F
+    {
+        return sign | 0x7C00;
+    }
+    // rounds to nearest, ties to even, dropping `shift` bits of `mantissa`
+    let round = |mantissa: u32, shift: u32| {
+        let kept = mantissa >> shift;
+        let rest = mantissa & ((1 << shift) - 1);
+        let half = 1 << (shift - 1);
+
+        kept + u32::from(rest > half || (rest == half && kept & 1 == 1))
+    };
+
+    if exponent <= 0
+    {
+        // subnormal, or too small for one
+        if exponent < -10
+        {
+            return sign;
+        }
+        return sign | round(mantissa | 0x80_0000, (14 - exponent) as u32) as u16;
+    }
+    // rounding up can carry into the exponent, up to infinity, which is right
+    sign | round(((exponent as u32) << 23) | mantissa, 13) as u16
+}
@@ -7,11 +7,16 @@ use std::cmp::max;
 use std::io::{BufRead, Read};
 
 use crate::components::Components;
-use crate::decoder::{Decoder, ImageInfo, MAX_COMPONENTS};
+use crate::decoder::{AdobeInfo, AppSegment, Decoder, JfifThumbnail, MAX_COMPONENTS};
 use crate::errors::DecodeErrors;
+use crate::exif::Exif;
+use crate::hierarchical::Hierarchy;
 use crate::huffman::HuffmanTable;
-use crate::marker::Marker;
 use crate::misc::{read_byte, read_u16_be, Aligned32, ColorSpace, SOFMarkers, UN_ZIGZAG};
+use crate::mpf::{Mpf, MPF_SIGNATURE};
+use crate::photoshop::PHOTOSHOP_SIGNATURE;
+use crate::reader::ByteReader;
+use crate::xmp::{ExtendedXmpChunk, EXTENDED_XMP_SIGNATURE, XMP_SIGNATURE};
 
 ///**B.2.4.2 Huffman table-specification syntax**
 #[allow(clippy::similar_names)]
@@ -86,6 +91,9 @@ where
             .map_err(|x| {
                 DecodeErrors::Format(format!("Could not read symbols into the buffer\n{}", x))
             })?;
+        // lossless images use an extra DC symbol, tables may also come before the
+        // frame header, in which case we can't tell what the image is yet.
+        let is_lossless = decoder.is_lossless || decoder.components.is_empty();
         // store
         match dc_or_ac
         {
@@ -96,6 +104,7 @@ where
                     symbols,
                     true,
                     decoder.is_progressive,
+                    is_lossless,
                 )?);
             }
             _ =>
@@ -105,6 +114,7 @@ where
                     symbols,
                     false,
                     decoder.is_progressive,
+                    is_lossless,
                 )?);
             }
         }
@@ -120,6 +130,80 @@ where
     Ok(())
 }
 
+///**B.2.4.3 Arithmetic conditioning table-specification syntax**
+pub(crate) fn parse_dac<R>(decoder: &mut Decoder, buf: &mut R) -> Result<(), DecodeErrors>
+where
+    R: Read,
+{
+    let mut length = read_u16_be(buf)?
+        .checked_sub(2)
+        .ok_or(DecodeErrors::FormatStatic("Invalid DAC length in image"))?;
+
+    if length % 2 != 0
+    {
+        return Err(DecodeErrors::Format(format!(
+            "Invalid DAC length {length}, expected a multiple of two"
+        )));
+    }
+    let conditioning = &mut decoder.conditioning;
+
+    while length > 0
+    {
+        // top 4 bits contain the table class, lower 4 bits the destination
+        let table_info = read_byte(buf)?;
+        let value = read_byte(buf)?;
+
+        let class = table_info >> 4;
+        let index = usize::from(table_info & 0xF);
+
+        if index >= MAX_COMPONENTS
+        {
+            return Err(DecodeErrors::Format(format!(
+                "Invalid DAC index {index}, expected between 0 and 3"
+            )));
+        }
+
+        match class
+        {
+            0 =>
+            {
+                // DC conditioning, lower bits contain L, upper bits contain U
+                let lower = value & 0xF;
+                let upper = value >> 4;
+
+                if lower > upper
+                {
+                    return Err(DecodeErrors::Format(format!(
+                        "Invalid DC conditioning value, L({lower}) greater than U({upper})"
+                    )));
+                }
+                conditioning.dc_lower[index] = lower;
+                conditioning.dc_upper[index] = upper;
+            }
+            1 =>
+            {
+                // AC conditioning, Kx
+                if !(1..=63).contains(&value)
+                {
+                    return Err(DecodeErrors::Format(format!(
+                        "Invalid AC conditioning value {value}, expected between 1 and 63"
+                    )));
+                }
+                conditioning.ac_k[index] = value;
+            }
+            _ =>
+            {
+                return Err(DecodeErrors::Format(format!(
+                    "Invalid DAC table class {class}, should be 0 or 1"
+                )));
+            }
+        }
+        length -= 2;
+    }
+
+    Ok(())
+}
+
 ///**B.2.4.1 Quantization table-specification syntax**
 #[allow(clippy::cast_possible_truncation)]
 pub(crate) fn parse_dqt<R>(decoder: &mut Decoder, buf: &mut R) -> Result<(), DecodeErrors>
@@ -166,11 +250,20 @@ where
             }
             1 =>
             {
-                // 16 bit quantization tables
-                //(cae) Before we enable this. Should 16 bit QT cause any other lib changes
-                return Err(DecodeErrors::DqtError(
-                    "Support for 16 bit quantization table is not complete".to_string(),
-                ));
+                // 16 bit quantization tables, values are stored big endian
+                let mut qt_values = [0; 128];
+
+                buf.read_exact(&mut qt_values).map_err(|x| {
+                    DecodeErrors::Format(format!("Could not read symbols into the buffer\n{x}"))
+                })?;
+                qt_length -= (precision_value as u16) + 1 /*QT BIT*/;
+
+                let qt_values: Vec<u16> = qt_values
+                    .chunks_exact(2)
+                    .map(|x| u16::from_be_bytes([x[0], x[1]]))
+                    .collect();
+
+                un_zig_zag(&qt_values)
             }
             _ =>
             {
@@ -196,7 +289,7 @@ where
 }
 
 /// Section:`B.2.2 Frame header syntax`
-
+#[allow(clippy::too_many_lines)]
 pub(crate) fn parse_start_of_frame<R>(
     buf: &mut R, sof: SOFMarkers, img: &mut Decoder,
 ) -> Result<(), DecodeErrors>
@@ -207,19 +300,33 @@ where
     let length = read_u16_be(buf)
         .map_err(|_| DecodeErrors::Format("Cannot read SOF length, exhausted data".to_string()))?;
 
-    // usually 8, but can be 12 and 16, we currently support only 8
-    // so sorry about that 12 bit images
+    // usually 8, extended sequential and progressive images can also have 12 bits of precision
+    // while lossless images can have anything between 2 and 16 bits
     let dt_precision = read_byte(buf)?;
 
-    if dt_precision != 8
+    let supported = match sof
+    {
+        SOFMarkers::ExtendedSequentialHuffman
+        | SOFMarkers::ExtendedSequentialDctArithmetic
+        | SOFMarkers::ProgressiveDctHuffman
+        | SOFMarkers::ProgressiveDctArithmetic
+        | SOFMarkers::DifferentialSequentialHuffman
+        | SOFMarkers::DifferentialProgressiveHuffman => matches!(dt_precision, 8 | 12),
+        SOFMarkers::LosslessHuffman | SOFMarkers::DifferentialLosslessHuffman =>
+        {
+            (2..=16).contains(&dt_precision)
+        }
+        _ => dt_precision == 8,
+    };
+
+    if !supported
     {
         return Err(DecodeErrors::SofError(format!(
-            "The library can only parse 8-bit images, the image has {} bits of precision",
-            dt_precision
+            "Unsupported precision for {sof:?} image, the image has {dt_precision} bits of precision"
         )));
     }
 
-    img.info.set_density(dt_precision);
+    img.info.set_precision(dt_precision);
 
     // read  and set the image height.
     let img_height = read_u16_be(buf).map_err(|_| {
@@ -247,8 +354,9 @@ where
         return Err(DecodeErrors::Format(format!("Image height {} greater than height limit {}. If use `set_limits` if you want to support huge images", img_height, img.options.get_max_height())));
     }
 
-    // Check image width or height is zero
-    if img_width == 0 || img_height == 0
+    // Check image width or height is zero, the height may be given by a DNL segment
+    // after the first scan, but not in hierarchical images
+    if img_width == 0 || (img_height == 0 && img.hierarchy.is_some())
     {
         return Err(DecodeErrors::ZeroError);
     }
@@ -275,13 +383,25 @@ where
 
     info!("Image components : {}", num_components);
 
+    if usize::from(num_components) > MAX_COMPONENTS
+    {
+        return Err(DecodeErrors::SofError(format!(
+            "Number of components {num_components} is more than the supported {MAX_COMPONENTS}"
+        )));
+    }
+
     if num_components == 1
     {
         // SOF sets the number of image components
         // and that to us translates to setting input and output
         // colorspaces to zero
-        img.input_colorspace = ColorSpace::GRAYSCALE;
-        img.options = img.options.set_out_colorspace(ColorSpace::GRAYSCALE);
+
+        // frames of hierarchical images may code a subset of the image components,
+        // the output colorspace was already chosen from the DHP marker
+        if img.hierarchy.is_none()
+        {
+            img.options = img.options.set_out_colorspace(ColorSpace::GRAYSCALE);
+        }
     }
 
     // set number of components
@@ -290,38 +410,62 @@ where
     let mut components = Vec::with_capacity(num_components as usize);
     let mut temp = [0; 3];
 
-    for _ in 0..num_components
+    for position in 0..usize::from(num_components)
     {
         // read 3 bytes for each component
         buf.read_exact(&mut temp)
             .map_err(|x| DecodeErrors::Format(format!("Could not read component data\n{}", x)))?;
         // create a component.
-        let component = Components::from(temp)?;
+        let mut component = Components::from(temp, position)?;
+
+        if components.iter().any(|c: &Components| c.id == component.id)
+        {
+            // Some broken encoders give every component the same id, like libjpeg-turbo
+            // we rename repeated ones to one more than the largest id so far, and scans
+            // referring to them do the same.
+            component.id = unique_id(components.iter().map(|c| c.id))?;
+        }
 
         components.push(component);
     }
 
     img.info.set_sof_marker(sof);
 
-    for component in &mut components
+    // a hierarchical image has multiple frames, don't carry over
+    // sampling information from the previous one
+    img.h_max = 1;
+    img.v_max = 1;
+    img.interleaved = false;
+
+    for component in &components
     {
         // compute interleaved image info
         // h_max contains the maximum horizontal component
         img.h_max = max(img.h_max, component.horizontal_sample);
         // v_max contains the maximum vertical component
         img.v_max = max(img.v_max, component.vertical_sample);
-        img.mcu_width = img.h_max * 8;
-        img.mcu_height = img.v_max * 8;
-        // Number of MCU's per width
-        img.mcu_x = (usize::from(img.info.width) + img.mcu_width - 1) / img.mcu_width;
-        // Number of MCU's per height
-        img.mcu_y = (usize::from(img.info.height) + img.mcu_height - 1) / img.mcu_height;
-
-        if img.h_max != 1 || img.v_max != 1
-        {
-            // interleaved images have horizontal and vertical sampling factors
-            // not equal to 1.
-            img.interleaved = true;
+    }
+
+    img.mcu_width = img.h_max * 8;
+    img.mcu_height = img.v_max * 8;
+    // Number of MCU's per width
+    img.mcu_x = (usize::from(img.info.width) + img.mcu_width - 1) / img.mcu_width;
+    // Number of MCU's per height
+    img.mcu_y = (usize::from(img.info.height) + img.mcu_height - 1) / img.mcu_height;
+
+    if img.h_max != 1 || img.v_max != 1
+    {
+        // interleaved images have horizontal and vertical sampling factors
+        // not equal to 1.
+        img.interleaved = true;
+    }
+
+    for component in &mut components
+    {
+        if sof.is_lossless()
+        {
+            // lossless images aren't quantized, so they have no quantization tables
+            continue;
         }
         // Extract quantization tables from the arrays into components
         let qt_table = *img.qt_tables[component.quantization_table_number as usize]
@@ -339,14 +483,199 @@ where
     }
 
     // delete quantization tables, we'll extract them from the components when
-    // needed
-    img.qt_tables = [None, None, None, None];
+    // needed, later frames of hierarchical images may still use them
+    if img.hierarchy.is_none()
+    {
+        img.qt_tables = [None, None, None, None];
+    }
     img.components = components;
+    img.input_colorspace = img.detect_colorspace(&img.components);
+
+    Ok(())
+}
+
+/// An id one larger than the largest of `ids`, used in place of repeated component ids
+fn unique_id(ids: impl Iterator<Item = u8>) -> Result<u8, DecodeErrors>
+{
+    ids.max()
+        .unwrap_or(0)
+        .checked_add(1)
+        .ok_or(DecodeErrors::FormatStatic(
+            "Repeated component id, corrupt jpeg",
+        ))
+}
+
+/// Section: `B.3.2 Define hierarchical progression segment syntax`
+///
+/// The DHP segment has the syntax of a frame header, it describes the image the
+/// frames of a hierarchical image build up to.
+pub(crate) fn parse_dhp<R>(buf: &mut R, img: &mut Decoder) -> Result<(), DecodeErrors>
+where
+    R: Read,
+{
+    if img.hierarchy.is_some() || !img.components.is_empty()
+    {
+        return Err(DecodeErrors::FormatStatic(
+            "DHP marker should appear once, before any frame",
+        ));
+    }
+    // components of the segment have no quantization tables, which lossless frames
+    // don't have either, so parse it as one.
+    parse_start_of_frame(buf, SOFMarkers::DifferentialLosslessHuffman, img)?;
+
+    if img.info.height == 0
+    {
+        return Err(DecodeErrors::ZeroError);
+    }
+
+    img.hierarchy = Some(Hierarchy {
+        width:      img.info.width,
+        height:     img.info.height,
+        precision:  img.info.precision,
+        components: std::mem::take(&mut img.components),
+        h_max:      img.h_max,
+        v_max:      img.v_max,
+        expand:     (false, false),
+    });
+
+    Ok(())
+}
+
+/// Section: `B.2.5 Define number of lines syntax`
+///
+/// Images whose frame header has a height of zero give it in a DNL segment
+/// after the first scan.
+pub(crate) fn parse_dnl<R>(buf: &mut R, img: &mut Decoder) -> Result<(), DecodeErrors>
+where
+    R: Read,
+{
+    if read_u16_be(buf)? != 4
+    {
+        return Err(DecodeErrors::FormatStatic("Bad DNL length, Corrupt JPEG"));
+    }
+
+    let lines = read_u16_be(buf)?;
+
+    if lines == 0
+    {
+        return Err(DecodeErrors::ZeroError);
+    }
+
+    if img.info.height != 0
+    {
+        if lines != img.info.height
+        {
+            return Err(DecodeErrors::Format(format!(
+                "DNL marker gives {lines} lines but the image height is {}",
+                img.info.height
+            )));
+        }
+        return Ok(());
+    }
+
+    if lines > img.options.get_max_height()
+    {
+        return Err(DecodeErrors::Format(format!("Image height {} greater than height limit {}. If use `set_limits` if you want to support huge images", lines, img.options.get_max_height())));
+    }
+
+    info!("Image height from DNL marker :{}", lines);
+
+    img.info.set_height(lines);
+    img.mcu_y = usize::from(lines).div_ceil(img.mcu_height);
+
+    Ok(())
+}
+
+/// Find the marker ending the first scan of an image, e.g. a DNL marker
+///
+/// `data` starts anywhere in the entropy coded data of the scan, returns the
+/// position of the marker if `data` has all of it.
+pub(crate) fn find_scan_end(data: &[u8]) -> Option<usize>
+{
+    let mut position = 0;
+
+    while position + 1 < data.len()
+    {
+        // stuffed zeros, fill bytes and restart markers are part of the scan
+        if data[position] == 0xFF && !matches!(data[position + 1], 0x00 | 0xFF | 0xD0..=0xD7)
+        {
+            return Some(position);
+        }
+        position += 1;
+    }
+    None
+}
+
+/// Find the EOI marker ending an image, once its last scan has been decoded
+///
+/// The entropy decoders stop right after the marker ending a scan, or before
+/// it if they had all the data they needed, so `buf` is either just past the
+/// EOI marker or somewhere in the last scan. Segments between the scan and EOI
+/// (e.g. comments) are skipped.
+///
+/// Returns the offset of the marker.
+pub(crate) fn find_eoi(buf: &mut ByteReader) -> Option<usize>
+{
+    if buf.previous() == Some([0xFF, 0xD9])
+    {
+        return Some(buf.position() - 2);
+    }
+
+    loop
+    {
+        let [first, second] = buf.peek()?;
+
+        if first == 0xFF
+        {
+            match second
+            {
+                // stuffed zeros, fill bytes and restart markers are part of the scan
+                0x00 | 0xFF | 0xD0..=0xD7 => (),
+                0xD9 => return Some(buf.position()),
+                _ =>
+                {
+                    let [.., high, low] = buf.peek::<4>()?;
+
+                    buf.skip(2 + usize::from(u16::from_be_bytes([high, low])));
+                    continue;
+                }
+            }
+        }
+        buf.skip(1);
+    }
+}
+
+/// Section: `B.3.3 Expand reference components segment syntax`
+pub(crate) fn parse_exp<R>(buf: &mut R, img: &mut Decoder) -> Result<(), DecodeErrors>
+where
+    R: Read,
+{
+    if read_u16_be(buf)? != 3
+    {
+        return Err(DecodeErrors::FormatStatic("Bad EXP length, Corrupt JPEG"));
+    }
+
+    let expand = read_byte(buf)?;
+    let (horizontal, vertical) = (expand >> 4, expand & 0xF);
+
+    if horizontal > 1 || vertical > 1
+    {
+        return Err(DecodeErrors::Format(format!(
+            "Invalid expansion factors {horizontal}, {vertical}, they should be 0 or 1"
+        )));
+    }
+
+    let hierarchy = img.hierarchy.as_mut().ok_or(DecodeErrors::FormatStatic(
+        "EXP marker found outside a hierarchical image",
+    ))?;
+
+    hierarchy.expand = (horizontal == 1, vertical == 1);
 
     Ok(())
 }
 
 /// Parse a start of scan data
+#[allow(clippy::too_many_lines)]
 pub(crate) fn parse_sos<R>(buf: &mut R, image: &mut Decoder) -> Result<(), DecodeErrors>
 where
     R: Read + BufRead,
@@ -369,10 +698,10 @@ where
 
     // Check number of components.
     // Currently ths library doesn't support images with more than 4 components
-    if !(1..4).contains(&ns)
+    if !(1..=4).contains(&ns)
     {
         return Err(DecodeErrors::SosError(format!(
-            "Number of components in start of scan should be less than 3 but more than 0. Found {}",
+            "Number of components in start of scan should be less than 5 but more than 0. Found {}",
             ns
         )));
     }
@@ -390,51 +719,69 @@ where
         // CS_i parameter, I don't need it so I might as well delete it
         let id = read_byte(buf)?;
 
-        if usize::from(id) > image.components.len()
+        // DC and AC huffman table position
+        // top 4 bits contain dc huffman destination table
+        // lower four bits contain ac huffman destination table
+        let y = read_byte(buf)?;
+
+        let id = if image.z_order[..usize::from(i)]
+            .iter()
+            .any(|k| image.components.get(*k).map(|c| c.id) == Some(id))
         {
-            return Err(DecodeErrors::SofError(format!(
-                "Too large component ID {}, expected value between 0 and {}",
-                id,
-                image.components.len()
-            )));
+            // see parse_start_of_frame on repeated ids
+            unique_id(
+                image.z_order[..usize::from(i)]
+                    .iter()
+                    .filter_map(|k| image.components.get(*k).map(|c| c.id)),
+            )?
         }
-        if seen[usize::from(id)]
+        else
+        {
+            id
+        };
+
+        // components are matched by their id, the frame header may contain fewer
+        // components than the image claims if it's corrupt
+        let j = image
+            .components
+            .iter()
+            .position(|c| c.id == id)
+            .ok_or_else(|| {
+                DecodeErrors::SofError(format!(
+                    "Invalid component id {}, expected a value between 0 and {}",
+                    id,
+                    image.components.len()
+                ))
+            })?;
+
+        if seen[j]
         {
             return Err(DecodeErrors::SofError(format!(
                 "Duplicate ID {} seen twice in the same component",
                 id
             )));
         }
-        seen[usize::from(id)] = true;
-        // DC and AC huffman table position
-        // top 4 bits contain dc huffman destination table
-        // lower four bits contain ac huffman destination table
-        let y = read_byte(buf)?;
+        seen[j] = true;
 
-        let mut j = 0;
-
-        while j < image.info.components
-        {
-            if image.components[j as usize].id == id
-            {
-                break;
-            }
+        image.components[j].dc_huff_table = usize::from((y >> 4) & 0xF);
+        image.components[j].ac_huff_table = usize::from(y & 0xF);
+        image.z_order[i as usize] = j;
+    }
 
-            j += 1;
-        }
+    if ns > 1
+    {
+        // B.2.3, an MCU of an interleaved scan may hold at most 10 blocks
+        let blocks: usize = image.z_order[..usize::from(ns)]
+            .iter()
+            .map(|k| image.components[*k].horizontal_sample * image.components[*k].vertical_sample)
+            .sum();
 
-        if j == image.info.components
+        if blocks > 10
         {
-            return Err(DecodeErrors::SofError(format!(
-                "Invalid component id {}, expected a value between 0 and {}",
-                id,
-                image.components.len()
+            return Err(DecodeErrors::SosError(format!(
+                "Too many blocks in an MCU of an interleaved scan, found {blocks}, expected at most 10"
             )));
         }
-
-        image.components[usize::from(j)].dc_huff_table = usize::from((y >> 4) & 0xF);
-        image.components[usize::from(j)].ac_huff_table = usize::from(y & 0xF);
-        image.z_order[i as usize] = j as usize;
     }
 
     // Collect the component spec parameters
@@ -452,6 +799,11 @@ where
     image.spec_end = read_byte(buf)? & 63;
 
     let bit_approx = read_byte(buf)?;
+
+    if image.is_lossless
+    {
+        return check_lossless_scan(image, bit_approx);
+    }
     // successive approximation bit position high
     image.succ_high = bit_approx >> 4;
 
@@ -476,67 +828,351 @@ where
     Ok(())
 }
 
-pub(crate) fn _parse_app<R>(
-    buf: &mut R, marker: Marker, _info: &mut ImageInfo,
+/// Validate the scan parameters of a lossless image
+///
+/// Lossless scans reuse the fields of the scan header, `Ss` selects the predictor,
+/// `Se` and `Ah` should be zero and `Al` holds the point transform.
+fn check_lossless_scan(image: &mut Decoder, bit_approx: u8) -> Result<(), DecodeErrors>
+{
+    if image.info.sof.is_differential()
+    {
+        // differential frames code differences from a reference, see Table H.1
+        if image.spec_start != 0
+        {
+            return Err(DecodeErrors::SosError(format!(
+                "Invalid predictor {} for a differential frame, it should be 0",
+                image.spec_start
+            )));
+        }
+    }
+    else if !(1..=7).contains(&image.spec_start)
+    {
+        return Err(DecodeErrors::SosError(format!(
+            "Invalid predictor {}, range should be 1-7",
+            image.spec_start
+        )));
+    }
+
+    if image.spec_end != 0 || bit_approx >> 4 != 0
+    {
+        warn!("Se and Ah should be zero for lossless images, ignoring them");
+    }
+
+    image.succ_high = 0;
+    // point transform
+    image.succ_low = bit_approx & 0xF;
+
+    if image.succ_low >= image.info.precision
+    {
+        return Err(DecodeErrors::SosError(format!(
+            "Invalid point transform {}, it should be less than the precision {}",
+            image.succ_low, image.info.precision
+        )));
+    }
+
+    Ok(())
+}
+
+/// Parse an APPn segment
+///
+/// Segments we understand are handed to the parser for their marker, the
+/// others are kept in `ImageInfo::app_segments` along with the `offset` of
+/// their marker.
+pub(crate) fn parse_app<R>(
+    buf: &mut R, n: u8, offset: usize, img: &mut Decoder,
 ) -> Result<(), DecodeErrors>
 where
     R: BufRead + Read,
 {
-    let length = read_u16_be(buf)?
-        .checked_sub(2)
-        .ok_or(DecodeErrors::Format(
-            "Invalid APP0 length. Length should be greater than 2".to_string(),
-        ))?;
+    let length = usize::from(read_u16_be(buf)?.checked_sub(2).ok_or_else(|| {
+        DecodeErrors::Format(format!(
+            "Invalid APP{n} length. Length should be greater than 2"
+        ))
+    })?);
+
+    let mut segment = vec![0; length];
 
-    let mut bytes_read = 0;
+    buf.read_exact(&mut segment)
+        .map_err(|x| DecodeErrors::Format(format!("Could not read APP{n} data\n{x}")))?;
 
-    match marker
+    let understood = match n
     {
-        Marker::APP(0) =>
+        0 => parse_app0(&segment, img),
+        1 => parse_app1(&segment, img),
+        2 => parse_app2(&segment, offset, img),
+        13 => parse_app13(&segment, img),
+        14 => parse_app14(&segment, img),
+        _ => false,
+    };
+
+    if !understood
+    {
+        info!("Keeping APP{n} segment of {length} bytes at {offset}");
+
+        img.info.app_segments.push(AppSegment {
+            marker: n,
+            offset,
+            data: segment,
+        });
+    }
+
+    Ok(())
+}
+
+/// Parse a COM segment, keeping the comment in `ImageInfo::comments`
+pub(crate) fn parse_com<R>(buf: &mut R, img: &mut Decoder) -> Result<(), DecodeErrors>
+where
+    R: BufRead + Read,
+{
+    let length = usize::from(read_u16_be(buf)?.checked_sub(2).ok_or(
+        DecodeErrors::FormatStatic("Invalid COM length. Length should be greater than 2"),
+    )?);
+
+    let mut comment = vec![0; length];
+
+    buf.read_exact(&mut comment)
+        .map_err(|x| DecodeErrors::Format(format!("Could not read comment\n{x}")))?;
+
+    img.info.comments.push(comment);
+
+    Ok(())
+}
+
+/// Parse an APP13 segment, keeping Photoshop image resources
+///
+/// Resources can continue in the next segment, the data of all segments is
+/// kept together and parsed when asked for.
+///
+/// Returns false if the segment isn't Photoshop's.
+fn parse_app13(segment: &[u8], img: &mut Decoder) -> bool
+{
+    match segment.strip_prefix(PHOTOSHOP_SIGNATURE)
+    {
+        Some(resources) =>
+        {
+            img.photoshop.extend_from_slice(resources);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Parse Adobe's APP14 segment
+///
+/// The transform flag tells whether a three component image is RGB or YCbCr and
+/// whether a four component one is CMYK or YCCK.
+///
+/// Returns false if the segment isn't Adobe's.
+fn parse_app14(segment: &[u8], img: &mut Decoder) -> bool
+{
+    // "Adobe", version, flags0, flags1 and the transform
+    if segment.len() < 12 || &segment[..5] != b"Adobe"
+    {
+        return false;
+    }
+    let read_u16 = |position: usize| u16::from_be_bytes([segment[position], segment[position + 1]]);
+
+    info!("Adobe color transform: {}", segment[11]);
+
+    img.adobe_transform = Some(segment[11]);
+    img.info.adobe = Some(AdobeInfo {
+        version:   read_u16(5),
+        flags0:    read_u16(7),
+        flags1:    read_u16(9),
+        transform: segment[11],
+    });
+
+    // the marker may come after the start of frame
+    img.input_colorspace = img.detect_colorspace(&img.components);
+
+    true
+}
+
+/// Parse an APP2 segment
+///
+/// Stores chunks of an embedded ICC profile, which are put together
+/// by `Decoder::icc_profile`, and the MP index of Multi-Picture files.
+///
+/// Returns false if the segment is neither a chunk of a profile nor MPF data.
+fn parse_app2(segment: &[u8], offset: usize, img: &mut Decoder) -> bool
+{
+    if let Some(tiff) = segment.strip_prefix(MPF_SIGNATURE)
+    {
+        // the marker, the length and the signature come before the TIFF header
+        match Mpf::parse(tiff, offset + 8)
         {
-            if length != 14
+            Some(_) if img.mpf.is_some() =>
             {
-                warn!("Incorrect length of APP0 ,{}, should be 14", length);
+                warn!("Image has more than one MP index, ignoring the others");
             }
-            // Don't handle APP0 as of now
-            buf.consume(length as usize);
+            Some(mpf) => img.mpf = Some(mpf),
+            None => info!("MPF segment without an index"),
         }
-        Marker::APP(1) =>
+        return true;
+    }
+    // "ICC_PROFILE\0", the sequence number and the number of chunks
+    if segment.len() < 14 || &segment[..12] != b"ICC_PROFILE\0"
+    {
+        return false;
+    }
+
+    info!("ICC profile chunk {} of {}", segment[12], segment[13]);
+
+    img.icc_chunks
+        .push((segment[12], segment[13], segment[14..].to_vec()));
+
+    true
+}
+
+/// Parse an APP0 segment
+///
+/// That's the JFIF segment, whose presence tells us the image is YCbCr and which
+/// gives the pixel density, and JFXX extension segments, which hold thumbnails.
+///
+/// Returns false if the segment is neither.
+fn parse_app0(segment: &[u8], img: &mut Decoder) -> bool
+{
+    if let Some(jfif) = segment.strip_prefix(b"JFIF\0")
+    {
+        img.jfif = true;
+        img.input_colorspace = img.detect_colorspace(&img.components);
+
+        // version, density units, x and y density and thumbnail dimensions
+        if jfif.len() < 9
         {
-            if length >= 6
+            warn!("JFIF segment is too short, ignoring its fields");
+            return true;
+        }
+        let info = &mut img.info;
+
+        info.jfif_version = Some((jfif[0], jfif[1]));
+        info.density_units = jfif[2];
+        info.set_x(u16::from_be_bytes([jfif[3], jfif[4]]));
+        info.set_y(u16::from_be_bytes([jfif[5], jfif[6]]));
+
+        let (width, height) = (jfif[7], jfif[8]);
+
+        if width != 0 && height != 0
+        {
+            match jfif.get(9..9 + usize::from(width) * usize::from(height) * 3)
             {
-                let mut buffer = [0_u8; 6];
+                Some(pixels) => info.jfif_thumbnails.push(JfifThumbnail::Rgb {
+                    width,
+                    height,
+                    pixels: pixels.to_vec(),
+                }),
+                None => warn!("JFIF thumbnail is truncated, ignoring it"),
+            }
+        }
 
-                buf.read_exact(&mut buffer).map_err(|x| {
-                    DecodeErrors::Format(format!("Could not read Exif data\n{}", x))
-                })?;
+        return true;
+    }
 
-                bytes_read += 6;
+    if let Some(jfxx) = segment.strip_prefix(b"JFXX\0")
+    {
+        let thumbnail = match jfxx
+        {
+            [0x10, jpeg @ ..] => Some(JfifThumbnail::Jpeg(jpeg.to_vec())),
+            [0x11, width, height, rest @ ..] =>
+            {
+                let count = usize::from(*width) * usize::from(*height);
+
+                rest.get(768..768 + count)
+                    .map(|indices| JfifThumbnail::Palette {
+                        width:   *width,
+                        height:  *height,
+                        palette: rest[..768].to_vec(),
+                        indices: indices.to_vec(),
+                    })
+            }
+            [0x13, width, height, rest @ ..] =>
+            {
+                let count = usize::from(*width) * usize::from(*height);
 
-                // https://web.archive.org/web/20190624045241if_/http://www.cipa.jp:80/std/documents/e/DC-008-Translation-2019-E.pdf
-                // 4.5.4 Basic Structure of Decoder Compressed Data
-                if &buffer == b"Exif\x00\x00"
-                {
-                    buf.consume(length as usize - bytes_read);
-                }
+                rest.get(..count * 3).map(|pixels| JfifThumbnail::Rgb {
+                    width:  *width,
+                    height: *height,
+                    pixels: pixels.to_vec(),
+                })
             }
+            _ => None,
+        };
+
+        match thumbnail
+        {
+            Some(thumbnail) => img.info.jfif_thumbnails.push(thumbnail),
+            None => warn!("Invalid or truncated JFXX thumbnail, ignoring it"),
         }
-        _ =>
-        {}
+
+        return true;
     }
 
-    Ok(())
+    false
+}
+
+/// Parse an APP1 segment
+///
+/// Keeps the EXIF metadata of the image, the first segment starting with
+/// `Exif\0\0` is used, and its XMP packets, which are put together by
+/// `Decoder::xmp` and `Decoder::extended_xmp`.
+///
+/// Returns false if the segment holds neither.
+fn parse_app1(segment: &[u8], img: &mut Decoder) -> bool
+{
+    // https://web.archive.org/web/20190624045241if_/http://www.cipa.jp:80/std/documents/e/DC-008-Translation-2019-E.pdf
+    // 4.5.4 Basic Structure of JPEG Compressed Data
+    if let Some(tiff) = segment.strip_prefix(b"Exif\0\0")
+    {
+        if img.exif.is_some()
+        {
+            warn!("Image has more than one EXIF segment, ignoring the others");
+        }
+        else
+        {
+            img.exif = Exif::parse(tiff.to_vec());
+
+            if img.exif.is_none()
+            {
+                warn!("Invalid EXIF header, ignoring EXIF data");
+            }
+        }
+    }
+    else if let Some(packet) = segment.strip_prefix(XMP_SIGNATURE)
+    {
+        if img.xmp.is_some()
+        {
+            warn!("Image has more than one XMP packet, ignoring the others");
+        }
+        else
+        {
+            img.xmp = Some(packet.to_vec());
+        }
+    }
+    else if let Some(chunk) = segment.strip_prefix(EXTENDED_XMP_SIGNATURE)
+    {
+        match ExtendedXmpChunk::parse(chunk)
+        {
+            Some(chunk) => img.extended_xmp_chunks.push(chunk),
+            None => warn!("Extended XMP chunk is too short, ignoring it"),
+        }
+    }
+    else
+    {
+        return false;
+    }
+
+    true
 }
 
 /// Small utility function to print Un-zig-zagged quantization tables
 
-fn un_zig_zag(a: &[u8]) -> [i32; 64]
+fn un_zig_zag<T: Copy + Into<i32>>(a: &[T]) -> [i32; 64]
 {
     let mut output = [0; 64];
 
     for i in 0..64
     {
-        output[UN_ZIGZAG[i]] = i32::from(a[i]);
+        output[UN_ZIGZAG[i]] = a[i].into();
     }
 
     output
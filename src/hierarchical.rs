@@ -0,0 +1,467 @@
+//! Routines for hierarchical decoding
+//!
+//! Hierarchical images (Annex J of the spec) are made up of a sequence of frames.
+//! The first frame is coded like any other image, usually at a lower resolution,
+//! and each following (differential) frame codes the difference between the image
+//! and a reference built from the frames before it. References may be up-sampled
+//! by two horizontally and/or vertically, as requested by an EXP marker,
+//! before a frame is added to them.
+//!
+//! The DHP marker describes the image all frames build up to, the last frame
+//! of each component holds that component at full resolution.
+//!
+//! We decode frames one at a time into component planes, which are then used as
+//! references for the next frame.
+
+use crate::components::Components;
+use crate::errors::DecodeErrors;
+use crate::marker::Marker;
+use crate::mcu_prog::get_marker;
+use crate::misc::ColorSpace;
+use crate::reader::ByteReader;
+use crate::worker::{
+    color_convert_u16, idct_component, interleave_u16, level_shift, upsample_component_u16,
+};
+use crate::Decoder;
+
+/// The image a hierarchical image builds up to, as described by its DHP marker
+#[derive(Clone)]
+pub(crate) struct Hierarchy
+{
+    pub(crate) width:      u16,
+    pub(crate) height:     u16,
+    pub(crate) precision:  u8,
+    /// Components of the image, frames refer to them by their ids
+    pub(crate) components: Vec<Components>,
+    pub(crate) h_max:      usize,
+    pub(crate) v_max:      usize,
+    /// Whether references of the next frame should be up-sampled
+    /// horizontally and vertically, set by the EXP marker
+    pub(crate) expand:     (bool, bool),
+}
+
+/// Samples of a single component
+struct Plane
+{
+    samples: Vec<u16>,
+    width:   usize,
+    height:  usize,
+}
+
+impl Decoder
+{
+    /// Decode a hierarchical image into 16 bit samples
+    ///
+    /// If the last frame is lossless, components are returned interleaved without
+    /// color conversion like `decode_lossless` does, otherwise they are converted
+    /// to the output colorspace like `decode_high_precision` does.
+    #[allow(clippy::cast_possible_truncation, clippy::too_many_lines)]
+    pub(crate) fn decode_hierarchical(
+        &mut self, reader: &mut ByteReader,
+    ) -> Result<Vec<u16>, DecodeErrors>
+    {
+        let hierarchy = self.hierarchy.clone().ok_or(DecodeErrors::FormatStatic(
+            "No DHP marker in hierarchical image",
+        ))?;
+
+        let mut references: Vec<Option<Plane>> =
+            hierarchy.components.iter().map(|_| None).collect();
+        let mut frames = 0;
+
+        loop
+        {
+            let differential = self.info.sof.is_differential();
+
+            if differential != (frames != 0)
+            {
+                return Err(DecodeErrors::FormatStatic(
+                    "Only the first frame of a hierarchical image should be non-differential",
+                ));
+            }
+            if self.info.precision != hierarchy.precision
+            {
+                return Err(DecodeErrors::Format(format!(
+                    "Frame precision {} differs from the hierarchical image precision {}",
+                    self.info.precision, hierarchy.precision
+                )));
+            }
+
+            frames += 1;
+
+            if frames > self.options.get_max_scans()
+            {
+                return Err(DecodeErrors::Format(format!(
+                    "Too many frames, exceeded limit of {}",
+                    self.options.get_max_scans()
+                )));
+            }
+
+            let (planes, marker) = self.decode_frame(reader, differential)?;
+
+            // expansion applies to references of this frame only
+            let (expand_h, expand_v) = self
+                .hierarchy
+                .as_mut()
+                .map_or((false, false), |h| std::mem::take(&mut h.expand));
+
+            for (component, frame) in self.components.iter().zip(planes)
+            {
+                let k = hierarchy
+                    .components
+                    .iter()
+                    .position(|c| c.id == component.id)
+                    .ok_or_else(|| {
+                        DecodeErrors::Format(format!(
+                            "Frame component {} is not in the DHP marker",
+                            component.id
+                        ))
+                    })?;
+
+                let plane = if differential
+                {
+                    let reference = references[k].take().ok_or_else(|| {
+                        DecodeErrors::Format(format!(
+                            "Differential frame for component {} has no reference",
+                            component.id
+                        ))
+                    })?;
+                    let reference = expand(reference, expand_h, expand_v);
+
+                    add_difference(&reference, frame, self.is_lossless, hierarchy.precision)?
+                }
+                else
+                {
+                    frame
+                };
+
+                references[k] = Some(plane);
+            }
+
+            if marker == Marker::EOI || !self.next_frame(reader, marker)?
+            {
+                break;
+            }
+        }
+
+        let last_lossless = self.is_lossless;
+
+        // report the image described by the DHP marker rather than the last frame
+        self.info.set_width(hierarchy.width);
+        self.info.set_height(hierarchy.height);
+        self.info.components = hierarchy.components.len() as u8;
+        self.input_colorspace = self.detect_colorspace(&hierarchy.components);
+
+        let width = usize::from(hierarchy.width);
+        let height = usize::from(hierarchy.height);
+        let mut planes = Vec::with_capacity(references.len());
+
+        for (component, reference) in hierarchy.components.iter().zip(references)
+        {
+            let plane = reference.ok_or_else(|| {
+                DecodeErrors::Format(format!(
+                    "No frame codes component {} of the hierarchical image",
+                    component.id
+                ))
+            })?;
+
+            let c_width = (width * component.horizontal_sample).div_ceil(hierarchy.h_max);
+            let c_height = (height * component.vertical_sample).div_ceil(hierarchy.v_max);
+
+            if plane.width != c_width || plane.height != c_height
+            {
+                return Err(DecodeErrors::Format(format!(
+                    "Component {} ends at {}x{}, expected the full resolution {}x{}",
+                    component.id, plane.width, plane.height, c_width, c_height
+                )));
+            }
+
+            planes.push(upsample_component_u16(
+                plane.samples,
+                c_width,
+                component,
+                width,
+                height,
+                hierarchy.h_max,
+                hierarchy.v_max,
+            ));
+        }
+
+        self.components = hierarchy.components;
+
+        if last_lossless
+        {
+            return Ok(interleave_u16(&planes, width, height));
+        }
+
+        let max_value = ((1_u32 << hierarchy.precision) - 1) as u16;
+
+        color_convert_u16(
+            &planes,
+            self.input_colorspace,
+            self.options.get_out_colorspace(),
+            width,
+            height,
+            max_value,
+            self.icc_transform(max_value).as_ref(),
+        )
+    }
+
+    /// Decode a single frame of a hierarchical image
+    ///
+    /// Returns a plane for each component in the frame, with samples for non-differential
+    /// frames and differences (stored as two's complement) for differential frames,
+    /// alongside the marker which ended the frame.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn decode_frame(
+        &mut self, reader: &mut ByteReader, differential: bool,
+    ) -> Result<(Vec<Plane>, Marker), DecodeErrors>
+    {
+        let width = usize::from(self.info.width);
+        let height = usize::from(self.info.height);
+
+        let (planes, marker): (Vec<(Vec<u16>, usize)>, Marker) = if self.is_lossless
+        {
+            let (planes, marker) = self.decode_lossless_planes(reader)?;
+            let (mcu_x, _) = self.lossless_mcus();
+
+            let planes = self
+                .components
+                .iter()
+                .zip(planes)
+                .map(|(component, plane)| (plane, mcu_x * component.horizontal_sample))
+                .collect();
+
+            (planes, marker)
+        }
+        else
+        {
+            if !matches!(self.components.len(), 1 | 3)
+            {
+                return Err(DecodeErrors::Format(format!(
+                    "Frames with {} components are not supported",
+                    self.components.len()
+                )));
+            }
+
+            let (block, _, marker) = self.decode_coefficients(reader)?;
+
+            if self.input_colorspace == ColorSpace::GRAYSCALE
+            {
+                // see decode_high_precision
+                self.h_max = self.components[0].horizontal_sample;
+                self.v_max = self.components[0].vertical_sample;
+            }
+
+            let precision = self.info.precision;
+            let shift = level_shift(precision);
+
+            let planes = self
+                .components
+                .iter()
+                .zip(block.iter())
+                .map(|(component, coeff)| {
+                    if differential
+                    {
+                        // differences aren't level shifted, they are kept as two's complement
+                        // and clamped to a range adding them to a reference can't overflow
+                        idct_component(coeff, component, precision, |x| {
+                            x.clamp(-0x8000, 0x7fff) as i16 as u16
+                        })
+                    }
+                    else
+                    {
+                        idct_component(coeff, component, precision, shift)
+                    }
+                })
+                .collect();
+
+            (planes, marker)
+        };
+
+        let planes = self
+            .components
+            .iter()
+            .zip(planes)
+            .map(|(component, (samples, stride))| {
+                let c_width = (width * component.horizontal_sample).div_ceil(self.h_max);
+                let c_height = (height * component.vertical_sample).div_ceil(self.v_max);
+
+                crop(
+                    &Plane {
+                        samples,
+                        width: stride,
+                        height: c_height,
+                    },
+                    c_width,
+                    c_height,
+                )
+            })
+            .collect::<Result<Vec<Plane>, DecodeErrors>>()?;
+
+        Ok((planes, marker))
+    }
+
+    /// Parse the headers between two frames, up to the first scan of the next frame
+    ///
+    /// Returns false if the image ended instead.
+    fn next_frame(&mut self, reader: &mut ByteReader, marker: Marker)
+        -> Result<bool, DecodeErrors>
+    {
+        let mut marker = marker;
+        let mut seen_frame = false;
+
+        loop
+        {
+            match marker
+            {
+                Marker::EOI => return Ok(false),
+                Marker::SOF(_) =>
+                {
+                    self.parse_marker_inner(marker, reader)?;
+
+                    seen_frame = true;
+                }
+                Marker::SOS =>
+                {
+                    if !seen_frame
+                    {
+                        return Err(DecodeErrors::FormatStatic(
+                            "Found a scan without a frame in hierarchical image",
+                        ));
+                    }
+
+                    self.parse_marker_inner(marker, reader)?;
+
+                    return Ok(true);
+                }
+                _ =>
+                {
+                    self.parse_marker_inner(marker, reader)?;
+                }
+            }
+
+            marker = get_marker(reader, &mut None)
+                .ok_or(DecodeErrors::FormatStatic("Marker missing where expected"))?;
+        }
+    }
+}
+
+/// Keep the top left `width` by `height` samples of a plane
+fn crop(plane: &Plane, width: usize, height: usize) -> Result<Plane, DecodeErrors>
+{
+    if plane.width < width || plane.height < height || plane.samples.len() < plane.width * height
+    {
+        return Err(DecodeErrors::Format(format!(
+            "Reference of {}x{} samples is too small for a frame of {}x{} samples",
+            plane.width, plane.height, width, height
+        )));
+    }
+
+    let samples = plane
+        .samples
+        .chunks_exact(plane.width)
+        .take(height)
+        .flat_map(|row| &row[..width])
+        .copied()
+        .collect();
+
+    Ok(Plane {
+        samples,
+        width,
+        height,
+    })
+}
+
+/// Up-sample a reference by two horizontally and/or vertically
+///
+/// See J.1.1.2 of the spec, new samples are the average of their two neighbours,
+/// with the last sample of each row or column repeated at the edge.
+#[allow(clippy::cast_possible_truncation)]
+fn expand(plane: Plane, horizontal: bool, vertical: bool) -> Plane
+{
+    let mut plane = plane;
+
+    if horizontal
+    {
+        let mut samples = Vec::with_capacity(plane.samples.len() * 2);
+
+        for row in plane.samples.chunks_exact(plane.width)
+        {
+            for (x, &sample) in row.iter().enumerate()
+            {
+                let next = row[(x + 1).min(plane.width - 1)];
+
+                samples.push(sample);
+                samples.push(((u32::from(sample) + u32::from(next)) >> 1) as u16);
+            }
+        }
+        plane = Plane {
+            samples,
+            width: plane.width * 2,
+            height: plane.height,
+        };
+    }
+    if vertical
+    {
+        let mut samples = Vec::with_capacity(plane.samples.len() * 2);
+        let rows: Vec<&[u16]> = plane.samples.chunks_exact(plane.width).collect();
+
+        for (y, row) in rows.iter().enumerate()
+        {
+            let next = rows[(y + 1).min(plane.height - 1)];
+
+            samples.extend_from_slice(row);
+            samples.extend(
+                row.iter()
+                    .zip(next.iter())
+                    .map(|(&a, &b)| ((u32::from(a) + u32::from(b)) >> 1) as u16),
+            );
+        }
+        plane = Plane {
+            samples,
+            width: plane.width,
+            height: plane.height * 2,
+        };
+    }
+
+    plane
+}
+
+/// Add the differences of a differential frame to its reference
+///
+/// Lossless frames reconstruct samples modulo 2^16, the others clamp them
+/// to the range of the image precision.
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_possible_wrap,
+    clippy::cast_sign_loss
+)]
+fn add_difference(
+    reference: &Plane, difference: Plane, lossless: bool, precision: u8,
+) -> Result<Plane, DecodeErrors>
+{
+    let reference = crop(reference, difference.width, difference.height)?;
+    let max_value = (1_i32 << precision) - 1;
+
+    let samples = reference
+        .samples
+        .iter()
+        .zip(difference.samples)
+        .map(|(&r, d)| {
+            if lossless
+            {
+                r.wrapping_add(d)
+            }
+            else
+            {
+                (i32::from(r) + i32::from(d as i16)).clamp(0, max_value) as u16
+            }
+        })
+        .collect();
+
+    Ok(Plane {
+        samples,
+        width: difference.width,
+        height: difference.height,
+    })
+}
@@ -43,7 +43,7 @@ pub struct HuffmanTable
 impl HuffmanTable
 {
     pub fn new(
-        codes: &[u8; 17], values: [u8; 256], is_dc: bool, is_progressive: bool,
+        codes: &[u8; 17], values: [u8; 256], is_dc: bool, is_progressive: bool, is_lossless: bool,
     ) -> Result<HuffmanTable, DecodeErrors>
     {
         let too_long_code = (i32::from(HUFF_LOOKAHEAD) + 1) << HUFF_LOOKAHEAD;
@@ -56,7 +56,7 @@ impl HuffmanTable
             ac_lookup: None,
         };
 
-        p.make_derived_table(is_dc, is_progressive)?;
+        p.make_derived_table(is_dc, is_progressive, is_lossless)?;
 
         Ok(p)
     }
@@ -70,8 +70,9 @@ impl HuffmanTable
         clippy::cast_sign_loss,
         clippy::too_many_lines
     )]
-    fn make_derived_table(&mut self, is_dc: bool, is_progressive: bool)
-        -> Result<(), DecodeErrors>
+    fn make_derived_table(
+        &mut self, is_dc: bool, is_progressive: bool, is_lossless: bool,
+    ) -> Result<(), DecodeErrors>
     {
         // build a list of code size
         let mut huff_size = [0; 257];
@@ -245,8 +246,9 @@ impl HuffmanTable
                             k += (!0_i16 << mag_bits) + 1;
                         };
 
-                        // if result is small enough fit into fast ac table
-                        if (-128..=127).contains(&k)
+                        // if result is small enough fit into fast ac table,
+                        // the value gets the top 6 bits of the entry
+                        if (-32..=31).contains(&k)
                         {
                             fast_ac[i] = (k << 10) + (run << 4) + (len + mag_bits);
                         }
@@ -258,14 +260,17 @@ impl HuffmanTable
 
         // Validate symbols as being reasonable
         // For AC tables, we make no check, but accept all byte values 0..255
-        // For DC tables, we require symbols to be in range 0..15
+        // For DC tables, we require symbols to be in range 0..15, lossless images
+        // also use 16
         if is_dc
         {
+            let max_symbol = if is_lossless { 16 } else { 15 };
+
             for i in 0..num_symbols
             {
                 let sym = self.values[i];
 
-                if sym > 15
+                if sym > max_symbol
                 {
                     return Err(DecodeErrors::HuffmanDecode("Bad Huffman Table".to_string()));
                 }
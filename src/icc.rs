@@ -0,0 +1,818 @@
+//! Conversion from embedded ICC profiles to sRGB
+//!
+//! We understand three kinds of profiles
+//! - Matrix/TRC RGB profiles, which linearize each channel with a curve and
+//!   go to the profile connection space (PCS) with a 3x3 matrix.
+//! - Gray TRC profiles, with a single curve giving the luminance.
+//! - Profiles with an `A2B0` lookup table (lut8, lut16 or lutAToB), which
+//!   is how CMYK profiles, and some RGB ones, describe their colors.
+//!
+//! Samples go to the PCS (D50 XYZ or Lab), then to linear sRGB with the
+//! Bradford adapted sRGB matrix and are finally gamma encoded.
+//!
+//! Transforms are built once per image, tables which depend on a single sample value
+//! are computed up front for every value a sample can take.
+
+use crate::misc::ColorSpace;
+
+/// D50 XYZ to linear sRGB, with Bradford chromatic adaptation
+const SRGB_FROM_XYZ: [[f32; 3]; 3] = [
+    [3.134_186, -1.617_209, -0.490_694_1],
+    [-0.978_748_5, 1.916_13, 0.033_433_4],
+    [0.071_963_9, -0.228_993_9, 1.405_754],
+];
+
+/// White point of the PCS
+const D50: [f32; 3] = [0.9642, 1.0, 0.8249];
+
+/// Number of steps of the table used to gamma encode linear sRGB values
+const ENCODE_STEPS: usize = 4096;
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16>
+{
+    Some(u16::from_be_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32>
+{
+    Some(u32::from_be_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+/// Read a `s15Fixed16Number`
+#[allow(clippy::cast_possible_wrap, clippy::cast_precision_loss)]
+fn read_fixed(data: &[u8], offset: usize) -> Option<f32>
+{
+    Some(read_u32(data, offset)? as i32 as f32 / 65536.0)
+}
+
+/// Data of the tag with signature `signature`
+fn find_tag(profile: &[u8], signature: [u8; 4]) -> Option<&[u8]>
+{
+    // entries are 12 bytes each, after the 128 byte header and the count
+    let count = (read_u32(profile, 128)? as usize).min(profile.len().saturating_sub(132) / 12);
+
+    (0..count).find_map(|i| {
+        let entry = 132 + i * 12;
+
+        if profile.get(entry..entry + 4)? != signature
+        {
+            return None;
+        }
+        let offset = read_u32(profile, entry + 4)? as usize;
+        let size = read_u32(profile, entry + 8)? as usize;
+
+        profile.get(offset..offset.checked_add(size)?)
+    })
+}
+
+/// A curve mapping values between 0 and 1
+enum Curve
+{
+    Identity,
+    Gamma(f32),
+    /// Equally spaced samples, linearly interpolated
+    Table(Vec<f32>),
+    /// Parametric curve of the given type, with up to seven parameters
+    Parametric(u16, [f32; 7]),
+}
+
+impl Curve
+{
+    /// Parse a `curv` or `para` element, returning the curve and its length in bytes
+    fn parse(data: &[u8]) -> Option<(Curve, usize)>
+    {
+        match data.get(..4)?
+        {
+            b"curv" =>
+            {
+                let count = read_u32(data, 8)? as usize;
+                let length = 12_usize.checked_add(count.checked_mul(2)?)?;
+
+                let curve = match count
+                {
+                    0 => Curve::Identity,
+                    1 => Curve::Gamma(f32::from(read_u16(data, 12)?) / 256.0),
+                    _ => Curve::Table(
+                        (0..count)
+                            .map(|i| read_u16(data, 12 + i * 2).map(|x| f32::from(x) / 65535.0))
+                            .collect::<Option<_>>()?,
+                    ),
+                };
+
+                Some((curve, length))
+            }
+            b"para" =>
+            {
+                let kind = read_u16(data, 8)?;
+                let count = match kind
+                {
+                    0 => 1,
+                    1 => 3,
+                    2 => 4,
+                    3 => 5,
+                    4 => 7,
+                    _ => return None,
+                };
+                let mut parameters = [0.0; 7];
+
+                for (i, parameter) in parameters.iter_mut().take(count).enumerate()
+                {
+                    *parameter = read_fixed(data, 12 + i * 4)?;
+                }
+
+                Some((Curve::Parametric(kind, parameters), 12 + count * 4))
+            }
+            _ => None,
+        }
+    }
+
+    #[allow(
+        clippy::many_single_char_names,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::cast_precision_loss
+    )]
+    fn eval(&self, x: f32) -> f32
+    {
+        let x = x.clamp(0.0, 1.0);
+
+        let y = match self
+        {
+            Curve::Identity => x,
+            Curve::Gamma(gamma) => x.powf(*gamma),
+            Curve::Table(table) =>
+            {
+                let position = x * (table.len() - 1) as f32;
+                let index = (position as usize).min(table.len() - 2);
+                let fraction = position - index as f32;
+
+                table[index] + (table[index + 1] - table[index]) * fraction
+            }
+            Curve::Parametric(kind, [g, a, b, c, d, e, f]) => match kind
+            {
+                0 => x.powf(*g),
+                1 if x >= -b / a => (a * x + b).powf(*g),
+                1 => 0.0,
+                2 if x >= -b / a => (a * x + b).powf(*g) + c,
+                2 => *c,
+                3 if x >= *d => (a * x + b).powf(*g),
+                3 => c * x,
+                _ if x >= *d => (a * x + b).powf(*g) + e,
+                _ => c * x + f,
+            },
+        };
+
+        if y.is_nan()
+        {
+            0.0
+        }
+        else
+        {
+            y.clamp(0.0, 1.0)
+        }
+    }
+}
+
+/// How the PCS values coming out of a lookup table are encoded
+#[derive(Copy, Clone)]
+enum Pcs
+{
+    Xyz,
+    /// Lab of lut8 tables, and the legacy 16 bit encoding of lut16 tables
+    LabLut8,
+    LabLut16,
+    /// Lab of lutAToB tables
+    Lab,
+}
+
+impl Pcs
+{
+    /// Convert normalized PCS values to D50 XYZ
+    #[allow(clippy::many_single_char_names)]
+    fn to_xyz(self, values: [f32; 3]) -> [f32; 3]
+    {
+        let (l, a, b) = match self
+        {
+            Pcs::Xyz => return values.map(|x| x * 65535.0 / 32768.0),
+            Pcs::LabLut8 | Pcs::Lab => (
+                values[0] * 100.0,
+                values[1] * 255.0 - 128.0,
+                values[2] * 255.0 - 128.0,
+            ),
+            Pcs::LabLut16 => (
+                values[0] * 65535.0 / 65280.0 * 100.0,
+                values[1] * 65535.0 / 256.0 - 128.0,
+                values[2] * 65535.0 / 256.0 - 128.0,
+            ),
+        };
+
+        let fy = (l + 16.0) / 116.0;
+        let f = [fy + a / 500.0, fy, fy - b / 200.0];
+
+        let mut xyz = [0.0; 3];
+
+        for ((out, f), white) in xyz.iter_mut().zip(f).zip(D50)
+        {
+            let t = if f > 6.0 / 29.0
+            {
+                f * f * f
+            }
+            else
+            {
+                3.0 * (6.0_f32 / 29.0).powi(2) * (f - 4.0 / 29.0)
+            };
+            *out = t * white;
+        }
+
+        xyz
+    }
+}
+
+/// A color lookup table, with `outputs` values for every grid point
+struct Clut
+{
+    grid:    Vec<usize>,
+    outputs: usize,
+    values:  Vec<f32>,
+}
+
+impl Clut
+{
+    fn new(grid: Vec<usize>, outputs: usize, values: Vec<f32>) -> Option<Clut>
+    {
+        let points = grid
+            .iter()
+            .try_fold(1_usize, |acc, x| acc.checked_mul(*x))?;
+
+        if grid.iter().any(|x| *x < 2) || values.len() != points.checked_mul(outputs)?
+        {
+            return None;
+        }
+
+        Some(Clut {
+            grid,
+            outputs,
+            values,
+        })
+    }
+
+    /// Multilinear interpolation of the table at `input`
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::cast_precision_loss
+    )]
+    fn eval(&self, input: &[f32], output: &mut [f32])
+    {
+        let dimensions = self.grid.len();
+        let mut base = 0;
+        let mut fractions = [0.0; 4];
+        let mut strides = [0; 4];
+        let mut stride = self.outputs;
+
+        for i in (0..dimensions).rev()
+        {
+            let position = input[i].clamp(0.0, 1.0) * (self.grid[i] - 1) as f32;
+            let index = (position as usize).min(self.grid[i] - 2);
+
+            fractions[i] = position - index as f32;
+            base += index * stride;
+            strides[i] = stride;
+            stride *= self.grid[i];
+        }
+
+        output[..self.outputs].fill(0.0);
+
+        for corner in 0..1_usize << dimensions
+        {
+            let mut weight = 1.0;
+            let mut offset = base;
+
+            for i in 0..dimensions
+            {
+                if corner & (1 << i) == 0
+                {
+                    weight *= 1.0 - fractions[i];
+                }
+                else
+                {
+                    weight *= fractions[i];
+                    offset += strides[i];
+                }
+            }
+
+            if weight > 0.0
+            {
+                for (out, value) in output
+                    .iter_mut()
+                    .zip(&self.values[offset..offset + self.outputs])
+                {
+                    *out += weight * value;
+                }
+            }
+        }
+    }
+}
+
+/// The stages of a lookup table after its input curves
+struct Lut
+{
+    clut:          Option<Clut>,
+    /// Curves after the CLUT, the output tables of lut8 and lut16 and
+    /// M curves of lutAToB
+    clut_curves:   Vec<Curve>,
+    /// Matrix and offsets of lutAToB
+    matrix:        Option<[f32; 12]>,
+    /// B curves of lutAToB
+    output_curves: Vec<Curve>,
+    pcs:           Pcs,
+}
+
+impl Lut
+{
+    /// Parse a lookup table with `inputs` input channels, returning its input curves
+    /// and the rest of it
+    fn parse(data: &[u8], inputs: usize, lab: bool) -> Option<(Vec<Curve>, Lut)>
+    {
+        if usize::from(*data.get(8)?) != inputs || *data.get(9)? != 3 || !(1..=4).contains(&inputs)
+        {
+            return None;
+        }
+
+        match data.get(..4)?
+        {
+            b"mft1" => Self::parse_lut8_16(data, inputs, 1, 256, 256, 48, lab),
+            b"mft2" =>
+            {
+                let input_entries = usize::from(read_u16(data, 48)?);
+                let output_entries = usize::from(read_u16(data, 50)?);
+
+                Self::parse_lut8_16(data, inputs, 2, input_entries, output_entries, 52, lab)
+            }
+            b"mAB " => Self::parse_a_to_b(data, inputs, lab),
+            _ => None,
+        }
+    }
+
+    /// Parse a lut8 or lut16 table, whose samples are `size` bytes long
+    fn parse_lut8_16(
+        data: &[u8], inputs: usize, size: usize, input_entries: usize, output_entries: usize,
+        start: usize, lab: bool,
+    ) -> Option<(Vec<Curve>, Lut)>
+    {
+        let grid = usize::from(*data.get(10)?);
+
+        if input_entries < 2 || output_entries < 2
+        {
+            return None;
+        }
+
+        let max = if size == 1 { 255.0 } else { 65535.0 };
+        let sample = |i: usize| -> Option<f32> {
+            let offset = start.checked_add(i.checked_mul(size)?)?;
+
+            if size == 1
+            {
+                data.get(offset).map(|x| f32::from(*x) / max)
+            }
+            else
+            {
+                read_u16(data, offset).map(|x| f32::from(x) / max)
+            }
+        };
+        let table = |first: usize, entries: usize| -> Option<Curve> {
+            Some(Curve::Table(
+                (first..first + entries)
+                    .map(sample)
+                    .collect::<Option<_>>()?,
+            ))
+        };
+
+        let input_curves = (0..inputs)
+            .map(|i| table(i * input_entries, input_entries))
+            .collect::<Option<Vec<_>>>()?;
+
+        let clut_start = inputs * input_entries;
+        let clut_length = grid
+            .checked_pow(u32::try_from(inputs).ok()?)?
+            .checked_mul(3)?;
+        let values = (clut_start..clut_start + clut_length)
+            .map(sample)
+            .collect::<Option<Vec<_>>>()?;
+
+        let output_start = clut_start + clut_length;
+        let clut_curves = (0..3)
+            .map(|i| table(output_start + i * output_entries, output_entries))
+            .collect::<Option<Vec<_>>>()?;
+
+        let pcs = match (lab, size)
+        {
+            (false, _) => Pcs::Xyz,
+            (true, 1) => Pcs::LabLut8,
+            (true, _) => Pcs::LabLut16,
+        };
+
+        Some((
+            input_curves,
+            Lut {
+                clut: Some(Clut::new(vec![grid; inputs], 3, values)?),
+                clut_curves,
+                matrix: None,
+                output_curves: vec![],
+                pcs,
+            },
+        ))
+    }
+
+    /// Parse a lutAToB table
+    fn parse_a_to_b(data: &[u8], inputs: usize, lab: bool) -> Option<(Vec<Curve>, Lut)>
+    {
+        let offset = |position: usize| read_u32(data, position).map(|x| x as usize);
+
+        let curves = |start: usize, count: usize| -> Option<Vec<Curve>> {
+            let mut position = start;
+            let mut curves = Vec::with_capacity(count);
+
+            for _ in 0..count
+            {
+                let (curve, length) = Curve::parse(data.get(position..)?)?;
+                curves.push(curve);
+                // curves are padded to four bytes
+                position = position.checked_add(length.checked_add(3)? & !3)?;
+            }
+            Some(curves)
+        };
+
+        let (b, matrix, m, clut, a) = (
+            offset(12)?,
+            offset(16)?,
+            offset(20)?,
+            offset(24)?,
+            offset(28)?,
+        );
+
+        // B curves are always present, the rest is optional
+        if b == 0 || (clut == 0 && inputs != 3)
+        {
+            return None;
+        }
+        let output_curves = curves(b, 3)?;
+
+        let matrix = if matrix == 0
+        {
+            None
+        }
+        else
+        {
+            let mut values = [0.0; 12];
+
+            for (i, value) in values.iter_mut().enumerate()
+            {
+                *value = read_fixed(data, matrix + i * 4)?;
+            }
+            Some(values)
+        };
+
+        let clut_curves = if m == 0 { vec![] } else { curves(m, 3)? };
+
+        let input_curves = if a == 0
+        {
+            (0..inputs).map(|_| Curve::Identity).collect()
+        }
+        else
+        {
+            curves(a, inputs)?
+        };
+
+        let clut = if clut == 0
+        {
+            None
+        }
+        else
+        {
+            let grid: Vec<usize> = data
+                .get(clut..clut + inputs)?
+                .iter()
+                .map(|x| usize::from(*x))
+                .collect();
+            let size = usize::from(*data.get(clut + 16)?);
+            let points: usize = grid.iter().product();
+
+            let values = (0..points * 3)
+                .map(|i| match size
+                {
+                    1 => data.get(clut + 20 + i).map(|x| f32::from(*x) / 255.0),
+                    2 => read_u16(data, clut + 20 + i * 2).map(|x| f32::from(x) / 65535.0),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>()?;
+
+            Some(Clut::new(grid, 3, values)?)
+        };
+
+        Some((
+            input_curves,
+            Lut {
+                clut,
+                clut_curves,
+                matrix,
+                output_curves,
+                pcs: if lab { Pcs::Lab } else { Pcs::Xyz },
+            },
+        ))
+    }
+
+    /// Run values which went through the input curves through the rest of the table,
+    /// returning D50 XYZ values
+    fn eval(&self, input: &[f32]) -> [f32; 3]
+    {
+        let mut values = [0.0; 4];
+
+        match &self.clut
+        {
+            Some(clut) => clut.eval(input, &mut values),
+            None => values[..3].copy_from_slice(&input[..3]),
+        }
+
+        let mut values = [values[0], values[1], values[2]];
+
+        for (value, curve) in values.iter_mut().zip(&self.clut_curves)
+        {
+            *value = curve.eval(*value);
+        }
+
+        if let Some(m) = &self.matrix
+        {
+            let [x, y, z] = values;
+
+            values = [
+                (m[0] * x + m[1] * y + m[2] * z + m[9]).clamp(0.0, 1.0),
+                (m[3] * x + m[4] * y + m[5] * z + m[10]).clamp(0.0, 1.0),
+                (m[6] * x + m[7] * y + m[8] * z + m[11]).clamp(0.0, 1.0),
+            ];
+        }
+
+        for (value, curve) in values.iter_mut().zip(&self.output_curves)
+        {
+            *value = curve.eval(*value);
+        }
+
+        self.pcs.to_xyz(values)
+    }
+}
+
+enum Kind
+{
+    /// Matrix from linear samples to linear sRGB
+    Matrix([[f32; 3]; 3]),
+    /// Linear samples are the luminance
+    Gray,
+    Lut(Lut),
+}
+
+/// A transform from the colors of an embedded ICC profile to sRGB
+pub(crate) struct IccTransform
+{
+    kind:      Kind,
+    /// Values of samples after the profile's input curves, for each channel
+    input:     Vec<Vec<f32>>,
+    /// Gamma encoded sRGB values, scaled to the sample range, of equally
+    /// spaced linear values
+    encode:    Vec<f32>,
+    max_value: u16,
+}
+
+impl IccTransform
+{
+    /// Create a transform for an image with `input` colorspace, decoded to `output`
+    /// with samples between 0 and `max_value`
+    ///
+    /// Returns `None` if the profile can't be understood, doesn't describe the image's
+    /// colorspace or if the output colorspace doesn't hold colors, e.g. YCbCr and CMYK output.
+    #[allow(clippy::cast_precision_loss)]
+    pub(crate) fn new(
+        profile: &[u8], input: ColorSpace, output: ColorSpace, max_value: u16,
+    ) -> Option<IccTransform>
+    {
+        let (signature, channels) = match (input, output)
+        {
+            (
+                ColorSpace::YCbCr | ColorSpace::RGB,
+                ColorSpace::RGB | ColorSpace::RGBA | ColorSpace::RGBX,
+            ) => (b"RGB ", 3),
+            (ColorSpace::GRAYSCALE, ColorSpace::GRAYSCALE) => (b"GRAY", 1),
+            (
+                ColorSpace::CMYK | ColorSpace::YCCK,
+                ColorSpace::RGB | ColorSpace::RGBA | ColorSpace::RGBX,
+            ) => (b"CMYK", 4),
+            _ => return None,
+        };
+
+        if profile.get(16..20)? != signature
+        {
+            warn!("ICC profile doesn't describe {input:?} samples, not converting to sRGB");
+            return None;
+        }
+        let lab = match profile.get(20..24)?
+        {
+            b"XYZ " => false,
+            b"Lab " => true,
+            _ => return None,
+        };
+
+        let (curves, kind) = if let Some(tag) = find_tag(profile, *b"A2B0")
+        {
+            let (curves, lut) = Lut::parse(tag, channels, lab)?;
+
+            (curves, Kind::Lut(lut))
+        }
+        else if channels == 3 && !lab
+        {
+            let mut curves = Vec::with_capacity(3);
+            let mut colorants = [[0.0; 3]; 3];
+
+            for (i, (trc, xyz)) in [(b"rTRC", b"rXYZ"), (b"gTRC", b"gXYZ"), (b"bTRC", b"bXYZ")]
+                .iter()
+                .enumerate()
+            {
+                curves.push(Curve::parse(find_tag(profile, **trc)?)?.0);
+
+                let xyz = find_tag(profile, **xyz)?;
+
+                for (j, row) in colorants.iter_mut().enumerate()
+                {
+                    row[i] = read_fixed(xyz, 8 + j * 4)?;
+                }
+            }
+            let mut matrix = [[0.0; 3]; 3];
+
+            for (i, row) in matrix.iter_mut().enumerate()
+            {
+                for (j, value) in row.iter_mut().enumerate()
+                {
+                    *value = (0..3).map(|k| SRGB_FROM_XYZ[i][k] * colorants[k][j]).sum();
+                }
+            }
+
+            (curves, Kind::Matrix(matrix))
+        }
+        else if channels == 1
+        {
+            (
+                vec![Curve::parse(find_tag(profile, *b"kTRC")?)?.0],
+                Kind::Gray,
+            )
+        }
+        else
+        {
+            return None;
+        };
+
+        let scale = f32::from(max_value);
+        // CMYK samples are stored inverted, as Adobe applications write them
+        let inverted = channels == 4;
+
+        let input = curves
+            .iter()
+            .map(|curve| {
+                (0..=max_value)
+                    .map(|x| {
+                        let x = f32::from(x) / scale;
+
+                        curve.eval(if inverted { 1.0 - x } else { x })
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let encode = (0..=ENCODE_STEPS)
+            .map(|x| {
+                let x = x as f32 / ENCODE_STEPS as f32;
+
+                let encoded = if x <= 0.003_130_8
+                {
+                    12.92 * x
+                }
+                else
+                {
+                    1.055 * x.powf(1.0 / 2.4) - 0.055
+                };
+                encoded * scale
+            })
+            .collect();
+
+        Some(IccTransform {
+            kind,
+            input,
+            encode,
+            max_value,
+        })
+    }
+
+    /// Gamma encode a linear value
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::cast_precision_loss
+    )]
+    fn encode(&self, x: f32) -> u16
+    {
+        let position = x.clamp(0.0, 1.0) * ENCODE_STEPS as f32;
+        let index = (position as usize).min(ENCODE_STEPS - 1);
+        let fraction = position - index as f32;
+
+        let value = self.encode[index] + (self.encode[index + 1] - self.encode[index]) * fraction;
+
+        (value.round() as u16).min(self.max_value)
+    }
+
+    /// Convert samples of a single pixel to sRGB
+    fn convert(&self, samples: &[u16]) -> [u16; 3]
+    {
+        let mut linear = [0.0; 4];
+
+        for ((value, sample), table) in linear.iter_mut().zip(samples).zip(&self.input)
+        {
+            *value = table[usize::from(*sample)];
+        }
+
+        let rgb = match &self.kind
+        {
+            Kind::Gray => return [self.encode(linear[0]); 3],
+            Kind::Matrix(matrix) =>
+            {
+                matrix.map(|row| row[0] * linear[0] + row[1] * linear[1] + row[2] * linear[2])
+            }
+            Kind::Lut(lut) =>
+            {
+                let xyz = lut.eval(&linear[..self.input.len()]);
+
+                SRGB_FROM_XYZ.map(|row| row[0] * xyz[0] + row[1] * xyz[1] + row[2] * xyz[2])
+            }
+        };
+
+        rgb.map(|x| self.encode(x))
+    }
+
+    /// Convert RGB(A/X) or grayscale pixels to sRGB in place, alpha is left as it is
+    pub(crate) fn convert_u16(&self, pixels: &mut [u16], num_components: usize)
+    {
+        let channels = self.input.len();
+
+        for pixel in pixels.chunks_exact_mut(num_components)
+        {
+            let srgb = self.convert(&pixel[..channels]);
+
+            pixel[..channels].copy_from_slice(&srgb[..channels]);
+        }
+    }
+
+    /// Convert 8 bit RGB(A/X) or grayscale pixels to sRGB in place
+    #[allow(clippy::cast_possible_truncation)]
+    pub(crate) fn convert_u8(&self, pixels: &mut [u8], num_components: usize)
+    {
+        let channels = self.input.len();
+        let mut samples = [0; 3];
+
+        for pixel in pixels.chunks_exact_mut(num_components)
+        {
+            for (sample, value) in samples.iter_mut().zip(pixel.iter())
+            {
+                *sample = u16::from(*value);
+            }
+
+            let srgb = self.convert(&samples[..channels]);
+
+            for (value, srgb) in pixel.iter_mut().zip(&srgb[..channels])
+            {
+                *value = *srgb as u8;
+            }
+        }
+    }
+
+    /// Convert CMYK pixels to sRGB(A/X)
+    pub(crate) fn cmyk_to_rgb_u16(&self, cmyk: &[u16], output: &mut [u16], num_components: usize)
+    {
+        for (pixel, out) in cmyk
+            .chunks_exact(4)
+            .zip(output.chunks_exact_mut(num_components))
+        {
+            out[..3].copy_from_slice(&self.convert(pixel));
+
+            if num_components == 4
+            {
+                out[3] = self.max_value;
+            }
+        }
+    }
+
+    /// Whether the transform takes CMYK samples
+    pub(crate) fn is_cmyk(&self) -> bool
+    {
+        self.input.len() == 4
+    }
+}
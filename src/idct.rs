@@ -29,6 +29,7 @@ use crate::decoder::IDCTPtr;
 #[cfg(feature = "X86")]
 use crate::idct::avx2::dequantize_and_idct_avx2;
 use crate::idct::scalar::dequantize_and_idct_int;
+pub use crate::idct::scalar::dequantize_and_idct_wide;
 
 #[cfg(feature = "x86")]
 mod avx2;
@@ -73,8 +74,8 @@ fn test_zeroes()
     let qt_table = Aligned32([1; 64]);
     let stride = 8;
     let coeff = vec![0; 64];
-    let output_scalar = dequantize_and_idct_int(&coeff, &qt_table, stride, 1, 1);
-    let output_avx = crate::idct::avx2::dequantize_and_idct_avx2(&coeff, &qt_table, stride, 1, 1);
+    let output_scalar = dequantize_and_idct_int(&coeff, &qt_table, stride);
+    let output_avx = crate::idct::avx2::dequantize_and_idct_avx2(&coeff, &qt_table, stride);
     assert_eq!(output_scalar, output_avx, "AVX and scalar do not match");
     // output should be 128 because IDCT does level shifting too..
     assert_eq!(output_scalar, &[128; 64], "Test for zeroes failed");
@@ -97,8 +98,8 @@ fn test_max()
         255, 255, 255, 0, 255, 0, 255, 0, 0, 0, 0, 255, 0, 255, 0, 255, 255, 0, 255, 0, 255, 0,
         158, 0, 49, 255, 0, 255, 0, 255, 0, 255, 255, 255, 0, 255, 0, 255, 49, 255, 255,
     ];
-    let output_scalar = dequantize_and_idct_int(&coeff, &qt_table, stride, 1, 1);
-    let output_avx = crate::idct::avx2::dequantize_and_idct_avx2(&coeff, &qt_table, stride, 1, 1);
+    let output_scalar = dequantize_and_idct_int(&coeff, &qt_table, stride);
+    let output_avx = crate::idct::avx2::dequantize_and_idct_avx2(&coeff, &qt_table, stride);
     assert_eq!(output_scalar, output_avx, "AVX and scalar do not match");
 
     assert_eq!(output_avx, &output, "Test for max IDCT failed");
@@ -120,8 +121,8 @@ fn test_min()
         0, 0, 0, 0, 255, 0, 255, 0, 255, 255, 255, 255, 0, 255, 0, 255, 0, 0, 255, 0, 255, 0, 255,
         98, 255, 207, 0, 255, 0, 255, 0, 255, 0, 0, 0, 255, 0, 255, 0, 207, 0, 0,
     ];
-    let output_scalar = dequantize_and_idct_int(&coeff, &qt_table, stride, 1, 1);
-    let output_avx = crate::idct::avx2::dequantize_and_idct_avx2(&coeff, &qt_table, stride, 1, 1);
+    let output_scalar = dequantize_and_idct_int(&coeff, &qt_table, stride);
+    let output_avx = crate::idct::avx2::dequantize_and_idct_avx2(&coeff, &qt_table, stride);
     assert_eq!(output_scalar, output_avx, "AVX and scalar do not match");
     assert_eq!(output_avx, &output, "Test for min IDCT fails");
 }
@@ -42,15 +42,14 @@ const SCALE_BITS: i32 = 512 + 65536 + (128 << 17);
 /// For documentation see module docs.
 
 pub fn dequantize_and_idct_avx2(
-    vector: &[i16], qt_table: &Aligned32<[i32; 64]>, stride: usize, samp_factors: usize,
-    v_samp: usize,
+    vector: &[i16], qt_table: &Aligned32<[i32; 64]>, stride: usize,
 ) -> Vec<i16>
 {
     unsafe {
         // We don't call this method directly because we need to flag the code function
         // with #[target_feature] so that the compiler does do weird stuff with
         // it
-        dequantize_and_idct_int_avx2(vector, qt_table, stride, samp_factors, v_samp)
+        dequantize_and_idct_int_avx2(vector, qt_table, stride)
     }
 }
 
@@ -62,8 +61,7 @@ pub fn dequantize_and_idct_avx2(
     unused_assignments
 )]
 unsafe fn dequantize_and_idct_int_avx2(
-    coeff: &[i16], qt_table: &Aligned32<[i32; 64]>, stride: usize, samp_factors: usize,
-    v_samp: usize,
+    coeff: &[i16], qt_table: &Aligned32<[i32; 64]>, stride: usize,
 ) -> Vec<i16>
 {
     let mut tmp_vector = vec![0; coeff.len()];
@@ -86,7 +84,8 @@ unsafe fn dequantize_and_idct_int_avx2(
 
     let qt_row7 = _mm256_load_si256(qt_table.0[56..=63].as_ptr().cast());
 
-    let chunks = coeff.len() * v_samp / samp_factors;
+    // a row of blocks
+    let chunks = stride * 8;
 
     // calculate position
     for (in_vector, out_vector) in coeff
@@ -160,10 +159,9 @@ unsafe fn dequantize_and_idct_int_avx2(
                 {
                     // AC terms all zero, idct of the block is  is (coeff[0] *qt[0])/8 + bias(128)
                     // (and clamped to 255)
+                    // multiply in i32, large coefficients overflow an i16
                     let idct_value = _mm_set1_epi16(
-                        ((vector[0].wrapping_mul(qt_table.0[0] as i16) >> 3) + 128)
-                            .max(0)
-                            .min(255),
+                        (((i32::from(vector[0]) * qt_table.0[0]) >> 3) + 128).clamp(0, 255) as i16,
                     );
                     macro_rules! store {
                         ($pos:tt,$value:tt) => {
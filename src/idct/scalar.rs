@@ -13,12 +13,13 @@ const SCALE_BITS: i32 = 512 + 65536 + (128 << 17);
 ///  - vector: A mutable reference( so that i can reuse memory) to a MCU worth
 ///    of numbers
 ///  - `qt_table`: A quantization table fro the MCU
+///  - stride: Distance between two rows of the output, blocks are laid out
+///    in rows of `stride / 8` blocks
 ///
 /// [`stbi_image.h`]:https://github.com/nothings/stb/blob/c9064e317699d2e495f36ba4f9ac037e88ee371a/stb_image.h#L2356
 #[allow(unused_assignments)]
 pub fn dequantize_and_idct_int(
-    vector: &[i16], qt_table: &Aligned32<[i32; 64]>, stride: usize, samp_factors: usize,
-    v_samp: usize,
+    vector: &[i16], qt_table: &Aligned32<[i32; 64]>, stride: usize,
 ) -> Vec<i16>
 {
     // Temporary variables.
@@ -27,7 +28,8 @@ pub fn dequantize_and_idct_int(
 
     let mut tmp = [0; 64];
 
-    let chunks = vector.len() * v_samp / samp_factors;
+    // a row of blocks
+    let chunks = stride * 8;
     // calculate position
     for (in_vector, out_vector) in vector
         .chunks_exact(chunks)
@@ -45,7 +47,9 @@ pub fn dequantize_and_idct_int(
             if &vector[1..] == &[0_i16; 63]
             {
                 // okay then if you work, yaay, let's write you really quick
-                let coeff = [((vector[0].wrapping_mul(qt_table.0[0] as i16)) >> 3) + 128; 8];
+                // multiply in i32, large coefficients overflow an i16, and clamp
+                // like other blocks
+                let coeff = [clamp(((i32::from(vector[0]) * qt_table.0[0]) >> 3) + 128); 8];
 
                 macro_rules! store {
                     ($index:tt) => {
@@ -309,3 +313,145 @@ fn dequantize(a: i16, b: i32) -> i32
 {
     i32::from(a) * b
 }
+
+/// Perform Integer IDCT on a single block of an image with more than 8 bits
+/// of precision, passing each (not level shifted) result through `store` before
+/// writing it to `output`
+///
+/// This is libjpeg's `jpeg_idct_islow`, the 8 bit routine above doesn't have enough
+/// headroom for 12 bit samples. Like libjpeg, images with 8 bits of precision keep
+/// an extra bit between the passes, so results match its output exactly.
+///
+/// `store` decides how samples are level shifted and clamped, differential frames
+/// of hierarchical images are neither.
+///
+/// # Arguments
+///  - block: 64 coefficients of a block in natural order
+///  - `qt_table`: The quantization table for the block
+///  - output: Where to write the 8 rows of the block
+///  - stride: Distance between two rows in `output`
+///  - precision: Sample precision of the image
+///  - store: Converts a result to a sample
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+pub fn dequantize_and_idct_wide<T: Copy>(
+    block: &[i16], qt_table: &Aligned32<[i32; 64]>, output: &mut [T], stride: usize, precision: u8,
+    store: impl Fn(i64) -> T,
+)
+{
+    const CONST_BITS: i64 = 13;
+
+    let pass1_bits: i64 = if precision == 8 { 2 } else { 1 };
+
+    let mut workspace = [0_i64; 64];
+
+    let descale = |x: i64, n: i64| (x + (1 << (n - 1))) >> n;
+
+    // Pass 1: process columns from input, store into workspace
+    for col in 0..8
+    {
+        let coeff =
+            |row: usize| i64::from(block[row * 8 + col]) * i64::from(qt_table.0[row * 8 + col]);
+
+        if (1..8).all(|row| block[row * 8 + col] == 0)
+        {
+            // AC terms all zero
+            let dc = coeff(0) << pass1_bits;
+
+            (0..8).for_each(|row| workspace[row * 8 + col] = dc);
+
+            continue;
+        }
+
+        let out = idct_1d(
+            [
+                coeff(0),
+                coeff(1),
+                coeff(2),
+                coeff(3),
+                coeff(4),
+                coeff(5),
+                coeff(6),
+                coeff(7),
+            ],
+            CONST_BITS,
+        );
+
+        for (row, value) in out.iter().enumerate()
+        {
+            workspace[row * 8 + col] = descale(*value, CONST_BITS - pass1_bits);
+        }
+    }
+
+    // Pass 2: process rows from workspace, store into output
+    for (row, out) in workspace.chunks_exact(8).zip(output.chunks_mut(stride))
+    {
+        let out = &mut out[..8];
+
+        if row[1..].iter().all(|x| *x == 0)
+        {
+            out.fill(store(descale(row[0], pass1_bits + 3)));
+
+            continue;
+        }
+
+        let values = idct_1d(row.try_into().unwrap(), CONST_BITS);
+
+        for (pixel, value) in out.iter_mut().zip(values.iter())
+        {
+            *pixel = store(descale(*value, CONST_BITS + pass1_bits + 3));
+        }
+    }
+}
+
+/// A single 1D pass of `jpeg_idct_islow`, results are scaled up by
+/// `1 << const_bits`
+fn idct_1d(x: [i64; 8], const_bits: i64) -> [i64; 8]
+{
+    // Even part
+    let z1 = (x[2] + x[6]) * 4433;
+    let tmp2 = z1 + x[6] * -15137;
+    let tmp3 = z1 + x[2] * 6270;
+
+    let tmp0 = (x[0] + x[4]) << const_bits;
+    let tmp1 = (x[0] - x[4]) << const_bits;
+
+    let tmp10 = tmp0 + tmp3;
+    let tmp13 = tmp0 - tmp3;
+    let tmp11 = tmp1 + tmp2;
+    let tmp12 = tmp1 - tmp2;
+
+    // Odd part
+    let (mut tmp0, mut tmp1, mut tmp2, mut tmp3) = (x[7], x[5], x[3], x[1]);
+
+    let z1 = tmp0 + tmp3;
+    let z2 = tmp1 + tmp2;
+    let z3 = tmp0 + tmp2;
+    let z4 = tmp1 + tmp3;
+    let z5 = (z3 + z4) * 9633;
+
+    tmp0 *= 2446;
+    tmp1 *= 16819;
+    tmp2 *= 25172;
+    tmp3 *= 12299;
+
+    let z1 = z1 * -7373;
+    let z2 = z2 * -20995;
+    let z3 = z3 * -16069 + z5;
+    let z4 = z4 * -3196 + z5;
+
+    tmp0 += z1 + z3;
+    tmp1 += z2 + z4;
+    tmp2 += z2 + z3;
+    tmp3 += z1 + z4;
+
+    [
+        tmp10 + tmp3,
+        tmp11 + tmp2,
+        tmp12 + tmp1,
+        tmp13 + tmp0,
+        tmp13 - tmp0,
+        tmp12 - tmp1,
+        tmp11 - tmp2,
+        tmp10 - tmp3,
+    ]
+}
@@ -0,0 +1,229 @@
+//! IPTC-IIM metadata from Photoshop resource 1028
+//!
+//! The metadata is a list of datasets (IPTC-NAA Information Interchange Model,
+//! chapter 6), each made of a tag marker (0x1C), a record number, a dataset
+//! number and the length of its data. Record 2, the application record, holds
+//! what people look for, e.g. captions, keywords, credit and copyright notices.
+//!
+//! Text is UTF-8 when record 1 says so with its coded character set dataset.
+//! Many writers don't say anything, we then use UTF-8 if the text is valid
+//! UTF-8, and Latin-1 otherwise.
+
+/// Escape sequence of the coded character set dataset (1:90) for UTF-8
+const UTF8_ESCAPE: &[u8] = b"\x1B%G";
+
+/// A dataset
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IptcDataset
+{
+    /// Record number, 2 for the application record
+    pub record:  u8,
+    /// Dataset number, e.g. 25 for keywords
+    pub dataset: u8,
+    pub data:    Vec<u8>,
+}
+
+/// IPTC-IIM metadata of an image
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Iptc
+{
+    pub(crate) datasets: Vec<IptcDataset>,
+}
+
+impl Iptc
+{
+    /// Parse datasets from resource 1028
+    ///
+    /// Returns `None` if there isn't a single valid dataset.
+    pub(crate) fn parse(data: &[u8]) -> Option<Iptc>
+    {
+        let mut datasets = Vec::new();
+        let mut position = 0;
+
+        // the resource is sometimes padded with zeros
+        while data.get(position).is_some_and(|x| *x == 0x1C)
+        {
+            let Some((dataset, end)) = parse_dataset(data, position)
+            else
+            {
+                warn!("Invalid IPTC dataset at {position}, ignoring the rest of the datasets");
+                break;
+            };
+            datasets.push(dataset);
+            position = end;
+        }
+
+        if datasets.is_empty()
+        {
+            return None;
+        }
+
+        Some(Iptc { datasets })
+    }
+
+    /// All datasets, in the order they were found
+    #[must_use]
+    pub fn datasets(&self) -> &[IptcDataset]
+    {
+        &self.datasets
+    }
+
+    /// The data of the first dataset `record:dataset`
+    #[must_use]
+    pub fn get(&self, record: u8, dataset: u8) -> Option<&[u8]>
+    {
+        self.get_all(record, dataset).next()
+    }
+
+    /// The data of all datasets `record:dataset`, for repeatable datasets
+    pub fn get_all(&self, record: u8, dataset: u8) -> impl Iterator<Item = &[u8]>
+    {
+        self.datasets
+            .iter()
+            .filter(move |x| x.record == record && x.dataset == dataset)
+            .map(|x| &x.data[..])
+    }
+
+    /// The text of the first dataset `record:dataset`
+    #[must_use]
+    pub fn get_str(&self, record: u8, dataset: u8) -> Option<String>
+    {
+        self.get(record, dataset).map(|x| self.text(x))
+    }
+
+    /// The text of all datasets `record:dataset`, for repeatable datasets
+    #[must_use]
+    pub fn get_all_str(&self, record: u8, dataset: u8) -> Vec<String>
+    {
+        self.get_all(record, dataset)
+            .map(|x| self.text(x))
+            .collect()
+    }
+
+    /// Decode text as the coded character set says
+    fn text(&self, data: &[u8]) -> String
+    {
+        let utf8 = self.get(1, 90) == Some(UTF8_ESCAPE);
+
+        match std::str::from_utf8(data)
+        {
+            Ok(text) => text.to_string(),
+            Err(_) if utf8 => String::from_utf8_lossy(data).into_owned(),
+            // Latin-1 code points are the byte values
+            Err(_) => data.iter().map(|x| char::from(*x)).collect(),
+        }
+    }
+
+    /// Short name of the object, e.g. a title (2:05)
+    #[must_use]
+    pub fn object_name(&self) -> Option<String>
+    {
+        self.get_str(2, 5)
+    }
+
+    /// Keywords (2:25)
+    #[must_use]
+    pub fn keywords(&self) -> Vec<String>
+    {
+        self.get_all_str(2, 25)
+    }
+
+    /// When the object was created, as `CCYYMMDD` (2:55)
+    #[must_use]
+    pub fn date_created(&self) -> Option<String>
+    {
+        self.get_str(2, 55)
+    }
+
+    /// Names of the creators, e.g. photographers (2:80)
+    #[must_use]
+    pub fn by_line(&self) -> Vec<String>
+    {
+        self.get_all_str(2, 80)
+    }
+
+    /// City where the object was made (2:90)
+    #[must_use]
+    pub fn city(&self) -> Option<String>
+    {
+        self.get_str(2, 90)
+    }
+
+    /// Country where the object was made (2:101)
+    #[must_use]
+    pub fn country(&self) -> Option<String>
+    {
+        self.get_str(2, 101)
+    }
+
+    /// Headline (2:105)
+    #[must_use]
+    pub fn headline(&self) -> Option<String>
+    {
+        self.get_str(2, 105)
+    }
+
+    /// Provider of the object, e.g. an agency (2:110)
+    #[must_use]
+    pub fn credit(&self) -> Option<String>
+    {
+        self.get_str(2, 110)
+    }
+
+    /// Original owner of the object (2:115)
+    #[must_use]
+    pub fn source(&self) -> Option<String>
+    {
+        self.get_str(2, 115)
+    }
+
+    /// Copyright notice (2:116)
+    #[must_use]
+    pub fn copyright(&self) -> Option<String>
+    {
+        self.get_str(2, 116)
+    }
+
+    /// Caption, a description of the object (2:120)
+    #[must_use]
+    pub fn caption(&self) -> Option<String>
+    {
+        self.get_str(2, 120)
+    }
+}
+
+/// Parse the dataset at `position`, returning it and where the next one starts
+fn parse_dataset(data: &[u8], position: usize) -> Option<(IptcDataset, usize)>
+{
+    let header = data.get(position..position + 5)?;
+    let length = u16::from_be_bytes([header[3], header[4]]);
+    let mut start = position + 5;
+
+    // extended datasets, the low bits say how many bytes the length takes
+    let length = if length & 0x8000 == 0
+    {
+        usize::from(length)
+    }
+    else
+    {
+        let count = usize::from(length & 0x7FFF);
+
+        if count > std::mem::size_of::<usize>()
+        {
+            return None;
+        }
+        let bytes = data.get(start..start + count)?;
+
+        start += count;
+        bytes.iter().fold(0, |x, y| (x << 8) | usize::from(*y))
+    };
+    let end = start.checked_add(length)?;
+
+    let dataset = IptcDataset {
+        record:  header[1],
+        dataset: header[2],
+        data:    data.get(start..end)?.to_vec(),
+    };
+
+    Some((dataset, end))
+}
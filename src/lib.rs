@@ -9,6 +9,29 @@
 //!  - Fast color convert functions
 //!  - RGBA and RGBX (4-Channel) color conversion functions
 //!  - YCbCr to GrayScale conversion.
+//!  - CMYK and YCCK (e.g. from Adobe applications) to CMYK and RGB(A) conversion.
+//!  - Images stored as RGB, detected from the JFIF and Adobe markers or component ids.
+//!  - Any sampling factors between 1 and 4, e.g. 4:1:1 and 4:1:0 images.
+//!  - Images giving their height in a DNL marker after the first scan, the output of
+//!    baseline images grows as rows are decoded, other images are read ahead to find it.
+//!  - Reading embedded ICC profiles, see `Decoder::icc_profile`, and converting
+//!    colors from them to sRGB.
+//!  - Reading EXIF metadata, see `Decoder::exif`, and turning images upright as their
+//!    orientation says.
+//!  - Reading XMP packets, including extended XMP, see `Decoder::xmp` and
+//!    `Decoder::extended_xmp`.
+//!  - Pixel densities, comments, thumbnails and other application segments, see `ImageInfo`.
+//!  - Reading IPTC metadata and other Photoshop image resources, see `Decoder::photoshop`.
+//!  - Multi-Picture (MPO) files, see `Decoder::mpf` and `Decoder::decode_mp_image`.
+//!  - UltraHDR and ISO 21496-1 gain maps, and decoding such images to HDR, see
+//!    `Decoder::gain_map` and `Decoder::decode_hdr`.
+//!  - Data after the end of the image, e.g. the video of motion photos, see
+//!    `Decoder::end_of_image` and `Decoder::trailer`.
+//!  - Depth maps and other auxiliary images of camera JPEGs, see
+//!    `Decoder::auxiliary_images`.
+//!  - Decoding the embedded preview from EXIF or JFXX segments, see `Decoder::thumbnail`.
+//!  - Decoding from any reader as the data comes, without reading it all first, see
+//!    `Decoder::decode_reader`.
 //!
 //! # Usage
 //! Add zune-jpeg to the dependencies in the project Cargo.toml
@@ -34,6 +57,30 @@
 //! decoder.decode_file("a_jpeg_file");
 //! ```
 //!
+//! ## Decode a CMYK or YCCK JPEG file to CMYK samples
+//! ```no_run
+//! use zune_jpeg::{ColorSpace, Decoder, ZuneJpegOptions};
+//! // samples are inverted, as Adobe applications store them
+//! let mut decoder = Decoder::new_with_options(ZuneJpegOptions::new().set_out_colorspace(ColorSpace::CMYK));
+//! let pixels = decoder.decode_file("a_cmyk_jpeg_file").unwrap();
+//! ```
+//!
+//! ## Decode a JPEG image from a socket
+//! ```no_run
+//! use std::net::TcpStream;
+//! use zune_jpeg::Decoder;
+//! let stream = TcpStream::connect("127.0.0.1:8080").unwrap();
+//! // bytes are read a few kilobytes at a time, as they are needed
+//! let pixels = Decoder::new().decode_reader(stream).unwrap();
+//! ```
+//!
+//! ## Decode a 12 bit JPEG file at full precision
+//! ```no_run
+//! use zune_jpeg::Decoder;
+//! // samples lie between 0 and 4095
+//! let mut pixels = Decoder::new().decode_file_u16("a_12_bit_jpeg_file").unwrap();
+//! ```
+//!
 //! ## Decode an image and get it's width and height.
 //! ```no_run
 //! use zune_jpeg::{Decoder, ZuneJpegOptions};
@@ -86,23 +133,38 @@
 #[macro_use]
 extern crate log;
 
-pub use crate::decoder::{Decoder, ImageInfo};
+pub use crate::decoder::{AdobeInfo, AppSegment, Decoder, ImageInfo, JfifThumbnail};
 pub use crate::misc::ColorSpace;
 pub use crate::options::ZuneJpegOptions;
+pub use crate::thumbnail::Thumbnail;
 
+mod arithmetic;
+pub mod auxiliary;
 mod bitstream;
 mod color_convert;
 mod components;
+pub mod container;
 mod decoder;
 pub mod errors;
+pub mod exif;
+pub mod gainmap;
 mod headers;
+mod hierarchical;
 mod huffman;
+mod icc;
 mod idct;
+pub mod iptc;
+mod lossless;
 mod marker;
 mod mcu;
 mod mcu_prog;
 mod misc;
+pub mod mpf;
 mod options;
+pub mod photoshop;
+mod reader;
+mod thumbnail;
 mod unsafe_utils;
 mod upsampler;
 mod worker;
+mod xmp;
@@ -0,0 +1,371 @@
+//! Routines for lossless decoding
+//!
+//! Lossless images (Annex H of the spec) don't use the DCT, instead each sample is
+//! predicted from its already decoded neighbours and only the difference
+//! between the prediction and the actual sample is Huffman coded.
+//!
+//! Differences are coded the same way DC coefficients are, so we reuse the DHT parsing
+//! and the bitstream, but reconstruction happens directly on samples, there is no
+//! quantization, IDCT or color conversion.
+//!
+//! Within a scan, an MCU of an interleaved scan contains `H*V` samples of each component,
+//! while a non-interleaved scan has a single sample per MCU.
+
+use crate::bitstream::BitStream;
+use crate::decoder::MAX_COMPONENTS;
+use crate::errors::DecodeErrors;
+use crate::headers::{parse_huffman, parse_sos};
+use crate::marker::Marker;
+use crate::mcu_prog::get_marker;
+use crate::reader::ByteReader;
+use crate::worker::{interleave_u16, upsample_component_u16};
+use crate::Decoder;
+
+impl Decoder
+{
+    /// Decode a lossless image into 16 bit samples
+    ///
+    /// Components are returned interleaved in the order they appear in the frame
+    /// header without any color conversion, since lossless images are usually stored in the
+    /// colorspace they were captured in.
+    pub(crate) fn decode_lossless(
+        &mut self, reader: &mut ByteReader,
+    ) -> Result<Vec<u16>, DecodeErrors>
+    {
+        let (planes, _) = self.decode_lossless_planes(reader)?;
+
+        Ok(self.finish_lossless_decoding(planes))
+    }
+
+    /// Decode all scans of a lossless frame
+    ///
+    /// Returns a plane for each component, padded to whole MCU's, and the marker
+    /// which ended the frame.
+    pub(crate) fn decode_lossless_planes(
+        &mut self, reader: &mut ByteReader,
+    ) -> Result<(Vec<Vec<u16>>, Marker), DecodeErrors>
+    {
+        let (mcu_x, mcu_y) = self.lossless_mcus();
+
+        // each component is stored in a plane padded to whole MCU's
+        let mut planes: Vec<Vec<u16>> = self
+            .components
+            .iter()
+            .map(|c| vec![0; mcu_x * c.horizontal_sample * mcu_y * c.vertical_sample])
+            .collect();
+
+        let mut stream = BitStream::new();
+        let mut seen_scans = 1;
+
+        self.parse_lossless_scan(reader, &mut stream, &mut planes)?;
+
+        let mut marker = get_marker(reader, &mut stream.marker)
+            .ok_or(DecodeErrors::FormatStatic("Marker missing where expected"))?;
+
+        while marker != Marker::EOI
+        {
+            match marker
+            {
+                Marker::DHT =>
+                {
+                    parse_huffman(self, reader)?;
+                }
+                // encoders may change the restart interval between scans,
+                // and give the height after the first scan
+                Marker::DRI | Marker::DNL =>
+                {
+                    self.parse_marker_inner(marker, reader)?;
+                }
+                Marker::SOS =>
+                {
+                    parse_sos(reader, self)?;
+
+                    self.parse_lossless_scan(reader, &mut stream, &mut planes)?;
+
+                    seen_scans += 1;
+
+                    if seen_scans > self.options.get_max_scans()
+                    {
+                        return Err(DecodeErrors::Format(format!(
+                            "Too many scans, exceeded limit of {}",
+                            self.options.get_max_scans()
+                        )));
+                    }
+                }
+                _ => break,
+            }
+
+            marker = get_marker(reader, &mut stream.marker)
+                .ok_or(DecodeErrors::FormatStatic("Marker missing where expected"))?;
+        }
+
+        Ok((planes, marker))
+    }
+
+    /// Number of MCU's of an interleaved scan in the x and y plane
+    pub(crate) fn lossless_mcus(&self) -> (usize, usize)
+    {
+        (
+            usize::from(self.info.width).div_ceil(self.h_max),
+            usize::from(self.info.height).div_ceil(self.v_max),
+        )
+    }
+
+    /// Decode a single lossless scan, reconstructing samples of the components
+    /// in the scan into `planes`
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::too_many_lines
+    )]
+    fn parse_lossless_scan(
+        &mut self, reader: &mut ByteReader, stream: &mut BitStream, planes: &mut [Vec<u16>],
+    ) -> Result<(), DecodeErrors>
+    {
+        stream.reset();
+
+        let num_scans = usize::from(self.num_scans);
+
+        if num_scans > self.components.len()
+        {
+            return Err(DecodeErrors::Format(format!(
+                "Number of scans {} cannot be greater than number of components, {}",
+                num_scans,
+                self.components.len()
+            )));
+        }
+
+        let scan_components = &self.z_order[..num_scans];
+        let mut tables = Vec::with_capacity(num_scans);
+
+        for &k in scan_components
+        {
+            let component = self.components.get(k).ok_or_else(|| {
+                DecodeErrors::Format(format!("Cannot find component {k}, corrupt image"))
+            })?;
+
+            let table = self
+                .dc_huffman_tables
+                .get(component.dc_huff_table & (MAX_COMPONENTS - 1))
+                .and_then(Option::as_ref)
+                .ok_or(DecodeErrors::FormatStatic(
+                    "No DC huffman table for component",
+                ))?;
+
+            tables.push(table);
+        }
+
+        let (mcu_x, mcu_y) = self.lossless_mcus();
+        let width = usize::from(self.info.width);
+        let height = usize::from(self.info.height);
+
+        let predictor = self.spec_start;
+        let point_transform = self.succ_low;
+        // prediction for the first sample of the scan and of each restart interval,
+        // differential frames code differences from their reference which aren't predicted
+        let initial = if self.info.sof.is_differential()
+        {
+            0
+        }
+        else
+        {
+            1_i32 << (self.info.precision - point_transform - 1)
+        };
+
+        // The first sample after a restart uses `initial` as its prediction, the
+        // rest of its row is predicted from the left and the first sample of other rows
+        // from above.
+        let mut needs_reset = [true; MAX_COMPONENTS];
+        let mut first_row = [0; MAX_COMPONENTS];
+
+        let mut todo = self.restart_interval;
+
+        let mut decode_sample = |reader: &mut ByteReader,
+                                 stream: &mut BitStream,
+                                 n: usize,
+                                 x: usize,
+                                 y: usize,
+                                 needs_reset: &mut [bool; MAX_COMPONENTS]|
+         -> Result<(), DecodeErrors> {
+            let k = scan_components[n];
+            let component = &self.components[k];
+            let stride = mcu_x * component.horizontal_sample;
+            let plane = &mut planes[k];
+
+            let diff = stream.decode_lossless_diff(reader, tables[n])?;
+            let pos = y * stride + x;
+
+            let prediction = if predictor == 0
+            {
+                0
+            }
+            else if needs_reset[n]
+            {
+                needs_reset[n] = false;
+                first_row[n] = y;
+
+                initial
+            }
+            else if y == first_row[n]
+            {
+                i32::from(plane[pos - 1])
+            }
+            else if x == 0
+            {
+                i32::from(plane[pos - stride])
+            }
+            else
+            {
+                let ra = i32::from(plane[pos - 1]);
+                let rb = i32::from(plane[pos - stride]);
+                let rc = i32::from(plane[pos - stride - 1]);
+
+                predict(ra, rb, rc, predictor)
+            };
+            // reconstruction is modulo 2^16
+            plane[pos] = (prediction + diff) as u16;
+
+            Ok(())
+        };
+
+        if num_scans == 1
+        {
+            // non-interleaved scans contain only the samples inside the component
+            let component = &self.components[scan_components[0]];
+            let c_width = (width * component.horizontal_sample).div_ceil(self.h_max);
+            let c_height = (height * component.vertical_sample).div_ceil(self.v_max);
+
+            for y in 0..c_height
+            {
+                for x in 0..c_width
+                {
+                    decode_sample(reader, stream, 0, x, y, &mut needs_reset)?;
+
+                    todo = todo.wrapping_sub(1);
+
+                    if todo == 0
+                    {
+                        todo = self.restart_interval;
+
+                        if handle_lossless_rst(reader, stream)
+                        {
+                            needs_reset = [true; MAX_COMPONENTS];
+                        }
+                    }
+                }
+            }
+        }
+        else
+        {
+            for i in 0..mcu_y
+            {
+                for j in 0..mcu_x
+                {
+                    for (n, &k) in scan_components.iter().enumerate()
+                    {
+                        let component = &self.components[k];
+                        let (h_samp, v_samp) =
+                            (component.horizontal_sample, component.vertical_sample);
+
+                        for v in 0..v_samp
+                        {
+                            for h in 0..h_samp
+                            {
+                                let x = j * h_samp + h;
+                                let y = i * v_samp + v;
+
+                                decode_sample(reader, stream, n, x, y, &mut needs_reset)?;
+                            }
+                        }
+                    }
+
+                    todo = todo.wrapping_sub(1);
+
+                    if todo == 0
+                    {
+                        todo = self.restart_interval;
+
+                        if handle_lossless_rst(reader, stream)
+                        {
+                            needs_reset = [true; MAX_COMPONENTS];
+                        }
+                    }
+                }
+            }
+        }
+
+        if point_transform != 0
+        {
+            for &k in scan_components
+            {
+                planes[k].iter_mut().for_each(|x| *x <<= point_transform);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Up-sample components if needed and interleave them into the output
+    fn finish_lossless_decoding(&self, planes: Vec<Vec<u16>>) -> Vec<u16>
+    {
+        let (mcu_x, _) = self.lossless_mcus();
+        let width = usize::from(self.info.width);
+        let height = usize::from(self.info.height);
+
+        let planes: Vec<(Vec<u16>, usize)> = self
+            .components
+            .iter()
+            .zip(planes)
+            .map(|(component, plane)| {
+                let stride = mcu_x * component.horizontal_sample;
+
+                upsample_component_u16(
+                    plane, stride, component, width, height, self.h_max, self.v_max,
+                )
+            })
+            .collect();
+
+        interleave_u16(&planes, width, height)
+    }
+}
+
+/// Compute the prediction for a sample from its left(`ra`), upper(`rb`)
+/// and upper left(`rc`) neighbours
+///
+/// See Table H.1 of the spec, predictor 0 (no prediction) is handled by the caller
+#[inline]
+fn predict(ra: i32, rb: i32, rc: i32, predictor: u8) -> i32
+{
+    match predictor
+    {
+        1 => ra,
+        2 => rb,
+        3 => rc,
+        4 => ra + rb - rc,
+        5 => ra + ((rb - rc) >> 1),
+        6 => rb + ((ra - rc) >> 1),
+        _ => (ra + rb) >> 1,
+    }
+}
+
+/// Handle the end of a restart interval
+///
+/// Returns true if we found an RST marker and predictions should start afresh.
+/// Other markers are left for the scan loop to handle
+#[cold]
+fn handle_lossless_rst(reader: &mut ByteReader, stream: &mut BitStream) -> bool
+{
+    if stream.marker.is_none()
+    {
+        stream.marker = get_marker(reader, &mut None);
+    }
+
+    if let Some(Marker::RST(_)) = stream.marker
+    {
+        stream.reset();
+
+        return true;
+    }
+
+    false
+}
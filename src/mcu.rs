@@ -27,9 +27,9 @@
 //!
 //!But as easy as this sounds in theory, in practice, it sucks...
 //!
-//! We essentially have to consider that down-sampled images have weird MCU arrangement and for such cases
-//! ! choose the path of decoding 2 whole MCU heights for horizontal/vertical upsampling and
-//! 4 whole MCU heights for horizontal and vertical upsampling, which when expressed in code doesn't look nice.
+//! We essentially have to consider that up-sampling a row of MCU's reaches into the rows of blocks
+//! above and below it. So a few rows of MCU's are post processed together once the rows after them
+//! are decoded, with copies of the rows of blocks bordering them, which when expressed in code doesn't look nice.
 //!
 //! There is also the overhead of synchronization which makes some things annoying.
 //!
@@ -54,14 +54,17 @@
 //! faster code in post processing..
 
 use std::cmp::min;
-use std::io::Cursor;
 use std::sync::Arc;
 
 use crate::bitstream::BitStream;
-use crate::components::{ComponentID, SubSampRatios};
 use crate::errors::DecodeErrors;
+use crate::headers::parse_dnl;
 use crate::marker::Marker;
-use crate::worker::post_process;
+use crate::reader::ByteReader;
+use crate::upsampler::UpSampler;
+use crate::worker::{
+    mcu_rows_per_task, post_process, GrowingOutput, McuRow, OrientedOutput, OutputBand,
+};
 use crate::{ColorSpace, Decoder};
 
 /// The size of a DC block for a MCU.
@@ -125,7 +128,7 @@ impl Decoder
     #[inline(never)]
     #[rustfmt::skip]
     pub(crate) fn decode_mcu_ycbcr_baseline(
-        &mut self, reader: &mut Cursor<Vec<u8>>,
+        &mut self, reader: &mut ByteReader,
     ) -> Result<Vec<u8>, DecodeErrors>
     {
         self.check_component_dimensions()?;
@@ -136,46 +139,17 @@ impl Decoder
             self.options.get_threads());
         info!("Created {} worker threads", scoped_pools.thread_count());
 
-        let (mut mcu_width, mut mcu_height);
-        let mut bias = 1;
-
-        if self.interleaved
-        {
-            // set upsampling functions
-            self.set_upsampling()?;
-
-            if self.sub_sample_ratio == SubSampRatios::H
-            {
-                // horizontal sub-sampling.
-
-                // Values for horizontal samples end halfway the image and do not complete an MCU width.
-                // To make it complete we multiply width by 2 and divide mcu_height by 2
-                mcu_width = self.mcu_x * 2;
-                mcu_height = self.mcu_y / 2;
-            } else if self.sub_sample_ratio == SubSampRatios::HV
-            {
-                mcu_width = self.mcu_x;
-                mcu_height = self.mcu_y / 2;
-                bias = 2;
-            } else {
-                mcu_width = self.mcu_x;
-                mcu_height = self.mcu_y;
-            }
-        } else {
-            // For non-interleaved images( (1*1) subsampling)
-            // number of MCU's are the widths (+7 to account for paddings) divided bu 8.
-            mcu_width = ((self.info.width + 7) / 8) as usize;
-            mcu_height = ((self.info.height + 7) / 8) as usize;
-        }
+        let mut mcu_width = self.mcu_x;
+        let mut mcu_height = self.mcu_y;
 
         if self.input_colorspace == ColorSpace::GRAYSCALE && self.interleaved {
             /*
             Apparently, grayscale images which can be down sampled exists, which is weird in the sense
             that it has one component Y, which is not usually down sampled.
 
-            This means some calculations will be wrong, so for that we explicitly reset params
-            for such occurrences, warn and reset the image info to appear as if it were
-            a non-sampled image to ensure decoding works
+            Its scan isn't interleaved though, so it's made of blocks rather than MCU's.
+            For that we explicitly reset params for such occurrences, warn and reset the image
+            info to appear as if it were a non-sampled image to ensure decoding works
             */
             if self.options.get_strict_mode(){
                 return Err(DecodeErrors::FormatStatic("[strict-mode]: Grayscale image with down-sampled component."))
@@ -187,196 +161,258 @@ impl Decoder
             self.h_max = 1;
             self.options = self.options.set_out_colorspace(ColorSpace::GRAYSCALE);
             self.v_max = 1;
-            self.sub_sample_ratio = SubSampRatios::None;
             self.components[0].vertical_sample = 1;
             self.components[0].width_stride = mcu_width * 8;
             self.components[0].horizontal_sample = 1;
             mcu_height = ((self.info.height + 7) / 8) as usize;
-            bias = 1;
         }
-        // Size of our output image(width*height)
-        let capacity = usize::from(self.info.width + 8) * usize::from(self.info.height + 8);
-        let component_capacity = mcu_width * DCT_BLOCK;
-        // Create an Arc of components to prevent cloning on every MCU width
-        let global_component = Arc::new(self.components.clone());
-        let is_hv = self.sub_sample_ratio == SubSampRatios::HV;
-        // There are some images where we need to overallocate  especially for small buffers,
-        // because the chunking calculation will do it wrongly,
-        // this only applies to  small down-sampled images
-        // See https://github.com/etemesi254/zune-jpeg/issues/11
-        let extra_space = usize::from(self.interleaved) * 128 * usize::from(self.height()) * self.options.get_out_colorspace().num_components();
+        self.set_color_convert();
         // things needed for post processing that we can remove out of the loop
         let input = self.input_colorspace;
         let output = self.options.get_out_colorspace();
         let idct_func = self.idct_func;
         let color_convert_16 = self.color_convert_16;
-        let width = usize::from(self.width());
+        let use_unsafe = self.options.get_use_unsafe();
+        let icc = self.icc_transform(255);
+        let icc = icc.as_ref();
+        let width = usize::from(self.info.width);
+        let mut height = usize::from(self.info.height);
         let h_max = self.h_max;
         let v_max = self.v_max;
-        // Halfway width size, used for vertical sub-sampling to write |Y2| in the right position.
-        let width_stride = (component_capacity * self.components[0].vertical_sample * self.components[0].horizontal_sample * bias) >> 1;
-        let hv_width_stride = width_stride >> 1;
+        // components we need for the output, e.g. only Y for grayscale
+        let needed = min(input.num_components(), output.num_components());
+        // Create an Arc of components to prevent cloning on every MCU width
+        let global_component = Arc::new(self.components.clone());
+        // Components whose up-sampling needs the rows of blocks bordering the MCU rows
+        let uses_neighbours: Vec<bool> = self.components.iter()
+            .map(|c| UpSampler::new(c, width, height, h_max, v_max).uses_neighbours())
+            .collect();
+        // Images whose height comes in a DNL segment after the scan are decoded until the
+        // segment is found, their output grows as rows are post processed and is turned
+        // once it's complete.
+        let dnl = height == 0;
+        let orientation = if dnl { 1 } else { self.orientation() };
+        let rows_per_task = mcu_rows_per_task(uses_neighbours.contains(&true), orientation);
+
+        if dnl
+        {
+            mcu_height = usize::MAX;
+        }
 
         let mut stream = BitStream::new();
         // Storage for decoded pixels
-        let mut global_channel = vec![0; (capacity * self.options.get_out_colorspace().num_components()) + extra_space];
+        let mut global_channel = vec![0; width * height * output.num_components()];
 
-        // Split output into different blocks each containing enough space for an MCU width
-        let mut chunks =
-            global_channel.chunks_exact_mut(width * output.num_components() * 8 * h_max * v_max);
+        // Split output into different blocks each containing enough space for a few MCU rows,
+        // where they land once turned by the EXIF orientation
+        let mut chunks = OrientedOutput::new(&mut global_channel, width, height,
+                                             output.num_components(), orientation);
+        let mut growing = GrowingOutput::new();
         let mut tmp = [0; DCT_BLOCK];
 
+        // Coefficients of the last rows of MCU's we decoded, they're post processed
+        // once we decode the next ones, which their up-sampling may reach into.
+        let mut pending: Option<[Vec<i16>; 3]> = None;
+        // The last row of blocks before them
+        let mut above: [Vec<i16>; 3] = [vec![], vec![], vec![]];
+        let mut tasks = mcu_height.div_ceil(rows_per_task);
+
         // Argument for scoped threadpools, see file docs.
         scoped_pools.scoped::<_, Result<(), DecodeErrors>>(|scope| {
-            for _ in 0..mcu_height
+            for i in 0..
             {
+                if i > tasks
+                {
+                    break;
+                }
                 // faster to memset than a later memcpy
 
-                // We allocate on every mcu_height since this is sent to a separate
+                // We allocate on every task since this is sent to a separate
                 // thread (that's how we're multi-threaded and thread safe).
 
                 let mut temporary = [vec![], vec![], vec![]];
 
-                for (pos, comp) in self.components.iter().enumerate()
+                if i < tasks
                 {
-                    // multiply capacity with sampling factor, it  should be 1*1 for un-sampled images
-                    // Allocate only needed components.
-                    if min(self.options.get_out_colorspace().num_components() - 1, pos) == pos
-                    {
-                        let len = component_capacity * comp.vertical_sample * comp.horizontal_sample * bias;
+                    let rows = min(rows_per_task, mcu_height - i * rows_per_task);
 
-                        temporary[pos] = vec![0; len];
-                    }
-                }
-                // Bias only affects 4:2:0(chroma quartered) sub-sampled images.
-                // since we want to fetch two MCU rows before we send it to post process
-                for v in 0..bias
-                {
-                    for j in 0..mcu_width
+                    for (pos, comp) in self.components.iter().enumerate().take(needed)
                     {
-                        // iterate over components
+                        // blocks of the component in the rows of MCU's
+                        temporary[pos] = vec![0; comp.width_stride * 8 * comp.vertical_sample * rows];
+                    }
 
-                        for pos in 0..self.input_colorspace.num_components()
+                    for row in 0..rows
+                    {
+                        if self.info.height == 0
                         {
-                            let component = &mut self.components[pos];
-                            let dc_table = self.dc_huffman_tables[component.dc_huff_table & 3]
-                                .as_ref()
-                                .ok_or_else(|| {
-                                    DecodeErrors::HuffmanDecode(format!(
-                                        "No DC table for component {:?}",
-                                        component.component_id
-                                    ))
-                                })?;
-                            let ac_table = self.ac_huffman_tables[component.ac_huff_table & 3]
-                                .as_ref()
-                                .ok_or_else(|| {
-                                    DecodeErrors::HuffmanDecode(format!(
-                                        "No AC table for component {:?}",
-                                        component.component_id
-                                    ))
-                                })?;
-
-                            // If image is interleaved iterate over scan  components,
-                            // otherwise if it-s non-interleaved, these routines iterate in
-                            // trivial scanline order(Y,Cb,Cr)
-                            for v_samp in 0..component.vertical_sample
+                            self.read_dnl_in_scan(&mut stream, reader, i * rows_per_task + row)?;
+
+                            if self.info.height != 0
                             {
-                                for h_samp in 0..component.horizontal_sample
-                                {
-                                    // only decode needed components
-                                    if min(self.options.get_out_colorspace().num_components() - 1, pos) == pos
-                                    {
-                                        // The spec  https://www.w3.org/Graphics/JPEG/itu-t81.pdf page 26
-
-                                        // Get position to write
-                                        // This is complex, don't even try to understand it. ~author
-                                        let is_y =
-                                            usize::from(component.component_id == ComponentID::Y);
-                                        // This only affects 4:2:0 images.
-                                        let y_offset = is_y
-                                            * v
-                                            * (hv_width_stride
-                                            + (hv_width_stride * (component.vertical_sample - 1)));
-                                        let another_stride =
-                                            (width_stride * v_samp * usize::from(!is_hv))
-                                                + hv_width_stride * v_samp * usize::from(is_hv);
-                                        let yet_another_stride = usize::from(is_hv)
-                                            * (width_stride >> 2)
-                                            * v
-                                            * usize::from(component.component_id != ComponentID::Y);
-                                        // offset calculator.
-                                        let start = (j * 64 * component.horizontal_sample)
-                                            + (h_samp * 64)
-                                            + another_stride
-                                            + y_offset
-                                            + yet_another_stride;
-                                        // It will always be zero since it's initialized per MCU height.
-                                        let tmp: &mut [i16; 64] = temporary.get_mut(pos).unwrap().get_mut(start..start + 64).unwrap().try_into().unwrap();
-
-                                        stream.decode_mcu_block(reader, dc_table, ac_table, tmp, &mut component.dc_pred)?;
-                                    } else {
-                                        // component not needed, decode and discard bits
-                                        stream.decode_mcu_block(reader, dc_table, ac_table, &mut tmp, &mut component.dc_pred)?;
-                                    }
-                                }
+                                height = usize::from(self.info.height);
+                                mcu_height = height.div_ceil(8 * v_max);
+                                tasks = mcu_height.div_ceil(rows_per_task);
+                                growing.reserve(width * height * output.num_components());
                             }
-                            self.todo = self.todo.wrapping_sub(1);
-                            // after every interleaved MCU that's a mcu, count down restart markers.
-                            if self.todo == 0
+                        }
+                        if i * rows_per_task + row == mcu_height
+                        {
+                            // the scan ended before the rows allocated for it
+                            for (pos, comp) in self.components.iter().enumerate().take(needed)
                             {
-                                self.handle_rst(&mut stream)?;
+                                temporary[pos].truncate(comp.width_stride * 8 * comp.vertical_sample * row);
                             }
+                            break;
+                        }
+                        for j in 0..mcu_width
+                        {
+                            // iterate over components
 
-                            // In some corrupt images, it may occur that header markers occur in the stream.
-                            // The spec EXPLICITLY FORBIDS this, specifically, in
-                            // routine F.2.2.5  it says
-                            // `The only valid marker which may occur within the Huffman coded data is the RSTm marker.`
-                            //
-                            // But libjpeg-turbo allows it because of some weird reason. so I'll also
-                            // allow it because of some weird reason.
-                            if let Some(m) = stream.marker
+                            for pos in 0..self.input_colorspace.num_components()
                             {
-                                if m == Marker::EOI
+                                let component = &mut self.components[pos];
+                                let dc_table = self.dc_huffman_tables[component.dc_huff_table & 3]
+                                    .as_ref()
+                                    .ok_or_else(|| {
+                                        DecodeErrors::HuffmanDecode(format!(
+                                            "No DC table for component {:?}",
+                                            component.component_id
+                                        ))
+                                    })?;
+                                let ac_table = self.ac_huffman_tables[component.ac_huff_table & 3]
+                                    .as_ref()
+                                    .ok_or_else(|| {
+                                        DecodeErrors::HuffmanDecode(format!(
+                                            "No AC table for component {:?}",
+                                            component.component_id
+                                        ))
+                                    })?;
+                                // blocks in a row of the component
+                                let blocks_wide = component.width_stride / 8;
+
+                                // If image is interleaved iterate over scan  components,
+                                // otherwise if it-s non-interleaved, these routines iterate in
+                                // trivial scanline order(Y,Cb,Cr)
+                                for v_samp in 0..component.vertical_sample
+                                {
+                                    for h_samp in 0..component.horizontal_sample
+                                    {
+                                        // only decode needed components
+                                        if pos < needed
+                                        {
+                                            // The spec  https://www.w3.org/Graphics/JPEG/itu-t81.pdf page 26
+
+                                            // blocks are kept in raster order, like the progressive decoder does
+                                            let start = ((row * component.vertical_sample + v_samp) * blocks_wide
+                                                + j * component.horizontal_sample
+                                                + h_samp) * 64;
+                                            // It will always be zero since it's initialized per MCU height.
+                                            let tmp: &mut [i16; 64] = temporary.get_mut(pos).unwrap().get_mut(start..start + 64).unwrap().try_into().unwrap();
+
+                                            stream.decode_mcu_block(reader, dc_table, ac_table, tmp, &mut component.dc_pred)?;
+                                        } else {
+                                            // component not needed, decode and discard bits
+                                            stream.decode_mcu_block(reader, dc_table, ac_table, &mut tmp, &mut component.dc_pred)?;
+                                        }
+                                    }
+                                }
+                                self.todo = self.todo.wrapping_sub(1);
+                                // after every interleaved MCU that's a mcu, count down restart markers.
+                                if self.todo == 0
                                 {
-                                    break;
+                                    self.handle_rst(&mut stream)?;
                                 }
 
-                                if let Marker::RST(_) = m { continue }
+                                // In some corrupt images, it may occur that header markers occur in the stream.
+                                // The spec EXPLICITLY FORBIDS this, specifically, in
+                                // routine F.2.2.5  it says
+                                // `The only valid marker which may occur within the Huffman coded data is the RSTm marker.`
+                                //
+                                // But libjpeg-turbo allows it because of some weird reason. so I'll also
+                                // allow it because of some weird reason.
+                                if let Some(m) = stream.marker
+                                {
+                                    // The stream reads ahead, so it may reach the end of the image while
+                                    // a few MCU's remain in its buffer, keep decoding those.
+                                    if let Marker::RST(_) | Marker::DNL | Marker::EOI = m { continue }
 
-                                error!("Marker `{:?}` Found within Huffman Stream, possibly corrupt jpeg",m);
-                                self.parse_marker_inner(m, reader)?;
+                                    error!("Marker `{:?}` Found within Huffman Stream, possibly corrupt jpeg",m);
+                                    self.parse_marker_inner(m, reader)?;
+                                }
                             }
                         }
                     }
                 }
+                let Some(blocks) = pending.take()
+                else
+                {
+                    pending = Some(temporary);
+                    continue;
+                };
+                // Copy the rows of blocks bordering the pending rows, to make multithreading safe
+                let mut below = [vec![], vec![], vec![]];
+                let mut next_above = [vec![], vec![], vec![]];
+
+                for (pos, comp) in self.components.iter().enumerate().take(needed)
+                {
+                    if uses_neighbours[pos]
+                    {
+                        let row_len = comp.width_stride * 8;
+
+                        below[pos] = temporary[pos].get(..row_len).unwrap_or_default().to_vec();
+                        next_above[pos] = blocks[pos][blocks[pos].len() - row_len..].to_vec();
+                    }
+                }
+                let above = std::mem::replace(&mut above, next_above);
                 // Clone things, to make multithreading safe
                 let component = global_component.clone();
-                let next_chunk = chunks.next().unwrap();
+                let band_rows = 8 * v_max * rows_per_task;
+                let next_chunk = (!dnl).then(|| chunks.next_band(band_rows));
+                let sender = growing.sender();
+                // rows decoded before the height is known aren't the last ones, which is
+                // all their up-sampling needs to know
+                let height = if height == 0 { usize::from(u16::MAX) } else { height };
+                let band_len = min(band_rows, height - (i - 1) * band_rows) * width * output.num_components();
 
                 scope.execute(move || {
 
-                    let mut coeff :[&[i16];3]=[&[];3];
+                    let mut coeff = [McuRow::default(); 3];
 
-                    temporary.iter().enumerate().for_each(|(pos,x)|{
-                        coeff[pos] = x;
+                    for (pos, row) in coeff.iter_mut().enumerate()
+                    {
+                        *row = McuRow { blocks: &blocks[pos], above: &above[pos], below: &below[pos] };
+                    }
+
+                    let mut grown = vec![];
+                    let band = next_chunk.unwrap_or_else(|| {
+                        grown = vec![0; band_len];
+                        OutputBand::Rows(&mut grown)
                     });
 
                     post_process(&coeff, &component,
-                                 idct_func, color_convert_16,
-                                 input, output, next_chunk,
-                                 width);
+                                 idct_func, color_convert_16, use_unsafe,
+                                 input, output, band,
+                                 (i - 1) * rows_per_task, width, height, h_max, v_max, icc);
+
+                    if dnl
+                    {
+                        // the output is only dropped once every task is done
+                        sender.send((i - 1, grown)).unwrap();
+                    }
                 });
+                growing.append();
+                pending = Some(temporary);
             }
             //everything is okay
             Ok(())
         })?;
         info!("Finished decoding image");
-        // remove excess allocation for images.
-        global_channel.truncate(
-            usize::from(self.width())
-                * usize::from(self.height())
-                * self.options.get_out_colorspace().num_components(),
-        );
+
+        if dnl
+        {
+            return Ok(self.orient(growing.finish()));
+        }
         return Ok(global_channel);
     }
     // handle RST markers.
@@ -401,7 +437,7 @@ impl Decoder
                     self.components.iter_mut().for_each(|x| x.dc_pred = 0);
                     // Start iterating again. from position.
                 }
-                Marker::EOI =>
+                Marker::EOI | Marker::DNL =>
                 {
                     // silent pass
                 }
@@ -416,4 +452,55 @@ impl Decoder
         }
         Ok(())
     }
+
+    /// Read the DNL segment giving the height of an image once its scan reaches it,
+    /// before decoding row `row` of MCU's
+    ///
+    /// The segment follows the entropy coded data, so it's found once the stream has
+    /// no more than the bits padding the last byte left. Until then the height stays
+    /// zero. Errors if the scan ends without the segment or has more rows than it says.
+    pub(crate) fn read_dnl_in_scan(
+        &mut self, stream: &mut BitStream, reader: &mut ByteReader, row: usize,
+    ) -> Result<(), DecodeErrors>
+    {
+        let missing = DecodeErrors::FormatStatic(
+            "Image height is zero and no DNL marker follows the first scan",
+        );
+
+        if stream.marker.is_none()
+        {
+            if reader.is_empty()
+            {
+                return Err(missing);
+            }
+            stream.read_to_marker(reader)?;
+        }
+
+        match stream.marker
+        {
+            None | Some(Marker::RST(_)) =>
+            {
+                let max_height = self.options.get_max_height();
+
+                if row * 8 * self.v_max >= usize::from(max_height)
+                {
+                    return Err(DecodeErrors::Format(format!("Image height greater than height limit {max_height}, no DNL marker was found after {row} rows of MCU's. If use `set_limits` if you want to support huge images")));
+                }
+            }
+            Some(Marker::DNL) =>
+            {
+                parse_dnl(reader, self)?;
+
+                if row > usize::from(self.info.height).div_ceil(8 * self.v_max)
+                {
+                    return Err(DecodeErrors::Format(format!(
+                        "DNL marker gives {} lines but the scan has {row} rows of MCU's",
+                        self.info.height
+                    )));
+                }
+            }
+            Some(_) => return Err(missing),
+        }
+        Ok(())
+    }
 }
@@ -26,18 +26,20 @@
 //!
 //! So here we use a different scheme. Just decode everything and then finally use threads when post processing.
 
-use std::io::Cursor;
+use std::cmp::min;
 use std::sync::Arc;
 
+use crate::arithmetic::ArithmeticDecoder;
 use crate::bitstream::BitStream;
-use crate::components::{ComponentID, SubSampRatios};
 use crate::decoder::MAX_COMPONENTS;
 use crate::errors::DecodeErrors;
 use crate::errors::DecodeErrors::Format;
-use crate::headers::{parse_huffman, parse_sos};
+use crate::headers::{parse_dac, parse_huffman, parse_sos};
 use crate::marker::Marker;
 use crate::misc::read_byte;
-use crate::worker::post_process;
+use crate::reader::ByteReader;
+use crate::worker::{mcu_rows_per_task, post_process, McuRow, OrientedOutput};
+use crate::upsampler::UpSampler;
 use crate::{ColorSpace, Decoder};
 
 impl Decoder
@@ -47,14 +49,30 @@ impl Decoder
     /// This routine decodes a progressive image, stopping if it finds any error.
     #[rustfmt::skip]
     pub(crate) fn decode_mcu_ycbcr_progressive(
-        &mut self, reader: &mut Cursor<Vec<u8>>,
+        &mut self, reader: &mut ByteReader,
     ) -> Result<Vec<u8>, DecodeErrors>
+    {
+        let (block, ..) = self.decode_coefficients(reader)?;
+
+        self.finish_progressive_decoding(&block)
+    }
+
+    /// Decode all scans in the image, returning the un-transformed
+    /// coefficients of each component, the width of a row of MCU's
+    /// in coefficients and the marker which ended the frame.
+    #[rustfmt::skip]
+    pub(crate) fn decode_coefficients(
+        &mut self, reader: &mut ByteReader,
+    ) -> Result<([Vec<i16>; MAX_COMPONENTS], usize, Marker), DecodeErrors>
     {
         self.check_component_dimensions()?;
         let mcu_height;
 
         // memory location for decoded pixels for components
-        let mut block = [vec![], vec![], vec![]];
+        //
+        // i16's are enough even for 12 bit images, whose coefficients are at most 15 bits
+        // plus a sign, successive approximation only splits them into bits.
+        let mut block = [vec![], vec![], vec![], vec![]];
         let mut mcu_width;
 
         let mut seen_scans = 1;
@@ -80,12 +98,13 @@ impl Decoder
 
         let mut stream = BitStream::new_progressive(self.succ_high, self.succ_low,
                                                     self.spec_start, self.spec_end);
+        let mut arithmetic = ArithmeticDecoder::new();
 
         // there are multiple scans in the stream, this should resolve the first scan
-        self.parse_entropy_coded_data(reader, &mut stream, &mut block)?;
+        self.parse_scan(reader, &mut stream, &mut arithmetic, &mut block)?;
 
         // extract marker
-        let mut marker = stream.marker.take().ok_or(DecodeErrors::FormatStatic("Marker missing where expected"))?;
+        let mut marker = self.take_marker(reader, &mut stream, &mut arithmetic).ok_or(DecodeErrors::FormatStatic("Marker missing where expected"))?;
         // if marker is EOI, we are done, otherwise continue scanning.
         'eoi: while marker != Marker::EOI
         {
@@ -95,6 +114,14 @@ impl Decoder
                 Marker::DHT => {
                     parse_huffman(self, reader)?;
                 }
+                Marker::DAC => {
+                    parse_dac(self, reader)?;
+                }
+                // encoders may change the restart interval between scans,
+                // and give the height after the first scan
+                Marker::DRI | Marker::DNL => {
+                    self.parse_marker_inner(marker, reader)?;
+                }
                 Marker::SOS =>
                     {
                         parse_sos(reader, self)?;
@@ -103,10 +130,10 @@ impl Decoder
                                                          self.spec_start, self.spec_end);
 
                         // after every SOS, marker, parse data for that scan.
-                        self.parse_entropy_coded_data(reader, &mut stream, &mut block)?;
+                        self.parse_scan(reader, &mut stream, &mut arithmetic, &mut block)?;
                         // extract marker, might either indicate end of image or we continue
                         // scanning(hence the continue statement to determine).
-                        marker = get_marker(reader, &mut stream).ok_or(DecodeErrors::FormatStatic("Marker missing where expected"))?;
+                        marker = self.take_marker(reader, &mut stream, &mut arithmetic).ok_or(DecodeErrors::FormatStatic("Marker missing where expected"))?;
                         seen_scans+=1;
 
                         if seen_scans >  self.options.get_max_scans(){
@@ -122,59 +149,78 @@ impl Decoder
                     }
             }
 
-            marker = get_marker(reader, &mut stream).ok_or(DecodeErrors::FormatStatic("Marker missing where expected"))?;
+            marker = self.take_marker(reader, &mut stream, &mut arithmetic).ok_or(DecodeErrors::FormatStatic("Marker missing where expected"))?;
         }
 
-        self.finish_progressive_decoding(&block, mcu_width)
+        Ok((block, mcu_width, marker))
     }
 
-    #[rustfmt::skip]
-    fn finish_progressive_decoding(&mut self, block: &[Vec<i16>; 3], mcu_width: usize) -> Result<Vec<u8>, DecodeErrors> {
-        self.set_upsampling()?;
+    /// Number of blocks in the x and y plane of a non interleaved scan of component `k`
+    fn non_interleaved_blocks(&self, k: usize) -> (usize, usize)
+    {
+        let component = &self.components[k];
 
-        let mut mcu_width = mcu_width;
-        let mut bias = 1;
+        (
+            (usize::from(self.info.width) * component.horizontal_sample).div_ceil(self.h_max * 8),
+            (usize::from(self.info.height) * component.vertical_sample).div_ceil(self.v_max * 8),
+        )
+    }
+
+    /// Decode the entropy coded data of a single scan using the
+    /// decoder the image was encoded with.
+    fn parse_scan(
+        &mut self, reader: &mut ByteReader, stream: &mut BitStream,
+        arithmetic: &mut ArithmeticDecoder, buffer: &mut [Vec<i16>; MAX_COMPONENTS],
+    ) -> Result<(), DecodeErrors>
+    {
+        if self.is_arithmetic
+        {
+            self.parse_arithmetic_coded_data(reader, arithmetic, buffer)
+        }
+        else if self.is_progressive
+        {
+            self.parse_entropy_coded_data(reader, stream, buffer)
+                .map(|_| ())
+        }
+        else
+        {
+            self.parse_sequential_coded_data(reader, stream, buffer)
+        }
+    }
 
-        if self.sub_sample_ratio == SubSampRatios::H
+    /// Get the marker which ended the last scan
+    fn take_marker(
+        &self, reader: &mut ByteReader, stream: &mut BitStream, arithmetic: &mut ArithmeticDecoder,
+    ) -> Option<Marker>
+    {
+        if self.is_arithmetic
         {
-            mcu_width *= 2;
+            get_marker(reader, &mut arithmetic.marker)
         }
-        if self.sub_sample_ratio == SubSampRatios::HV {
-            bias = 2;
+        else
+        {
+            get_marker(reader, &mut stream.marker)
         }
+    }
 
+    #[rustfmt::skip]
+    fn finish_progressive_decoding(&mut self, block: &[Vec<i16>; MAX_COMPONENTS]) -> Result<Vec<u8>, DecodeErrors> {
         if self.input_colorspace == ColorSpace::GRAYSCALE && self.interleaved {
             /*
             Apparently, grayscale images which can be down sampled exists, which is weird in the sense
             that it has one component Y, which is not usually down sampled.
 
-            This means some calculations will be wrong, so for that we explicitly reset params
-            for such occurrences, warn and reset the image info to appear as if it were
-            a non-sampled image to ensure decoding works
-
-            NOTE: not tested on progressive images as I couldn't find such an image.
+            Its sampling factors are meaningless, but they're also the largest in the image,
+            so it's decoded like a non-sampled image.
             */
             if self.options.get_strict_mode(){
                 return Err(DecodeErrors::FormatStatic("[strict-mode]: Grayscale image with down-sampled component."))
             }
-            warn!("Grayscale image with down-sampled component, resetting component details");
-            self.h_max = 1;
-            self.v_max = 1;
-            self.sub_sample_ratio = SubSampRatios::None;
-            self.components[0].vertical_sample = 1;
-            self.components[0].width_stride = mcu_width * 8;
-            self.components[0].horizontal_sample = mcu_width;
-            bias = 1;
+            warn!("Grayscale image with down-sampled component");
         }
-        // remove items from  top block
-        let y = &block[0];
-        let cb = &block[1];
-        let cr = &block[2];
-        let extra_space = usize::from(self.interleaved) * 128 * usize::from(self.height()) * self.options.get_out_colorspace().num_components();
-        let capacity = usize::from(self.info.width + 8) * usize::from(self.info.height + 8);
-
-        let mut out_vector = vec![0_u8; capacity * self.options.get_out_colorspace().num_components() + extra_space];
+        let mut out_vector = vec![0_u8; usize::from(self.info.width) * usize::from(self.info.height) * self.options.get_out_colorspace().num_components()];
 
+        self.set_color_convert();
         // Things we need for multithreading.
         let h_max = self.h_max;
         let v_max = self.v_max;
@@ -183,76 +229,67 @@ impl Decoder
         let output = self.options.get_out_colorspace();
         let idct_func = self.idct_func;
         let color_convert_16 = self.color_convert_16;
-        let width = usize::from(self.width());
-        // Divide the output into small blocks and send to threads/
-        let chunks_size = width * self.options.get_out_colorspace().num_components() * 8 * h_max * v_max;
-        let out_chunks = out_vector.chunks_exact_mut(chunks_size);
-        // Chunk sizes. Each determine how many pixels go per thread.
-        let y_chunk_size =
-            mcu_width * self.components[0].vertical_sample * self.components[0].horizontal_sample * bias;
-        // Divide into chunks
-        let y_chunk = y.chunks_exact(y_chunk_size);
+        let use_unsafe = self.options.get_use_unsafe();
+        let icc = self.icc_transform(255);
+        let icc = icc.as_ref();
+        let width = usize::from(self.info.width);
+        let height = usize::from(self.info.height);
+        let uses_neighbours = self.components.iter()
+            .any(|c| UpSampler::new(c, width, height, h_max, v_max).uses_neighbours());
+        let rows_per_task = mcu_rows_per_task(uses_neighbours, self.orientation());
+        // Divide the output into small blocks and send to threads, where they land
+        // once turned by the EXIF orientation
+        let rows_per_chunk = 8 * v_max * rows_per_task;
+        let mut out_chunks = OrientedOutput::new(&mut out_vector, width, height,
+                                                 output.num_components(), self.orientation());
 
         let mut pool = scoped_threadpool::Pool::new(self.options.get_threads());
 
-        if self.input_colorspace.num_components() == 3 {
-            let cb_chunk_size =
-                mcu_width * self.components[1].vertical_sample * self.components[1].horizontal_sample * bias;
-
-            let cb_chunk = cb.chunks_exact(cb_chunk_size);
-            let cr_chunk = cr.chunks_exact(cb_chunk_size);
-            // open threads.
-            pool.scoped(|scope| {
-                for (((y, cb), cr), out) in y_chunk
-                    .zip(cb_chunk)
-                    .zip(cr_chunk)
-                    .zip(out_chunks)
-                {
-                    let component = components.clone();
+        pool.scoped(|scope| {
+            for i in 0..height.div_ceil(rows_per_chunk)
+            {
+                let out = out_chunks.next_band(rows_per_chunk);
+                let component = components.clone();
+                let mut coeff = [McuRow::default(); 3];
 
-                    scope.execute(move || {
-                        post_process(&[y, cb, cr], &component, idct_func, color_convert_16,
-                                          input, output, out, width,
-                        );
-                    });
-                }
-            });
-        } else {
-            // one component
-            pool.scoped(|scope| {
-                for (y, out) in y_chunk.zip(out_chunks)
+                for ((row, comp), block) in coeff.iter_mut().zip(component.iter()).zip(block)
                 {
-                    let component = components.clone();
-
-                    scope.execute(move || {
-                        post_process(&[y, &[], &[]], &component, idct_func, color_convert_16,
-                                          input, output, out, width,
-                        );
-                    });
+                    // a row of MCU's holds `vertical_sample` rows of blocks of the component
+                    let row_len = comp.width_stride * 8;
+                    let task_len = row_len * comp.vertical_sample * rows_per_task;
+                    let start = i * task_len;
+                    let end = min(start + task_len, block.len());
+
+                    *row = McuRow {
+                        blocks: &block[start..end],
+                        above:  block.get(start.saturating_sub(row_len)..start).unwrap_or_default(),
+                        below:  block.get(end..end + row_len).unwrap_or_default(),
+                    };
                 }
-            });
-        }
-        debug!("Finished decoding image");
 
-        out_vector.truncate(
-            usize::from(self.width())
-                * usize::from(self.height())
-                * self.options.get_out_colorspace().num_components(),
-        );
+                scope.execute(move || {
+                    post_process(&coeff, &component, idct_func, color_convert_16, use_unsafe,
+                                 input, output, out, i * rows_per_task, width, height, h_max, v_max, icc,
+                    );
+                });
+            }
+        });
+        debug!("Finished decoding image");
 
         return Ok(out_vector);
     }
 
 
     #[rustfmt::skip]
-    #[allow(clippy::too_many_lines)]
+    #[allow(clippy::too_many_lines, clippy::cast_sign_loss)]
     fn parse_entropy_coded_data(
-        &mut self, reader: &mut Cursor<Vec<u8>>, stream: &mut BitStream, buffer: &mut [Vec<i16>; 3],
+        &mut self, reader: &mut ByteReader, stream: &mut BitStream, buffer: &mut [Vec<i16>; MAX_COMPONENTS],
     ) -> Result<bool, DecodeErrors>
     {
         self.check_component_dimensions()?;
         stream.reset();
         self.components.iter_mut().for_each(|x| x.dc_pred = 0);
+        self.todo = self.restart_interval;
 
         if usize::from(self.num_scans) > self.input_colorspace.num_components() {
             return Err(Format(format!("Number of scans {} cannot be greater than number of components, {}", self.num_scans, self.input_colorspace.num_components())));
@@ -275,19 +312,10 @@ impl Decoder
                 return Err(DecodeErrors::Format(format!("Cannot find component {}, corrupt image", k)));
             }
 
-            let (mcu_width, mcu_height);
-            // For Y channel  or non interleaved scans , mcu's is the image dimensions divided
-            // by 8
-            if self.components[k].component_id == ComponentID::Y || !self.interleaved
-            {
-                mcu_width = ((self.info.width + 7) / 8) as usize;
-                mcu_height = ((self.info.height + 7) / 8) as usize;
-            } else {
-                // For other channels, in an interleaved mcu, number of MCU's
-                // are determined by some weird maths done in headers.rs->parse_sos()
-                mcu_width = self.mcu_x;
-                mcu_height = self.mcu_y;
-            }
+            // non interleaved scans only contain the blocks inside the component,
+            // for components with the largest sampling factors (e.g. Y and K) that's the image
+            // dimensions divided by 8
+            let (mcu_width, mcu_height) = self.non_interleaved_blocks(k);
             let mut i = 0;
             let mut j = 0;
 
@@ -347,6 +375,9 @@ impl Decoder
 
                                 i += (j + stream.eob_run as usize - 1) / mcu_width;
                                 j = (j + stream.eob_run as usize - 1) % mcu_width;
+                                // skipped blocks count towards the restart interval, EOB runs
+                                // never cross it.
+                                self.todo = self.todo.wrapping_sub(stream.eob_run as usize - 1);
                                 stream.eob_run = 0;
                             } else {
                                 stream.decode_mcu_ac_first(reader, ac_table, data)?;
@@ -357,11 +388,11 @@ impl Decoder
                         }
                     }
                     j += 1;
-                    self.todo -= 1;
+                    self.todo = self.todo.wrapping_sub(1);
 
                     if self.todo == 0
                     {
-                        self.handle_rst(stream)?;
+                        self.handle_huffman_rst(reader, stream);
                     }
                 }
                 j = 0;
@@ -415,34 +446,308 @@ impl Decoder
                                 }
                             }
                         }
-                        // We want wrapping subtraction here because it means
-                        // we get a higher number in the case this underflows
-                        self.todo = self.todo.wrapping_sub(1);
-                        // after every scan that's a mcu, count down restart markers.
-                        if self.todo == 0 {
-                            self.handle_rst(stream)?;
-                        }
+                    }
+                    // We want wrapping subtraction here because it means
+                    // we get a higher number in the case this underflows
+                    self.todo = self.todo.wrapping_sub(1);
+                    // restart intervals count MCU's, not blocks of components
+                    if self.todo == 0 {
+                        self.handle_huffman_rst(reader, stream);
                     }
                 }
             }
         }
         return Ok(true);
     }
+
+    /// Decode a scan of arithmetic coded data
+    ///
+    /// Both sequential and progressive arithmetic images accumulate coefficients
+    /// for the whole image and carry out IDCT when we are done.
+    #[rustfmt::skip]
+    fn parse_arithmetic_coded_data(
+        &mut self, reader: &mut ByteReader, arithmetic: &mut ArithmeticDecoder,
+        buffer: &mut [Vec<i16>; MAX_COMPONENTS],
+    ) -> Result<(), DecodeErrors>
+    {
+        self.check_component_dimensions()?;
+        arithmetic.start_scan(self);
+        self.components.iter_mut().for_each(|x| x.dc_pred = 0);
+        // restart intervals are counted from the start of a scan
+        self.todo = self.restart_interval;
+
+        if usize::from(self.num_scans) > self.input_colorspace.num_components() {
+            return Err(Format(format!("Number of scans {} cannot be greater than number of components, {}", self.num_scans, self.input_colorspace.num_components())));
+        }
+
+        if self.num_scans == 1
+        {
+            if self.is_progressive && self.spec_end != 0 && self.spec_start == 0
+            {
+                return Err(DecodeErrors::FormatStatic("Can't merge DC and AC corrupt jpeg"));
+            }
+            // non interleaved data, process one block at a time in trivial scanline order
+            let k = self.z_order[0];
+
+            if k >= self.components.len() {
+                return Err(DecodeErrors::Format(format!("Cannot find component {k}, corrupt image")));
+            }
+
+            let (mcu_width, mcu_height) = self.non_interleaved_blocks(k);
+
+            for i in 0..mcu_height
+            {
+                for j in 0..mcu_width
+                {
+                    let component = &mut self.components[k];
+                    let start = 64 * (j + i * (component.width_stride / 8));
+
+                    let data: &mut [i16; 64] = buffer.get_mut(k)
+                        .and_then(|x| x.get_mut(start..start + 64))
+                        .ok_or_else(|| DecodeErrors::Format(format!("Cannot find component {k}, corrupt image")))?
+                        .try_into().unwrap();
+
+                    arithmetic.decode_block(reader, component.dc_huff_table & (MAX_COMPONENTS - 1),
+                                                component.ac_huff_table & (MAX_COMPONENTS - 1),
+                                                k, data, &mut component.dc_pred)?;
+
+                    self.todo = self.todo.wrapping_sub(1);
+
+                    if self.todo == 0
+                    {
+                        self.handle_arithmetic_rst(reader, arithmetic);
+                    }
+                }
+            }
+        } else {
+            // Only DC coefficients can be interleaved in progressive images
+            if self.is_progressive && self.spec_end != 0
+            {
+                return Err(DecodeErrors::FormatStatic("Can't merge dc and AC corrupt jpeg"));
+            }
+
+            for i in 0..self.mcu_y
+            {
+                for j in 0..self.mcu_x
+                {
+                    // process scan n elements in order
+                    for k in 0..self.num_scans
+                    {
+                        let n = self.z_order[k as usize];
+
+                        if n >= self.components.len() {
+                            return Err(DecodeErrors::Format(format!("Cannot find component {n}, corrupt image")));
+                        }
+
+                        let component = &mut self.components[n];
+
+                        for v_samp in 0..component.vertical_sample
+                        {
+                            for h_samp in 0..component.horizontal_sample
+                            {
+                                let x2 = j * component.horizontal_sample + h_samp;
+                                let y2 = i * component.vertical_sample + v_samp;
+                                let position = 64 * (x2 + y2 * component.width_stride / 8);
+
+                                let data: &mut [i16; 64] = buffer.get_mut(n)
+                                    .and_then(|x| x.get_mut(position..position + 64))
+                                    .ok_or_else(|| DecodeErrors::Format(format!("Cannot find component {n}, corrupt image")))?
+                                    .try_into().unwrap();
+
+                                arithmetic.decode_block(reader, component.dc_huff_table & (MAX_COMPONENTS - 1),
+                                                            component.ac_huff_table & (MAX_COMPONENTS - 1),
+                                                            n, data, &mut component.dc_pred)?;
+                            }
+                        }
+                    }
+                    self.todo = self.todo.wrapping_sub(1);
+
+                    if self.todo == 0
+                    {
+                        self.handle_arithmetic_rst(reader, arithmetic);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Decode a scan of Huffman coded sequential data into coefficients
+    ///
+    /// This is used for images whose samples can't be handled by the baseline
+    /// decoder, e.g. 12 bit images, where we need all coefficients before
+    /// carrying out IDCT.
+    #[rustfmt::skip]
+    fn parse_sequential_coded_data(
+        &mut self, reader: &mut ByteReader, stream: &mut BitStream,
+        buffer: &mut [Vec<i16>; MAX_COMPONENTS],
+    ) -> Result<(), DecodeErrors>
+    {
+        self.check_component_dimensions()?;
+        stream.reset();
+        self.components.iter_mut().for_each(|x| x.dc_pred = 0);
+        self.todo = self.restart_interval;
+
+        if usize::from(self.num_scans) > self.input_colorspace.num_components() {
+            return Err(Format(format!("Number of scans {} cannot be greater than number of components, {}", self.num_scans, self.input_colorspace.num_components())));
+        }
+
+        if self.num_scans == 1
+        {
+            // non interleaved data, process one block at a time in trivial scanline order
+            let k = self.z_order[0];
+
+            if k >= self.components.len() {
+                return Err(DecodeErrors::Format(format!("Cannot find component {k}, corrupt image")));
+            }
+
+            let (mcu_width, mcu_height) = self.non_interleaved_blocks(k);
+
+            for i in 0..mcu_height
+            {
+                for j in 0..mcu_width
+                {
+                    let start = 64 * (j + i * (self.components[k].width_stride / 8));
+
+                    self.decode_sequential_block(reader, stream, buffer, k, start)?;
+
+                    self.todo = self.todo.wrapping_sub(1);
+
+                    if self.todo == 0
+                    {
+                        self.handle_huffman_rst(reader, stream);
+                    }
+                }
+            }
+        } else {
+            for i in 0..self.mcu_y
+            {
+                for j in 0..self.mcu_x
+                {
+                    // process scan n elements in order
+                    for k in 0..self.num_scans
+                    {
+                        let n = self.z_order[k as usize];
+
+                        if n >= self.components.len() {
+                            return Err(DecodeErrors::Format(format!("Cannot find component {n}, corrupt image")));
+                        }
+
+                        let component = &self.components[n];
+                        let (h_samp, v_samp, stride) = (component.horizontal_sample, component.vertical_sample, component.width_stride / 8);
+
+                        for v in 0..v_samp
+                        {
+                            for h in 0..h_samp
+                            {
+                                let x2 = j * h_samp + h;
+                                let y2 = i * v_samp + v;
+
+                                self.decode_sequential_block(reader, stream, buffer, n, 64 * (x2 + y2 * stride))?;
+                            }
+                        }
+                    }
+                    self.todo = self.todo.wrapping_sub(1);
+
+                    if self.todo == 0
+                    {
+                        self.handle_huffman_rst(reader, stream);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Decode a single sequential block of component `k` into the buffer
+    /// starting at `start`
+    fn decode_sequential_block(
+        &mut self, reader: &mut ByteReader, stream: &mut BitStream,
+        buffer: &mut [Vec<i16>; MAX_COMPONENTS], k: usize, start: usize,
+    ) -> Result<(), DecodeErrors>
+    {
+        let component = &mut self.components[k];
+
+        let dc_table = self
+            .dc_huffman_tables
+            .get(component.dc_huff_table & (MAX_COMPONENTS - 1))
+            .and_then(Option::as_ref)
+            .ok_or(DecodeErrors::FormatStatic(
+                "No DC huffman table for component",
+            ))?;
+
+        let ac_table = self
+            .ac_huffman_tables
+            .get(component.ac_huff_table & (MAX_COMPONENTS - 1))
+            .and_then(Option::as_ref)
+            .ok_or(DecodeErrors::FormatStatic(
+                "No AC huffman table for component",
+            ))?;
+
+        let data: &mut [i16; 64] = buffer
+            .get_mut(k)
+            .and_then(|x| x.get_mut(start..start + 64))
+            .ok_or_else(|| {
+                DecodeErrors::Format(format!("Cannot find component {k}, corrupt image"))
+            })?
+            .try_into()
+            .unwrap();
+
+        stream.decode_mcu_block(reader, dc_table, ac_table, data, &mut component.dc_pred)
+    }
+
+    /// Handle the end of a restart interval in Huffman coded data
+    ///
+    /// If the marker isn't an RST marker, we leave it for the scan loop to handle.
+    #[cold]
+    fn handle_huffman_rst(&mut self, reader: &mut ByteReader, stream: &mut BitStream)
+    {
+        self.todo = self.restart_interval;
+
+        if stream.marker.is_none()
+        {
+            // the bit buffer may not have reached the marker, what remains
+            // before it is padding, so skip to it.
+            stream.marker = get_marker(reader, &mut None);
+        }
+
+        if let Some(Marker::RST(_)) = stream.marker
+        {
+            stream.reset();
+            self.components.iter_mut().for_each(|x| x.dc_pred = 0);
+        }
+    }
+
+    /// Handle the end of a restart interval in arithmetic coded data
+    ///
+    /// If the marker isn't an RST marker, we leave it for the scan loop to handle.
+    #[cold]
+    fn handle_arithmetic_rst(&mut self, reader: &mut ByteReader, arithmetic: &mut ArithmeticDecoder)
+    {
+        self.todo = self.restart_interval;
+
+        arithmetic.find_marker(reader);
+
+        if let Some(Marker::RST(_)) = arithmetic.marker
+        {
+            // statistics, registers and dc predictions all start afresh
+            arithmetic.reset();
+            self.components.iter_mut().for_each(|x| x.dc_pred = 0);
+        }
+    }
 }
 
 ///Get a marker from the bit-stream.
 ///
 /// This reads until it gets a marker or end of file is encountered
-fn get_marker(reader: &mut Cursor<Vec<u8>>, stream: &mut BitStream) -> Option<Marker>
+pub(crate) fn get_marker(reader: &mut ByteReader, marker: &mut Option<Marker>) -> Option<Marker>
 {
-    if let Some(marker) = stream.marker
+    if let Some(marker) = marker.take()
     {
-        stream.marker = None;
         return Some(marker);
     }
 
     // read until we get a marker
-    let len = u64::try_from(reader.get_ref().len()).unwrap();
     loop
     {
         let marker = read_byte(reader).ok()?;
@@ -463,7 +768,7 @@ fn get_marker(reader: &mut Cursor<Vec<u8>>, stream: &mut BitStream) -> Option<Ma
                     .ok();
             }
 
-            if reader.position() >= len
+            if reader.is_empty()
             {
                 // end of buffer
                 return None;
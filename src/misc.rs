@@ -22,6 +22,15 @@ pub const START_OF_FRAME_PROG_DCT: u16 = 0xffc2;
 
 pub const START_OF_FRAME_LOS_SEQ: u16 = 0xffc3;
 
+/// Start of differential sequential DCT Huffman coding
+pub const START_OF_FRAME_DIFF_SEQ: u16 = 0xffc5;
+
+/// Start of differential progressive DCT Huffman coding
+pub const START_OF_FRAME_DIFF_PROG_DCT: u16 = 0xffc6;
+
+/// Start of differential lossless Huffman coding
+pub const START_OF_FRAME_DIFF_LOS: u16 = 0xffc7;
+
 /// Start of extended sequential DCT arithmetic coding
 
 pub const START_OF_FRAME_EXT_AR: u16 = 0xffc9;
@@ -155,6 +164,12 @@ pub enum SOFMarkers
     ProgressiveDctArithmetic,
     /// Lossless ( sequential), arithmetic coding
     LosslessArithmetic,
+    /// Differential sequential DCT, Huffman coding
+    DifferentialSequentialHuffman,
+    /// Differential progressive DCT, Huffman coding
+    DifferentialProgressiveHuffman,
+    /// Differential lossless (sequential), Huffman coding
+    DifferentialLosslessHuffman,
 }
 
 impl Default for SOFMarkers
@@ -176,6 +191,7 @@ impl SOFMarkers
             Self::BaselineDct
                 | Self::ExtendedSequentialHuffman
                 | Self::ExtendedSequentialDctArithmetic
+                | Self::DifferentialSequentialHuffman
         )
     }
 
@@ -183,7 +199,10 @@ impl SOFMarkers
 
     pub fn is_lossless(self) -> bool
     {
-        matches!(self, Self::LosslessHuffman | Self::LosslessArithmetic)
+        matches!(
+            self,
+            Self::LosslessHuffman | Self::LosslessArithmetic | Self::DifferentialLosslessHuffman
+        )
     }
 
     /// Check whether a marker is a progressive marker or not
@@ -192,7 +211,20 @@ impl SOFMarkers
     {
         matches!(
             self,
-            Self::ProgressiveDctHuffman | Self::ProgressiveDctArithmetic
+            Self::ProgressiveDctHuffman
+                | Self::ProgressiveDctArithmetic
+                | Self::DifferentialProgressiveHuffman
+        )
+    }
+
+    /// Check whether a marker starts a differential frame of a hierarchical image
+    pub fn is_differential(self) -> bool
+    {
+        matches!(
+            self,
+            Self::DifferentialSequentialHuffman
+                | Self::DifferentialProgressiveHuffman
+                | Self::DifferentialLosslessHuffman
         )
     }
 
@@ -209,6 +241,9 @@ impl SOFMarkers
             START_OF_FRAME_LOS_SEQ_AR => Some(Self::LosslessArithmetic),
             START_OF_FRAME_EXT_SEQ => Some(Self::ExtendedSequentialHuffman),
             START_OF_FRAME_EXT_AR => Some(Self::ExtendedSequentialDctArithmetic),
+            START_OF_FRAME_DIFF_SEQ => Some(Self::DifferentialSequentialHuffman),
+            START_OF_FRAME_DIFF_PROG_DCT => Some(Self::DifferentialProgressiveHuffman),
+            START_OF_FRAME_DIFF_LOS => Some(Self::DifferentialLosslessHuffman),
             _ => None,
         }
     }
@@ -233,6 +268,18 @@ impl fmt::Debug for SOFMarkers
             }
             Self::ProgressiveDctArithmetic => write!(f, "Progressive DCT, arithmetic coding"),
             Self::LosslessArithmetic => write!(f, "Lossless (sequential) arithmetic coding"),
+            Self::DifferentialSequentialHuffman =>
+            {
+                write!(f, "Differential sequential DCT, Huffman coding")
+            }
+            Self::DifferentialProgressiveHuffman =>
+            {
+                write!(f, "Differential progressive DCT, Huffman coding")
+            }
+            Self::DifferentialLosslessHuffman =>
+            {
+                write!(f, "Differential lossless (sequential), Huffman coding")
+            }
         }
     }
 }
@@ -0,0 +1,184 @@
+//! Multi-Picture Format index from APP2 segments
+//!
+//! MPO files (CIPA DC-007) are JPEG images put one after the other, the first
+//! one has an APP2 segment starting with `MPF\0` followed by a TIFF structure
+//! whose first directory, the MP Index IFD, lists every image of the file.
+//!
+//! Each entry of the index gives the type of an image, e.g. the primary image,
+//! a disparity image of a stereo pair or a frame of a multi-angle set, its size
+//! and its offset, relative to the TIFF header of the first image (the first
+//! image itself has offset 0). Offsets are turned into offsets from the start of
+//! the file when parsing.
+//!
+//! The other images have an MPF segment too, describing themselves without an
+//! index, we only look at the segment of the first image.
+
+use crate::exif::{ExifValue, Reader};
+
+/// Signature of APP2 segments holding Multi-Picture Format data
+pub(crate) const MPF_SIGNATURE: &[u8] = b"MPF\0";
+
+/// Images in an index past which we assume the data is corrupt
+const MAX_IMAGES: usize = 1024;
+
+/// Type of an image of a Multi-Picture file
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MpImageType
+{
+    /// The primary image, a baseline JPEG image
+    Primary,
+    /// A preview of 640 by 480 pixels
+    LargeThumbnailVga,
+    /// A preview of 1920 by 1080 pixels
+    LargeThumbnailFullHd,
+    /// A frame of a panorama
+    Panorama,
+    /// A view of a stereo pair or of a stereoscopic set
+    Disparity,
+    /// A frame of a set taken from several angles
+    MultiAngle,
+    /// Type code 0, used e.g. for gain maps
+    Undefined,
+    /// Any other type code
+    Unknown(u32),
+}
+
+impl MpImageType
+{
+    fn from_code(code: u32) -> MpImageType
+    {
+        match code
+        {
+            0x03_0000 => MpImageType::Primary,
+            0x01_0001 => MpImageType::LargeThumbnailVga,
+            0x01_0002 => MpImageType::LargeThumbnailFullHd,
+            0x02_0001 => MpImageType::Panorama,
+            0x02_0002 => MpImageType::Disparity,
+            0x02_0003 => MpImageType::MultiAngle,
+            0x00_0000 => MpImageType::Undefined,
+            _ => MpImageType::Unknown(code),
+        }
+    }
+}
+
+/// An entry of the MP index
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MpImage
+{
+    pub image_type:       MpImageType,
+    /// Whether this is the image to show when only one can be
+    pub representative:   bool,
+    /// Whether other images depend on this one
+    pub dependent_parent: bool,
+    /// Whether this image depends on another one
+    pub dependent_child:  bool,
+    /// Format of the image data, 0 for JPEG
+    pub format:           u8,
+    /// Where the image starts, from the start of the file
+    pub offset:           usize,
+    /// Size of the image in bytes
+    pub size:             usize,
+    /// Numbers of the entries of images this one depends on, starting at 1,
+    /// 0 if there is none
+    pub dependent_images: [u16; 2],
+}
+
+/// The MP index of a Multi-Picture file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mpf
+{
+    pub(crate) images: Vec<MpImage>,
+}
+
+impl Mpf
+{
+    /// Parse the index from the TIFF structure following `MPF\0`, which starts
+    /// `position` bytes into the file
+    ///
+    /// Returns `None` if the header is invalid or there is no index.
+    pub(crate) fn parse(data: &[u8], position: usize) -> Option<Mpf>
+    {
+        let reader = Reader::new(data)?;
+        let directory = reader.u32(4)? as usize;
+        let count = usize::from(reader.u16(directory)?);
+
+        let mut number_of_images = None;
+        let mut entries = None;
+
+        for i in 0..count
+        {
+            let entry = directory + 2 + i * 12;
+
+            match (reader.u16(entry)?, reader.value(entry))
+            {
+                (0xB001, Some(value)) => number_of_images = value.to_u32(),
+                (0xB002, Some(ExifValue::Undefined(value))) => entries = Some(value),
+                _ => (),
+            }
+        }
+        let entries = entries?;
+        let mut number_of_images = number_of_images.map_or(usize::MAX, |x| x as usize);
+
+        if number_of_images != entries.len() / 16
+        {
+            warn!(
+                "MP index says it has {number_of_images} images but has {} entries",
+                entries.len() / 16
+            );
+            number_of_images = number_of_images.min(entries.len() / 16);
+        }
+        if number_of_images > MAX_IMAGES
+        {
+            warn!("MP index has {number_of_images} images, ignoring it");
+            return None;
+        }
+
+        let entries = Reader {
+            data:       &entries,
+            big_endian: reader.big_endian,
+        };
+        let images = (0..number_of_images)
+            .map(|i| {
+                let entry = i * 16;
+                let attribute = entries.u32(entry)?;
+                // the first image starts the file
+                let offset = match entries.u32(entry + 8)? as usize
+                {
+                    0 => 0,
+                    offset => position.checked_add(offset)?,
+                };
+
+                Some(MpImage {
+                    image_type: MpImageType::from_code(attribute & 0x00FF_FFFF),
+                    dependent_parent: attribute & (1 << 31) != 0,
+                    dependent_child: attribute & (1 << 30) != 0,
+                    representative: attribute & (1 << 29) != 0,
+                    format: ((attribute >> 24) & 7) as u8,
+                    offset,
+                    size: entries.u32(entry + 4)? as usize,
+                    dependent_images: [entries.u16(entry + 12)?, entries.u16(entry + 14)?],
+                })
+            })
+            .collect::<Option<Vec<MpImage>>>()?;
+
+        Some(Mpf { images })
+    }
+
+    /// Images of the file, in the order of the index
+    #[must_use]
+    pub fn images(&self) -> &[MpImage]
+    {
+        &self.images
+    }
+
+    /// The bytes of image `index` in `buf`, the file the index was read from
+    ///
+    /// Returns `None` if there is no such image or it's past the end of `buf`.
+    #[must_use]
+    pub fn image_data<'a>(&self, buf: &'a [u8], index: usize) -> Option<&'a [u8]>
+    {
+        let image = self.images.get(index)?;
+
+        buf.get(image.offset..image.offset.checked_add(image.size)?)
+    }
+}
@@ -2,39 +2,46 @@ use std::num::NonZeroU32;
 
 use crate::ColorSpace;
 /// Options available that influence decoding.
+#[allow(clippy::struct_excessive_bools)]
 #[derive(Copy, Clone)]
 pub struct ZuneJpegOptions
 {
     /// Whether or not we wre allowed
     /// to use unsafe code
-    use_unsafe:     bool,
+    use_unsafe:        bool,
     /// The output colorspace
     /// expected from a decode procedure.
-    out_colorspace: ColorSpace,
+    out_colorspace:    ColorSpace,
     /// Number of threads
     /// to spawn for this decoder
-    num_threads:    u32,
+    num_threads:       u32,
     /// Limits for the decoder
     /// These prevent OOM exhaustion
-    max_width:      u16,
-    max_height:     u16,
+    max_width:         u16,
+    max_height:        u16,
     /// Maximum number of scans to allow in the image
-    max_scans:      usize,
+    max_scans:         usize,
     /// Treat warnings as errors.
-    strict_mode:    bool,
+    strict_mode:       bool,
+    /// Convert colors from the embedded ICC profile to sRGB
+    icc_to_srgb:       bool,
+    /// Rotate and flip the output as the EXIF orientation says
+    apply_orientation: bool,
 }
 impl Default for ZuneJpegOptions
 {
     fn default() -> Self
     {
         Self {
-            use_unsafe:     true,
-            out_colorspace: ColorSpace::RGB,
-            num_threads:    4,
-            max_width:      1 << 14,
-            max_height:     1 << 14,
-            max_scans:      64,
-            strict_mode:    false,
+            use_unsafe:        true,
+            out_colorspace:    ColorSpace::RGB,
+            num_threads:       4,
+            max_width:         1 << 14,
+            max_height:        1 << 14,
+            max_scans:         64,
+            strict_mode:       false,
+            icc_to_srgb:       false,
+            apply_orientation: false,
         }
     }
 }
@@ -157,4 +164,45 @@ impl ZuneJpegOptions
         self.strict_mode = choice;
         self
     }
+    /// Get whether colors are converted from the embedded ICC profile to sRGB
+    #[must_use]
+    pub const fn get_icc_to_srgb(&self) -> bool
+    {
+        self.icc_to_srgb
+    }
+    /// Set whether to convert colors from the embedded ICC profile to sRGB
+    ///
+    /// This applies to RGB(A/X) output of color images, including CMYK ones,
+    /// and grayscale output of grayscale images. Images without a profile,
+    /// or with one that can't be understood, are decoded as usual.
+    ///
+    /// Default is false.
+    #[must_use]
+    pub fn set_icc_to_srgb(mut self, choice: bool) -> ZuneJpegOptions
+    {
+        self.icc_to_srgb = choice;
+        self
+    }
+    /// Get whether the output is rotated and flipped as the EXIF orientation says
+    #[must_use]
+    pub const fn get_apply_orientation(&self) -> bool
+    {
+        self.apply_orientation
+    }
+    /// Set whether to rotate and flip the output as the EXIF orientation says
+    ///
+    /// Phones and cameras store pictures the way the sensor saw them and record
+    /// how they should be turned in the `Orientation` tag, this turns the decoded
+    /// pixels upright.
+    ///
+    /// `Decoder::width`, `Decoder::height` and `Decoder::info` describe the turned
+    /// image, so width and height are swapped for images rotated by 90 degrees.
+    ///
+    /// Default is false.
+    #[must_use]
+    pub fn set_apply_orientation(mut self, choice: bool) -> ZuneJpegOptions
+    {
+        self.apply_orientation = choice;
+        self
+    }
 }
@@ -0,0 +1,220 @@
+//! Photoshop image resources from APP13 segments
+//!
+//! The segments start with `Photoshop 3.0\0` followed by image resource
+//! blocks, each made of a signature (`8BIM`), a resource id, a name as a
+//! Pascal string and the resource data, both padded to an even length.
+//! Resources can be split over several segments, so the data of all of them
+//! is put together before being parsed.
+//!
+//! The resources most images have are IPTC-IIM metadata (1028), see
+//! [`crate::iptc`], the resolution of the image (1005) and a thumbnail
+//! (1036, or 1033 for files written by Photoshop 4).
+
+use crate::iptc::Iptc;
+
+/// Signature of APP13 segments holding image resources
+pub(crate) const PHOTOSHOP_SIGNATURE: &[u8] = b"Photoshop 3.0\0";
+
+/// Signatures of resource blocks, `8BIM` is used by Photoshop, the others by
+/// older versions and other applications
+const RESOURCE_SIGNATURES: [&[u8; 4]; 5] = [b"8BIM", b"MeSa", b"PHUT", b"AgHg", b"DCSR"];
+
+/// An image resource block
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Resource
+{
+    /// Resource id, e.g. 1028 for IPTC-IIM metadata
+    pub id:   u16,
+    /// Name of the resource, usually empty
+    pub name: Vec<u8>,
+    pub data: Vec<u8>,
+}
+
+/// Resolution of the image, from resource 1005
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ResolutionInfo
+{
+    /// Horizontal resolution in pixels per `horizontal_unit`
+    pub horizontal:      f64,
+    /// 1 for pixels per inch, 2 for pixels per centimeter
+    pub horizontal_unit: u16,
+    /// Unit the width is displayed in, 1 for inches, 2 for centimeters,
+    /// 3 for points, 4 for picas and 5 for columns
+    pub width_unit:      u16,
+    /// Vertical resolution in pixels per `vertical_unit`
+    pub vertical:        f64,
+    /// 1 for pixels per inch, 2 for pixels per centimeter
+    pub vertical_unit:   u16,
+    /// Unit the height is displayed in, as `width_unit`
+    pub height_unit:     u16,
+}
+
+/// A JPEG thumbnail from resource 1036 or 1033
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PhotoshopThumbnail
+{
+    pub width:  u32,
+    pub height: u32,
+    /// The JPEG image
+    pub data:   Vec<u8>,
+    /// Whether red and blue are swapped in the image, as Photoshop 4
+    /// (resource 1033) wrote them
+    pub bgr:    bool,
+}
+
+/// Image resources of an image
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Photoshop
+{
+    pub(crate) resources: Vec<Resource>,
+}
+
+impl Photoshop
+{
+    /// Parse resource blocks from the data of APP13 segments, after their
+    /// signature
+    ///
+    /// Returns `None` if there isn't a single valid block.
+    pub(crate) fn parse(mut data: &[u8]) -> Option<Photoshop>
+    {
+        let mut resources = Vec::new();
+
+        while !data.is_empty()
+        {
+            let Some(resource) = parse_resource(&mut data)
+            else
+            {
+                warn!("Invalid Photoshop resource block, ignoring the rest of the resources");
+                break;
+            };
+            resources.push(resource);
+        }
+
+        if resources.is_empty()
+        {
+            return None;
+        }
+
+        Some(Photoshop { resources })
+    }
+
+    /// All resource blocks, in the order they were found
+    #[must_use]
+    pub fn resources(&self) -> &[Resource]
+    {
+        &self.resources
+    }
+
+    /// The data of the first resource with `id`
+    #[must_use]
+    pub fn get(&self, id: u16) -> Option<&[u8]>
+    {
+        self.resources
+            .iter()
+            .find(|resource| resource.id == id)
+            .map(|resource| &resource.data[..])
+    }
+
+    /// IPTC-IIM metadata, from resource 1028
+    #[must_use]
+    pub fn iptc(&self) -> Option<Iptc>
+    {
+        Iptc::parse(self.get(0x0404)?)
+    }
+
+    /// Resolution of the image, from resource 1005
+    #[must_use]
+    pub fn resolution(&self) -> Option<ResolutionInfo>
+    {
+        let data = self.get(0x03ED)?;
+
+        if data.len() < 16
+        {
+            warn!("Photoshop resolution info is too short, ignoring it");
+            return None;
+        }
+        let u16_at = |i: usize| u16::from_be_bytes([data[i], data[i + 1]]);
+        // resolutions are 16.16 fixed point numbers
+        let fixed_at =
+            |i: usize| f64::from(u32::from_be_bytes(data[i..i + 4].try_into().unwrap())) / 65536.0;
+
+        Some(ResolutionInfo {
+            horizontal:      fixed_at(0),
+            horizontal_unit: u16_at(4),
+            width_unit:      u16_at(6),
+            vertical:        fixed_at(8),
+            vertical_unit:   u16_at(12),
+            height_unit:     u16_at(14),
+        })
+    }
+
+    /// The JPEG thumbnail, from resource 1036, or 1033 if the image doesn't have
+    /// the former
+    ///
+    /// Returns `None` if there is no thumbnail or it isn't a JPEG image (raw
+    /// thumbnails are allowed by the format, but not written in practice).
+    #[must_use]
+    pub fn thumbnail(&self) -> Option<PhotoshopThumbnail>
+    {
+        let (data, bgr) = match self.get(0x040C)
+        {
+            Some(data) => (data, false),
+            None => (self.get(0x0409)?, true),
+        };
+        // format, width, height, bytes per row, total size, compressed size,
+        // bits per pixel and number of planes, followed by the image
+        if data.len() < 28
+        {
+            warn!("Photoshop thumbnail is too short, ignoring it");
+            return None;
+        }
+        let u32_at = |i: usize| u32::from_be_bytes(data[i..i + 4].try_into().unwrap());
+
+        // format 1 is JPEG
+        if u32_at(0) != 1
+        {
+            warn!("Photoshop thumbnail isn't a JPEG image, ignoring it");
+            return None;
+        }
+
+        Some(PhotoshopThumbnail {
+            width: u32_at(4),
+            height: u32_at(8),
+            data: data[28..].to_vec(),
+            bgr,
+        })
+    }
+}
+
+/// Parse the resource block at the start of `data`, moving past it
+fn parse_resource(data: &mut &[u8]) -> Option<Resource>
+{
+    let block = *data;
+
+    if !RESOURCE_SIGNATURES.iter().any(|x| block.starts_with(*x))
+    {
+        return None;
+    }
+    let id = u16::from_be_bytes(block.get(4..6)?.try_into().ok()?);
+
+    // the name is a Pascal string, its length byte included it's padded to an
+    // even length
+    let name_length = usize::from(*block.get(6)?);
+    let name = block.get(7..7 + name_length)?.to_vec();
+    let position = 7 + name_length + (name_length + 1) % 2;
+
+    let size = u32::from_be_bytes(block.get(position..position + 4)?.try_into().ok()?);
+    let start = position + 4;
+    let end = start.checked_add(usize::try_from(size).ok()?)?;
+    let resource = Resource {
+        id,
+        name,
+        data: block.get(start..end)?.to_vec(),
+    };
+
+    // the data is padded to an even length too, the padding of the last block
+    // is sometimes left out
+    *data = block.get(end + end % 2..).unwrap_or(&[]);
+
+    Some(resource)
+}
@@ -0,0 +1,271 @@
+//! The source of the bytes of an image
+//!
+//! Images in memory are read in place, other sources are read on demand into a
+//! small buffer, so decoding from a file or a socket doesn't need the whole
+//! image in memory.
+//!
+//! Entropy decoders read past the end of the data (e.g. truncated images) as
+//! zeroes, the position keeps counting so it can be compared against the
+//! length of the data. Errors of the source are treated as the end of the
+//! data, and reported once decoding is done, see `check_error`.
+
+use std::borrow::Cow;
+use std::io::{BufRead, ErrorKind, Read};
+
+/// Bytes read from the source at a time
+const CHUNK_SIZE: usize = 4096;
+
+/// Bytes before the position kept when refilling, so the bytes a marker
+/// ending the data was made of can be looked at, see `previous`
+const KEEP: usize = 2;
+
+/// Reads an image from memory or from any `Read` source
+pub(crate) struct ByteReader<'a>
+{
+    /// Where bytes come from once `buffer` is exhausted, `None` for images
+    /// in memory and at the end of the source
+    source:   Option<&'a mut dyn Read>,
+    /// Bytes read so far, that haven't been discarded
+    buffer:   Cow<'a, [u8]>,
+    /// Position of the next byte in `buffer`, past its end once the source
+    /// has nothing left
+    position: usize,
+    /// Offset of the first byte of `buffer` from the start of the data
+    start:    usize,
+    /// An error from the source
+    error:    Option<std::io::Error>,
+}
+
+impl<'a> ByteReader<'a>
+{
+    /// Read an image already in memory
+    pub(crate) fn new(data: &'a [u8]) -> ByteReader<'a>
+    {
+        ByteReader {
+            source:   None,
+            buffer:   Cow::Borrowed(data),
+            position: 0,
+            start:    0,
+            error:    None,
+        }
+    }
+
+    /// Read an image from `source`, as needed
+    pub(crate) fn from_reader(source: &'a mut dyn Read) -> ByteReader<'a>
+    {
+        ByteReader {
+            source:   Some(source),
+            buffer:   Cow::Owned(Vec::with_capacity(CHUNK_SIZE + KEEP)),
+            position: 0,
+            start:    0,
+            error:    None,
+        }
+    }
+
+    /// Offset of the next byte from the start of the data
+    pub(crate) fn position(&self) -> usize
+    {
+        self.start + self.position
+    }
+
+    /// Bytes read but not consumed yet
+    pub(crate) fn buffered(&self) -> &[u8]
+    {
+        self.buffer.get(self.position..).unwrap_or(&[])
+    }
+
+    /// The `N` bytes before the position, if they are still in the buffer
+    pub(crate) fn previous<const N: usize>(&self) -> Option<[u8; N]>
+    {
+        let start = self.position.checked_sub(N)?;
+
+        self.buffer.get(start..self.position)?.try_into().ok()
+    }
+
+    /// Read another chunk from the source, after the bytes already buffered
+    ///
+    /// Returns `false` at the end of the source.
+    pub(crate) fn read_more(&mut self) -> bool
+    {
+        let Some(source) = self.source.as_mut()
+        else
+        {
+            return false;
+        };
+        let buffer = self.buffer.to_mut();
+        let end = buffer.len();
+
+        buffer.resize(end + CHUNK_SIZE, 0);
+
+        loop
+        {
+            match source.read(&mut buffer[end..])
+            {
+                Ok(0) =>
+                {
+                    buffer.truncate(end);
+                    self.source = None;
+
+                    return false;
+                }
+                Ok(length) =>
+                {
+                    buffer.truncate(end + length);
+
+                    return true;
+                }
+                Err(error) if error.kind() == ErrorKind::Interrupted => (),
+                Err(error) =>
+                {
+                    buffer.truncate(end);
+                    self.source = None;
+                    self.error = Some(error);
+
+                    return false;
+                }
+            }
+        }
+    }
+
+    /// Make sure `n` bytes are buffered, if the data has that many left
+    ///
+    /// Returns `false` if it hasn't.
+    #[inline]
+    pub(crate) fn fill(&mut self, n: usize) -> bool
+    {
+        if self.buffered().len() >= n
+        {
+            return true;
+        }
+        self.refill(n)
+    }
+
+    #[cold]
+    fn refill(&mut self, n: usize) -> bool
+    {
+        if self.source.is_none()
+        {
+            return false;
+        }
+        // drop consumed bytes, so the buffer stays small
+        let discard = self.position.saturating_sub(KEEP);
+
+        if discard > 0
+        {
+            self.buffer.to_mut().drain(..discard);
+            self.start += discard;
+            self.position -= discard;
+        }
+
+        while self.buffered().len() < n
+        {
+            if !self.read_more()
+            {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Whether there is no data left
+    pub(crate) fn is_empty(&mut self) -> bool
+    {
+        !self.fill(1)
+    }
+
+    /// Read a byte, zero past the end of the data
+    #[inline]
+    pub(crate) fn read_u8(&mut self) -> u8
+    {
+        if let Some(byte) = self.buffer.get(self.position)
+        {
+            self.position += 1;
+
+            return *byte;
+        }
+        if self.refill(1)
+        {
+            return self.read_u8();
+        }
+        self.position += 1;
+
+        0
+    }
+
+    /// The next `N` bytes, without consuming them
+    ///
+    /// Returns `None` if the data has less than `N` bytes left.
+    #[inline]
+    pub(crate) fn peek<const N: usize>(&mut self) -> Option<[u8; N]>
+    {
+        if !self.fill(N)
+        {
+            return None;
+        }
+        self.buffered()[..N].try_into().ok()
+    }
+
+    /// Skip `n` bytes, or what's left of the data if it has less
+    pub(crate) fn skip(&mut self, n: usize)
+    {
+        let mut n = n;
+
+        loop
+        {
+            let available = n.min(self.buffered().len());
+
+            self.position += available;
+            n -= available;
+
+            if n == 0 || !self.fill(1)
+            {
+                return;
+            }
+        }
+    }
+
+    /// Return the error the source returned, if any
+    ///
+    /// The decoder sees the end of the data instead, e.g. entropy decoders
+    /// can't report errors as they read bytes, so this should be checked once
+    /// it's done, whether it succeeded or not.
+    pub(crate) fn check_error(&mut self) -> std::io::Result<()>
+    {
+        self.error.take().map_or(Ok(()), Err)
+    }
+}
+
+impl Read for ByteReader<'_>
+{
+    /// Reads fill `buf` unless the data ends, even across chunks of the source
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize>
+    {
+        let mut read = 0;
+
+        while read < buf.len() && self.fill(1)
+        {
+            let available = self.buffered();
+            let length = available.len().min(buf.len() - read);
+
+            buf[read..read + length].copy_from_slice(&available[..length]);
+            self.position += length;
+            read += length;
+        }
+        Ok(read)
+    }
+}
+
+impl BufRead for ByteReader<'_>
+{
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]>
+    {
+        self.fill(1);
+
+        Ok(self.buffered())
+    }
+
+    fn consume(&mut self, amt: usize)
+    {
+        self.position = (self.position + amt).min(self.buffer.len());
+    }
+}
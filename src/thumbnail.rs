@@ -0,0 +1,203 @@
+//! Embedded previews
+//!
+//! Images can carry small previews of themselves for file browsers and
+//! galleries, a JPEG image in IFD1 of the EXIF segment (by far the most common,
+//! cameras and phones write one), a JPEG image in Photoshop image resources,
+//! and thumbnails in the JFIF segment or in JFXX extension segments, which are
+//! JPEG images, palette indices or RGB pixels.
+//!
+//! The previews are found while reading headers, this picks the largest one
+//! and decodes it.
+
+use crate::decoder::JfifThumbnail;
+use crate::errors::DecodeErrors;
+use crate::exif::Exif;
+use crate::misc::ColorSpace;
+use crate::worker::apply_orientation;
+use crate::Decoder;
+
+/// A decoded thumbnail
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Thumbnail
+{
+    pub width:      u16,
+    pub height:     u16,
+    /// Colorspace of `pixels`, the output colorspace of the decoder for JPEG
+    /// thumbnails and RGB for uncompressed ones
+    pub colorspace: ColorSpace,
+    pub pixels:     Vec<u8>,
+}
+
+/// A preview found in the headers
+enum Candidate<'a>
+{
+    /// A JPEG image, and whether it has red and blue swapped
+    Jpeg(&'a [u8], bool),
+    Uncompressed(&'a JfifThumbnail),
+}
+
+impl Decoder
+{
+    /// Decode the largest preview embedded in the image
+    ///
+    /// Previews are looked for in the EXIF segment, in Photoshop image
+    /// resources and in the JFIF and JFXX segments. JPEG previews are decoded with the options of this decoder,
+    /// uncompressed ones (palette indices or RGB pixels) are returned as RGB
+    /// pixels. If the options ask for the EXIF orientation to be applied, it's
+    /// applied to the preview too.
+    ///
+    /// This **must** be called after `read_headers`, `decode_buffer` or
+    /// `decode_file`, the decoder itself is left as it was.
+    ///
+    /// Returns `Ok(None)` if the image has no preview.
+    ///
+    /// # Errors
+    /// If the chosen JPEG preview can't be decoded.
+    pub fn thumbnail(&self) -> Result<Option<Thumbnail>, DecodeErrors>
+    {
+        let options = self.options.set_apply_orientation(false);
+
+        let mut best = None;
+        let mut best_size = 0;
+
+        let photoshop = self.photoshop().and_then(|x| x.thumbnail());
+
+        let jpegs = self
+            .exif
+            .as_ref()
+            .and_then(Exif::thumbnail)
+            .map(|x| (x, false))
+            .into_iter()
+            .chain(photoshop.as_ref().map(|x| (&x.data[..], x.bgr)))
+            .chain(self.info.jfif_thumbnails.iter().filter_map(|x| match x
+            {
+                JfifThumbnail::Jpeg(data) => Some((&data[..], false)),
+                _ => None,
+            }));
+
+        for (data, bgr) in jpegs
+        {
+            let mut decoder = Decoder::new_with_options(options);
+
+            if let Err(err) = decoder.read_headers(data)
+            {
+                warn!("Ignoring a thumbnail whose headers can't be read: {err:?}");
+                continue;
+            }
+            let size = usize::from(decoder.width()) * usize::from(decoder.height());
+
+            if size > best_size
+            {
+                best = Some(Candidate::Jpeg(data, bgr));
+                best_size = size;
+            }
+        }
+
+        for thumbnail in &self.info.jfif_thumbnails
+        {
+            let size = match thumbnail
+            {
+                JfifThumbnail::Palette { width, height, .. }
+                | JfifThumbnail::Rgb { width, height, .. } =>
+                {
+                    usize::from(*width) * usize::from(*height)
+                }
+                JfifThumbnail::Jpeg(_) => continue,
+            };
+            // JPEG previews win ties, they're likely made for the image
+            if size > best_size
+            {
+                best = Some(Candidate::Uncompressed(thumbnail));
+                best_size = size;
+            }
+        }
+
+        let mut thumbnail = match best
+        {
+            None => return Ok(None),
+            Some(Candidate::Jpeg(data, bgr)) =>
+            {
+                let mut decoder = Decoder::new_with_options(options);
+                let mut pixels = decoder.decode_buffer(data)?;
+                let colorspace = decoder.get_output_colorspace();
+
+                if bgr
+                    && matches!(
+                        colorspace,
+                        ColorSpace::RGB | ColorSpace::RGBA | ColorSpace::RGBX
+                    )
+                {
+                    for pixel in pixels.chunks_exact_mut(colorspace.num_components())
+                    {
+                        pixel.swap(0, 2);
+                    }
+                }
+
+                Thumbnail {
+                    width: decoder.width(),
+                    height: decoder.height(),
+                    colorspace,
+                    pixels,
+                }
+            }
+            Some(Candidate::Uncompressed(thumbnail)) => uncompressed_thumbnail(thumbnail),
+        };
+
+        let orientation = self.orientation();
+
+        if orientation != 1
+        {
+            thumbnail.pixels = apply_orientation(
+                thumbnail.pixels,
+                usize::from(thumbnail.width),
+                usize::from(thumbnail.height),
+                orientation,
+            );
+            if orientation >= 5
+            {
+                std::mem::swap(&mut thumbnail.width, &mut thumbnail.height);
+            }
+        }
+
+        Ok(Some(thumbnail))
+    }
+}
+
+/// RGB pixels of a palette or RGB thumbnail
+fn uncompressed_thumbnail(thumbnail: &JfifThumbnail) -> Thumbnail
+{
+    let (width, height, pixels) = match thumbnail
+    {
+        JfifThumbnail::Palette {
+            width,
+            height,
+            palette,
+            indices,
+        } =>
+        {
+            let pixels = indices
+                .iter()
+                .flat_map(|x| {
+                    let start = usize::from(*x) * 3;
+
+                    palette[start..start + 3].iter().copied()
+                })
+                .collect();
+
+            (*width, *height, pixels)
+        }
+        JfifThumbnail::Rgb {
+            width,
+            height,
+            pixels,
+        } => (*width, *height, pixels.clone()),
+        JfifThumbnail::Jpeg(_) => unreachable!("JPEG thumbnails are decoded"),
+    };
+
+    Thumbnail {
+        width: u16::from(width),
+        height: u16::from(height),
+        colorspace: ColorSpace::RGB,
+        pixels,
+    }
+}
@@ -38,8 +38,6 @@
 //! For our sliding window approach, `A` is the 1st and `B` is either the 0th term or 2nd term
 //! depending on position we are writing.(see scalar code).
 //!
-//! For vector code see module sse for explanation.
-//!
 //! # Vertical bi-linear.
 //! Vertical up-sampling is a bit trickier.
 //!
@@ -66,86 +64,328 @@
 //!
 //! # Horizontal vertical downsampling/chroma quartering.
 //!
-//! Carry out a vertical filter in the first pass, then a horizontal filter in the second pass.
-#[cfg(feature = "x86")]
-#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-pub use sse::upsample_horizontal_sse;
-
-use crate::components::UpSampler;
-pub use crate::upsampler::scalar::{upsample_horizontal, upsample_vertical};
+//! Carry out a vertical filter first, then a horizontal filter on its results.
+//!
+//! # Other sampling factors
+//! Like libjpeg, the filter is only used when a component is up-sampled two times
+//! horizontally, vertically or both, otherwise samples are replicated, e.g. for 4:1:1
+//! images or a component sampled twice in an image whose largest sampling factor is three.
+//!
+//! Rounding also follows libjpeg, which alternates between rounding ties up and down
+//! between neighbouring outputs, so that our up-sampling matches it exactly.
+//!
+//! # SIMD
+//! 8 bit images sampled two times horizontally, vertically or both use SSE and AVX2
+//! routines where the CPU supports them, which filter rows of 16 bit samples.
+use crate::components::Components;
+use crate::upsampler::scalar::{upsample_nearest, upsample_triangle, upsample_vertical};
 
 mod avx2;
 mod scalar;
 mod sse;
 
-// choose best possible implementation for this platform
-pub fn choose_horizontal_samp_function(use_unsafe:bool) -> UpSampler
+/// A SIMD routine up-sampling a row of an 8 bit image, takes the same
+/// arguments as [`UpSampler::upsample_row`]
+pub type UpSampleRow = fn(near: &[i16], far: &[i16], y: usize, output: &mut [i16]);
+
+/// Samples we up-sample, 8 bit images keep theirs in `i16`'s, wider ones in `u16`'s
+///
+/// The filters work in `i32`'s, which hold sums of samples of up to 16 bits.
+pub trait Sample: Copy
+{
+    fn widen(self) -> i32;
+
+    fn narrow(value: i32) -> Self;
+
+    /// Up-sample a row with the SIMD routine of `upsampler` if it has one for
+    /// these samples, returns whether it did
+    fn upsample_simd(
+        _upsampler: &UpSampler, _near: &[Self], _far: &[Self], _y: usize, _output: &mut [Self],
+    ) -> bool
+    {
+        false
+    }
+}
+
+impl Sample for i16
 {
-    if use_unsafe {
-        #[cfg(all(feature = "x86", any(target_arch = "x86_64", target_arch = "x86")))]
+    fn widen(self) -> i32
+    {
+        i32::from(self)
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn narrow(value: i32) -> Self
+    {
+        value as i16
+    }
+
+    fn upsample_simd(
+        upsampler: &UpSampler, near: &[Self], far: &[Self], y: usize, output: &mut [Self],
+    ) -> bool
+    {
+        if let Some(upsample_row) = upsampler.simd
         {
-            if is_x86_feature_detected!("sse4.1")
-            {
-                debug!("Using sse H up-sampler");
-                return sse::upsample_horizontal_sse;
-            }
+            upsample_row(near, far, y, output);
+            return true;
         }
+        false
     }
-    debug!("Using scalar H up-sampler");
-    return scalar::upsample_horizontal;
 }
-pub fn choose_hv_samp_function(use_unsafe:bool) -> UpSampler
+
+impl Sample for u16
 {
-    if use_unsafe {
-        #[cfg(all(feature = "x86", any(target_arch = "x86_64", target_arch = "x86")))]
+    fn widen(self) -> i32
+    {
+        i32::from(self)
+    }
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn narrow(value: i32) -> Self
+    {
+        value as u16
+    }
+}
+
+/// Up-samples a component to the dimensions of the image, a row at a time
+///
+/// Rows of the component are passed in by the caller, which can keep as many of
+/// them as it wants, e.g. a row of MCU's and the rows bordering it.
+#[derive(Copy, Clone)]
+pub struct UpSampler
+{
+    /// Sampling factor of the component and the largest one in the image, horizontally
+    h_samp:     (usize, usize),
+    /// Sampling factor of the component and the largest one in the image, vertically
+    v_samp:     (usize, usize),
+    /// Dimensions of the component, samples past them are padding
+    width:      usize,
+    height:     usize,
+    /// Whether to filter horizontally, vertically, or replicate samples if neither
+    triangle_h: bool,
+    triangle_v: bool,
+    /// SIMD routine for 8 bit samples, if the CPU supports one for these factors
+    simd:       Option<UpSampleRow>,
+}
+
+impl UpSampler
+{
+    /// Create an up-sampler for `component` of an image `width` by `height` pixels,
+    /// whose largest sampling factors are `h_max` and `v_max`
+    pub fn new(
+        component: &Components, width: usize, height: usize, h_max: usize, v_max: usize,
+    ) -> UpSampler
+    {
+        let h_samp = (component.horizontal_sample, h_max);
+        let v_samp = (component.vertical_sample, v_max);
+        let width = (width * h_samp.0).div_ceil(h_max);
+        // libjpeg only filters components wider than two samples horizontally
+        let (triangle_h, triangle_v) = match (h_samp.0 * 2 == h_max, v_samp.0 * 2 == v_max)
         {
-            if is_x86_feature_detected!("avx2")
+            (true, true) if width > 2 => (true, true),
+            (true, false) if width > 2 && v_samp.0 == v_max => (true, false),
+            (false, true) if h_samp.0 == h_max => (false, true),
+            _ => (false, false),
+        };
+
+        UpSampler {
+            h_samp,
+            v_samp,
+            width,
+            height: (height * v_samp.0).div_ceil(v_max),
+            triangle_h,
+            triangle_v,
+            simd: None,
+        }
+    }
+
+    /// Up-sample 8 bit images with SIMD routines where the CPU supports them,
+    /// if `use_unsafe` is set
+    #[must_use]
+    pub fn set_use_unsafe(mut self, use_unsafe: bool) -> UpSampler
+    {
+        self.simd = None;
+
+        if use_unsafe
+        {
+            #[cfg(all(feature = "x86", any(target_arch = "x86_64", target_arch = "x86")))]
             {
-                debug!("Using avx HV up-sampler");
-                return avx2::upsample_hv_simd;
+                self.simd = match (self.triangle_h, self.triangle_v)
+                {
+                    (true, true) if is_x86_feature_detected!("avx2") =>
+                    {
+                        Some(avx2::upsample_hv_avx2)
+                    }
+                    (true, false) if is_x86_feature_detected!("sse2") =>
+                    {
+                        Some(sse::upsample_horizontal_sse)
+                    }
+                    (false, true) if is_x86_feature_detected!("sse2") =>
+                    {
+                        Some(sse::upsample_vertical_sse)
+                    }
+                    _ => None,
+                };
             }
         }
+        self
+    }
+
+    /// Whether the component is sampled less than the image
+    pub fn is_needed(&self) -> bool
+    {
+        self.h_samp.0 != self.h_samp.1 || self.v_samp.0 != self.v_samp.1
     }
-    debug!("using scalar HV up-sampler");
-    return scalar::upsample_hv;
-}
 
-/// Upsample nothing
+    /// Whether output rows also depend on the rows above and below the one
+    /// covering them
+    pub fn uses_neighbours(&self) -> bool
+    {
+        self.triangle_v
+    }
 
-pub fn upsample_no_op(_: &[i16], _: usize) -> Vec<i16>
-{
-    return Vec::new();
+    /// Rows of the component needed for output row `y`, the one covering it and its
+    /// next nearest row which the vertical filter mixes in
+    ///
+    /// Rows past the edges of the component are replaced by the edge rows.
+    pub fn source_rows(&self, y: usize) -> (usize, usize)
+    {
+        let near = (y * self.v_samp.0 / self.v_samp.1).min(self.height - 1);
+
+        if !self.triangle_v
+        {
+            return (near, near);
+        }
+        // the first of two output rows is closer to the row above
+        let far = if y % 2 == 1
+        {
+            (near + 1).min(self.height - 1)
+        }
+        else
+        {
+            near.saturating_sub(1)
+        };
+
+        (near, far)
+    }
+
+    /// Up-sample output row `y` from the rows `source_rows` returned, `output` is as
+    /// wide as the image
+    pub fn upsample_row<T: Sample>(
+        &self, near: &[T], far: &[T], y: usize, sums: &mut Vec<i32>, output: &mut [T],
+    )
+    {
+        let (near, far) = (&near[..self.width], &far[..self.width]);
+
+        if T::upsample_simd(self, near, far, y, output)
+        {
+            return;
+        }
+
+        match (self.triangle_h, self.triangle_v)
+        {
+            (true, true) =>
+            {
+                sums.clear();
+                sums.extend(
+                    near.iter()
+                        .zip(far)
+                        .map(|(near, far)| near.widen() * 3 + far.widen()),
+                );
+
+                upsample_triangle(sums, output, 4, (8, 7));
+            }
+            (true, false) =>
+            {
+                sums.clear();
+                sums.extend(near.iter().map(|x| x.widen()));
+
+                upsample_triangle(sums, output, 2, (1, 2));
+            }
+            (false, true) =>
+            {
+                let bias = if y % 2 == 1 { 2 } else { 1 };
+
+                upsample_vertical(near, far, output, bias);
+            }
+            (false, false) => upsample_nearest(near, output, self.h_samp),
+        }
+    }
 }
 
 //---------------------------------------------
 // TEST
 //----------------------------------------------
-#[test]
+#[cfg(test)]
 #[cfg(feature = "x86")]
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-fn upsample_sse_v1()
+#[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+fn compare_simd(h_samp: usize, v_samp: usize, width: usize)
 {
-    let v: Vec<i16> = (0..128).collect();
+    let scalar = UpSampler {
+        h_samp:     (1, h_samp),
+        v_samp:     (1, v_samp),
+        width:      width.div_ceil(h_samp),
+        height:     2,
+        triangle_h: h_samp == 2,
+        triangle_v: v_samp == 2,
+        simd:       None,
+    };
+    let simd = scalar.set_use_unsafe(true);
+
+    if simd.simd.is_none()
+    {
+        return;
+    }
+    let near: Vec<i16> = (0..scalar.width)
+        .map(|x| ((x * 97 + 13) % 256) as i16)
+        .collect();
+    let far: Vec<i16> = near.iter().rev().copied().collect();
+
+    for y in 0..2
+    {
+        let mut expected = vec![0; width];
+        let mut output = vec![0; width];
 
-    assert_eq!(
-        upsample_horizontal_sse(&v, v.len() * 2),
-        crate::upsampler::scalar::upsample_horizontal(&v, v.len() * 2),
-        "Algorithms do not match"
-    );
+        scalar.upsample_row(&near, &far, y, &mut Vec::new(), &mut expected);
+        simd.upsample_row(&near, &far, y, &mut Vec::new(), &mut output);
+
+        assert_eq!(
+            output, expected,
+            "Algorithms do not match for width {width}"
+        );
+    }
 }
 
 #[test]
 #[cfg(feature = "x86")]
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-fn upsample_sse_v2()
+fn upsample_horizontal_simd()
 {
-    use crate::upsampler::scalar::upsample_horizontal;
+    for width in [5, 16, 17, 18, 19, 33, 64, 127, 2560]
+    {
+        compare_simd(2, 1, width);
+    }
+}
 
-    let v: Vec<i16> = (0..1280).rev().collect();
+#[test]
+#[cfg(feature = "x86")]
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn upsample_vertical_simd()
+{
+    for width in [1, 7, 8, 9, 31, 64, 1281]
+    {
+        compare_simd(1, 2, width);
+    }
+}
 
-    assert_eq!(
-        upsample_horizontal_sse(&v, v.len() * 2),
-        upsample_horizontal(&v, v.len() * 2),
-        "Algorithms do not match"
-    );
+#[test]
+#[cfg(feature = "x86")]
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn upsample_hv_simd()
+{
+    for width in [5, 33, 34, 35, 36, 67, 128, 255, 2560]
+    {
+        compare_simd(2, 2, width);
+    }
 }
@@ -1,50 +1,76 @@
 use std::cmp::min;
+use std::collections::BTreeMap;
 use std::convert::TryInto;
+use std::sync::mpsc::{channel, Receiver, Sender};
 
-use crate::color_convert::{ycbcr_to_grayscale, ycbcr_to_ycbcr};
+use crate::color_convert::{
+    cmyk_to_rgb_u16, rgb_to_rgb, ycbcr_to_grayscale, ycbcr_to_rgb_u16, ycbcr_to_ycbcr,
+    ycck_to_cmyk_u16,
+};
 use crate::components::Components;
 use crate::decoder::{ColorConvert16Ptr, IDCTPtr};
-use crate::misc::ColorSpace;
+use crate::errors::DecodeErrors;
+use crate::icc::IccTransform;
+use crate::idct::dequantize_and_idct_wide;
+use crate::misc::{Aligned32, ColorSpace};
+use crate::upsampler::UpSampler;
+/// Coefficients of a component in a few rows of MCU's
+///
+/// Blocks are in raster order, like the progressive decoder keeps them for the
+/// whole image. `above` and `below` are the rows of blocks bordering them, empty at
+/// the edges of the image, which vertical up-sampling reaches into.
+#[derive(Copy, Clone, Default)]
+pub(crate) struct McuRow<'a>
+{
+    pub blocks: &'a [i16],
+    pub above:  &'a [i16],
+    pub below:  &'a [i16],
+}
+
+/// Rows of MCU's post processed together
+///
+/// When up-sampling reads the rows of blocks bordering them, each group carries
+/// out IDCT on those too, so a few rows are grouped. So are rows the EXIF
+/// orientation turns into columns, which then fill longer runs of the output.
+/// Otherwise rows are post processed one at a time, while their coefficients
+/// are still in cache.
+pub(crate) fn mcu_rows_per_task(uses_neighbours: bool, orientation: u16) -> usize
+{
+    if uses_neighbours || orientation >= 5
+    {
+        4
+    }
+    else
+    {
+        1
+    }
+}
+
 /// Handle everything else in jpeg processing that doesn't involve bitstream decoding
 ///
-/// This handles routines for images which are interleaved for non-interleaved use post_process_non_interleaved
+/// This carries out IDCT, up-sampling and color conversion of a few rows of MCU's.
 ///
 /// # Arguments
 /// - coeff - Contains Y,Cb,Cr components straight from the bitstream decoder
 /// - component_data - Contains metadata for unprocessed values, e.g QT tables and such
 /// - idct_func - IDCT function pointer
 /// - color_convert_16 - Carry out color conversion on 2 mcu's
-/// - color_convert - Carry out color conversion on a single MCU
 /// - input_colorspace - The colorspace the image is in
 /// - output_colorspace: Colorspace to change the value to
-/// - output - Where to write the converted data
-/// - mcu_len - Number of MCU's per width
-/// - width - Width of the image.
-/// - position: Offset from which to write the pixels
-#[allow(
-clippy::too_many_arguments,
-clippy::cast_sign_loss,
-clippy::cast_possible_truncation,
-clippy::doc_markdown,
-clippy::single_match
-)]
-#[rustfmt::skip]
+/// - band - Where to write the converted data, the rows of the image covered by the MCU rows
+///   turned by the EXIF orientation
+/// - mcu_row - Index of the first MCU row
+/// - width, height - Dimensions of the image
+/// - h_max, v_max - Maximum sampling factors of the image
+/// - icc - Transform from the embedded ICC profile to sRGB, if asked for
+#[allow(clippy::too_many_arguments, clippy::doc_markdown)]
 pub(crate) fn post_process(
-    coeff: &[&[i16]; 3],
-    component_data: &[Components],
-    idct_func: IDCTPtr,
-    color_convert_16: ColorConvert16Ptr,
-    input_colorspace: ColorSpace,
-    output_colorspace: ColorSpace,
-    output: &mut [u8],
-    width: usize,
-) // so many parameters..
-{
-    // maximum sampling factors are in Y-channel, no need to pass them.
-    let h_samp = component_data[0].horizontal_sample;
-    let v_samp = component_data[0].vertical_sample;
-    // carry out dequantization and inverse DCT
-
+    coeff: &[McuRow; 3], component_data: &[Components], idct_func: IDCTPtr,
+    color_convert_16: ColorConvert16Ptr, use_unsafe: bool, input_colorspace: ColorSpace,
+    output_colorspace: ColorSpace, mut band: OutputBand<u8>, mcu_row: usize, width: usize,
+    height: usize, h_max: usize, v_max: usize, icc: Option<&IccTransform>,
+)
+{
     // So we want to carry out IDCT and upsampling
     // But assuming we have an RGB image but the user asked for a grey-scale image,
     // we don't need to carry out idct and upsampling or even color conversion.
@@ -60,72 +86,120 @@ pub(crate) fn post_process(
         input_colorspace.num_components(),
         output_colorspace.num_components(),
     );
-    let mut unprocessed = [vec![], vec![], vec![]];
-
+    let num_components = output_colorspace.num_components();
 
-    (0..x).for_each(|z| {
-        // Calculate stride
-        // Stride is basically how many pixels must we traverse to write an MCU
-        // e.g For an 8*8 MCU in a 16*16 image
-        // stride is 16 because move 16 pixels from location, you are below where you are writing
+    let mut components: Vec<ComponentRows> = component_data
+        .iter()
+        .zip(coeff)
+        .take(x)
+        .map(|(component, coeff)| {
+            let upsampler =
+                UpSampler::new(component, width, height, h_max, v_max).set_use_unsafe(use_unsafe);
 
-        // carry out IDCT.
-        let v_samp_idct = {
-            if z == 0 { 1 } else { v_samp }
-        };
-        unprocessed[z] = idct_func(coeff[z],
-                                   &component_data[z].quantization_table,
-                                   component_data[z].width_stride,
-                                   h_samp * v_samp,
-                                   v_samp_idct);
-    });
+            ComponentRows::new(component, *coeff, upsampler, idct_func, mcu_row)
+        })
+        .collect();
 
-    post_process_inner(&mut unprocessed, component_data, color_convert_16,
-                       input_colorspace, output_colorspace, output,  width);
-}
+    let mut upsampled = vec![vec![0; width]; x];
+    let mut sums = Vec::with_capacity(width);
+    let rows_per_mcu = 8 * v_max;
+    let stride = width * num_components;
 
-#[rustfmt::skip]
-pub(crate) fn post_process_inner(
-    unprocessed: &mut [Vec<i16>; 3], component_data: &[Components],
-    color_convert_16: ColorConvert16Ptr,
-    input_colorspace: ColorSpace, output_colorspace: ColorSpace, output: &mut [u8],
-    width: usize,
-) // so many parameters..
-{
-    let x = min(
-        input_colorspace.num_components(),
-        output_colorspace.num_components(),
-    );
-    // maximum sampling factors are in Y-channel, no need to pass them.
-    let h_samp = component_data[0].horizontal_sample;
-    let v_samp = component_data[0].vertical_sample;
+    // rows that are turned are written to the output once they're all converted
+    let mut turned = vec![];
+    let pixels = match &mut band
+    {
+        OutputBand::Rows(rows) => &mut **rows,
+        OutputBand::Turned { .. } =>
+        {
+            turned = vec![0; band.rows(stride) * stride];
+            &mut turned
+        }
+    };
 
-    if h_samp != 1 || v_samp != 1
+    for (i, mcu_output) in pixels.chunks_mut(stride * rows_per_mcu).enumerate()
     {
-        // carry out upsampling , the return vector overwrites the original vector
-        for i in 1..x
+        if i > 0
         {
-            unprocessed[i] = (component_data[i].up_sampler)(&unprocessed[i], unprocessed[0].len());
+            for component in &mut components
+            {
+                component.advance(idct_func);
+            }
         }
+        let first_row = (mcu_row + i) * rows_per_mcu;
+
+        for (y, out) in (first_row..).zip(mcu_output.chunks_exact_mut(stride))
+        {
+            // rows of components that aren't sub-sampled are used as they are
+            let mut rows: [&[i16]; 3] = [&[]; 3];
+
+            for ((component, upsampled), row) in
+                components.iter().zip(upsampled.iter_mut()).zip(&mut rows)
+            {
+                let (near, far) = component.upsampler.source_rows(y);
+
+                if component.upsampler.is_needed()
+                {
+                    component.upsampler.upsample_row(
+                        component.row(near),
+                        component.row(far),
+                        y,
+                        &mut sums,
+                        upsampled,
+                    );
+                    *row = upsampled;
+                }
+                else
+                {
+                    *row = &component.row(near)[..width];
+                }
+            }
+
+            convert_row(
+                &rows,
+                out,
+                input_colorspace,
+                output_colorspace,
+                color_convert_16,
+            );
+        }
+    }
+
+    if let Some(icc) = icc
+    {
+        icc.convert_u8(pixels, num_components);
     }
+    if let OutputBand::Turned { .. } = band
+    {
+        band.write(&turned, stride);
+    }
+}
+
+/// Color convert a row of samples of each component into the output colorspace
+fn convert_row(
+    rows: &[&[i16]; 3], out: &mut [u8], input_colorspace: ColorSpace,
+    output_colorspace: ColorSpace, color_convert_16: ColorConvert16Ptr,
+)
+{
+    let num_components = output_colorspace.num_components();
 
-    // color convert
     match (input_colorspace, output_colorspace)
     {
         (ColorSpace::YCbCr | ColorSpace::GRAYSCALE, ColorSpace::GRAYSCALE) =>
         {
-            ycbcr_to_grayscale(&unprocessed[0], width, output);
+            ycbcr_to_grayscale(rows[0], out);
         }
-
         (ColorSpace::YCbCr, ColorSpace::YCbCr) =>
         {
-            ycbcr_to_ycbcr(unprocessed, width, h_samp, v_samp, output);
+            ycbcr_to_ycbcr(rows[0], rows[1], rows[2], out);
         }
-
         (ColorSpace::YCbCr, ColorSpace::RGB | ColorSpace::RGBA | ColorSpace::RGBX) =>
         {
-            color_convert_ycbcr(unprocessed, width, h_samp, v_samp,
-                output_colorspace, color_convert_16,  output);
+            ycbcr_to_rgb_row(color_convert_16, rows, out, num_components);
+        }
+        (ColorSpace::RGB, ColorSpace::RGB | ColorSpace::RGBA | ColorSpace::RGBX) =>
+        {
+            rgb_to_rgb(rows[0], rows[1], rows[2], out, num_components);
         }
         // For the other components we do nothing(currently)
         _ =>
@@ -133,119 +207,823 @@ pub(crate) fn post_process_inner(
     }
 }
 
-/// Do color-conversion for interleaved MCU
-#[allow(
-    clippy::similar_names,
-    clippy::too_many_arguments,
-    clippy::needless_pass_by_value,
-    clippy::unwrap_used
-)]
-fn color_convert_ycbcr(
-    mcu_block: &[Vec<i16>; 3], width: usize, h_samp: usize, v_samp: usize,
-    output_colorspace: ColorSpace, color_convert_16: ColorConvert16Ptr, output: &mut [u8],
+/// Convert a row of YCbCr samples to RGB(A/X) with `color_convert_16`
+fn ycbcr_to_rgb_row(
+    color_convert_16: ColorConvert16Ptr, rows: &[&[i16]; 3], output: &mut [u8],
+    num_components: usize,
 )
 {
-    let mcu_chunks = mcu_block[0].len() / (h_samp * v_samp);
-    // Width of image which takes into account fill bytes(it may be larger than actual width).
-    let width_chunk = mcu_chunks >> 3;
-    let stride = width * output_colorspace.num_components();
-
-    let mut start = 0;
-    let mut end = stride;
-    // over allocate to account for fill bytes
-    let mut temp = vec![];
-
-    if width < 16
-    {
-        // Allocate temporary buffer for small widths less than
-        // 16.
-        temp = vec![0; 16 * output_colorspace.num_components()];
-    }
-    // We need to chunk per width to ensure we can discard extra values at the end of the width.
-    // Since the encoder may pad bits to ensure the width is a multiple of 8.
-    for ((y_width, cb_width), cr_width) in mcu_block[0]
-        .chunks_exact(width_chunk)
-        .zip(mcu_block[1].chunks_exact(width_chunk))
-        .zip(mcu_block[2].chunks_exact(width_chunk))
-    {
-        let elements = (y_width.len() / 16).saturating_sub(1);
-
-        let mut position = 0;
-        let out = &mut output[start..end];
-
-        if width < 16
-        {
-            // allocate temporary buffers for the values received from idct
-            let mut y_out = [0; 16];
-            let mut cb_out = [0; 16];
-            let mut cr_out = [0; 16];
-            // copy those small widths to that buffer
-            y_out[0..y_width.len()].copy_from_slice(y_width);
-            cb_out[0..cb_width.len()].copy_from_slice(cb_width);
-            cr_out[0..cr_width.len()].copy_from_slice(cr_width);
-            // we handle widths less than 16 a bit differently, allocating a temporary
-            // buffer and writing to that and then flushing to the out buffer
-            // because of the optimizations applied below,
-            (color_convert_16)(&y_out, &cb_out, &cr_out, &mut temp, &mut 0);
-            // copy to stride
-            out[position..position + width * output_colorspace.num_components()]
-                .copy_from_slice(&temp[0..width * output_colorspace.num_components()]);
-            // increment position
-            start += stride;
-            end += stride;
-            // next
-            continue;
-        }
-
-        // Chunk in outputs of 16 to pass to color_convert as an array of 16 i16's.
-        for ((y, cb), cr) in y_width
-            .chunks_exact(16)
-            .zip(cb_width.chunks_exact(16))
-            .zip(cr_width.chunks_exact(16))
-            .take(elements)
-        {
-            (color_convert_16)(
-                y.try_into().unwrap(),
-                cb.try_into().unwrap(),
-                cr.try_into().unwrap(),
-                out,
-                &mut position,
-            );
+    // color_convert_16 converts 16 pixels at a time, writing up to 64 bytes, pixels
+    // too close to the end of the row go through a buffer
+    let direct = if output.len() < 64
+    {
+        0
+    }
+    else
+    {
+        min(
+            (output.len() - 64) / (16 * num_components) + 1,
+            rows[0].len() / 16,
+        )
+    };
+    let mut position = 0;
+
+    for ((y, cb), cr) in rows[0]
+        .chunks_exact(16)
+        .zip(rows[1].chunks_exact(16))
+        .zip(rows[2].chunks_exact(16))
+        .take(direct)
+    {
+        (color_convert_16)(
+            y.try_into().unwrap(),
+            cb.try_into().unwrap(),
+            cr.try_into().unwrap(),
+            output,
+            &mut position,
+        );
+    }
+
+    for ((y, cb), cr) in rows[0][direct * 16..]
+        .chunks(16)
+        .zip(rows[1][direct * 16..].chunks(16))
+        .zip(rows[2][direct * 16..].chunks(16))
+    {
+        let pad = |samples: &[i16]| {
+            let mut padded = [0; 16];
+            padded[..samples.len()].copy_from_slice(samples);
+            padded
+        };
+        let mut converted = [0; 64];
+        let length = y.len() * num_components;
+
+        (color_convert_16)(&pad(y), &pad(cb), &pad(cr), &mut converted, &mut 0);
+
+        output[position..position + length].copy_from_slice(&converted[..length]);
+        position += length;
+    }
+}
+
+/// Samples of a component in a row of MCU's, and the rows bordering them
+///
+/// IDCT is carried out on one row of MCU's at a time, which keeps the samples in
+/// cache while they're up-sampled and color converted. When vertical up-sampling
+/// reads the rows bordering them, the next row of MCU's is kept too.
+struct ComponentRows<'a>
+{
+    upsampler:          UpSampler,
+    quantization_table: &'a Aligned32<[i32; 64]>,
+    coeff:              McuRow<'a>,
+    /// Number of coefficients in a row of MCU's
+    mcu_len:            usize,
+    /// Index of the row of MCU's in `coeff` which `samples` holds
+    mcu:                usize,
+    samples:            Vec<i16>,
+    next:               Vec<i16>,
+    above:              Vec<i16>,
+    below:              Vec<i16>,
+    /// Index of the first row of `samples` in the component
+    first_row:          usize,
+    stride:             usize,
+}
+
+impl<'a> ComponentRows<'a>
+{
+    /// Carry out IDCT on the first row of MCU's of `coeff`, which is row `mcu_row`
+    /// of the image
+    fn new(
+        component: &'a Components, coeff: McuRow<'a>, upsampler: UpSampler, idct_func: IDCTPtr,
+        mcu_row: usize,
+    ) -> ComponentRows<'a>
+    {
+        let stride = component.width_stride;
+        let quantization_table = &component.quantization_table;
+        // only the last row of samples above and the first one below are used
+        let edge_row = |blocks: &[i16], row: usize| {
+            if blocks.is_empty() || !upsampler.uses_neighbours()
+            {
+                return vec![];
+            }
+            idct_func(blocks, quantization_table, stride)[row * stride..(row + 1) * stride].to_vec()
+        };
+        let mut rows = ComponentRows {
+            upsampler,
+            quantization_table,
+            coeff,
+            mcu_len: stride * 8 * component.vertical_sample,
+            mcu: 0,
+            samples: vec![],
+            next: vec![],
+            above: edge_row(coeff.above, 7),
+            below: edge_row(coeff.below, 0),
+            first_row: mcu_row * 8 * component.vertical_sample,
+            stride,
+        };
+
+        rows.samples = rows.idct(idct_func, 0);
+
+        if upsampler.uses_neighbours()
+        {
+            rows.next = rows.idct(idct_func, 1);
+        }
+        rows
+    }
+
+    /// Samples of row `mcu` of MCU's, empty past the last one
+    fn idct(&self, idct_func: IDCTPtr, mcu: usize) -> Vec<i16>
+    {
+        match self
+            .coeff
+            .blocks
+            .get(mcu * self.mcu_len..(mcu + 1) * self.mcu_len)
+        {
+            Some(blocks) => idct_func(blocks, self.quantization_table, self.stride),
+            None => vec![],
         }
+    }
 
-        // we have more pixels in the end that can't be handled by the main loop.
-        // move pointer back a little bit to get last 16 bytes,
-        // color convert, and overwrite
-        // This means some values will be color converted twice.
+    /// Move on to the next row of MCU's
+    fn advance(&mut self, idct_func: IDCTPtr)
+    {
+        let rows = self.samples.len() / self.stride;
 
-        let diff = 64_usize.saturating_sub(stride.saturating_sub(position));
+        self.mcu += 1;
+        self.first_row += rows;
 
-        position = position.saturating_sub(diff);
+        if self.upsampler.uses_neighbours()
+        {
+            self.above = self.samples[(rows - 1) * self.stride..].to_vec();
+            let next = self.idct(idct_func, self.mcu + 1);
 
-        (color_convert_16)(
-            y_width
-                .rchunks_exact(16)
-                .next()
-                .unwrap()
-                .try_into()
-                .unwrap(),
-            cb_width
-                .rchunks_exact(16)
-                .next()
-                .unwrap()
-                .try_into()
-                .unwrap(),
-            cr_width
-                .rchunks_exact(16)
-                .next()
-                .unwrap()
-                .try_into()
-                .unwrap(),
+            self.samples = std::mem::replace(&mut self.next, next);
+        }
+        else
+        {
+            self.samples = self.idct(idct_func, self.mcu);
+        }
+    }
+
+    /// Row `y` of the component, which is either in the MCU row or borders it
+    fn row(&self, y: usize) -> &[i16]
+    {
+        if y < self.first_row
+        {
+            &self.above
+        }
+        else if y >= self.first_row + self.samples.len() / self.stride
+        {
+            match self.next.get(..self.stride)
+            {
+                Some(row) => row,
+                None => &self.below,
+            }
+        }
+        else
+        {
+            let start = (y - self.first_row) * self.stride;
+
+            &self.samples[start..start + self.stride]
+        }
+    }
+}
+
+/// Handle post processing for images decoded into coefficients at once, e.g. those with
+/// more than 8 bits of precision or four components
+///
+/// Unlike `post_process`, this works on the whole image at once, carrying out
+/// IDCT and up-sampling one component plane at a time before color conversion.
+///
+/// # Arguments
+/// - coeff - Coefficients of each component, as laid out by the progressive decoder
+/// - component_data - Contains metadata for unprocessed values, e.g QT tables and such
+/// - input_colorspace - The colorspace the image is in
+/// - output_colorspace: Colorspace to change the value to
+/// - width, height - Dimensions of the image
+/// - h_max, v_max - Maximum sampling factors of the image
+/// - precision - Sample precision of the image
+/// - icc - Transform from the embedded ICC profile to sRGB, if asked for
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn post_process_u16(
+    coeff: &[Vec<i16>], component_data: &[Components], input_colorspace: ColorSpace,
+    output_colorspace: ColorSpace, width: usize, height: usize, h_max: usize, v_max: usize,
+    precision: u8, icc: Option<&IccTransform>,
+) -> Result<Vec<u16>, DecodeErrors>
+{
+    // grayscale output only needs the luminance, every other conversion
+    // needs all components of the image
+    let x = if output_colorspace == ColorSpace::GRAYSCALE
+    {
+        1
+    }
+    else
+    {
+        input_colorspace.num_components()
+    };
+    let mut planes = Vec::with_capacity(x);
+
+    for z in 0..x
+    {
+        let component = &component_data[z];
+        let (plane, stride) =
+            idct_component(&coeff[z], component, precision, level_shift(precision));
+
+        planes.push(upsample_component_u16(
+            plane, stride, component, width, height, h_max, v_max,
+        ));
+    }
+
+    color_convert_u16(
+        &planes,
+        input_colorspace,
+        output_colorspace,
+        width,
+        height,
+        (1 << precision) - 1,
+        icc,
+    )
+}
+
+/// Returns a function that level shifts and clamps IDCT results of an
+/// image with `precision` bits
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+pub(crate) fn level_shift(precision: u8) -> impl Fn(i64) -> u16 + Copy
+{
+    let max_value = (1_i64 << precision) - 1;
+
+    move |x| (x + (max_value + 1) / 2).clamp(0, max_value) as u16
+}
+
+/// Carry out IDCT on all blocks of a component laid out by the progressive decoder,
+/// passing the results through `store`
+///
+/// Returns the plane and the distance between its rows
+pub(crate) fn idct_component<T: Copy + Default>(
+    coeff: &[i16], component: &Components, precision: u8, store: impl Fn(i64) -> T + Copy,
+) -> (Vec<T>, usize)
+{
+    let blocks_wide = component.width_stride / 8;
+    let stride = blocks_wide * 8;
+
+    let mut plane = vec![T::default(); coeff.len()];
+
+    for (i, block) in coeff.chunks_exact(64).enumerate()
+    {
+        let start = (i / blocks_wide) * 8 * stride + (i % blocks_wide) * 8;
+
+        dequantize_and_idct_wide(
+            block,
+            &component.quantization_table,
+            &mut plane[start..],
+            stride,
+            precision,
+            store,
+        );
+    }
+
+    (plane, stride)
+}
+
+/// Up-sample a component plane (whose rows are `stride` samples apart) to the
+/// image dimensions if it's sub-sampled
+///
+/// Returns the new plane and the distance between its rows
+pub(crate) fn upsample_component_u16(
+    plane: Vec<u16>, stride: usize, component: &Components, width: usize, height: usize,
+    h_max: usize, v_max: usize,
+) -> (Vec<u16>, usize)
+{
+    let upsampler = UpSampler::new(component, width, height, h_max, v_max);
+
+    if !upsampler.is_needed()
+    {
+        return (plane, stride);
+    }
+
+    let mut output = vec![0; width * height];
+    let mut sums = Vec::with_capacity(width);
+
+    for (y, out) in output.chunks_exact_mut(width.max(1)).enumerate()
+    {
+        let (near, far) = upsampler.source_rows(y);
+
+        upsampler.upsample_row(
+            &plane[near * stride..],
+            &plane[far * stride..],
+            y,
+            &mut sums,
             out,
-            &mut position,
         );
+    }
+
+    (output, width)
+}
+
+/// Color convert full resolution component planes into the output colorspace
+///
+/// # Arguments
+/// - planes - Planes of each component and the distance between their rows
+/// - `max_value` - Largest value a sample can hold, e.g 4095 for 12 bit images
+/// - icc - Transform from the embedded ICC profile to sRGB, applied to each row
+///
+/// # Errors
+/// If there is no conversion from the input to the output colorspace
+pub(crate) fn color_convert_u16(
+    planes: &[(Vec<u16>, usize)], input_colorspace: ColorSpace, output_colorspace: ColorSpace,
+    width: usize, height: usize, max_value: u16, icc: Option<&IccTransform>,
+) -> Result<Vec<u16>, DecodeErrors>
+{
+    let num_components = output_colorspace.num_components();
+    let mut output = vec![0; width * height * num_components];
+    // four component images are converted to CMYK before anything else
+    let mut cmyk = vec![0; width * 4];
+
+    let row = |z: usize, y: usize| &planes[z].0[y * planes[z].1..y * planes[z].1 + width];
+
+    for (y, out) in output.chunks_exact_mut(width * num_components).enumerate()
+    {
+        match (input_colorspace, output_colorspace)
+        {
+            (ColorSpace::YCbCr | ColorSpace::GRAYSCALE, ColorSpace::GRAYSCALE) =>
+            {
+                out.copy_from_slice(row(0, y));
+            }
+            (ColorSpace::YCbCr, ColorSpace::YCbCr) =>
+            {
+                for (((pixel, y), cb), cr) in out
+                    .chunks_exact_mut(3)
+                    .zip(row(0, y))
+                    .zip(row(1, y))
+                    .zip(row(2, y))
+                {
+                    pixel.copy_from_slice(&[*y, *cb, *cr]);
+                }
+            }
+            (ColorSpace::YCbCr, ColorSpace::RGB | ColorSpace::RGBA | ColorSpace::RGBX) =>
+            {
+                ycbcr_to_rgb_u16(
+                    row(0, y),
+                    row(1, y),
+                    row(2, y),
+                    out,
+                    num_components,
+                    max_value,
+                );
+            }
+            (ColorSpace::RGB, ColorSpace::RGB | ColorSpace::RGBA | ColorSpace::RGBX) =>
+            {
+                for (x, pixel) in out.chunks_exact_mut(num_components).enumerate()
+                {
+                    pixel[..3].copy_from_slice(&[row(0, y)[x], row(1, y)[x], row(2, y)[x]]);
+
+                    if num_components == 4
+                    {
+                        pixel[3] = max_value;
+                    }
+                }
+            }
+            (
+                ColorSpace::CMYK | ColorSpace::YCCK,
+                ColorSpace::CMYK | ColorSpace::RGB | ColorSpace::RGBA | ColorSpace::RGBX,
+            ) =>
+            {
+                if input_colorspace == ColorSpace::YCCK
+                {
+                    ycck_to_cmyk_u16(
+                        row(0, y),
+                        row(1, y),
+                        row(2, y),
+                        row(3, y),
+                        &mut cmyk,
+                        max_value,
+                    );
+                }
+                else
+                {
+                    for (x, pixel) in cmyk.chunks_exact_mut(4).enumerate()
+                    {
+                        for (z, sample) in pixel.iter_mut().enumerate()
+                        {
+                            *sample = row(z, y)[x];
+                        }
+                    }
+                }
+
+                if output_colorspace == ColorSpace::CMYK
+                {
+                    out.copy_from_slice(&cmyk);
+                }
+                else if let Some(icc) = icc
+                {
+                    icc.cmyk_to_rgb_u16(&cmyk, out, num_components);
+                }
+                else
+                {
+                    cmyk_to_rgb_u16(&cmyk, out, num_components, max_value);
+                }
+            }
+            _ =>
+            {
+                return Err(DecodeErrors::Format(format!(
+                    "Conversion from {input_colorspace:?} to {output_colorspace:?} colorspace is not supported"
+                )));
+            }
+        }
+
+        if let Some(icc) = icc.filter(|icc| !icc.is_cmyk())
+        {
+            icc.convert_u16(out, num_components);
+        }
+    }
+
+    Ok(output)
+}
+
+/// Interleave full resolution component planes as they are, without color conversion
+pub(crate) fn interleave_u16(planes: &[(Vec<u16>, usize)], width: usize, height: usize)
+    -> Vec<u16>
+{
+    let num_components = planes.len();
+    let mut output = vec![0; width * height * num_components];
+
+    for (y, out) in output.chunks_exact_mut(width * num_components).enumerate()
+    {
+        for (x, pixel) in out.chunks_exact_mut(num_components).enumerate()
+        {
+            for (sample, (plane, stride)) in pixel.iter_mut().zip(planes)
+            {
+                *sample = plane[y * stride + x];
+            }
+        }
+    }
+
+    output
+}
+
+/// Rotate and flip decoded pixels of an image `width` pixels wide and `height`
+/// pixels high as the EXIF `orientation` says, so that they're upright
+///
+/// Flips (orientations 2 to 4) move pixels in place. Orientations 5 to 8 swap
+/// rows and columns, which makes the image `height` pixels wide, they're written
+/// to a new buffer a few rows at a time.
+pub(crate) fn apply_orientation<T: Copy>(
+    mut pixels: Vec<T>, width: usize, height: usize, orientation: u16,
+) -> Vec<T>
+{
+    if width == 0 || height == 0 || pixels.is_empty()
+    {
+        return pixels;
+    }
+    let components = pixels.len() / (width * height);
+    let stride = width * components;
+
+    let swap_pixels = |pixels: &mut [T], a: usize, b: usize| {
+        for z in 0..components
+        {
+            pixels.swap(a * components + z, b * components + z);
+        }
+    };
+
+    match orientation
+    {
+        // mirror horizontally
+        2 =>
+        {
+            for row in pixels.chunks_exact_mut(stride)
+            {
+                for x in 0..width / 2
+                {
+                    swap_pixels(row, x, width - 1 - x);
+                }
+            }
+        }
+        // rotate by 180 degrees
+        3 =>
+        {
+            let count = width * height;
+
+            for i in 0..count / 2
+            {
+                swap_pixels(&mut pixels, i, count - 1 - i);
+            }
+        }
+        // mirror vertically
+        4 =>
+        {
+            for y in 0..height / 2
+            {
+                let (top, bottom) = pixels.split_at_mut((height - 1 - y) * stride);
+
+                top[y * stride..(y + 1) * stride].swap_with_slice(&mut bottom[..stride]);
+            }
+        }
+        5..=8 =>
+        {
+            let mut turned = vec![pixels[0]; pixels.len()];
+            let mut output =
+                OrientedOutput::new(&mut turned, width, height, components, orientation);
+
+            // the rows stay in cache while they're spread over the columns of the output
+            for rows in pixels.chunks(stride * 16)
+            {
+                output.next_band(16).write(rows, stride);
+            }
+
+            return turned;
+        }
+        _ => (),
+    }
+
+    pixels
+}
+
+/// The output of an image `width` pixels wide and `height` pixels high, turned
+/// as the EXIF orientation says
+///
+/// It's split into the bands that rows of the upright image land on, in the
+/// order they're decoded. Orientations 2 to 4 turn those rows into rows of the
+/// output from the top or the bottom, orientations 5 to 8 into columns from the
+/// left or the right.
+pub(crate) struct OrientedOutput<'a, T>
+{
+    /// What's left of the output, its rows for orientations 2 to 8 (in the
+    /// order columns of the upright image land on them for 5 to 8)
+    lines:       Vec<&'a mut [T]>,
+    orientation: u16,
+    stride:      usize,
+    components:  usize,
+}
+
+impl<'a, T: Copy> OrientedOutput<'a, T>
+{
+    pub(crate) fn new(
+        output: &'a mut [T], width: usize, height: usize, components: usize, orientation: u16,
+    ) -> OrientedOutput<'a, T>
+    {
+        let stride = width * components;
+        let mut lines: Vec<&mut [T]> = match orientation
+        {
+            2..=4 => output.chunks_exact_mut(stride.max(1)).collect(),
+            5..=8 => output
+                .chunks_exact_mut((height * components).max(1))
+                .collect(),
+            _ => vec![output],
+        };
+
+        // bands are taken from the end of the rows, which is the top for 2, and
+        // columns of the upright image are taken bottom up for 7 and 8
+        match orientation
+        {
+            2 | 7 | 8 => lines.reverse(),
+            _ => (),
+        }
+
+        OrientedOutput {
+            lines,
+            orientation,
+            stride,
+            components,
+        }
+    }
+
+    /// The band the next `rows` rows of the upright image land on, or
+    /// those left if there are fewer
+    pub(crate) fn next_band(&mut self, rows: usize) -> OutputBand<'a, T>
+    {
+        match self.orientation
+        {
+            2..=4 =>
+            {
+                let rows = rows.min(self.lines.len());
+                let lines = self.lines.split_off(self.lines.len() - rows);
+
+                OutputBand::Turned {
+                    lines:       lines.into_iter().rev().collect(),
+                    orientation: self.orientation,
+                    components:  self.components,
+                }
+            }
+            5..=8 =>
+            {
+                let length = (rows * self.components).min(self.lines[0].len());
+                let from_end = matches!(self.orientation, 6 | 7);
+
+                let lines = self
+                    .lines
+                    .iter_mut()
+                    .map(|line| {
+                        let whole = std::mem::take(line);
+
+                        if from_end
+                        {
+                            let (rest, band) = whole.split_at_mut(whole.len() - length);
+                            *line = rest;
+                            band
+                        }
+                        else
+                        {
+                            let (band, rest) = whole.split_at_mut(length);
+                            *line = rest;
+                            band
+                        }
+                    })
+                    .collect();
+
+                OutputBand::Turned {
+                    lines,
+                    orientation: self.orientation,
+                    components: self.components,
+                }
+            }
+            _ =>
+            {
+                let output = std::mem::take(&mut self.lines[0]);
+                let (band, rest) = output.split_at_mut((rows * self.stride).min(output.len()));
+
+                self.lines[0] = rest;
+
+                OutputBand::Rows(band)
+            }
+        }
+    }
+}
+
+/// Where a band of rows of the upright image goes in the output
+pub(crate) enum OutputBand<'a, T>
+{
+    /// Rows of the output, when it's upright
+    Rows(&'a mut [T]),
+    /// Lines of the output the rows turn onto, see [`OrientedOutput`]
+    Turned
+    {
+        lines:       Vec<&'a mut [T]>,
+        orientation: u16,
+        components:  usize,
+    },
+}
+
+impl<T: Copy> OutputBand<'_, T>
+{
+    /// Number of rows of the upright image in the band, which are `stride`
+    /// samples long
+    pub(crate) fn rows(&self, stride: usize) -> usize
+    {
+        match self
+        {
+            OutputBand::Rows(rows) => rows.len() / stride.max(1),
+            OutputBand::Turned {
+                lines,
+                orientation,
+                components,
+            } =>
+            {
+                if *orientation >= 5
+                {
+                    lines.first().map_or(0, |x| x.len() / components)
+                }
+                else
+                {
+                    lines.len()
+                }
+            }
+        }
+    }
+
+    /// Write rows of the upright image, `stride` samples long, where they
+    /// land in the output
+    pub(crate) fn write(&mut self, pixels: &[T], stride: usize)
+    {
+        match self
+        {
+            OutputBand::Rows(rows) => rows.copy_from_slice(pixels),
+            OutputBand::Turned {
+                lines,
+                orientation,
+                components,
+            } => match components
+            {
+                1 => turn::<T, 1>(lines, *orientation, pixels, stride),
+                2 => turn::<T, 2>(lines, *orientation, pixels, stride),
+                3 => turn::<T, 3>(lines, *orientation, pixels, stride),
+                _ => turn::<T, 4>(lines, *orientation, pixels, stride),
+            },
+        }
+    }
+}
+
+/// Write rows of the upright image, `stride` samples long, onto `lines` of the
+/// output turned by `orientation`, for pixels of `C` samples
+fn turn<T: Copy, const C: usize>(
+    lines: &mut [&mut [T]], orientation: u16, pixels: &[T], stride: usize,
+)
+{
+    match orientation
+    {
+        2 | 3 =>
+        {
+            for (line, row) in lines.iter_mut().zip(pixels.chunks_exact(stride))
+            {
+                for (out, pixel) in line.chunks_exact_mut(C).zip(row.chunks_exact(C).rev())
+                {
+                    out.copy_from_slice(pixel);
+                }
+            }
+        }
+        4 =>
+        {
+            for (line, row) in lines.iter_mut().zip(pixels.chunks_exact(stride))
+            {
+                line.copy_from_slice(row);
+            }
+        }
+        _ =>
+        {
+            // a column of the band is a line of the output, bottom up for 6 and 7
+            let from_end = matches!(orientation, 6 | 7);
+
+            for (x, line) in lines.iter_mut().enumerate()
+            {
+                let column = pixels
+                    .chunks_exact(stride)
+                    .map(|row| &row[x * C..x * C + C]);
+
+                if from_end
+                {
+                    for (out, pixel) in line.chunks_exact_mut(C).zip(column.rev())
+                    {
+                        out.copy_from_slice(pixel);
+                    }
+                }
+                else
+                {
+                    for (out, pixel) in line.chunks_exact_mut(C).zip(column)
+                    {
+                        out.copy_from_slice(pixel);
+                    }
+                }
+            }
+        }
+    }
+}
 
-        start += stride;
-        end += stride;
+/// The output of an image whose height isn't known until its scan ends, see
+/// `Decoder::read_dnl_in_scan`
+///
+/// Workers send the bands of rows they post processed, numbered in the order
+/// they're decoded, which are appended to the output in that order.
+pub(crate) struct GrowingOutput<T>
+{
+    pixels:   Vec<T>,
+    /// Number of the next band to append
+    next:     usize,
+    /// Bands received before the ones above them
+    waiting:  BTreeMap<usize, Vec<T>>,
+    sender:   Sender<(usize, Vec<T>)>,
+    receiver: Receiver<(usize, Vec<T>)>,
+}
+
+impl<T: Copy> GrowingOutput<T>
+{
+    pub(crate) fn new() -> GrowingOutput<T>
+    {
+        let (sender, receiver) = channel();
+
+        GrowingOutput {
+            pixels: vec![],
+            next: 0,
+            waiting: BTreeMap::new(),
+            sender,
+            receiver,
+        }
+    }
+
+    /// Where workers send bands to
+    pub(crate) fn sender(&self) -> Sender<(usize, Vec<T>)>
+    {
+        self.sender.clone()
+    }
+
+    /// Make room for `length` samples in all, once the height is known
+    pub(crate) fn reserve(&mut self, length: usize)
+    {
+        self.pixels
+            .reserve(length.saturating_sub(self.pixels.len()));
+    }
+
+    /// Append the bands received so far that follow the output
+    pub(crate) fn append(&mut self)
+    {
+        self.waiting.extend(self.receiver.try_iter());
+
+        while let Some(band) = self.waiting.remove(&self.next)
+        {
+            self.pixels.extend_from_slice(&band);
+            self.next += 1;
+        }
+    }
+
+    /// The output, once every band has been sent
+    pub(crate) fn finish(mut self) -> Vec<T>
+    {
+        self.append();
+        self.pixels
     }
 }
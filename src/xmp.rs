@@ -0,0 +1,308 @@
+//! XMP metadata from APP1 segments
+//!
+//! The standard XMP packet is stored in a segment starting with
+//! `http://ns.adobe.com/xap/1.0/\0`. Packets too large for a single segment
+//! are split into the standard packet and an extended one (XMP specification
+//! part 3, 1.1.3.1), written in chunks in segments starting with
+//! `http://ns.adobe.com/xmp/extension/\0`. Each chunk has the GUID of the
+//! extended packet, its length and the offset of the chunk in it.
+//!
+//! The GUID is the MD5 digest of the extended packet as 32 hexadecimal digits,
+//! the standard packet names it in its `xmpNote:HasExtendedXMP` property.
+
+use std::fmt::Write;
+
+/// Signature of segments holding the standard XMP packet
+pub(crate) const XMP_SIGNATURE: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+
+/// Signature of segments holding chunks of the extended XMP packet
+pub(crate) const EXTENDED_XMP_SIGNATURE: &[u8] = b"http://ns.adobe.com/xmp/extension/\0";
+
+/// A chunk of the extended XMP packet
+pub(crate) struct ExtendedXmpChunk
+{
+    /// GUID of the packet the chunk belongs to
+    guid:   [u8; 32],
+    /// Length of the whole packet
+    length: usize,
+    /// Where the chunk goes in the packet
+    offset: usize,
+    data:   Vec<u8>,
+}
+
+impl ExtendedXmpChunk
+{
+    /// Parse a chunk from a segment, after its signature
+    pub(crate) fn parse(segment: &[u8]) -> Option<ExtendedXmpChunk>
+    {
+        let guid = segment.get(..32)?.try_into().ok()?;
+        let length = u32::from_be_bytes(segment.get(32..36)?.try_into().ok()?);
+        let offset = u32::from_be_bytes(segment.get(36..40)?.try_into().ok()?);
+
+        Some(ExtendedXmpChunk {
+            guid,
+            length: length as usize,
+            offset: offset as usize,
+            data: segment[40..].to_vec(),
+        })
+    }
+}
+
+/// Put the extended packet with `guid` back together from its chunks
+///
+/// Returns `None` if the chunks disagree on the length of the packet, don't
+/// cover all of it or if the packet doesn't match its GUID.
+pub(crate) fn assemble_extended_xmp(chunks: &[ExtendedXmpChunk], guid: &str) -> Option<Vec<u8>>
+{
+    let mut chunks: Vec<&ExtendedXmpChunk> = chunks
+        .iter()
+        .filter(|chunk| chunk.guid.eq_ignore_ascii_case(guid.as_bytes()))
+        .collect();
+
+    let length = chunks.first()?.length;
+
+    if chunks.iter().any(|chunk| chunk.length != length)
+    {
+        warn!("Extended XMP chunks disagree on the length of the packet");
+        return None;
+    }
+    // the chunks have to hold the whole packet, don't trust the length before checking
+    if chunks.iter().map(|chunk| chunk.data.len()).sum::<usize>() < length
+    {
+        warn!("Extended XMP packet is missing chunks");
+        return None;
+    }
+    chunks.sort_by_key(|chunk| chunk.offset);
+
+    let mut packet = vec![0; length];
+    let mut end = 0;
+
+    for chunk in chunks
+    {
+        if chunk.offset > end
+        {
+            warn!("Extended XMP packet is missing data at offset {end}");
+            return None;
+        }
+        let chunk_end = chunk.offset.saturating_add(chunk.data.len());
+
+        let Some(destination) = packet.get_mut(chunk.offset..chunk_end)
+        else
+        {
+            warn!(
+                "Extended XMP chunk at offset {} is out of bounds",
+                chunk.offset
+            );
+            return None;
+        };
+        destination.copy_from_slice(&chunk.data);
+        end = end.max(chunk_end);
+    }
+
+    if end != length
+    {
+        warn!("Extended XMP packet is missing data at offset {end}");
+        return None;
+    }
+
+    let mut digest = String::with_capacity(32);
+
+    for byte in md5(&packet)
+    {
+        let _ = write!(digest, "{byte:02X}");
+    }
+
+    if !digest.eq_ignore_ascii_case(guid)
+    {
+        warn!("Extended XMP packet doesn't match its GUID {guid}, ignoring it");
+        return None;
+    }
+
+    Some(packet)
+}
+
+/// The value of the simple property `name` (e.g. `xmpNote:HasExtendedXMP`),
+/// written either as an attribute or as an element
+///
+/// This isn't an XML parser, it finds the first occurrence of the property
+/// and returns its value as written, without resolving entities.
+pub(crate) fn xmp_property<'a>(xmp: &'a str, name: &str) -> Option<&'a str>
+{
+    // the property has to start right after whitespace or '<', otherwise
+    // e.g. `Offset` would be found in `MicroVideoOffset`
+    let starts_property = |position: usize| {
+        xmp[..position]
+            .chars()
+            .next_back()
+            .is_some_and(|c| c.is_whitespace() || c == '<')
+    };
+
+    let mut start = 0;
+
+    while let Some(found) = xmp[start..].find(name)
+    {
+        let position = start + found;
+        let rest = &xmp[position + name.len()..];
+
+        start = position + name.len();
+
+        if !starts_property(position)
+        {
+            continue;
+        }
+        // attribute, name="value" or name='value'
+        let attribute = rest.trim_start().strip_prefix('=').map(str::trim_start);
+
+        if let Some(value) = attribute
+        {
+            let quote = value.chars().next()?;
+
+            if quote == '"' || quote == '\''
+            {
+                let value = &value[1..];
+
+                return value.find(quote).map(|end| &value[..end]);
+            }
+        }
+        // element, <name>value</name>
+        if let Some(value) = rest.strip_prefix('>')
+        {
+            return value.find("</").map(|end| value[..end].trim());
+        }
+    }
+
+    None
+}
+
+/// Shift amounts of the MD5 rounds
+const MD5_SHIFTS: [u32; 16] = [7, 12, 17, 22, 5, 9, 14, 20, 4, 11, 16, 23, 6, 10, 15, 21];
+
+/// Constants of the MD5 rounds, the integer parts of `abs(sin(i + 1)) * 2^32`
+const MD5_CONSTANTS: [u32; 64] = [
+    0xD76A_A478,
+    0xE8C7_B756,
+    0x2420_70DB,
+    0xC1BD_CEEE,
+    0xF57C_0FAF,
+    0x4787_C62A,
+    0xA830_4613,
+    0xFD46_9501,
+    0x6980_98D8,
+    0x8B44_F7AF,
+    0xFFFF_5BB1,
+    0x895C_D7BE,
+    0x6B90_1122,
+    0xFD98_7193,
+    0xA679_438E,
+    0x49B4_0821,
+    0xF61E_2562,
+    0xC040_B340,
+    0x265E_5A51,
+    0xE9B6_C7AA,
+    0xD62F_105D,
+    0x0244_1453,
+    0xD8A1_E681,
+    0xE7D3_FBC8,
+    0x21E1_CDE6,
+    0xC337_07D6,
+    0xF4D5_0D87,
+    0x455A_14ED,
+    0xA9E3_E905,
+    0xFCEF_A3F8,
+    0x676F_02D9,
+    0x8D2A_4C8A,
+    0xFFFA_3942,
+    0x8771_F681,
+    0x6D9D_6122,
+    0xFDE5_380C,
+    0xA4BE_EA44,
+    0x4BDE_CFA9,
+    0xF6BB_4B60,
+    0xBEBF_BC70,
+    0x289B_7EC6,
+    0xEAA1_27FA,
+    0xD4EF_3085,
+    0x0488_1D05,
+    0xD9D4_D039,
+    0xE6DB_99E5,
+    0x1FA2_7CF8,
+    0xC4AC_5665,
+    0xF429_2244,
+    0x432A_FF97,
+    0xAB94_23A7,
+    0xFC93_A039,
+    0x655B_59C3,
+    0x8F0C_CC92,
+    0xFFEF_F47D,
+    0x8584_5DD1,
+    0x6FA8_7E4F,
+    0xFE2C_E6E0,
+    0xA301_4314,
+    0x4E08_11A1,
+    0xF753_7E82,
+    0xBD3A_F235,
+    0x2AD7_D2BB,
+    0xEB86_D391,
+];
+
+/// MD5 digest of `data` (RFC 1321), GUIDs of extended XMP packets are made of it
+#[allow(clippy::many_single_char_names)]
+fn md5(data: &[u8]) -> [u8; 16]
+{
+    let mut message = data.to_vec();
+
+    message.push(0x80);
+
+    while message.len() % 64 != 56
+    {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64).wrapping_mul(8)).to_le_bytes());
+
+    let mut state: [u32; 4] = [0x6745_2301, 0xEFCD_AB89, 0x98BA_DCFE, 0x1032_5476];
+
+    for block in message.chunks_exact(64)
+    {
+        let words: Vec<u32> = block
+            .chunks_exact(4)
+            .map(|x| u32::from_le_bytes(x.try_into().unwrap()))
+            .collect();
+
+        let [mut a, mut b, mut c, mut d] = state;
+
+        for i in 0..64
+        {
+            let (f, g) = match i / 16
+            {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let rotated = a
+                .wrapping_add(f)
+                .wrapping_add(MD5_CONSTANTS[i])
+                .wrapping_add(words[g])
+                .rotate_left(MD5_SHIFTS[(i / 16) * 4 + i % 4]);
+
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(rotated);
+        }
+
+        for (value, new) in state.iter_mut().zip([a, b, c, d])
+        {
+            *value = value.wrapping_add(new);
+        }
+    }
+
+    let mut digest = [0; 16];
+
+    for (bytes, value) in digest.chunks_exact_mut(4).zip(state)
+    {
+        bytes.copy_from_slice(&value.to_le_bytes());
+    }
+
+    digest
+}
@@ -0,0 +1,91 @@
+//! Tests for arithmetic coded images
+//!
+//! The arithmetic images were created by transcoding Huffman coded images
+//! with `jpegtran -arithmetic`, which keeps the coefficients intact, so they
+//! should decode to exactly the same pixels as their Huffman counterparts.
+mod common;
+
+use common::{path, test_image};
+use zune_jpeg::Decoder;
+
+fn decode(file: &str) -> Vec<u8>
+{
+    Decoder::new()
+        .decode_file(file)
+        .expect("Test failed decoding")
+}
+
+#[test]
+fn arithmetic_sequential()
+{
+    let huffman = decode(&test_image("test-baseline.jpg"));
+    let arithmetic = decode(&path("arithmetic_sequential.jpg"));
+
+    assert!(huffman == arithmetic);
+}
+
+#[test]
+fn arithmetic_sequential_sampled_with_restarts()
+{
+    let huffman = decode(&path("huffman_sequential_hv.jpg"));
+    let arithmetic = decode(&path("arithmetic_sequential_hv_restart.jpg"));
+
+    assert!(huffman == arithmetic);
+}
+
+#[test]
+fn arithmetic_sequential_non_interleaved()
+{
+    let huffman = decode(&path("huffman_sequential_hv.jpg"));
+    let arithmetic = decode(&path("arithmetic_sequential_non_interleaved.jpg"));
+
+    assert!(huffman == arithmetic);
+}
+
+#[test]
+fn arithmetic_progressive()
+{
+    let huffman = decode(&test_image("test-baseline.jpg"));
+    let arithmetic = decode(&test_image("test-arithmetic-coding.jpg"));
+
+    assert!(huffman == arithmetic);
+}
+
+#[test]
+fn arithmetic_progressive_sampled_with_restarts()
+{
+    let huffman = decode(&path("huffman_sequential_hv.jpg"));
+    let arithmetic = decode(&path("arithmetic_progressive_hv_restart.jpg"));
+
+    assert!(huffman == arithmetic);
+}
+
+#[test]
+fn conditioning_of_previous_image_not_kept()
+{
+    let read = |file: &str| std::fs::read(path(file)).unwrap();
+    let dac = |data: &[u8]| {
+        data.windows(4)
+            .position(|x| x == b"\xFF\xCC\x00\x0A")
+            .unwrap()
+    };
+
+    // AC conditioning (Kx) of 1 instead of the default 5
+    let mut first = read("arithmetic_sequential.jpg");
+    let position = dac(&first);
+
+    assert_eq!(first[position + 7], 5);
+    first[position + 7] = 1;
+
+    // the image has default conditioning, drop its DAC segment
+    let mut second = read("arithmetic_sequential_hv_restart.jpg");
+    let position = dac(&second);
+
+    second.drain(position..position + 12);
+
+    let mut decoder = Decoder::new();
+    let _ = decoder.decode_buffer(&first);
+    let arithmetic = decoder.decode_buffer(&second).unwrap();
+
+    assert!(decode(&path("huffman_sequential_hv.jpg")) == arithmetic);
+}
@@ -0,0 +1,258 @@
+//! Tests for depth maps and other auxiliary images
+//!
+//! `gdepth.jpg` has GDepth and GImage XMP properties, split between the
+//! standard and extended packets, with a JPEG depth map of 16 by 12 pixels, a
+//! PNG confidence map and the original image of 61 by 45 pixels.
+//!
+//! `dynamic_depth.jpg` is a Dynamic Depth file of 2062 bytes followed by a
+//! depth map (184 bytes), its confidence (156 bytes, 8 by 6 pixels) and the
+//! original image (1370 bytes).
+mod common;
+
+use common::path;
+use zune_jpeg::auxiliary::{AuxiliaryRole, DepthFormat, DepthMetadata};
+use zune_jpeg::{ColorSpace, Decoder, ZuneJpegOptions};
+
+/// Check a decoded depth map has the gradient it was made from
+fn check_depth_map(pixels: &[u8])
+{
+    assert_eq!(pixels.len(), 16 * 12);
+
+    for (i, value) in pixels.iter().enumerate()
+    {
+        let expected = (i % 16) * 17;
+
+        assert!(
+            usize::from(*value).abs_diff(expected) <= 4,
+            "pixel {i}, {value} instead of {expected}"
+        );
+    }
+}
+
+#[test]
+fn gdepth_images()
+{
+    let data = std::fs::read(path("gdepth.jpg")).unwrap();
+    let images = Decoder::new().auxiliary_images(&data).unwrap();
+
+    let roles: Vec<_> = images.iter().map(|x| x.role.clone()).collect();
+
+    assert_eq!(
+        roles,
+        [
+            AuxiliaryRole::Depth,
+            AuxiliaryRole::Confidence,
+            AuxiliaryRole::Original
+        ]
+    );
+    assert!(images.iter().all(|x| x.offset.is_none()));
+
+    let [depth, confidence, original] = &images[..]
+    else
+    {
+        unreachable!()
+    };
+
+    assert_eq!(depth.mime, "image/jpeg");
+    assert_eq!(
+        depth.depth,
+        Some(DepthMetadata {
+            format:       DepthFormat::RangeInverse,
+            near:         0.5,
+            far:          4.25,
+            units:        Some("m".to_string()),
+            measure_type: Some("OpticalAxis".to_string()),
+        })
+    );
+
+    let options = ZuneJpegOptions::new().set_out_colorspace(ColorSpace::GRAYSCALE);
+    let mut decoder = Decoder::new_with_options(options);
+
+    check_depth_map(&decoder.decode_auxiliary_image(depth).unwrap());
+
+    // PNG images are listed, but can't be decoded
+    assert_eq!(confidence.mime, "image/png");
+    assert!(confidence.data.starts_with(b"\x89PNG"));
+    assert!(confidence.depth.is_none());
+    assert!(decoder.decode_auxiliary_image(confidence).is_err());
+
+    assert_eq!(original.mime, "image/jpeg");
+    decoder.decode_auxiliary_image(original).unwrap();
+    assert_eq!((decoder.width(), decoder.height()), (61, 45));
+}
+
+#[test]
+fn restart_interval_of_primary_image()
+{
+    let mut data = std::fs::read(path("dynamic_depth.jpg")).unwrap();
+    let mpo = std::fs::read(path("mpo_stereo.jpg")).unwrap();
+
+    // the original image becomes the progressive disparity image of `mpo_stereo.jpg`
+    let length = data
+        .windows(13)
+        .position(|x| x == b"Length=\"1370\"")
+        .unwrap();
+    data[length + 8..length + 12].copy_from_slice(b"0944");
+    data.truncate(2402);
+    data.extend_from_slice(&mpo[1046..1990]);
+
+    // a DRI marker with an interval of one MCU
+    data.splice(2..2, [0xFF, 0xDD, 0x00, 0x04, 0x00, 0x01]);
+
+    let mut decoder = Decoder::new();
+    let images = decoder.auxiliary_images(&data).unwrap();
+
+    assert_eq!(images[2].role, AuxiliaryRole::Original);
+    assert_eq!(
+        decoder.decode_auxiliary_image(&images[2]).unwrap(),
+        Decoder::new().decode_buffer(&mpo[1046..1990]).unwrap()
+    );
+}
+
+#[test]
+fn dynamic_depth_items()
+{
+    let data = std::fs::read(path("dynamic_depth.jpg")).unwrap();
+    let images = Decoder::new().auxiliary_images(&data).unwrap();
+
+    let found: Vec<_> = images
+        .iter()
+        .map(|x| (x.role.clone(), x.offset, x.data.len()))
+        .collect();
+
+    assert_eq!(
+        found,
+        [
+            (AuxiliaryRole::Depth, Some(2062), 184),
+            (AuxiliaryRole::Confidence, Some(2246), 156),
+            (AuxiliaryRole::Original, Some(2402), 1370)
+        ]
+    );
+    assert_eq!(images[0].data, &data[2062..2246]);
+    assert_eq!(
+        images[0].depth,
+        Some(DepthMetadata {
+            format:       DepthFormat::RangeLinear,
+            near:         0.25,
+            far:          8.0,
+            units:        Some("Meters".to_string()),
+            measure_type: Some("OpticalRay".to_string()),
+        })
+    );
+    assert!(images[1].depth.is_none());
+
+    let options = ZuneJpegOptions::new().set_out_colorspace(ColorSpace::RGBA);
+    let mut decoder = Decoder::new_with_options(options);
+
+    check_depth_map(&decoder.decode_auxiliary_image(&images[0]).unwrap());
+
+    let confidence = decoder.decode_auxiliary_image(&images[1]).unwrap();
+
+    assert_eq!(confidence.len(), 8 * 6);
+
+    // grayscale images don't change the options of the decoder
+    let original = decoder.decode_auxiliary_image(&images[2]).unwrap();
+
+    assert_eq!(original.len(), 61 * 45 * 4);
+    assert_eq!(decoder.get_output_colorspace(), ColorSpace::RGBA);
+}
+
+#[test]
+fn depth_distances()
+{
+    let mut depth = DepthMetadata {
+        format:       DepthFormat::RangeLinear,
+        near:         0.25,
+        far:          8.0,
+        units:        None,
+        measure_type: None,
+    };
+
+    assert_eq!(depth.distance(0.0), Some(0.25));
+    assert_eq!(depth.distance(0.5), Some(4.125));
+    assert_eq!(depth.distance(1.0), Some(8.0));
+
+    depth.format = DepthFormat::RangeInverse;
+
+    assert_eq!(depth.distance(0.0), Some(0.25));
+    assert_eq!(depth.distance(1.0), Some(8.0));
+    // halfway between the inverse distances
+    assert!((depth.distance(0.5).unwrap() - 1.0 / (0.5 * (1.0 / 8.0 + 4.0))).abs() < 1e-6);
+
+    depth.format = DepthFormat::Other("Unknown".to_string());
+
+    assert_eq!(depth.distance(0.5), None);
+}
+
+#[test]
+fn gain_map_listed_once()
+{
+    let mut data = std::fs::read(path("ultrahdr_gain_map.jpg")).unwrap();
+    let gain_map = Decoder::new().gain_map(&data).unwrap().unwrap();
+
+    // in the container directory and the MP index
+    let images = Decoder::new().auxiliary_images(&data).unwrap();
+
+    assert_eq!(images.len(), 1);
+    assert_eq!(images[0].role, AuxiliaryRole::GainMap);
+    assert_eq!(images[0].offset, Some(1759));
+    assert_eq!(images[0].data, gain_map.data);
+
+    // only in the MP index
+    let directory = data
+        .windows(20)
+        .position(|x| x == b"<Container:Directory")
+        .unwrap();
+    data[directory + 1] = b'X';
+
+    let images = Decoder::new().auxiliary_images(&data).unwrap();
+
+    assert_eq!(images.len(), 1);
+    assert_eq!(images[0].role, AuxiliaryRole::GainMap);
+    assert_eq!(images[0].mime, "image/jpeg");
+    assert_eq!(images[0].data, gain_map.data);
+}
+
+#[test]
+fn mp_image_without_role()
+{
+    let mut data = std::fs::read(path("ultrahdr_gain_map.jpg")).unwrap();
+
+    // nothing says the second image is a gain map any more
+    let directory = data
+        .windows(20)
+        .position(|x| x == b"<Container:Directory")
+        .unwrap();
+    data[directory + 1] = b'X';
+
+    let version = 1759
+        + data[1759..]
+            .windows(13)
+            .position(|x| x == b"hdrgm:Version")
+            .unwrap();
+    data[version + 6] = b'X';
+
+    let signature = data.windows(7).rposition(|x| x == b"urn:iso").unwrap();
+    data[signature] = b'x';
+
+    let images = Decoder::new().auxiliary_images(&data).unwrap();
+
+    assert_eq!(images.len(), 1);
+    assert_eq!(images[0].role, AuxiliaryRole::Unknown);
+    assert_eq!(images[0].offset, Some(1759));
+}
+
+#[test]
+fn images_without_auxiliary_images()
+{
+    // views of a stereo pair aren't auxiliary images
+    for file in ["google_pixel.jpg", "mpo_stereo.jpg", "motion_photo.jpg"]
+    {
+        let data = std::fs::read(path(file)).unwrap();
+
+        assert!(
+            Decoder::new().auxiliary_images(&data).unwrap().is_empty(),
+            "{file}"
+        );
+    }
+}
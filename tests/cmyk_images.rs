@@ -0,0 +1,96 @@
+//! Tests for four component (CMYK and YCCK) images
+//!
+//! The images were encoded with libjpeg-turbo from Adobe style (inverted) CMYK samples,
+//! with component ids changed to 1..4 like Photoshop writes them.
+//! The CMYK references (PAM files) are libjpeg-turbo's raw CMYK output, and the
+//! RGB references are `djpeg`'s output.
+mod common;
+
+use common::{path, read_pnm};
+use zune_jpeg::{ColorSpace, Decoder, ZuneJpegOptions};
+
+fn decode(file: &str, colorspace: ColorSpace) -> Vec<u8>
+{
+    Decoder::new_with_options(ZuneJpegOptions::new().set_out_colorspace(colorspace))
+        .decode_file(path(file))
+        .expect("Test failed decoding")
+}
+
+#[test]
+fn cmyk_adobe()
+{
+    let pixels = decode("cmyk_adobe.jpg", ColorSpace::CMYK);
+
+    assert!(pixels == read_pnm("cmyk_adobe.pam"));
+}
+
+#[test]
+fn cmyk_adobe_to_rgb()
+{
+    let pixels = decode("cmyk_adobe.jpg", ColorSpace::RGB);
+
+    assert!(pixels == read_pnm("cmyk_adobe.ppm"));
+}
+
+#[test]
+fn cmyk_without_adobe_marker()
+{
+    // four component images without an APP14 marker are assumed to be CMYK
+    let pixels = decode("cmyk_no_adobe.jpg", ColorSpace::CMYK);
+
+    assert!(pixels == read_pnm("cmyk_no_adobe.pam"));
+}
+
+#[test]
+fn ycck_sampled()
+{
+    // Y and K are sampled 2x2, Cb and Cr 1x1
+    let pixels = decode("ycck_adobe_hv.jpg", ColorSpace::CMYK);
+
+    assert!(pixels == read_pnm("ycck_adobe_hv.pam"));
+}
+
+#[test]
+fn ycck_sampled_to_rgba()
+{
+    let pixels = decode("ycck_adobe_hv.jpg", ColorSpace::RGBA);
+    let reference = read_pnm("ycck_adobe_hv.ppm");
+
+    for (pixel, rgb) in pixels.chunks_exact(4).zip(reference.chunks_exact(3))
+    {
+        assert_eq!(&pixel[..3], rgb);
+        assert_eq!(pixel[3], 255);
+    }
+    assert_eq!(pixels.len() / 4, reference.len() / 3);
+}
+
+#[test]
+fn ycck_progressive_restarts()
+{
+    let pixels = decode("ycck_progressive.jpg", ColorSpace::CMYK);
+
+    assert!(pixels == read_pnm("ycck_progressive.pam"));
+}
+
+#[test]
+fn cmyk_to_grayscale_unsupported()
+{
+    let result =
+        Decoder::new_with_options(ZuneJpegOptions::new().set_out_colorspace(ColorSpace::GRAYSCALE))
+            .decode_file(path("cmyk_adobe.jpg"));
+
+    assert!(result.is_err());
+}
+
+#[test]
+fn adobe_marker_of_previous_image_not_kept()
+{
+    let mut decoder =
+        Decoder::new_with_options(ZuneJpegOptions::new().set_out_colorspace(ColorSpace::CMYK));
+
+    decoder.decode_file(path("ycck_adobe_hv.jpg")).unwrap();
+
+    let pixels = decoder.decode_file(path("cmyk_no_adobe.jpg")).unwrap();
+
+    assert!(pixels == read_pnm("cmyk_no_adobe.pam"));
+}
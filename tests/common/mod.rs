@@ -0,0 +1,79 @@
+//! Helpers shared by the tests
+//!
+//! References are binary PNM or PAM files, as written by djpeg and other
+//! decoders.
+#![allow(dead_code)]
+
+/// Path of a file in `tests/inputs`
+pub fn path(file: &str) -> String
+{
+    env!("CARGO_MANIFEST_DIR").to_string() + "/tests/inputs/" + file
+}
+
+/// Path of a file in `test-images`
+pub fn test_image(file: &str) -> String
+{
+    env!("CARGO_MANIFEST_DIR").to_string() + "/test-images/" + file
+}
+
+/// The maximum value and the samples of a binary PNM or PAM file
+fn read_samples(file: &str) -> (u32, Vec<u8>)
+{
+    let data = std::fs::read(path(file)).unwrap();
+
+    let (header, start) = match data.windows(7).position(|x| x == b"ENDHDR\n")
+    {
+        Some(position) => (&data[..position], position + 7),
+        // PNM headers are three lines, magic, dimensions and maximum value
+        None =>
+        {
+            let start = data
+                .iter()
+                .enumerate()
+                .filter(|(_, x)| **x == b'\n')
+                .nth(2)
+                .unwrap()
+                .0
+                + 1;
+
+            (&data[..start], start)
+        }
+    };
+    let header = std::str::from_utf8(header).unwrap();
+    let maximum = header
+        .lines()
+        .find_map(|x| x.strip_prefix("MAXVAL "))
+        .or_else(|| header.lines().nth(2))
+        .unwrap()
+        .trim()
+        .parse()
+        .unwrap();
+
+    (maximum, data[start..].to_vec())
+}
+
+/// Read the samples of an 8 bit binary PNM or PAM file
+pub fn read_pnm(file: &str) -> Vec<u8>
+{
+    let (maximum, samples) = read_samples(file);
+
+    assert!(maximum < 256, "{file} has more than 8 bits per sample");
+
+    samples
+}
+
+/// Read the samples of a binary PNM or PAM file, 16 bit samples are big
+/// endian
+pub fn read_pnm_u16(file: &str) -> Vec<u16>
+{
+    let (maximum, samples) = read_samples(file);
+
+    if maximum < 256
+    {
+        return samples.into_iter().map(u16::from).collect();
+    }
+    samples
+        .chunks_exact(2)
+        .map(|x| u16::from_be_bytes([x[0], x[1]]))
+        .collect()
+}
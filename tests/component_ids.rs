@@ -0,0 +1,34 @@
+//! Tests for images whose components don't use the usual 1, 2 and 3 identifiers
+//!
+//! Each image is an existing test image with its identifiers rewritten in the
+//! frame and scan headers, so it should decode to the same pixels as the original.
+mod common;
+
+use common::path;
+use zune_jpeg::Decoder;
+
+fn assert_same_pixels(file: &str, original: &str)
+{
+    let pixels = Decoder::new().decode_file(path(file)).unwrap();
+    let reference = Decoder::new().decode_file(path(original)).unwrap();
+
+    assert!(pixels == reference);
+}
+
+#[test]
+fn zero_based_ids()
+{
+    assert_same_pixels("component_ids_zero_based.jpg", "huffman_sequential_hv.jpg");
+}
+
+#[test]
+fn ascii_ids_progressive()
+{
+    assert_same_pixels("component_ids_ascii.jpg", "progressive_8_bit_coarse_qt.jpg");
+}
+
+#[test]
+fn duplicate_ids()
+{
+    assert_same_pixels("component_ids_duplicate.jpg", "huffman_sequential_hv.jpg");
+}
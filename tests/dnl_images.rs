@@ -0,0 +1,235 @@
+//! Tests for images that give their height in a DNL segment after the first scan
+//!
+//! The images were made from other test images by setting the frame header's height to
+//! zero and inserting a DNL segment after the entropy coded data of the first scan.
+mod common;
+
+use common::path;
+use zune_jpeg::{ColorSpace, Decoder, ZuneJpegOptions};
+
+/// Position of the frame header, the first segment with a SOF marker
+fn frame_header(data: &[u8]) -> usize
+{
+    let mut position = 2;
+
+    while !matches!(data[position + 1], 0xC0..=0xC3 | 0xC9..=0xCB)
+    {
+        position += 2 + usize::from(u16::from_be_bytes([data[position + 2], data[position + 3]]));
+    }
+    position
+}
+
+/// Write the height from the DNL segment into the frame header
+///
+/// The DNL segment is kept, so the image only differs in where the decoder
+/// learns the height from.
+fn restore_height(mut data: Vec<u8>) -> Vec<u8>
+{
+    let dnl = data
+        .windows(4)
+        .position(|x| x == [0xFF, 0xDC, 0x00, 0x04])
+        .unwrap();
+    let height = [data[dnl + 4], data[dnl + 5]];
+    let sof = frame_header(&data);
+
+    data[sof + 5..sof + 7].copy_from_slice(&height);
+
+    data
+}
+
+/// Move the height of an image with a single scan from its frame header to a
+/// DNL segment after the scan
+fn with_dnl(mut data: Vec<u8>) -> Vec<u8>
+{
+    let sof = frame_header(&data);
+    let height = [data[sof + 5], data[sof + 6]];
+
+    data[sof + 5..sof + 7].fill(0);
+
+    // the scan ends right before the EOI marker
+    let end = data.len() - 2;
+    data.splice(end..end, [0xFF, 0xDC, 0x00, 0x04, height[0], height[1]]);
+
+    data
+}
+
+fn compare(file: &str)
+{
+    let data = std::fs::read(path(file)).unwrap();
+    let options = ZuneJpegOptions::new().set_out_colorspace(ColorSpace::YCbCr);
+
+    let pixels = Decoder::new_with_options(options)
+        .decode_buffer(&data)
+        .expect("Test failed decoding");
+    let expected = Decoder::new_with_options(options)
+        .decode_buffer(&restore_height(data))
+        .unwrap();
+
+    assert!(pixels == expected);
+}
+
+fn compare_u16(file: &str)
+{
+    let data = std::fs::read(path(file)).unwrap();
+
+    let pixels = Decoder::new()
+        .decode_buffer_u16(&data)
+        .expect("Test failed decoding");
+    let expected = Decoder::new()
+        .decode_buffer_u16(&restore_height(data))
+        .unwrap();
+
+    assert!(pixels == expected);
+}
+
+#[test]
+fn dnl_sequential()
+{
+    compare("dnl_sequential.jpg");
+}
+
+#[test]
+fn dnl_sequential_restarts()
+{
+    compare("dnl_sequential_restart.jpg");
+}
+
+#[test]
+fn dnl_progressive()
+{
+    compare("dnl_progressive.jpg");
+}
+
+#[test]
+fn dnl_arithmetic_restarts()
+{
+    compare("dnl_arithmetic_restart.jpg");
+}
+
+#[test]
+fn dnl_12_bit_restarts()
+{
+    compare_u16("dnl_12_bit_restart.jpg");
+}
+
+#[test]
+fn dnl_lossless()
+{
+    compare_u16("dnl_lossless.jpg");
+}
+
+#[test]
+fn dnl_height_in_info()
+{
+    let data = std::fs::read(path("dnl_progressive.jpg")).unwrap();
+    let mut decoder = Decoder::new();
+
+    decoder.read_headers(&data).unwrap();
+
+    assert_eq!(decoder.info().unwrap().height, 77);
+}
+
+#[test]
+fn zero_height_without_dnl()
+{
+    let mut data = std::fs::read(path("dnl_sequential.jpg")).unwrap();
+    let dnl = data
+        .windows(4)
+        .position(|x| x == [0xFF, 0xDC, 0x00, 0x04])
+        .unwrap();
+
+    data.drain(dnl..dnl + 6);
+
+    assert!(Decoder::new().decode_buffer(&data).is_err());
+}
+
+#[test]
+fn dnl_height_mismatch()
+{
+    let mut data = std::fs::read(path("dnl_progressive.jpg")).unwrap();
+    let dnl = data
+        .windows(4)
+        .position(|x| x == [0xFF, 0xDC, 0x00, 0x04])
+        .unwrap();
+    // a second DNL segment that disagrees with the first
+    data.splice(dnl + 6..dnl + 6, [0xFF, 0xDC, 0x00, 0x04, 0x00, 0x20]);
+
+    assert!(Decoder::new().decode_buffer(&data).is_err());
+}
+
+#[test]
+fn dnl_baseline_images()
+{
+    // baseline images are decoded as the scan comes, whatever their sampling factors
+    for file in [
+        "noise_sequential.jpg",
+        "rgb_ids.jpg",
+        "sampling_3x2.jpg",
+        "sampling_411.jpg",
+        "huffman_sequential_hv.jpg",
+    ]
+    {
+        let data = std::fs::read(path(file)).unwrap();
+
+        let expected = Decoder::new().decode_buffer(&data).unwrap();
+        let pixels = Decoder::new().decode_buffer(&with_dnl(data)).expect(file);
+
+        assert!(pixels == expected, "{file}");
+    }
+}
+
+#[test]
+fn dnl_height_after_decoding()
+{
+    // the baseline decoder only reads the DNL segment once the scan gets there
+    let data = std::fs::read(path("dnl_sequential.jpg")).unwrap();
+    let mut decoder = Decoder::new();
+
+    decoder.read_headers(&data).unwrap();
+    assert_eq!(decoder.info().unwrap().height, 0);
+
+    decoder.decode_buffer(&data).unwrap();
+    assert_eq!(decoder.info().unwrap().height, 45);
+}
+
+#[test]
+fn dnl_orientation()
+{
+    // the EXIF segment of `exif_big_endian_gps.jpg` put after the SOI, turned 90 degrees
+    let exif = std::fs::read(path("exif_big_endian_gps.jpg")).unwrap();
+    let length = usize::from(u16::from_be_bytes([exif[4], exif[5]]));
+    let mut data = std::fs::read(path("huffman_sequential_hv.jpg")).unwrap();
+
+    data.splice(2..2, exif[2..4 + length].iter().copied());
+
+    let entry = data
+        .windows(8)
+        .position(|x| x == [0x01, 0x12, 0, 3, 0, 0, 0, 1])
+        .unwrap();
+    data[entry + 9] = 6;
+
+    let options = ZuneJpegOptions::new().set_apply_orientation(true);
+
+    let mut decoder = Decoder::new_with_options(options);
+    let expected = decoder.decode_buffer(&data).unwrap();
+    let info = decoder.info().unwrap();
+
+    let mut decoder = Decoder::new_with_options(options);
+    let pixels = decoder.decode_buffer(&with_dnl(data)).unwrap();
+
+    assert!(pixels == expected);
+    assert!(decoder.info().unwrap() == info);
+}
+
+#[test]
+fn dnl_fewer_lines_than_the_scan()
+{
+    let mut data = std::fs::read(path("dnl_sequential.jpg")).unwrap();
+    let dnl = data
+        .windows(4)
+        .position(|x| x == [0xFF, 0xDC, 0x00, 0x04])
+        .unwrap();
+    data[dnl + 4..dnl + 6].copy_from_slice(&16_u16.to_be_bytes());
+
+    assert!(Decoder::new().decode_buffer(&data).is_err());
+}
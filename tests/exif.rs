@@ -0,0 +1,178 @@
+//! Tests for reading EXIF metadata
+//!
+//! `exif_big_endian_gps.jpg` has a hand written, big endian, EXIF segment with
+//! GPS coordinates, the Pixel image a little endian one from a phone.
+mod common;
+
+use common::path;
+use zune_jpeg::exif::{ExifValue, Ifd, Rational, SRational};
+use zune_jpeg::Decoder;
+
+fn read_headers(data: &[u8]) -> Decoder
+{
+    let mut decoder = Decoder::new();
+    decoder.read_headers(data).unwrap();
+
+    decoder
+}
+
+/// `exif_big_endian_gps.jpg` with the pointer to the Exif IFD set to `offset`
+fn with_exif_pointer(offset: u32) -> Vec<u8>
+{
+    let mut data = std::fs::read(path("exif_big_endian_gps.jpg")).unwrap();
+
+    // tag 0x8769 of type long with a single value
+    let entry = data
+        .windows(8)
+        .position(|x| x == [0x87, 0x69, 0, 4, 0, 0, 0, 1])
+        .unwrap();
+    data[entry + 8..entry + 12].copy_from_slice(&offset.to_be_bytes());
+
+    data
+}
+
+#[test]
+fn little_endian_camera_exif()
+{
+    let data = std::fs::read(path("google_pixel.jpg")).unwrap();
+    let decoder = read_headers(&data);
+    let exif = decoder.exif().unwrap();
+
+    assert_eq!(exif.make(), Some("Google"));
+    assert_eq!(exif.model(), Some("Pixel 5"));
+    assert_eq!(exif.orientation(), Some(1));
+    assert_eq!(exif.date_time_original(), Some("2022:02:02 16:01:58"));
+    assert_eq!(exif.offset_time_original(), Some("+01:00"));
+    assert_eq!(
+        exif.exposure_time(),
+        Some(Rational {
+            numerator:   29364,
+            denominator: 1_000_000,
+        })
+    );
+    assert_eq!(exif.f_number().and_then(Rational::to_f64), Some(1.73));
+    assert_eq!(exif.iso(), Some(389));
+    assert_eq!(exif.focal_length().and_then(Rational::to_f64), Some(4.38));
+    assert_eq!(exif.x_resolution().and_then(Rational::to_f64), Some(72.0));
+    assert_eq!(exif.resolution_unit(), Some(2));
+    // the GPS IFD only has the direction the camera was facing
+    assert_eq!(exif.gps_latitude(), None);
+    assert_eq!(
+        exif.get(Ifd::Gps, 0x0011),
+        Some(&ExifValue::Rational(vec![Rational {
+            numerator:   161,
+            denominator: 1,
+        }]))
+    );
+    assert_eq!(
+        exif.get(Ifd::Interoperability, 0x0001),
+        Some(&ExifValue::Ascii("R98".to_string()))
+    );
+    // thumbnail offset in IFD1
+    assert_eq!(
+        exif.get(Ifd::Thumbnail, 0x0201),
+        Some(&ExifValue::Long(vec![1132]))
+    );
+    assert_eq!(exif.entries().len(), 13 + 42 + 2 + 2 + 9);
+}
+
+#[test]
+fn big_endian_exif_with_gps()
+{
+    let data = std::fs::read(path("exif_big_endian_gps.jpg")).unwrap();
+    let decoder = read_headers(&data);
+    let exif = decoder.exif().unwrap();
+
+    assert_eq!(exif.make(), Some("Zune"));
+    assert_eq!(exif.model(), Some("Test Camera"));
+    assert_eq!(exif.orientation(), Some(6));
+    assert_eq!(exif.date_time_original(), Some("2020:06:15 12:30:45"));
+    assert_eq!(exif.exposure_time().and_then(Rational::to_f64), Some(0.004));
+    assert_eq!(exif.iso(), Some(400));
+    assert_eq!(exif.y_resolution().and_then(Rational::to_f64), Some(300.0));
+
+    // 33°51'54.24" S 151°12'30.6" E, 12.5 meters below sea level
+    let latitude = -(33.0 + 51.0 / 60.0 + 54.24 / 3600.0);
+    let longitude = 151.0 + 12.0 / 60.0 + 30.6 / 3600.0;
+
+    assert!((exif.gps_latitude().unwrap() - latitude).abs() < 1e-9);
+    assert!((exif.gps_longitude().unwrap() - longitude).abs() < 1e-9);
+    assert_eq!(exif.gps_altitude(), Some(-12.5));
+
+    assert_eq!(
+        exif.get(Ifd::Exif, 0x9201),
+        Some(&ExifValue::SRational(vec![SRational {
+            numerator:   -8,
+            denominator: 1,
+        }]))
+    );
+    assert_eq!(
+        exif.get(Ifd::Exif, 0x9000),
+        Some(&ExifValue::Undefined(b"0232".to_vec()))
+    );
+    assert_eq!(
+        exif.get(Ifd::Thumbnail, 0x0103),
+        Some(&ExifValue::Short(vec![6]))
+    );
+}
+
+#[test]
+fn exif_is_kept_after_decoding()
+{
+    let data = std::fs::read(path("exif_big_endian_gps.jpg")).unwrap();
+    let mut decoder = Decoder::new();
+
+    decoder.decode_buffer(&data).unwrap();
+
+    assert_eq!(decoder.exif().and_then(|x| x.orientation()), Some(6));
+}
+
+#[test]
+fn directory_loop_is_ignored()
+{
+    // point the Exif IFD back at IFD0
+    let data = with_exif_pointer(8);
+    let decoder = read_headers(&data);
+    let exif = decoder.exif().unwrap();
+
+    assert_eq!(exif.make(), Some("Zune"));
+    assert_eq!(exif.exposure_time(), None);
+    assert!(exif.gps_latitude().is_some());
+}
+
+#[test]
+fn out_of_bounds_directory_is_ignored()
+{
+    let data = with_exif_pointer(0xFFFF_0000);
+    let mut decoder = Decoder::new();
+
+    decoder.decode_buffer(&data).unwrap();
+
+    let exif = decoder.exif().unwrap();
+
+    assert_eq!(exif.orientation(), Some(6));
+    assert_eq!(exif.iso(), None);
+}
+
+#[test]
+fn invalid_tiff_header()
+{
+    let mut data = std::fs::read(path("exif_big_endian_gps.jpg")).unwrap();
+    let header = data.windows(4).position(|x| x == b"MM\0*").unwrap();
+
+    data[header..header + 2].copy_from_slice(b"XX");
+
+    let mut decoder = Decoder::new();
+
+    decoder.decode_buffer(&data).unwrap();
+
+    assert!(decoder.exif().is_none());
+}
+
+#[test]
+fn image_without_exif()
+{
+    let data = std::fs::read(path("icc_display_p3.jpg")).unwrap();
+
+    assert!(read_headers(&data).exif().is_none());
+}
@@ -0,0 +1,240 @@
+//! Tests for applying the EXIF orientation to decoded pixels
+//!
+//! Images are made by changing the orientation of `exif_big_endian_gps.jpg`,
+//! or by copying its EXIF segment into other images, expected results turn
+//! decodes of the image without its orientation applied.
+mod common;
+
+use common::path;
+use zune_jpeg::{ColorSpace, Decoder, ZuneJpegOptions};
+
+/// Set the orientation in the EXIF segment of `exif_big_endian_gps.jpg` in `data`
+fn set_orientation(data: &mut [u8], orientation: u8)
+{
+    // tag 0x0112 of type short with a single value
+    let entry = data
+        .windows(8)
+        .position(|x| x == [0x01, 0x12, 0, 3, 0, 0, 0, 1])
+        .unwrap();
+    data[entry + 9] = orientation;
+}
+
+/// `exif_big_endian_gps.jpg` with its orientation set to `orientation`
+fn with_orientation(orientation: u8) -> Vec<u8>
+{
+    let mut data = std::fs::read(path("exif_big_endian_gps.jpg")).unwrap();
+
+    set_orientation(&mut data, orientation);
+
+    data
+}
+
+/// `file` with the EXIF segment of `exif_big_endian_gps.jpg` put after its SOI
+fn with_exif_of_fixture(file: &str) -> Vec<u8>
+{
+    let exif = std::fs::read(path("exif_big_endian_gps.jpg")).unwrap();
+    let length = usize::from(u16::from_be_bytes([exif[4], exif[5]]));
+    let data = std::fs::read(path(file)).unwrap();
+
+    let mut image = data[..2].to_vec();
+    image.extend_from_slice(&exif[2..4 + length]);
+    image.extend_from_slice(&data[2..]);
+
+    image
+}
+
+fn oriented_decoder() -> Decoder
+{
+    Decoder::new_with_options(ZuneJpegOptions::new().set_apply_orientation(true))
+}
+
+/// Turn an image `width` pixels wide 90 degrees clockwise
+fn rotate<T: Copy>(pixels: &[T], width: usize, components: usize) -> Vec<T>
+{
+    let height = pixels.len() / (width * components);
+    let mut output = Vec::with_capacity(pixels.len());
+
+    for x in 0..width
+    {
+        for y in (0..height).rev()
+        {
+            let start = (y * width + x) * components;
+
+            output.extend_from_slice(&pixels[start..start + components]);
+        }
+    }
+
+    output
+}
+
+/// Mirror an image `width` pixels wide horizontally
+fn mirror<T: Copy>(pixels: &[T], width: usize, components: usize) -> Vec<T>
+{
+    pixels
+        .chunks_exact(width * components)
+        .flat_map(|row| row.chunks_exact(components).rev().flatten().copied())
+        .collect()
+}
+
+/// The pixels of an upright image `width` pixels wide and `height` high,
+/// stored with `orientation`
+fn expected<T: Copy>(
+    pixels: &[T], width: usize, height: usize, components: usize, orientation: u8,
+) -> Vec<T>
+{
+    let turn = |pixels: &[T], times: usize| {
+        let mut pixels = pixels.to_vec();
+
+        for i in 0..times
+        {
+            let w = if i % 2 == 0 { width } else { height };
+
+            pixels = rotate(&pixels, w, components);
+        }
+        pixels
+    };
+
+    match orientation
+    {
+        2 => mirror(pixels, width, components),
+        3 => turn(pixels, 2),
+        4 => mirror(&turn(pixels, 2), width, components),
+        5 => mirror(&turn(pixels, 1), height, components),
+        6 => turn(pixels, 1),
+        7 => turn(&mirror(pixels, width, components), 1),
+        8 => turn(pixels, 3),
+        _ => pixels.to_vec(),
+    }
+}
+
+#[test]
+fn all_orientations()
+{
+    let upright = Decoder::new().decode_buffer(&with_orientation(1)).unwrap();
+
+    for orientation in 1..=8
+    {
+        let mut decoder = oriented_decoder();
+        let pixels = decoder
+            .decode_buffer(&with_orientation(orientation))
+            .unwrap();
+
+        assert_eq!(
+            pixels,
+            expected(&upright, 61, 45, 3, orientation),
+            "orientation {orientation}"
+        );
+
+        let (width, height) = if orientation >= 5 { (45, 61) } else { (61, 45) };
+
+        assert_eq!((decoder.width(), decoder.height()), (width, height));
+    }
+}
+
+#[test]
+fn progressive_orientations()
+{
+    // sub-sampled, so post processing writes bands of 64 rows of the upright image
+    let mut data = with_exif_of_fixture("progressive_8_bit_coarse_qt.jpg");
+    let options = ZuneJpegOptions::new().set_out_colorspace(ColorSpace::RGBA);
+
+    set_orientation(&mut data, 1);
+
+    let upright = Decoder::new_with_options(options)
+        .decode_buffer(&data)
+        .unwrap();
+
+    for orientation in 2..=8
+    {
+        set_orientation(&mut data, orientation);
+
+        let pixels = Decoder::new_with_options(options.set_apply_orientation(true))
+            .decode_buffer(&data)
+            .unwrap();
+
+        assert_eq!(
+            pixels,
+            expected(&upright, 123, 77, 4, orientation),
+            "orientation {orientation}"
+        );
+    }
+}
+
+#[test]
+fn dimensions_after_reading_headers()
+{
+    let mut decoder = oriented_decoder();
+
+    decoder.read_headers(&with_orientation(8)).unwrap();
+
+    let info = decoder.info().unwrap();
+
+    assert_eq!((decoder.width(), decoder.height()), (45, 61));
+    assert_eq!((info.width, info.height), (45, 61));
+}
+
+#[test]
+fn high_precision_orientation()
+{
+    let mut data = with_exif_of_fixture("rgb_12_bit.jpg");
+    let mut decoder = Decoder::new();
+    let upright = decoder.decode_buffer_u16(&data).unwrap();
+    let (width, height) = (usize::from(decoder.width()), usize::from(decoder.height()));
+
+    for orientation in 5..=8
+    {
+        set_orientation(&mut data, orientation);
+
+        let mut decoder = oriented_decoder();
+        let pixels = decoder.decode_buffer_u16(&data).unwrap();
+
+        assert_eq!(
+            pixels,
+            expected(&upright, width, height, 3, orientation),
+            "orientation {orientation}"
+        );
+        assert_eq!(
+            (usize::from(decoder.width()), usize::from(decoder.height())),
+            (height, width)
+        );
+    }
+}
+
+#[test]
+fn grayscale_output_orientation()
+{
+    let data = with_orientation(7);
+    let options = ZuneJpegOptions::new().set_out_colorspace(ColorSpace::GRAYSCALE);
+
+    let upright = Decoder::new_with_options(options)
+        .decode_buffer(&data)
+        .unwrap();
+    let pixels = Decoder::new_with_options(options.set_apply_orientation(true))
+        .decode_buffer(&data)
+        .unwrap();
+
+    assert_eq!(pixels, expected(&upright, 61, 45, 1, 7));
+}
+
+#[test]
+fn orientation_is_off_by_default()
+{
+    let mut decoder = Decoder::new();
+    let pixels = decoder.decode_buffer(&with_orientation(6)).unwrap();
+
+    assert_eq!(pixels.len(), 61 * 45 * 3);
+    assert_eq!((decoder.width(), decoder.height()), (61, 45));
+}
+
+#[test]
+fn invalid_orientation_is_ignored()
+{
+    let upright = Decoder::new().decode_buffer(&with_orientation(1)).unwrap();
+    let mut decoder = oriented_decoder();
+
+    assert_eq!(
+        decoder.decode_buffer(&with_orientation(9)).unwrap(),
+        upright
+    );
+    assert_eq!((decoder.width(), decoder.height()), (61, 45));
+}
@@ -0,0 +1,210 @@
+//! Tests for gain maps and HDR decoding
+//!
+//! `ultrahdr_gain_map.jpg` is an SDR image of 61 by 45 pixels (1759 bytes)
+//! followed by a grayscale gain map of 16 by 12 pixels, black on its left half
+//! and white on its right half. The gain map has both `hdrgm:` XMP properties
+//! and ISO 21496-1 metadata, with gains between 1 and 4 applied fully from a
+//! display boost of 4. `ultrahdr_gain_map_gray.jpg` has the same gain map
+//! after a grayscale SDR image.
+mod common;
+
+use common::path;
+use zune_jpeg::gainmap::GainMapMetadata;
+use zune_jpeg::{ColorSpace, Decoder, ZuneJpegOptions};
+
+const OFFSET: f32 = 1.0 / 64.0;
+
+fn expected_metadata() -> GainMapMetadata
+{
+    GainMapMetadata {
+        gain_map_min:          [0.0; 3],
+        gain_map_max:          [2.0; 3],
+        gamma:                 [1.0; 3],
+        offset_sdr:            [OFFSET; 3],
+        offset_hdr:            [OFFSET; 3],
+        hdr_capacity_min:      0.0,
+        hdr_capacity_max:      2.0,
+        base_rendition_is_hdr: false,
+    }
+}
+
+fn srgb_to_linear(value: u8) -> f32
+{
+    let v = f32::from(value) / 255.0;
+
+    if v <= 0.04045
+    {
+        v / 12.92
+    }
+    else
+    {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Check HDR values against the SDR image, for gains of `left` and `right`
+/// on either side of the gain map
+fn check_gains(hdr: &[f32], sdr: &[u8], left: f32, right: f32)
+{
+    assert_eq!(hdr.len(), sdr.len());
+
+    for (i, (hdr, sdr)) in hdr.iter().zip(sdr).enumerate()
+    {
+        let x = (i / 3) % 61;
+        // columns in between are interpolated
+        let gain = match x
+        {
+            0..=28 => left,
+            32.. => right,
+            _ => continue,
+        };
+        let expected = ((srgb_to_linear(*sdr) + OFFSET) * gain - OFFSET).max(0.0);
+
+        assert!(
+            (hdr - expected).abs() < 1e-5,
+            "value {i}, {hdr} instead of {expected}"
+        );
+    }
+}
+
+#[test]
+fn gain_map_metadata()
+{
+    let data = std::fs::read(path("ultrahdr_gain_map.jpg")).unwrap();
+    let gain_map = Decoder::new().gain_map(&data).unwrap().unwrap();
+
+    assert_eq!(gain_map.metadata, expected_metadata());
+    assert_eq!(gain_map.data, &data[1759..]);
+
+    let mut decoder = Decoder::new();
+
+    decoder.read_headers(&gain_map.data).unwrap();
+    assert_eq!((decoder.width(), decoder.height()), (16, 12));
+}
+
+#[test]
+fn xmp_metadata_without_iso_metadata()
+{
+    let mut data = std::fs::read(path("ultrahdr_gain_map.jpg")).unwrap();
+
+    // the signature of the ISO segments
+    for _ in 0..2
+    {
+        let signature = data.windows(7).position(|x| x == b"urn:iso").unwrap();
+        data[signature] = b'x';
+    }
+    let gain_map = Decoder::new().gain_map(&data).unwrap().unwrap();
+
+    assert_eq!(gain_map.metadata, expected_metadata());
+}
+
+#[test]
+fn iso_metadata_without_xmp_metadata()
+{
+    let mut data = std::fs::read(path("ultrahdr_gain_map.jpg")).unwrap();
+    let gain_map_start = 1759;
+
+    let version = gain_map_start
+        + data[gain_map_start..]
+            .windows(13)
+            .position(|x| x == b"hdrgm:Version")
+            .unwrap();
+    data[version + 6] = b'X';
+
+    let gain_map = Decoder::new().gain_map(&data).unwrap().unwrap();
+
+    assert_eq!(gain_map.metadata, expected_metadata());
+
+    // and without either, there is no gain map
+    let signature = data.windows(7).rposition(|x| x == b"urn:iso").unwrap();
+    data[signature] = b'x';
+
+    assert!(Decoder::new().gain_map(&data).unwrap().is_none());
+    assert!(Decoder::new().decode_hdr(&data, 4.0).is_err());
+}
+
+#[test]
+fn hdr_at_display_boosts()
+{
+    let data = std::fs::read(path("ultrahdr_gain_map.jpg")).unwrap();
+    let sdr = Decoder::new().decode_buffer(&data).unwrap();
+
+    // no boost, SDR
+    let hdr = Decoder::new().decode_hdr(&data, 1.0).unwrap();
+    check_gains(&hdr, &sdr, 1.0, 1.0);
+
+    // half of the gain, in log2 terms
+    let hdr = Decoder::new().decode_hdr(&data, 2.0).unwrap();
+    check_gains(&hdr, &sdr, 1.0, 2.0);
+
+    // all of the gain, more boost doesn't change anything
+    for boost in [4.0, 16.0]
+    {
+        let hdr = Decoder::new().decode_hdr(&data, boost).unwrap();
+        check_gains(&hdr, &sdr, 1.0, 4.0);
+    }
+}
+
+#[test]
+fn hdr_of_grayscale_image()
+{
+    let data = std::fs::read(path("ultrahdr_gain_map_gray.jpg")).unwrap();
+    let gray = Decoder::new().decode_buffer(&data).unwrap();
+    let sdr: Vec<u8> = gray.iter().flat_map(|x| [*x; 3]).collect();
+
+    let hdr = Decoder::new().decode_hdr(&data, 4.0).unwrap();
+
+    check_gains(&hdr, &sdr, 1.0, 4.0);
+}
+
+#[test]
+fn hdr_ignores_output_colorspace()
+{
+    let data = std::fs::read(path("ultrahdr_gain_map.jpg")).unwrap();
+    let options = ZuneJpegOptions::new().set_out_colorspace(ColorSpace::RGBA);
+    let mut decoder = Decoder::new_with_options(options);
+
+    let hdr = decoder.decode_hdr(&data, 4.0).unwrap();
+
+    assert_eq!(hdr, Decoder::new().decode_hdr(&data, 4.0).unwrap());
+    assert_eq!(decoder.get_output_colorspace(), ColorSpace::RGBA);
+    assert_eq!((decoder.width(), decoder.height()), (61, 45));
+}
+
+/// The value of half float bits
+fn f16_to_f32(bits: u16) -> f32
+{
+    let exponent = i32::from((bits >> 10) & 0x1F);
+    let mantissa = f32::from(bits & 0x3FF);
+
+    match exponent
+    {
+        0 => mantissa * 2f32.powi(-24),
+        _ => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+#[test]
+fn half_float_output()
+{
+    let data = std::fs::read(path("ultrahdr_gain_map.jpg")).unwrap();
+    let hdr = Decoder::new().decode_hdr(&data, 4.0).unwrap();
+    let half = Decoder::new().decode_hdr_f16(&data, 4.0).unwrap();
+
+    assert_eq!(hdr.len(), half.len());
+
+    for (value, bits) in hdr.iter().zip(half)
+    {
+        // 11 bits of precision, and subnormals below 2^-14
+        assert!((f16_to_f32(bits) - value).abs() <= (value / 2048.0).max(2f32.powi(-25)));
+    }
+}
+
+#[test]
+fn image_without_gain_map()
+{
+    let data = std::fs::read(path("mpo_stereo.jpg")).unwrap();
+
+    assert!(Decoder::new().gain_map(&data).unwrap().is_none());
+    assert!(Decoder::new().decode_hdr(&data, 4.0).is_err());
+}
@@ -0,0 +1,63 @@
+//! Tests for hierarchical images
+//!
+//! Images whose last frame is lossless should decode to exactly the samples they
+//! were encoded from, for the others the reference was reconstructed by the encoder.
+mod common;
+
+use common::{path, read_pnm, read_pnm_u16};
+use zune_jpeg::Decoder;
+
+#[test]
+fn hierarchical_lossless_12_bit()
+{
+    // three lossless frames, at a quarter, half and full resolution
+    let mut decoder = Decoder::new();
+    let pixels = decoder
+        .decode_file_u16(path("hierarchical_lossless_12_bit.jpg"))
+        .unwrap();
+
+    assert_eq!(decoder.width(), 40);
+    assert_eq!(decoder.height(), 30);
+    assert!(pixels == read_pnm_u16("lossless_12_bit.ppm"));
+}
+
+#[test]
+fn hierarchical_8_bit_gray()
+{
+    // a DCT frame at half the width, followed by a differential DCT frame
+    let pixels = Decoder::new()
+        .decode_file(path("hierarchical_8_bit_gray.jpg"))
+        .unwrap();
+    let reference = read_pnm("hierarchical_8_bit_gray.pgm");
+
+    assert!(pixels == reference);
+}
+
+#[test]
+fn hierarchical_dct_to_lossless_8_bit()
+{
+    // a DCT frame at half resolution, refined by a lossless differential frame
+    let pixels = Decoder::new()
+        .decode_file_u16(path("hierarchical_dct_to_lossless_8_bit.jpg"))
+        .unwrap();
+    let reference: Vec<u16> = read_pnm_u16("lossless_12_bit.ppm")
+        .iter()
+        .map(|x| x >> 4)
+        .collect();
+
+    assert!(pixels == reference);
+}
+
+#[test]
+fn differential_frame_without_dhp()
+{
+    // change the first frame of the image into a differential one
+    let mut data = std::fs::read(path("hierarchical_8_bit_gray.jpg")).unwrap();
+    let dhp = data.windows(2).position(|x| x == [0xFF, 0xDE]).unwrap();
+    data[dhp + 1] = 0xFE;
+
+    let sof = data.windows(2).position(|x| x == [0xFF, 0xC0]).unwrap();
+    data[sof + 1] = 0xC5;
+
+    assert!(Decoder::new().decode_buffer(&data).is_err());
+}
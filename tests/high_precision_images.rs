@@ -0,0 +1,105 @@
+//! Tests for extended sequential and 12 bit progressive images
+//!
+//! The 12 bit images were encoded with a 12 bit build of libjpeg-turbo's `cjpeg`,
+//! and the reference outputs are its `djpeg` output, we use the same IDCT,
+//! up-sampling and color conversion so samples should match exactly. 8 bit
+//! images go through our own IDCT and color conversion, so their samples may be
+//! a few off from `djpeg`'s.
+//!
+//! Progressive images were made with `jpegtran -progressive`, whose scan script
+//! uses successive approximation.
+mod common;
+
+use common::{path, read_pnm_u16};
+use zune_jpeg::Decoder;
+
+fn decode(file: &str) -> Vec<u16>
+{
+    Decoder::new()
+        .decode_file_u16(path(file))
+        .expect("Test failed decoding")
+}
+
+#[test]
+fn sequential_12_bit_sampled()
+{
+    let pixels = decode("sequential_12_bit_hv.jpg");
+
+    assert!(pixels == read_pnm_u16("sequential_12_bit_hv.ppm"));
+}
+
+#[test]
+fn sequential_12_bit_restarts()
+{
+    let pixels = decode("sequential_12_bit_h_restart.jpg");
+
+    assert!(pixels == read_pnm_u16("sequential_12_bit_h_restart.ppm"));
+}
+
+#[test]
+fn sequential_12_bit_grayscale()
+{
+    let pixels = decode("sequential_12_bit_gray.jpg");
+
+    assert!(pixels == read_pnm_u16("sequential_12_bit_gray.pgm"));
+}
+
+#[test]
+fn sequential_12_bit_small()
+{
+    let pixels = decode("sequential_12_bit_3x5.jpg");
+
+    assert!(pixels == read_pnm_u16("sequential_12_bit_3x5.ppm"));
+}
+
+#[test]
+fn sequential_12_bit_to_8_bit()
+{
+    let pixels = Decoder::new()
+        .decode_file(path("sequential_12_bit_hv.jpg"))
+        .unwrap();
+
+    let expected: Vec<u8> = read_pnm_u16("sequential_12_bit_hv.ppm")
+        .iter()
+        .map(|x| (x >> 4) as u8)
+        .collect();
+
+    assert!(pixels == expected);
+}
+
+#[test]
+fn sequential_8_bit()
+{
+    // coarse quantization forces 16 bit quantization tables, and hence SOF1
+    let pixels = decode("sequential_8_bit_sof1.jpg");
+    let expected = read_pnm_u16("sequential_8_bit_sof1.ppm");
+
+    assert_eq!(pixels.len(), expected.len());
+
+    for (pixel, expected) in pixels.iter().zip(&expected)
+    {
+        assert!(pixel.abs_diff(*expected) <= 3);
+    }
+}
+
+#[test]
+fn progressive_12_bit_sampled()
+{
+    let pixels = decode("progressive_12_bit_hv.jpg");
+
+    assert!(pixels == read_pnm_u16("sequential_12_bit_hv.ppm"));
+}
+
+#[test]
+fn progressive_12_bit_large_coefficients()
+{
+    // a noisy checkerboard at quality 100 has coefficients close to the
+    // limits of 12 bit images
+    let expected = read_pnm_u16("progressive_12_bit_checker.ppm");
+
+    let huffman = decode("progressive_12_bit_checker.jpg");
+    let arithmetic = decode("progressive_12_bit_checker_arithmetic.jpg");
+
+    assert!(huffman == expected);
+    assert!(arithmetic == expected);
+}
@@ -0,0 +1,100 @@
+//! Tests for reading embedded ICC profiles
+//!
+//! `icc_display_p3.jpg` was encoded with `cjpeg -icc display_p3.icc`, which stores
+//! the profile in a single APP2 chunk. Images with more chunks are made by splitting it.
+mod common;
+
+use common::path;
+use zune_jpeg::Decoder;
+
+/// Split the profile of `icc_display_p3.jpg` into APP2 chunks of `size` bytes,
+/// written in the order given by `order`
+fn split_profile(size: usize, order: &[usize]) -> Vec<u8>
+{
+    let data = std::fs::read(path("icc_display_p3.jpg")).unwrap();
+    let profile = std::fs::read(path("display_p3.icc")).unwrap();
+
+    let start = data.windows(2).position(|x| x == [0xFF, 0xE2]).unwrap();
+    let end = start + 2 + usize::from(u16::from_be_bytes([data[start + 2], data[start + 3]]));
+
+    let chunks: Vec<&[u8]> = profile.chunks(size).collect();
+    let mut segments = Vec::new();
+
+    for &index in order
+    {
+        segments.extend_from_slice(&[0xFF, 0xE2]);
+        segments.extend_from_slice(&(16 + chunks[index].len() as u16).to_be_bytes());
+        segments.extend_from_slice(b"ICC_PROFILE\0");
+        segments.extend_from_slice(&[index as u8 + 1, chunks.len() as u8]);
+        segments.extend_from_slice(chunks[index]);
+    }
+
+    let mut image = data[..start].to_vec();
+    image.extend_from_slice(&segments);
+    image.extend_from_slice(&data[end..]);
+
+    image
+}
+
+fn read_profile(data: &[u8]) -> Option<Vec<u8>>
+{
+    let mut decoder = Decoder::new();
+    decoder.read_headers(data).unwrap();
+
+    decoder.icc_profile()
+}
+
+#[test]
+fn single_chunk_profile()
+{
+    let data = std::fs::read(path("icc_display_p3.jpg")).unwrap();
+
+    assert_eq!(
+        read_profile(&data),
+        Some(std::fs::read(path("display_p3.icc")).unwrap())
+    );
+}
+
+#[test]
+fn chunks_out_of_order()
+{
+    let data = split_profile(200, &[2, 0, 1]);
+
+    assert_eq!(
+        read_profile(&data),
+        Some(std::fs::read(path("display_p3.icc")).unwrap())
+    );
+}
+
+#[test]
+fn missing_chunk()
+{
+    assert_eq!(read_profile(&split_profile(200, &[0, 2])), None);
+}
+
+#[test]
+fn repeated_chunk()
+{
+    assert_eq!(read_profile(&split_profile(200, &[0, 1, 1])), None);
+}
+
+#[test]
+fn no_profile()
+{
+    let data = std::fs::read(path("sequential_single_scan.jpg")).unwrap();
+
+    assert_eq!(read_profile(&data), None);
+}
+
+#[test]
+fn profile_after_decoding()
+{
+    let mut decoder = Decoder::new();
+    let pixels = decoder.decode_file(path("icc_display_p3.jpg")).unwrap();
+
+    assert!(!pixels.is_empty());
+    assert_eq!(
+        decoder.icc_profile(),
+        Some(std::fs::read(path("display_p3.icc")).unwrap())
+    );
+}
@@ -0,0 +1,150 @@
+//! Tests for converting colors from embedded ICC profiles to sRGB
+//!
+//! The profiles are small hand written ones, a Display P3 matrix/TRC profile, a
+//! gray profile with a gamma of 1.8 and two CMYK profiles, one with a lut16 table
+//! and Lab PCS and one with a lutAToB table and XYZ PCS.
+//!
+//! `icc_display_p3.jpg` and `icc_gray.jpg` were encoded with libjpeg-turbo's
+//! `cjpeg -icc`. References are Little CMS's conversions (relative colorimetric
+//! intent, without optimizations) of `djpeg`'s output, or of jpeg-decoder's for
+//! the CMYK images which `djpeg` can't write. Our IDCT and color conversion round
+//! differently from theirs by up to three, before the profile is applied.
+mod common;
+
+use common::{path, read_pnm_u16};
+use zune_jpeg::{ColorSpace, Decoder, ZuneJpegOptions};
+
+fn decode(file: &str, colorspace: ColorSpace, icc_to_srgb: bool) -> Vec<u8>
+{
+    let options = ZuneJpegOptions::new()
+        .set_out_colorspace(colorspace)
+        .set_icc_to_srgb(icc_to_srgb);
+
+    Decoder::new_with_options(options)
+        .decode_file(path(file))
+        .expect("Test failed decoding")
+}
+
+fn assert_close(pixels: &[u16], reference: &[u16], tolerance: u16)
+{
+    assert_eq!(pixels.len(), reference.len());
+
+    for (pixel, expected) in pixels.iter().zip(reference)
+    {
+        assert!(pixel.abs_diff(*expected) <= tolerance);
+    }
+}
+
+fn widen(pixels: &[u8]) -> Vec<u16>
+{
+    pixels.iter().map(|x| u16::from(*x)).collect()
+}
+
+#[test]
+fn display_p3_to_srgb()
+{
+    let pixels = decode("icc_display_p3.jpg", ColorSpace::RGB, true);
+
+    assert_close(&widen(&pixels), &read_pnm_u16("icc_display_p3.ppm"), 3);
+}
+
+#[test]
+fn display_p3_to_srgba()
+{
+    let decode_u16 = |colorspace| {
+        let options = ZuneJpegOptions::new()
+            .set_out_colorspace(colorspace)
+            .set_icc_to_srgb(true);
+
+        Decoder::new_with_options(options)
+            .decode_file_u16(path("icc_12_bit_display_p3.jpg"))
+            .unwrap()
+    };
+    let rgb = decode_u16(ColorSpace::RGB);
+    let rgba = decode_u16(ColorSpace::RGBA);
+
+    for (rgba, rgb) in rgba.chunks_exact(4).zip(rgb.chunks_exact(3))
+    {
+        assert_eq!(&rgba[..3], rgb);
+        assert_eq!(rgba[3], 4095);
+    }
+}
+
+#[test]
+fn gray_to_srgb()
+{
+    let pixels = decode("icc_gray.jpg", ColorSpace::GRAYSCALE, true);
+
+    assert_close(&widen(&pixels), &read_pnm_u16("icc_gray.pgm"), 1);
+}
+
+#[test]
+fn cmyk_lut16_to_srgb()
+{
+    let pixels = decode("icc_cmyk_lut16.jpg", ColorSpace::RGB, true);
+
+    assert_close(&widen(&pixels), &read_pnm_u16("icc_cmyk_lut16.ppm"), 2);
+}
+
+#[test]
+fn ycck_lut_a_to_b_to_srgb()
+{
+    let pixels = decode("icc_ycck_a2b.jpg", ColorSpace::RGB, true);
+
+    assert_close(&widen(&pixels), &read_pnm_u16("icc_ycck_a2b.ppm"), 3);
+}
+
+#[test]
+fn high_precision_to_srgb()
+{
+    let pixels = Decoder::new_with_options(ZuneJpegOptions::new().set_icc_to_srgb(true))
+        .decode_file_u16(path("icc_12_bit_display_p3.jpg"))
+        .unwrap();
+
+    assert_close(&pixels, &read_pnm_u16("icc_12_bit_display_p3.ppm"), 1);
+}
+
+#[test]
+fn conversion_off_by_default()
+{
+    let pixels = Decoder::new()
+        .decode_file(path("icc_display_p3.jpg"))
+        .unwrap();
+
+    assert!(pixels == decode("icc_display_p3.jpg", ColorSpace::RGB, false));
+    assert!(pixels != decode("icc_display_p3.jpg", ColorSpace::RGB, true));
+}
+
+#[test]
+fn raw_output_is_not_converted()
+{
+    // YCbCr and CMYK output hold samples as stored, not colors
+    assert!(
+        decode("icc_display_p3.jpg", ColorSpace::YCbCr, true)
+            == decode("icc_display_p3.jpg", ColorSpace::YCbCr, false)
+    );
+    assert!(
+        decode("icc_cmyk_lut16.jpg", ColorSpace::CMYK, true)
+            == decode("icc_cmyk_lut16.jpg", ColorSpace::CMYK, false)
+    );
+}
+
+#[test]
+fn tag_count_larger_than_profile()
+{
+    let mut data = std::fs::read(path("icc_display_p3.jpg")).unwrap();
+
+    // the tag count follows the 128 byte header of the profile
+    let profile = data
+        .windows(12)
+        .position(|x| x == b"ICC_PROFILE\0")
+        .unwrap()
+        + 14;
+    data[profile + 128..profile + 132].copy_from_slice(&[0xFF; 4]);
+
+    let pixels = Decoder::new_with_options(ZuneJpegOptions::new().set_icc_to_srgb(true))
+        .decode_buffer(&data)
+        .unwrap();
+
+    assert!(pixels == decode("icc_display_p3.jpg", ColorSpace::RGB, true));
+}
@@ -0,0 +1,55 @@
+//! Tests for images whose width is not a multiple of 16
+//!
+//! Color conversion works on 16 pixels at a time, so the end of each row is
+//! converted separately. `width_70.jpg` is `width_61.jpg` with its first nine
+//! columns repeated on the right, both were encoded with libjpeg-turbo's
+//! `cjpeg -sample 1x1`.
+//!
+//! References are `djpeg` output, our color conversion rounds differently from
+//! libjpeg's by up to three.
+mod common;
+
+use common::{path, read_pnm};
+use zune_jpeg::{ColorSpace, Decoder, ZuneJpegOptions};
+
+fn assert_close(pixels: &[u8], reference: &[u8])
+{
+    assert_eq!(pixels.len(), reference.len());
+
+    for (pixel, expected) in pixels.iter().zip(reference)
+    {
+        assert!(pixel.abs_diff(*expected) <= 3);
+    }
+}
+
+#[test]
+fn width_61()
+{
+    let pixels = Decoder::new().decode_file(path("width_61.jpg")).unwrap();
+
+    assert_close(&pixels, &read_pnm("width_61.ppm"));
+}
+
+#[test]
+fn width_70()
+{
+    let pixels = Decoder::new().decode_file(path("width_70.jpg")).unwrap();
+
+    assert_close(&pixels, &read_pnm("width_70.ppm"));
+}
+
+#[test]
+fn width_70_to_rgba()
+{
+    let rgb = Decoder::new().decode_file(path("width_70.jpg")).unwrap();
+    let rgba =
+        Decoder::new_with_options(ZuneJpegOptions::new().set_out_colorspace(ColorSpace::RGBA))
+            .decode_file(path("width_70.jpg"))
+            .unwrap();
+
+    for (rgba, rgb) in rgba.chunks_exact(4).zip(rgb.chunks_exact(3))
+    {
+        assert_eq!(&rgba[..3], rgb);
+        assert_eq!(rgba[3], 255);
+    }
+}
@@ -1,3 +1,6 @@
+mod common;
+
+use common::path;
 use zune_jpeg::Decoder;
 
 #[test]
@@ -79,3 +82,17 @@ fn mul_with_overflow()
         matches!(err, zune_jpeg::errors::DecodeErrors::SofError(x) if x == "Length of start of frame differs from expected 584,value is 65281")
     );
 }
+
+#[test]
+fn too_many_blocks_in_mcu()
+{
+    // luminance sampled 4x4 times, 16 blocks per MCU, plus one for each chroma
+    let mut data = std::fs::read(path("huffman_sequential_hv.jpg")).unwrap();
+    let sof = data.windows(2).position(|x| x == [0xFF, 0xC0]).unwrap();
+
+    data[sof + 11] = 0x44;
+
+    let err = Decoder::new().decode_buffer(&data).unwrap_err();
+
+    assert!(matches!(err, zune_jpeg::errors::DecodeErrors::SosError(x) if x.contains("found 18")));
+}
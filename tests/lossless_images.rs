@@ -0,0 +1,98 @@
+//! Tests for lossless images
+//!
+//! The images were encoded from the reference PPM/PGM files, so decoding should give
+//! back exactly the same samples (minus the bits dropped by the point transform).
+mod common;
+
+use common::{path, read_pnm_u16};
+use zune_jpeg::Decoder;
+
+fn decode(file: &str) -> Vec<u16>
+{
+    Decoder::new()
+        .decode_file_u16(path(file))
+        .expect("Test failed decoding")
+}
+
+#[test]
+fn lossless_12_bit_predictors()
+{
+    let reference = read_pnm_u16("lossless_12_bit.ppm");
+
+    for predictor in 1..=7
+    {
+        let pixels = decode(&format!("lossless_12_bit_predictor_{predictor}.jpg"));
+
+        assert!(pixels == reference, "Predictor {predictor} failed");
+    }
+}
+
+#[test]
+fn lossless_12_bit_non_interleaved()
+{
+    let pixels = decode("lossless_12_bit_non_interleaved.jpg");
+
+    assert!(pixels == read_pnm_u16("lossless_12_bit.ppm"));
+}
+
+#[test]
+fn lossless_12_bit_restarts()
+{
+    let pixels = decode("lossless_12_bit_restarts.jpg");
+
+    assert!(pixels == read_pnm_u16("lossless_12_bit.ppm"));
+}
+
+#[test]
+fn lossless_16_bit_restarts()
+{
+    // contains differences of 32768, which have no extra bits, the huffman
+    // table for them comes before the frame header
+    let pixels = decode("lossless_16_bit_gray_restarts.jpg");
+
+    assert!(pixels == read_pnm_u16("lossless_16_bit_gray.pgm"));
+}
+
+#[test]
+fn lossless_16_bit_point_transform()
+{
+    let pixels = decode("lossless_16_bit_gray_point_transform.jpg");
+
+    let reference: Vec<u16> = read_pnm_u16("lossless_16_bit_gray.pgm")
+        .iter()
+        .map(|x| x & !0b111)
+        .collect();
+
+    assert!(pixels == reference);
+}
+
+#[test]
+fn lossless_2_bit_to_8_bit()
+{
+    let pixels = Decoder::new()
+        .decode_file(path("lossless_2_bit_gray.jpg"))
+        .unwrap();
+
+    // the image holds the top two bits of the 16 bit image
+    let reference: Vec<u8> = read_pnm_u16("lossless_16_bit_gray.pgm")
+        .iter()
+        .map(|x| ((x >> 14) << 6) as u8)
+        .collect();
+
+    assert!(pixels == reference);
+}
+
+#[test]
+fn lossless_8_bit_sampled()
+{
+    // luma is the top 8 bits of the red channel, chroma is constant
+    let pixels = decode("lossless_8_bit_sampled.jpg");
+    let reference = read_pnm_u16("lossless_12_bit.ppm");
+
+    assert_eq!(pixels.len(), reference.len());
+
+    for (pixel, expected) in pixels.chunks_exact(3).zip(reference.chunks_exact(3))
+    {
+        assert_eq!(pixel, &[expected[0] >> 4, 100, 200]);
+    }
+}
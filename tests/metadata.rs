@@ -0,0 +1,161 @@
+//! Tests for metadata reported in `ImageInfo`
+//!
+//! `metadata_segments.jpg` has a JFIF segment with a density of 300 dots per inch
+//! and a thumbnail, JFXX segments with thumbnails of each kind, a comment, an
+//! Adobe segment and two application segments we don't interpret.
+mod common;
+
+use common::path;
+use zune_jpeg::{AdobeInfo, AppSegment, Decoder, JfifThumbnail};
+
+fn read_headers(file: &str) -> Decoder
+{
+    let data = std::fs::read(path(file)).unwrap();
+    let mut decoder = Decoder::new();
+
+    decoder.read_headers(&data).unwrap();
+
+    decoder
+}
+
+#[test]
+fn jfif_density()
+{
+    let info = read_headers("metadata_segments.jpg").info().unwrap();
+
+    assert_eq!(info.jfif_version, Some((1, 2)));
+    assert_eq!(info.density_units, 1);
+    assert_eq!((info.x_density, info.y_density), (300, 300));
+    assert_eq!(info.precision, 8);
+}
+
+#[test]
+fn sample_precision()
+{
+    let info = read_headers("sequential_12_bit_gray.jpg").info().unwrap();
+
+    assert_eq!(info.precision, 12);
+}
+
+#[test]
+fn jfif_and_jfxx_thumbnails()
+{
+    let info = read_headers("metadata_segments.jpg").info().unwrap();
+    let thumbnails = &info.jfif_thumbnails;
+
+    assert_eq!(thumbnails.len(), 4);
+    assert_eq!(
+        thumbnails[0],
+        JfifThumbnail::Rgb {
+            width:  2,
+            height: 1,
+            pixels: vec![255, 0, 0, 0, 0, 255],
+        }
+    );
+
+    let JfifThumbnail::Jpeg(jpeg) = &thumbnails[1]
+    else
+    {
+        panic!("expected a JPEG thumbnail, found {:?}", thumbnails[1]);
+    };
+    assert_eq!(jpeg.len(), 348);
+    assert_eq!(&jpeg[..2], &[0xFF, 0xD8]);
+
+    let JfifThumbnail::Palette {
+        width,
+        height,
+        palette,
+        indices,
+    } = &thumbnails[2]
+    else
+    {
+        panic!("expected a palette thumbnail, found {:?}", thumbnails[2]);
+    };
+    assert_eq!((*width, *height), (2, 2));
+    assert_eq!(palette.len(), 768);
+    assert_eq!(indices, &[0, 1, 2, 255]);
+
+    assert_eq!(
+        thumbnails[3],
+        JfifThumbnail::Rgb {
+            width:  1,
+            height: 2,
+            pixels: vec![10, 20, 30, 40, 50, 60],
+        }
+    );
+}
+
+#[test]
+fn comments_and_adobe_segment()
+{
+    let info = read_headers("metadata_segments.jpg").info().unwrap();
+
+    assert_eq!(info.comments, vec![b"zune-jpeg metadata test".to_vec()]);
+    assert_eq!(
+        info.adobe,
+        Some(AdobeInfo {
+            version:   100,
+            flags0:    0x8000,
+            flags1:    0,
+            transform: 1,
+        })
+    );
+
+    let info = read_headers("cmyk_adobe.jpg").info().unwrap();
+
+    assert_eq!(info.adobe.map(|x| x.transform), Some(0));
+    assert_eq!(info.jfif_version, None);
+}
+
+#[test]
+fn unknown_application_segments()
+{
+    let info = read_headers("metadata_segments.jpg").info().unwrap();
+
+    assert_eq!(
+        info.app_segments,
+        vec![
+            AppSegment {
+                marker: 11,
+                offset: 1213,
+                data:   b"JP\0\x01custom data".to_vec(),
+            },
+            AppSegment {
+                marker: 1,
+                offset: 1232,
+                data:   b"http://example.com/unknown\0payload".to_vec(),
+            },
+        ]
+    );
+}
+
+#[test]
+fn metadata_is_not_repeated_by_decoding()
+{
+    let data = std::fs::read(path("metadata_segments.jpg")).unwrap();
+    let mut decoder = Decoder::new();
+
+    decoder.read_headers(&data).unwrap();
+    decoder.decode_buffer(&data).unwrap();
+
+    let info = decoder.info().unwrap();
+
+    assert_eq!(info.comments.len(), 1);
+    assert_eq!(info.jfif_thumbnails.len(), 4);
+    assert_eq!(info.app_segments.len(), 2);
+}
+
+#[test]
+fn recognized_segments_are_not_kept()
+{
+    // EXIF, XMP, ICC profile and JFIF segments
+    let info = read_headers("google_pixel.jpg").info().unwrap();
+
+    assert!(info.app_segments.is_empty());
+    assert_eq!(info.jfif_version, Some((1, 2)));
+    assert_eq!(
+        (info.density_units, info.x_density, info.y_density),
+        (0, 1, 1)
+    );
+    assert!(info.jfif_thumbnails.is_empty());
+}
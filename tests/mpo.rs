@@ -0,0 +1,151 @@
+//! Tests for Multi-Picture (MPO) files
+//!
+//! `mpo_stereo.jpg` is a big endian MPO file with a primary image of 61 by 45
+//! pixels (1046 bytes), a disparity image of the same size (944 bytes) and a
+//! multi-angle frame of 16 by 12 pixels (408 bytes). The TIFF header of the
+//! MP index is at byte 28. `mpo_gray.jpg` is the same file with a grayscale
+//! image of 16 by 12 pixels as its last image.
+mod common;
+
+use common::path;
+use zune_jpeg::mpf::{MpImage, MpImageType};
+use zune_jpeg::{ColorSpace, Decoder, ZuneJpegOptions};
+
+fn image(image_type: MpImageType, offset: usize, size: usize) -> MpImage
+{
+    MpImage {
+        image_type,
+        representative: false,
+        dependent_parent: false,
+        dependent_child: false,
+        format: 0,
+        offset,
+        size,
+        dependent_images: [0, 0],
+    }
+}
+
+#[test]
+fn mp_index()
+{
+    let data = std::fs::read(path("mpo_stereo.jpg")).unwrap();
+    let mut decoder = Decoder::new();
+
+    decoder.read_headers(&data).unwrap();
+
+    let primary = MpImage {
+        representative: true,
+        dependent_parent: true,
+        dependent_images: [2, 3],
+        ..image(MpImageType::Primary, 0, 1046)
+    };
+
+    assert_eq!(
+        decoder.mpf().unwrap().images(),
+        [
+            primary,
+            image(MpImageType::Disparity, 1046, 944),
+            image(MpImageType::MultiAngle, 1990, 408)
+        ]
+    );
+}
+
+#[test]
+fn decode_each_image()
+{
+    let data = std::fs::read(path("mpo_stereo.jpg")).unwrap();
+    let ranges = [(0, 1046), (1046, 1990), (1990, 2398)];
+    let dimensions = [(61, 45), (61, 45), (16, 12)];
+
+    for (index, ((start, end), dimensions)) in ranges.iter().zip(dimensions).enumerate()
+    {
+        let expected = Decoder::new().decode_buffer(&data[*start..*end]).unwrap();
+
+        let mut decoder = Decoder::new();
+        let pixels = decoder.decode_mp_image(&data, index).unwrap();
+
+        assert_eq!(pixels, expected, "image {index}");
+        assert_eq!((decoder.width(), decoder.height()), dimensions);
+    }
+
+    // the primary image is what's decoded normally
+    assert_eq!(
+        Decoder::new().decode_buffer(&data).unwrap(),
+        Decoder::new().decode_mp_image(&data, 0).unwrap()
+    );
+}
+
+#[test]
+fn restart_interval_of_primary_image()
+{
+    let mut data = std::fs::read(path("mpo_stereo.jpg")).unwrap();
+    let expected = Decoder::new().decode_buffer(&data[1046..1990]).unwrap();
+
+    // a DRI marker with an interval of one MCU, right after SOI keeps the
+    // offsets of the MP index, which are from its TIFF header
+    data.splice(2..2, [0xFF, 0xDD, 0x00, 0x04, 0x00, 0x01]);
+
+    assert_eq!(Decoder::new().decode_mp_image(&data, 1).unwrap(), expected);
+}
+
+#[test]
+fn image_not_in_index()
+{
+    let data = std::fs::read(path("mpo_stereo.jpg")).unwrap();
+
+    assert!(Decoder::new().decode_mp_image(&data, 3).is_err());
+    // the last image is cut short
+    assert!(Decoder::new().decode_mp_image(&data[..2000], 2).is_err());
+}
+
+#[test]
+fn number_of_images_larger_than_entries()
+{
+    let mut data = std::fs::read(path("mpo_stereo.jpg")).unwrap();
+
+    // tag 0xB001 of type long with a single value
+    let entry = data
+        .windows(8)
+        .position(|x| x == [0xB0, 0x01, 0, 4, 0, 0, 0, 1])
+        .unwrap();
+    data[entry + 11] = 5;
+
+    let mut decoder = Decoder::new();
+
+    decoder.read_headers(&data).unwrap();
+
+    assert_eq!(decoder.mpf().unwrap().images().len(), 3);
+}
+
+#[test]
+fn image_without_mp_index()
+{
+    let data = std::fs::read(path("google_pixel.jpg")).unwrap();
+    let mut decoder = Decoder::new();
+
+    decoder.read_headers(&data).unwrap();
+
+    assert!(decoder.mpf().is_none());
+    assert!(Decoder::new().decode_mp_image(&data, 0).is_err());
+}
+
+#[test]
+fn grayscale_image_keeps_options()
+{
+    let data = std::fs::read(path("mpo_gray.jpg")).unwrap();
+    let options = ZuneJpegOptions::new().set_out_colorspace(ColorSpace::RGBA);
+    let mut decoder = Decoder::new_with_options(options);
+
+    assert_eq!(decoder.decode_mp_image(&data, 2).unwrap().len(), 16 * 12);
+    assert_eq!(decoder.get_output_colorspace(), ColorSpace::RGBA);
+
+    let primary = decoder.decode_mp_image(&data, 0).unwrap();
+
+    assert_eq!(primary.len(), 61 * 45 * 4);
+    assert_eq!(
+        primary,
+        Decoder::new_with_options(options)
+            .decode_mp_image(&data, 0)
+            .unwrap()
+    );
+}
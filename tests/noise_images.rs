@@ -0,0 +1,40 @@
+//! Tests for images of noise, whose AC coefficients are large
+//!
+//! The images were encoded at quality 100 with optimized Huffman tables, which
+//! give short codes to coefficients between 32 and 127. The progressive image
+//! has a single AC scan, without successive approximation, so coefficients are
+//! as large as in the sequential image.
+//!
+//! The reference is libjpeg-turbo's `djpeg` output, our IDCT rounds a bit
+//! differently so samples may be off by one.
+mod common;
+
+use common::{path, read_pnm};
+use zune_jpeg::Decoder;
+
+fn assert_matches_reference(file: &str)
+{
+    let pixels = Decoder::new()
+        .decode_file(path(file))
+        .expect("Test failed decoding");
+    let reference = read_pnm("noise.pgm");
+
+    assert_eq!(pixels.len(), reference.len());
+
+    for (pixel, expected) in pixels.iter().zip(&reference)
+    {
+        assert!(pixel.abs_diff(*expected) <= 1, "{file}");
+    }
+}
+
+#[test]
+fn noise_sequential()
+{
+    assert_matches_reference("noise_sequential.jpg");
+}
+
+#[test]
+fn noise_progressive()
+{
+    assert_matches_reference("noise_progressive.jpg");
+}
@@ -0,0 +1,161 @@
+//! Tests for reading Photoshop image resources and IPTC metadata
+//!
+//! `iptc_photoshop.jpg` has resolution info, IPTC-IIM metadata, a named
+//! resource and a JPEG thumbnail of 16 by 12 pixels, the resources are split
+//! over two APP13 segments.
+mod common;
+
+use common::path;
+use zune_jpeg::photoshop::{ResolutionInfo, Resource};
+use zune_jpeg::{ColorSpace, Decoder};
+
+fn read_headers(data: &[u8]) -> Decoder
+{
+    let mut decoder = Decoder::new();
+    decoder.read_headers(data).unwrap();
+
+    decoder
+}
+
+#[test]
+fn resources_split_over_segments()
+{
+    let data = std::fs::read(path("iptc_photoshop.jpg")).unwrap();
+    let photoshop = read_headers(&data).photoshop().unwrap();
+    let resources = photoshop.resources();
+
+    let ids: Vec<u16> = resources.iter().map(|x| x.id).collect();
+
+    assert_eq!(ids, [1005, 1028, 2999, 1036]);
+    assert_eq!(resources[1].name, b"IPTC");
+    assert_eq!(
+        resources[2],
+        Resource {
+            id:   2999,
+            name: b"named".to_vec(),
+            data: b"odd".to_vec(),
+        }
+    );
+}
+
+#[test]
+fn iptc_fields()
+{
+    let data = std::fs::read(path("iptc_photoshop.jpg")).unwrap();
+    let iptc = read_headers(&data).photoshop().unwrap().iptc().unwrap();
+
+    assert_eq!(iptc.object_name().as_deref(), Some("Harbour at dusk"));
+    assert_eq!(iptc.keywords(), ["harbour", "boats", "café"]);
+    assert_eq!(iptc.date_created().as_deref(), Some("20220202"));
+    assert_eq!(iptc.by_line(), ["Jane Doe"]);
+    assert_eq!(iptc.headline().as_deref(), Some("Evening in the harbour"));
+    assert_eq!(iptc.credit().as_deref(), Some("Zune Photo Agency"));
+    assert_eq!(iptc.source().as_deref(), Some("Zune Archive"));
+    assert_eq!(iptc.copyright().as_deref(), Some("© 2022 Jane Doe"));
+    assert_eq!(
+        iptc.caption().as_deref(),
+        Some("Boats moored in the harbour at dusk.")
+    );
+    assert_eq!(iptc.city().as_deref(), Some("Sydney"));
+    assert_eq!(iptc.country().as_deref(), Some("Australia"));
+    // record version
+    assert_eq!(iptc.get(2, 0), Some(&[0, 4][..]));
+    assert_eq!(iptc.datasets().len(), 15);
+}
+
+#[test]
+fn latin1_text_without_character_set()
+{
+    let mut data = std::fs::read(path("iptc_photoshop.jpg")).unwrap();
+
+    // drop the coded character set, and make the copyright sign Latin-1
+    let escape = data.windows(3).position(|x| x == b"\x1B%G").unwrap();
+    data[escape + 2] = b'@';
+
+    let sign = data.windows(2).position(|x| x == "©".as_bytes()).unwrap();
+    data[sign] = b' ';
+    data[sign + 1] = 0xA9;
+
+    let iptc = read_headers(&data).photoshop().unwrap().iptc().unwrap();
+
+    assert_eq!(iptc.copyright().as_deref(), Some(" © 2022 Jane Doe"));
+    // valid UTF-8 is still read as such
+    assert_eq!(iptc.keywords()[2], "café");
+}
+
+#[test]
+fn resolution_info()
+{
+    let data = std::fs::read(path("iptc_photoshop.jpg")).unwrap();
+    let photoshop = read_headers(&data).photoshop().unwrap();
+
+    assert_eq!(
+        photoshop.resolution(),
+        Some(ResolutionInfo {
+            horizontal:      300.0,
+            horizontal_unit: 1,
+            width_unit:      1,
+            vertical:        300.5,
+            vertical_unit:   1,
+            height_unit:     2,
+        })
+    );
+}
+
+#[test]
+fn photoshop_thumbnail()
+{
+    let data = std::fs::read(path("iptc_photoshop.jpg")).unwrap();
+    let decoder = read_headers(&data);
+    let thumbnail = decoder.photoshop().unwrap().thumbnail().unwrap();
+
+    assert_eq!((thumbnail.width, thumbnail.height), (16, 12));
+    assert!(!thumbnail.bgr);
+
+    let expected = Decoder::new().decode_buffer(&thumbnail.data).unwrap();
+    let decoded = decoder.thumbnail().unwrap().unwrap();
+
+    assert_eq!((decoded.width, decoded.height), (16, 12));
+    assert_eq!(decoded.pixels, expected);
+}
+
+#[test]
+fn photoshop_4_thumbnail_is_bgr()
+{
+    let mut data = std::fs::read(path("iptc_photoshop.jpg")).unwrap();
+    let expected = Decoder::new()
+        .decode_buffer(
+            &read_headers(&data)
+                .photoshop()
+                .unwrap()
+                .thumbnail()
+                .unwrap()
+                .data,
+        )
+        .unwrap();
+
+    // make it resource 1033
+    let resource = data.windows(6).position(|x| x == b"8BIM\x04\x0C").unwrap();
+    data[resource + 5] = 0x09;
+
+    let decoder = read_headers(&data);
+
+    assert!(decoder.photoshop().unwrap().thumbnail().unwrap().bgr);
+
+    let thumbnail = decoder.thumbnail().unwrap().unwrap();
+    let swapped: Vec<u8> = expected
+        .chunks_exact(3)
+        .flat_map(|x| [x[2], x[1], x[0]])
+        .collect();
+
+    assert_eq!(thumbnail.colorspace, ColorSpace::RGB);
+    assert_eq!(thumbnail.pixels, swapped);
+}
+
+#[test]
+fn image_without_photoshop_resources()
+{
+    let data = std::fs::read(path("google_pixel.jpg")).unwrap();
+
+    assert!(read_headers(&data).photoshop().is_none());
+}
@@ -0,0 +1,204 @@
+//! Tests for decoding from readers
+//!
+//! Readers return few bytes at a time, so markers, stuffed bytes and segment
+//! lengths end up split between reads.
+mod common;
+
+use std::io::{Error, ErrorKind, Read};
+
+use common::path;
+use zune_jpeg::Decoder;
+
+/// A reader returning at most `chunk` bytes per read, and stopping before
+/// every 0xFF byte, interrupted every other read
+struct Trickle<'a>
+{
+    data:        &'a [u8],
+    chunk:       usize,
+    interrupted: bool,
+}
+
+impl<'a> Trickle<'a>
+{
+    fn new(data: &'a [u8], chunk: usize) -> Trickle<'a>
+    {
+        Trickle {
+            data,
+            chunk,
+            interrupted: false,
+        }
+    }
+}
+
+impl Read for Trickle<'_>
+{
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize>
+    {
+        self.interrupted = !self.interrupted;
+
+        if self.interrupted
+        {
+            return Err(Error::from(ErrorKind::Interrupted));
+        }
+        let length = self
+            .data
+            .iter()
+            .skip(1)
+            .position(|x| *x == 0xFF)
+            .map_or(self.data.len(), |x| x + 1)
+            .min(self.chunk)
+            .min(buf.len());
+
+        buf[..length].copy_from_slice(&self.data[..length]);
+        self.data = &self.data[length..];
+
+        Ok(length)
+    }
+}
+
+/// A reader failing after `data`
+struct Failing<'a>
+{
+    data: &'a [u8],
+}
+
+impl Read for Failing<'_>
+{
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize>
+    {
+        if self.data.is_empty()
+        {
+            return Err(Error::new(ErrorKind::ConnectionReset, "connection reset"));
+        }
+        self.data.read(buf)
+    }
+}
+
+#[test]
+fn same_pixels_as_buffers()
+{
+    for file in [
+        "huffman_sequential_hv.jpg",
+        "rgb_progressive.jpg",
+        "arithmetic_sequential_hv_restart.jpg",
+        "arithmetic_progressive_hv_restart.jpg",
+        "sequential_12_bit_hv.jpg",
+        "lossless_12_bit_restarts.jpg",
+        "hierarchical_8_bit_gray.jpg",
+        "dnl_sequential.jpg",
+        "cmyk_adobe.jpg",
+        "motion_photo.jpg",
+    ]
+    {
+        let data = std::fs::read(path(file)).unwrap();
+        let mut decoder = Decoder::new();
+
+        let expected = decoder.decode_buffer(&data).unwrap();
+        let end_of_image = decoder.end_of_image();
+        let expected_u16 = decoder.decode_buffer_u16(&data).unwrap();
+
+        for chunk in [1, 3, 4096]
+        {
+            let mut decoder = Decoder::new();
+
+            let pixels = decoder.decode_reader(Trickle::new(&data, chunk)).unwrap();
+
+            assert!(pixels == expected, "{file}, {chunk} bytes per read");
+            assert_eq!(decoder.end_of_image(), end_of_image, "{file}");
+
+            let pixels = decoder
+                .decode_reader_u16(Trickle::new(&data, chunk))
+                .unwrap();
+
+            assert!(pixels == expected_u16, "{file}, {chunk} bytes per read");
+        }
+    }
+}
+
+#[test]
+fn metadata_of_readers()
+{
+    let data = std::fs::read(path("google_pixel.jpg")).unwrap();
+    let mut decoder = Decoder::new();
+
+    decoder.read_headers(&data).unwrap();
+
+    let exif = decoder.exif().cloned();
+    let xmp = decoder.xmp();
+
+    decoder.decode_reader(Trickle::new(&data, 7)).unwrap();
+
+    assert!(exif.is_some());
+    assert_eq!(decoder.exif().cloned(), exif);
+    assert_eq!(decoder.xmp(), xmp);
+    assert_eq!(decoder.end_of_image(), Some(0x34_2C3E));
+}
+
+#[test]
+fn large_scan_with_dnl()
+{
+    // the main image, not the EXIF thumbnail, its height is after a scan of 3 MB
+    let mut data = std::fs::read(path("google_pixel.jpg")).unwrap();
+    let expected = Decoder::new().decode_buffer(&data).unwrap();
+
+    let sof = data
+        .windows(5)
+        .rposition(|x| x == b"\xFF\xC0\x00\x11\x08")
+        .unwrap();
+    assert_eq!(data[sof + 5..sof + 7], 4032_u16.to_be_bytes());
+    data[sof + 5..sof + 7].fill(0);
+
+    data.splice(0x34_2C3E..0x34_2C3E, *b"\xFF\xDC\x00\x04\x0F\xC0");
+
+    let pixels = Decoder::new()
+        .decode_reader(Trickle::new(&data, 4096))
+        .unwrap();
+
+    assert!(pixels == expected);
+}
+
+#[test]
+fn data_after_the_image_is_not_read()
+{
+    // the Pixel has 31702 bytes of debug data after the image
+    let data = std::fs::read(path("google_pixel.jpg")).unwrap();
+    let mut reader = &data[..];
+
+    Decoder::new().decode_reader(&mut reader).unwrap();
+
+    assert!(reader.len() > 20000, "{} bytes left", reader.len());
+}
+
+#[test]
+fn files_are_read_as_needed()
+{
+    let file = path("huffman_sequential_hv.jpg");
+    let expected = Decoder::new()
+        .decode_buffer(&std::fs::read(&file).unwrap())
+        .unwrap();
+
+    assert!(Decoder::new().decode_file(&file).unwrap() == expected);
+    assert!(Decoder::new()
+        .decode_file("tests/inputs/missing.jpg")
+        .is_err());
+}
+
+#[test]
+fn reader_errors()
+{
+    let data = std::fs::read(path("rgb_progressive.jpg")).unwrap();
+
+    // in the headers and in the image data
+    for length in [100, data.len() / 2]
+    {
+        let reader = Failing {
+            data: &data[..length],
+        };
+        let error = Decoder::new().decode_reader(reader).unwrap_err();
+
+        assert!(
+            format!("{error:?}").contains("connection reset"),
+            "{error:?}"
+        );
+    }
+}
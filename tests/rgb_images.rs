@@ -0,0 +1,116 @@
+//! Tests for three component images stored as RGB instead of YCbCr
+//!
+//! The images were encoded with libjpeg-turbo's `cjpeg -rgb`, which writes an Adobe
+//! marker and calls components R, G and B. `rgb_adobe.jpg` has its components renamed to
+//! 1, 2 and 3 so only the marker tells it's RGB, while `rgb_ids.jpg` has no marker.
+//!
+//! References are `djpeg` output, 8 bit images go through our own IDCT which may
+//! round differently from libjpeg's by one.
+mod common;
+
+use common::{path, read_pnm_u16};
+use zune_jpeg::{ColorSpace, Decoder, ZuneJpegOptions};
+
+fn assert_close(pixels: &[u8], reference: &[u16])
+{
+    assert_eq!(pixels.len(), reference.len());
+
+    for (pixel, expected) in pixels.iter().zip(reference)
+    {
+        assert!(u16::from(*pixel).abs_diff(*expected) <= 1);
+    }
+}
+
+#[test]
+fn rgb_adobe_marker()
+{
+    let pixels = Decoder::new().decode_file(path("rgb_adobe.jpg")).unwrap();
+
+    assert_close(&pixels, &read_pnm_u16("rgb.ppm"));
+}
+
+#[test]
+fn rgb_component_ids()
+{
+    let pixels = Decoder::new().decode_file(path("rgb_ids.jpg")).unwrap();
+
+    assert_close(&pixels, &read_pnm_u16("rgb.ppm"));
+}
+
+#[test]
+fn rgb_progressive()
+{
+    let pixels = Decoder::new()
+        .decode_file(path("rgb_progressive.jpg"))
+        .unwrap();
+
+    assert_close(&pixels, &read_pnm_u16("rgb_progressive.ppm"));
+}
+
+#[test]
+fn rgb_to_rgba()
+{
+    let rgb = Decoder::new().decode_file(path("rgb_ids.jpg")).unwrap();
+    let rgba =
+        Decoder::new_with_options(ZuneJpegOptions::new().set_out_colorspace(ColorSpace::RGBA))
+            .decode_file(path("rgb_ids.jpg"))
+            .unwrap();
+
+    for (rgba, rgb) in rgba.chunks_exact(4).zip(rgb.chunks_exact(3))
+    {
+        assert_eq!(&rgba[..3], rgb);
+        assert_eq!(rgba[3], 255);
+    }
+}
+
+#[test]
+fn rgb_12_bit()
+{
+    let pixels = Decoder::new()
+        .decode_file_u16(path("rgb_12_bit.jpg"))
+        .unwrap();
+
+    assert!(pixels == read_pnm_u16("rgb_12_bit.ppm"));
+}
+
+#[test]
+fn jfif_marker_means_ycbcr()
+{
+    // a JFIF marker overrides the Adobe marker and component ids
+    let mut data = std::fs::read(path("rgb_progressive.jpg")).unwrap();
+    data.splice(
+        2..2,
+        *b"\xFF\xE0\x00\x10JFIF\x00\x01\x01\x00\x00\x01\x00\x01\x00\x00",
+    );
+
+    let ycbcr = Decoder::new().decode_buffer(&data).unwrap();
+    let rgb = Decoder::new()
+        .decode_file(path("rgb_progressive.jpg"))
+        .unwrap();
+
+    assert!(ycbcr != rgb);
+}
+
+#[test]
+fn rgb_to_grayscale_unsupported()
+{
+    let result =
+        Decoder::new_with_options(ZuneJpegOptions::new().set_out_colorspace(ColorSpace::GRAYSCALE))
+            .decode_file(path("rgb_ids.jpg"));
+
+    assert!(result.is_err());
+}
+
+#[test]
+fn jfif_marker_of_previous_image_not_kept()
+{
+    let mut decoder = Decoder::new();
+
+    decoder
+        .decode_file(path("huffman_sequential_hv.jpg"))
+        .unwrap();
+
+    let pixels = decoder.decode_file(path("rgb_ids.jpg")).unwrap();
+
+    assert_close(&pixels, &read_pnm_u16("rgb.ppm"));
+}
@@ -0,0 +1,94 @@
+//! Tests for images with sampling factors other than the usual 4:4:4, 4:2:2 and 4:2:0
+//!
+//! The images were encoded with libjpeg-turbo's `cjpeg -sample`, and references are
+//! `djpeg`'s output. Up-sampling follows libjpeg's, but our IDCT and color conversion
+//! round differently, so samples may be off by a few.
+//!
+//! `sampling_fractional.jpg` has its components sampled 3x1, 2x1 and 1x1 which libjpeg
+//! refuses to up-sample, so only its luminance is compared.
+mod common;
+
+use common::{path, read_pnm};
+use zune_jpeg::{ColorSpace, Decoder, ZuneJpegOptions};
+
+fn assert_close(pixels: &[u8], reference: &[u8], tolerance: u8)
+{
+    assert_eq!(pixels.len(), reference.len());
+
+    for (pixel, expected) in pixels.iter().zip(reference)
+    {
+        assert!(pixel.abs_diff(*expected) <= tolerance);
+    }
+}
+
+fn assert_matches_reference(file: &str, reference: &str)
+{
+    let pixels = Decoder::new().decode_file(path(file)).unwrap();
+
+    assert_close(&pixels, &read_pnm(reference), 3);
+}
+
+#[test]
+fn sampling_411()
+{
+    assert_matches_reference("sampling_411.jpg", "sampling_411.ppm");
+}
+
+#[test]
+fn sampling_410_progressive()
+{
+    assert_matches_reference(
+        "sampling_410_progressive.jpg",
+        "sampling_410_progressive.ppm",
+    );
+}
+
+#[test]
+fn sampling_3x2()
+{
+    assert_matches_reference("sampling_3x2.jpg", "sampling_3x2.ppm");
+}
+
+#[test]
+fn sampling_1x4()
+{
+    assert_matches_reference("sampling_1x4.jpg", "sampling_1x4.ppm");
+}
+
+#[test]
+fn chroma_sampled_more_than_luma()
+{
+    // Y is 1x1 while Cb and Cr are 2x2
+    assert_matches_reference(
+        "sampling_chroma_2x2_progressive.jpg",
+        "sampling_chroma_2x2_progressive.ppm",
+    );
+}
+
+#[test]
+fn chroma_not_1x1()
+{
+    // Y is 2x2, Cb 1x2 and Cr 2x1
+    assert_matches_reference("sampling_chroma_1x2_2x1.jpg", "sampling_chroma_1x2_2x1.ppm");
+}
+
+#[test]
+fn fractional_sampling()
+{
+    let gray =
+        Decoder::new_with_options(ZuneJpegOptions::new().set_out_colorspace(ColorSpace::GRAYSCALE))
+            .decode_file(path("sampling_fractional.jpg"))
+            .unwrap();
+
+    assert_close(&gray, &read_pnm("sampling_fractional.pgm"), 1);
+
+    let mut decoder = Decoder::new();
+    let pixels = decoder
+        .decode_file(path("sampling_fractional.jpg"))
+        .unwrap();
+
+    assert_eq!(
+        pixels.len(),
+        usize::from(decoder.width()) * usize::from(decoder.height()) * 3
+    );
+}
@@ -0,0 +1,63 @@
+//! Tests for sequential images whose components are split across multiple scans
+//!
+//! The images were made from `sequential_single_scan.jpg` and a 4:4:4 version of it
+//! (`sequential_single_scan_444.jpg`) with `jpegtran -scans`, so they hold the same
+//! coefficients as the original and should decode to the same pixels.
+mod common;
+
+use common::path;
+use zune_jpeg::{ColorSpace, Decoder, ZuneJpegOptions};
+
+fn decode(file: &str) -> Vec<u8>
+{
+    Decoder::new_with_options(ZuneJpegOptions::new().set_out_colorspace(ColorSpace::YCbCr))
+        .decode_file(path(file))
+        .expect("Test failed decoding")
+}
+
+#[test]
+fn scan_per_component()
+{
+    assert!(decode("sequential_scan_per_component.jpg") == decode("sequential_single_scan.jpg"));
+}
+
+#[test]
+fn luma_scan_and_chroma_scan_with_restarts()
+{
+    // a scan with Y, then a scan with Cb and Cr interleaved
+    assert!(decode("sequential_two_scans_restart.jpg") == decode("sequential_single_scan.jpg"));
+}
+
+#[test]
+fn scan_per_component_444()
+{
+    // the bitstream reads ahead to the end of the image while the last
+    // MCU's of the single scan are buffered
+    assert!(
+        decode("sequential_scan_per_component_444.jpg")
+            == decode("sequential_single_scan_444.jpg")
+    );
+}
+
+#[test]
+fn scan_per_component_restarts()
+{
+    assert!(
+        decode("sequential_scan_per_component_444_restart.jpg")
+            == decode("sequential_scan_per_component_444.jpg")
+    );
+}
+
+#[test]
+fn scan_per_component_to_rgb()
+{
+    let mut decoder = Decoder::new();
+    let pixels = decoder
+        .decode_file(path("sequential_scan_per_component.jpg"))
+        .unwrap();
+
+    assert_eq!(
+        pixels.len(),
+        usize::from(decoder.width()) * usize::from(decoder.height()) * 3
+    );
+}
@@ -0,0 +1,154 @@
+//! Tests for decoding embedded previews
+//!
+//! The Pixel image has a JPEG thumbnail in its EXIF segment,
+//! `metadata_segments.jpg` JFIF and JFXX thumbnails of every kind, the largest
+//! being a JPEG image of 8 by 8 pixels.
+mod common;
+
+use common::path;
+use zune_jpeg::{ColorSpace, Decoder, JfifThumbnail, Thumbnail, ZuneJpegOptions};
+
+fn read_headers(data: &[u8], options: ZuneJpegOptions) -> Decoder
+{
+    let mut decoder = Decoder::new_with_options(options);
+    decoder.read_headers(data).unwrap();
+
+    decoder
+}
+
+/// `metadata_segments.jpg` without its JFXX segment holding a JPEG thumbnail
+fn without_jpeg_thumbnail() -> Vec<u8>
+{
+    let data = std::fs::read(path("metadata_segments.jpg")).unwrap();
+    let start = data.windows(6).position(|x| x == b"JFXX\0\x10").unwrap() - 4;
+    let length = usize::from(u16::from_be_bytes([data[start + 2], data[start + 3]]));
+
+    let mut image = data[..start].to_vec();
+    image.extend_from_slice(&data[start + 2 + length..]);
+
+    image
+}
+
+/// 2 by 2 palette thumbnail of `metadata_segments.jpg`, as RGB pixels
+///
+/// Color `n` of its palette is `3n`, `3n + 1` and `3n + 2`, modulo 256.
+fn palette_pixels(indices: [usize; 4]) -> Vec<u8>
+{
+    indices
+        .iter()
+        .flat_map(|x| (0..3).map(move |i| ((x * 3 + i) % 256) as u8))
+        .collect()
+}
+
+#[test]
+fn exif_thumbnail()
+{
+    let data = std::fs::read(path("google_pixel.jpg")).unwrap();
+    let decoder = read_headers(&data, ZuneJpegOptions::new());
+    let thumbnail = decoder.thumbnail().unwrap().unwrap();
+
+    // offset and length of IFD1, after the TIFF header at byte 12
+    let jpeg = &data[12 + 1132..12 + 1132 + 40843];
+    let mut expected = Decoder::new();
+    let pixels = expected.decode_buffer(jpeg).unwrap();
+
+    assert_eq!(
+        thumbnail,
+        Thumbnail {
+            width: expected.width(),
+            height: expected.height(),
+            colorspace: ColorSpace::RGB,
+            pixels,
+        }
+    );
+    assert_eq!((thumbnail.width, thumbnail.height), (378, 504));
+    // the decoder still describes the image itself
+    assert_eq!((decoder.width(), decoder.height()), (3024, 4032));
+}
+
+#[test]
+fn largest_jfxx_thumbnail_is_chosen()
+{
+    let data = std::fs::read(path("metadata_segments.jpg")).unwrap();
+    let decoder = read_headers(&data, ZuneJpegOptions::new());
+
+    let info = decoder.info().unwrap();
+    let JfifThumbnail::Jpeg(jpeg) = &info.jfif_thumbnails[1]
+    else
+    {
+        panic!("expected a JPEG thumbnail");
+    };
+    let expected = Decoder::new().decode_buffer(jpeg).unwrap();
+
+    let thumbnail = decoder.thumbnail().unwrap().unwrap();
+
+    assert_eq!((thumbnail.width, thumbnail.height), (8, 8));
+    assert_eq!(thumbnail.pixels, expected);
+}
+
+#[test]
+fn jpeg_thumbnail_uses_decoder_options()
+{
+    let data = std::fs::read(path("metadata_segments.jpg")).unwrap();
+    let options = ZuneJpegOptions::new().set_out_colorspace(ColorSpace::RGBA);
+    let thumbnail = read_headers(&data, options).thumbnail().unwrap().unwrap();
+
+    assert_eq!(thumbnail.colorspace, ColorSpace::RGBA);
+    assert_eq!(thumbnail.pixels.len(), 8 * 8 * 4);
+}
+
+#[test]
+fn palette_thumbnail_as_rgb()
+{
+    let data = without_jpeg_thumbnail();
+    let options = ZuneJpegOptions::new().set_out_colorspace(ColorSpace::RGBA);
+    let thumbnail = read_headers(&data, options).thumbnail().unwrap().unwrap();
+
+    assert_eq!(
+        thumbnail,
+        Thumbnail {
+            width:      2,
+            height:     2,
+            colorspace: ColorSpace::RGB,
+            pixels:     palette_pixels([0, 1, 2, 255]),
+        }
+    );
+}
+
+#[test]
+fn thumbnail_orientation()
+{
+    // put the EXIF segment of `exif_big_endian_gps.jpg`, with orientation 6,
+    // after the SOI
+    let exif = std::fs::read(path("exif_big_endian_gps.jpg")).unwrap();
+    let length = usize::from(u16::from_be_bytes([exif[4], exif[5]]));
+    let data = without_jpeg_thumbnail();
+
+    let mut image = data[..2].to_vec();
+    image.extend_from_slice(&exif[2..4 + length]);
+    image.extend_from_slice(&data[2..]);
+
+    let options = ZuneJpegOptions::new().set_apply_orientation(true);
+    let thumbnail = read_headers(&image, options).thumbnail().unwrap().unwrap();
+
+    // turned 90 degrees clockwise
+    assert_eq!(thumbnail.pixels, palette_pixels([2, 0, 255, 1]));
+
+    let thumbnail = read_headers(&image, ZuneJpegOptions::new())
+        .thumbnail()
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(thumbnail.pixels, palette_pixels([0, 1, 2, 255]));
+}
+
+#[test]
+fn image_without_thumbnail()
+{
+    // IFD1 says the thumbnail is a JPEG image, but doesn't point at one
+    let data = std::fs::read(path("exif_big_endian_gps.jpg")).unwrap();
+    let decoder = read_headers(&data, ZuneJpegOptions::new());
+
+    assert!(decoder.exif().unwrap().thumbnail().is_none());
+    assert_eq!(decoder.thumbnail().unwrap(), None);
+}
@@ -0,0 +1,178 @@
+//! Tests for the end of images and data after it
+//!
+//! `motion_photo.jpg` is a progressive image whose EOI marker is at byte 1780,
+//! followed by 8 bytes of padding and an MP4 video of 556 bytes. Its XMP
+//! packet has both a container directory and the older `GCamera:MicroVideo`
+//! properties.
+mod common;
+
+use common::path;
+use zune_jpeg::container::{ContainerItem, ItemSemantic};
+use zune_jpeg::Decoder;
+
+fn video() -> ContainerItem
+{
+    ContainerItem {
+        semantic: ItemSemantic::MotionPhoto,
+        mime:     "video/mp4".to_string(),
+        offset:   1790,
+        length:   556,
+    }
+}
+
+#[test]
+fn end_of_image_of_each_coding_process()
+{
+    for file in [
+        "huffman_sequential_hv.jpg",
+        "rgb_progressive.jpg",
+        "arithmetic_sequential_hv_restart.jpg",
+        "arithmetic_progressive_hv_restart.jpg",
+        "sequential_12_bit_hv.jpg",
+        "lossless_12_bit_restarts.jpg",
+        "hierarchical_8_bit_gray.jpg",
+        "dnl_sequential.jpg",
+        "cmyk_adobe.jpg",
+    ]
+    {
+        let data = std::fs::read(path(file)).unwrap();
+        let mut decoder = Decoder::new();
+
+        decoder.read_headers(&data).unwrap();
+        assert_eq!(decoder.end_of_image(), None, "{file}");
+
+        decoder.decode_buffer(&data).unwrap();
+        assert_eq!(decoder.end_of_image(), Some(data.len() - 2), "{file}");
+        assert!(decoder.trailer(&data).is_none(), "{file}");
+
+        decoder.decode_buffer_u16(&data).unwrap();
+        assert_eq!(decoder.end_of_image(), Some(data.len() - 2), "{file}");
+    }
+}
+
+#[test]
+fn motion_photo()
+{
+    let data = std::fs::read(path("motion_photo.jpg")).unwrap();
+    let mut decoder = Decoder::new();
+
+    decoder.decode_buffer(&data).unwrap();
+
+    assert_eq!(decoder.end_of_image(), Some(1780));
+
+    let trailer = decoder.trailer(&data).unwrap();
+
+    assert_eq!(trailer.offset, 1782);
+    assert_eq!(trailer.data, &data[1782..]);
+    assert_eq!(trailer.items, [video()]);
+
+    let video = trailer.motion_photo().unwrap();
+
+    assert_eq!(video, &data[1790..]);
+    assert_eq!(&video[4..8], b"ftyp");
+}
+
+#[test]
+fn micro_video_without_directory()
+{
+    let mut data = std::fs::read(path("motion_photo.jpg")).unwrap();
+
+    let directory = data
+        .windows(20)
+        .position(|x| x == b"<Container:Directory")
+        .unwrap();
+    data[directory + 1] = b'X';
+
+    let mut decoder = Decoder::new();
+
+    decoder.decode_buffer(&data).unwrap();
+
+    let trailer = decoder.trailer(&data).unwrap();
+
+    assert_eq!(trailer.items, [video()]);
+    assert_eq!(trailer.motion_photo(), Some(&data[1790..]));
+}
+
+#[test]
+fn items_larger_than_trailer()
+{
+    let mut data = std::fs::read(path("motion_photo.jpg")).unwrap();
+
+    let length = data
+        .windows(17)
+        .position(|x| x == b"Item:Length=\"556\"")
+        .unwrap();
+    data[length + 13] = b'9';
+
+    let mut decoder = Decoder::new();
+
+    decoder.decode_buffer(&data).unwrap();
+
+    let trailer = decoder.trailer(&data).unwrap();
+
+    assert!(trailer.items.is_empty());
+    assert!(trailer.motion_photo().is_none());
+    assert_eq!(trailer.data.len(), 564);
+}
+
+#[test]
+fn undeclared_trailer()
+{
+    // the Pixel has debug data after the image, not described by its XMP
+    let data = std::fs::read(path("google_pixel.jpg")).unwrap();
+    let mut decoder = Decoder::new();
+
+    decoder.decode_buffer(&data).unwrap();
+
+    assert_eq!(decoder.end_of_image(), Some(0x34_2C3E));
+
+    let trailer = decoder.trailer(&data).unwrap();
+
+    assert_eq!(trailer.data.len(), 31702);
+    assert!(trailer.data.starts_with(b"aecDebug"));
+    assert!(trailer.items.is_empty());
+}
+
+#[test]
+fn gain_map_item()
+{
+    let data = std::fs::read(path("ultrahdr_gain_map.jpg")).unwrap();
+    let mut decoder = Decoder::new();
+
+    decoder.decode_buffer(&data).unwrap();
+
+    let trailer = decoder.trailer(&data).unwrap();
+
+    assert_eq!(trailer.offset, 1759);
+    assert_eq!(trailer.items.len(), 1);
+    assert_eq!(trailer.items[0].semantic, ItemSemantic::GainMap);
+    assert_eq!(trailer.items[0].mime, "image/jpeg");
+    assert_eq!(
+        trailer.item_data(&trailer.items[0]),
+        Some(&Decoder::new().gain_map(&data).unwrap().unwrap().data[..])
+    );
+}
+
+#[test]
+fn end_of_mp_image()
+{
+    let data = std::fs::read(path("mpo_stereo.jpg")).unwrap();
+    let mut decoder = Decoder::new();
+
+    decoder.decode_mp_image(&data, 1).unwrap();
+
+    assert_eq!(decoder.end_of_image(), Some(1046 + 944 - 2));
+}
+
+#[test]
+fn image_without_end_of_image()
+{
+    let data = std::fs::read(path("huffman_sequential_hv.jpg")).unwrap();
+    let data = &data[..data.len() - 2];
+    let mut decoder = Decoder::new();
+
+    decoder.decode_buffer(data).unwrap();
+
+    assert_eq!(decoder.end_of_image(), None);
+    assert!(decoder.trailer(data).is_none());
+}
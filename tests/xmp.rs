@@ -0,0 +1,131 @@
+//! Tests for reading XMP packets
+//!
+//! The Pixel image has a standard packet naming an extended packet,
+//! which is split into two chunks.
+mod common;
+
+use common::path;
+use zune_jpeg::Decoder;
+
+const EXTENSION: &[u8] = b"http://ns.adobe.com/xmp/extension/\0";
+
+/// Start and end of the segments of the Pixel image holding extended XMP chunks
+fn extended_segments(data: &[u8]) -> Vec<(usize, usize)>
+{
+    let mut segments = Vec::new();
+    let mut position = 2;
+
+    // walk the markers before the first scan
+    while data[position + 1] != 0xDA
+    {
+        let length = usize::from(u16::from_be_bytes([data[position + 2], data[position + 3]]));
+        let end = position + 2 + length;
+
+        if data[position + 1] == 0xE1 && data[position + 4..].starts_with(EXTENSION)
+        {
+            segments.push((position, end));
+        }
+        position = end;
+    }
+
+    segments
+}
+
+fn read_headers(data: &[u8]) -> Decoder
+{
+    let mut decoder = Decoder::new();
+    decoder.read_headers(data).unwrap();
+
+    decoder
+}
+
+#[test]
+fn standard_and_extended_packets()
+{
+    let data = std::fs::read(path("google_pixel.jpg")).unwrap();
+    let decoder = read_headers(&data);
+
+    let xmp = decoder.xmp().unwrap();
+
+    assert!(xmp.starts_with("<x:xmpmeta"));
+    assert!(xmp.contains("xmpNote:HasExtendedXMP=\"8B26F08C49EB990BB930EBC435D1F6D8\""));
+
+    let extended = decoder.extended_xmp().unwrap();
+
+    assert_eq!(extended.len(), 65591);
+    assert!(extended.starts_with("<x:xmpmeta"));
+    assert!(extended.contains("GCamera:hdrp_makernote=\""));
+    assert!(extended.trim_end().ends_with("</x:xmpmeta>"));
+}
+
+#[test]
+fn extended_chunks_out_of_order()
+{
+    let data = std::fs::read(path("google_pixel.jpg")).unwrap();
+    let segments = extended_segments(&data);
+    let ((first, _), (second, end)) = (segments[0], segments[1]);
+
+    let mut image = data[..first].to_vec();
+    image.extend_from_slice(&data[second..end]);
+    image.extend_from_slice(&data[first..second]);
+    image.extend_from_slice(&data[end..]);
+
+    let expected = read_headers(&data).extended_xmp();
+
+    assert!(expected.is_some());
+    assert_eq!(read_headers(&image).extended_xmp(), expected);
+}
+
+#[test]
+fn missing_extended_chunk()
+{
+    let data = std::fs::read(path("google_pixel.jpg")).unwrap();
+    let (start, end) = extended_segments(&data)[1];
+
+    let mut image = data[..start].to_vec();
+    image.extend_from_slice(&data[end..]);
+
+    let decoder = read_headers(&image);
+
+    assert!(decoder.xmp().is_some());
+    assert!(decoder.extended_xmp().is_none());
+}
+
+#[test]
+fn extended_packet_not_matching_its_guid()
+{
+    let mut data = std::fs::read(path("google_pixel.jpg")).unwrap();
+    let (_, end) = extended_segments(&data)[1];
+
+    // a byte of the packet data, at the end of the last chunk
+    data[end - 20] ^= 1;
+
+    assert!(read_headers(&data).extended_xmp().is_none());
+}
+
+#[test]
+fn extended_packet_not_named_by_the_standard_packet()
+{
+    let mut data = std::fs::read(path("google_pixel.jpg")).unwrap();
+    let guid = data
+        .windows(24)
+        .position(|x| x == b"HasExtendedXMP=\"8B26F08C")
+        .unwrap();
+
+    data[guid + 16] = b'0';
+
+    let decoder = read_headers(&data);
+
+    assert!(decoder.xmp().unwrap().contains("\"0B26F08C"));
+    assert!(decoder.extended_xmp().is_none());
+}
+
+#[test]
+fn image_without_xmp()
+{
+    let data = std::fs::read(path("exif_big_endian_gps.jpg")).unwrap();
+    let decoder = read_headers(&data);
+
+    assert!(decoder.xmp().is_none());
+    assert!(decoder.extended_xmp().is_none());
+}